@@ -0,0 +1,63 @@
+// Protocol parser benchmarks
+//
+// Run with `cargo bench --bench protocol`. Covers the packet shapes seen on a
+// busy LAN: plain IPMsg text, FeiQ GBK presence, attachment offers and a
+// large message close to the UDP limit.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use neolan_lib::network::{parse_message, serialize_message, RawPacket};
+
+fn sample_packets() -> Vec<(&'static str, Vec<u8>)> {
+    let mut large = b"1:1761386707:alice:alice-pc:288:".to_vec();
+    large.extend(std::iter::repeat(b"lorem ipsum: dolor sit amet ").take(2000).flatten());
+
+    vec![
+        ("ipmsg_text", b"1:123:Alice:alice-pc:288:Hello World".to_vec()),
+        (
+            "feiq_entry_gbk",
+            b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-IOHG15K:6291459:\xd5\xc5\xc8\xfd\0\xbf\xaa\xb7\xa2\xb2\xbf\0"
+                .to_vec(),
+        ),
+        (
+            "attachment_offer",
+            b"1:100:bob:bob-pc:2097440:\x000:notes.txt:1a:65000000:1:\x071:photo.jpg:2f000:65000001:1:\x07\x00".to_vec(),
+        ),
+        ("large_text", large),
+    ]
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_message");
+    for (name, packet) in sample_packets() {
+        group.throughput(Throughput::Bytes(packet.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &packet, |b, packet| {
+            b.iter(|| parse_message(black_box(packet)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_split(c: &mut Criterion) {
+    let mut group = c.benchmark_group("raw_packet_split");
+    for (name, packet) in sample_packets() {
+        group.throughput(Throughput::Bytes(packet.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &packet, |b, packet| {
+            b.iter(|| RawPacket::split(black_box(packet)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize_parse");
+    for (name, packet) in sample_packets() {
+        let msg = parse_message(&packet).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &msg, |b, msg| {
+            b.iter(|| parse_message(&serialize_message(black_box(msg)).unwrap()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_split, bench_round_trip);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+// 抓包解析示例
+//
+// 将 NeoLan 录制的 UDP 抓包文件逐条解析为可读文本，便于排查与飞秋（FeiQ）/IPMsg 的互通问题。
+//
+// 录制抓包：
+// NEOLAN_CAPTURE=capture.jsonl npm run tauri dev
+//
+// 解析抓包：
+// cargo run --example dissect_capture -- capture.jsonl
+//
+// 只显示收到的包：
+// cargo run --example dissect_capture -- capture.jsonl --in
+//
+// 回放抓包（送入 PeerManager → MessageHandler）：
+// NEOLAN_REPLAY=capture.jsonl npm run tauri dev
+
+use neolan_lib::network::{read_capture, Direction};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let Some(path) = args.next() else {
+        eprintln!("用法: dissect_capture <capture.jsonl> [--in | --out]");
+        std::process::exit(2);
+    };
+    let filter = match args.next().as_deref() {
+        Some("--in") => Some(Direction::In),
+        Some("--out") => Some(Direction::Out),
+        _ => None,
+    };
+
+    let records = match read_capture(&path) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("读取抓包失败: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for record in records.iter().filter(|r| filter.is_none_or(|d| r.direction == d)) {
+        println!("{}", record.dissect());
+    }
+
+    println!("\n共 {} 个数据包", records.len());
+}
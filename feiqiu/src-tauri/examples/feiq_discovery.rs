@@ -66,6 +66,10 @@ fn create_ipmsg_message(
         sender_host: sender_host.to_string(),
         msg_type: command,
         content: extra.to_string(),
+        extensions: Vec::new(),
+        raw_extensions: Vec::new(),
+        feiq_header: None,
+        encoding: None,
     };
     // 使用 protocol.rs 中的序列化函数
     protocol::serialize_message(&msg).unwrap_or_else(|e| {
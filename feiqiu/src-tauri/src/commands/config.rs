@@ -3,7 +3,8 @@
 // This module provides IPC interface between frontend and backend for configuration operations.
 // All commands are exposed to the frontend via Tauri's invoke system.
 
-use crate::config::AppConfig;
+use crate::config::{AppConfig, DiscoveryMode};
+use crate::network::HostEntry;
 use crate::state::AppState;
 use crate::{NeoLanError, Result};
 use serde::{Deserialize, Serialize};
@@ -31,9 +32,32 @@ pub struct ConfigDto {
     pub tcp_port_end: u16,
 
     /// Peer discovery settings
+    #[serde(default)]
+    pub discovery_mode: DiscoveryMode,
+    #[serde(default = "default_multicast_group")]
+    pub multicast_group: String,
+    #[serde(default = "default_multicast_ttl")]
+    pub multicast_ttl: u32,
+    #[serde(default = "default_skip_virtual_interfaces")]
+    pub skip_virtual_interfaces: bool,
+    #[serde(default)]
+    pub excluded_interfaces: Vec<String>,
+    /// Managed through the extra broadcast host commands (ignored by `set_config`)
+    #[serde(default)]
+    pub extra_broadcast_hosts: Vec<String>,
     pub heartbeat_interval: u64,
     pub peer_timeout: u64,
 
+    /// Inbound flood protection (0 disables the limit)
+    #[serde(default = "default_rate_limit_packets_per_second")]
+    pub rate_limit_packets_per_second: u32,
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    #[serde(default = "default_max_parse_errors_per_minute")]
+    pub max_parse_errors_per_minute: u32,
+    #[serde(default = "default_quarantine_seconds")]
+    pub quarantine_seconds: u64,
+
     /// Security settings
     pub encryption_enabled: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -68,8 +92,18 @@ impl ConfigDto {
             udp_port: config.udp_port,
             tcp_port_start: config.tcp_port_start,
             tcp_port_end: config.tcp_port_end,
+            discovery_mode: config.discovery_mode,
+            multicast_group: config.multicast_group.clone(),
+            multicast_ttl: config.multicast_ttl,
+            skip_virtual_interfaces: config.skip_virtual_interfaces,
+            excluded_interfaces: config.excluded_interfaces.clone(),
+            extra_broadcast_hosts: config.extra_broadcast_hosts.clone(),
             heartbeat_interval: config.heartbeat_interval,
             peer_timeout: config.peer_timeout,
+            rate_limit_packets_per_second: config.rate_limit_packets_per_second,
+            rate_limit_burst: config.rate_limit_burst,
+            max_parse_errors_per_minute: config.max_parse_errors_per_minute,
+            quarantine_seconds: config.quarantine_seconds,
             encryption_enabled: config.encryption_enabled,
             encryption_key: config.encryption_key.clone(),
             offline_message_retention_days: config.offline_message_retention_days,
@@ -88,6 +122,16 @@ impl ConfigDto {
             udp_port: self.udp_port,
             tcp_port_start: self.tcp_port_start,
             tcp_port_end: self.tcp_port_end,
+            discovery_mode: self.discovery_mode,
+            multicast_group: self.multicast_group.clone(),
+            multicast_ttl: self.multicast_ttl,
+            skip_virtual_interfaces: self.skip_virtual_interfaces,
+            excluded_interfaces: self.excluded_interfaces.clone(),
+            extra_broadcast_hosts: self.extra_broadcast_hosts.clone(),
+            rate_limit_packets_per_second: self.rate_limit_packets_per_second,
+            rate_limit_burst: self.rate_limit_burst,
+            max_parse_errors_per_minute: self.max_parse_errors_per_minute,
+            quarantine_seconds: self.quarantine_seconds,
             heartbeat_interval: self.heartbeat_interval,
             peer_timeout: self.peer_timeout,
             encryption_enabled: self.encryption_enabled,
@@ -123,6 +167,30 @@ impl ConfigDto {
                 .get("tcp_port_end")
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(9000),
+            discovery_mode: map
+                .get("discovery_mode")
+                .and_then(|s| parse_discovery_mode(s))
+                .unwrap_or_default(),
+            multicast_group: map
+                .get("multicast_group")
+                .cloned()
+                .unwrap_or_else(default_multicast_group),
+            multicast_ttl: map
+                .get("multicast_ttl")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_multicast_ttl),
+            skip_virtual_interfaces: map
+                .get("skip_virtual_interfaces")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_skip_virtual_interfaces),
+            excluded_interfaces: map
+                .get("excluded_interfaces")
+                .map(|s| parse_interface_list(s))
+                .unwrap_or_default(),
+            extra_broadcast_hosts: map
+                .get("extra_broadcast_hosts")
+                .map(|s| parse_interface_list(s))
+                .unwrap_or_default(),
             heartbeat_interval: map
                 .get("heartbeat_interval")
                 .and_then(|s| s.parse().ok())
@@ -131,6 +199,22 @@ impl ConfigDto {
                 .get("peer_timeout")
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(180),
+            rate_limit_packets_per_second: map
+                .get("rate_limit_packets_per_second")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_rate_limit_packets_per_second),
+            rate_limit_burst: map
+                .get("rate_limit_burst")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_rate_limit_burst),
+            max_parse_errors_per_minute: map
+                .get("max_parse_errors_per_minute")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_max_parse_errors_per_minute),
+            quarantine_seconds: map
+                .get("quarantine_seconds")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(default_quarantine_seconds),
             encryption_enabled: map
                 .get("encryption_enabled")
                 .and_then(|s| s.parse().ok())
@@ -169,8 +253,36 @@ impl ConfigDto {
         map.insert("udp_port".to_string(), self.udp_port.to_string());
         map.insert("tcp_port_start".to_string(), self.tcp_port_start.to_string());
         map.insert("tcp_port_end".to_string(), self.tcp_port_end.to_string());
+        map.insert(
+            "discovery_mode".to_string(),
+            discovery_mode_str(self.discovery_mode).to_string(),
+        );
+        map.insert("multicast_group".to_string(), self.multicast_group.clone());
+        map.insert("multicast_ttl".to_string(), self.multicast_ttl.to_string());
+        map.insert(
+            "skip_virtual_interfaces".to_string(),
+            self.skip_virtual_interfaces.to_string(),
+        );
+        map.insert(
+            "excluded_interfaces".to_string(),
+            self.excluded_interfaces.join(","),
+        );
+        map.insert(
+            "extra_broadcast_hosts".to_string(),
+            self.extra_broadcast_hosts.join(","),
+        );
         map.insert("heartbeat_interval".to_string(), self.heartbeat_interval.to_string());
         map.insert("peer_timeout".to_string(), self.peer_timeout.to_string());
+        map.insert(
+            "rate_limit_packets_per_second".to_string(),
+            self.rate_limit_packets_per_second.to_string(),
+        );
+        map.insert("rate_limit_burst".to_string(), self.rate_limit_burst.to_string());
+        map.insert(
+            "max_parse_errors_per_minute".to_string(),
+            self.max_parse_errors_per_minute.to_string(),
+        );
+        map.insert("quarantine_seconds".to_string(), self.quarantine_seconds.to_string());
         map.insert(
             "encryption_enabled".to_string(),
             self.encryption_enabled.to_string(),
@@ -225,6 +337,22 @@ impl ConfigDto {
             ));
         }
 
+        // Validate flood protection
+        if self.rate_limit_packets_per_second > 0 && self.rate_limit_burst == 0 {
+            return Err(NeoLanError::Validation(
+                "rate_limit_burst must be > 0 when rate limiting is enabled".to_string(),
+            ));
+        }
+
+        // Validate multicast discovery settings
+        self.to_app_config().multicast_addr()?;
+        if self.multicast_ttl == 0 || self.multicast_ttl > 255 {
+            return Err(NeoLanError::Validation(
+                "multicast_ttl must be between 1 and 255".to_string(),
+            ));
+        }
+        crate::network::expand_hosts(&self.extra_broadcast_hosts)?;
+
         // Validate log level
         match self.log_level.as_str() {
             "trace" | "debug" | "info" | "warn" | "error" => {}
@@ -261,8 +389,18 @@ impl Default for ConfigDto {
             udp_port: 2425,
             tcp_port_start: 8000,
             tcp_port_end: 9000,
+            discovery_mode: DiscoveryMode::default(),
+            multicast_group: default_multicast_group(),
+            multicast_ttl: default_multicast_ttl(),
+            skip_virtual_interfaces: default_skip_virtual_interfaces(),
+            excluded_interfaces: Vec::new(),
+            extra_broadcast_hosts: Vec::new(),
             heartbeat_interval: 60,
             peer_timeout: 180,
+            rate_limit_packets_per_second: default_rate_limit_packets_per_second(),
+            rate_limit_burst: default_rate_limit_burst(),
+            max_parse_errors_per_minute: default_max_parse_errors_per_minute(),
+            quarantine_seconds: default_quarantine_seconds(),
             encryption_enabled: false,
             encryption_key: None,
             offline_message_retention_days: 30,
@@ -276,6 +414,63 @@ impl Default for ConfigDto {
     }
 }
 
+fn default_multicast_group() -> String {
+    AppConfig::DEFAULT_MULTICAST_GROUP.to_string()
+}
+
+fn default_multicast_ttl() -> u32 {
+    AppConfig::DEFAULT_MULTICAST_TTL
+}
+
+fn default_skip_virtual_interfaces() -> bool {
+    true
+}
+
+fn default_rate_limit_packets_per_second() -> u32 {
+    AppConfig::DEFAULT_RATE_LIMIT_PACKETS_PER_SECOND
+}
+
+fn default_rate_limit_burst() -> u32 {
+    AppConfig::DEFAULT_RATE_LIMIT_BURST
+}
+
+fn default_max_parse_errors_per_minute() -> u32 {
+    AppConfig::DEFAULT_MAX_PARSE_ERRORS_PER_MINUTE
+}
+
+fn default_quarantine_seconds() -> u64 {
+    AppConfig::DEFAULT_QUARANTINE_SECONDS
+}
+
+/// Parse a comma-separated list key value ("docker*, eth1", "10.8.0.7, 10.8.1.0/24")
+fn parse_interface_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse a discovery mode key value ("broadcast", "multicast" or "both")
+fn parse_discovery_mode(value: &str) -> Option<DiscoveryMode> {
+    match value {
+        "broadcast" => Some(DiscoveryMode::Broadcast),
+        "multicast" => Some(DiscoveryMode::Multicast),
+        "both" => Some(DiscoveryMode::Both),
+        _ => None,
+    }
+}
+
+/// Get the key value of a discovery mode
+fn discovery_mode_str(mode: DiscoveryMode) -> &'static str {
+    match mode {
+        DiscoveryMode::Broadcast => "broadcast",
+        DiscoveryMode::Multicast => "multicast",
+        DiscoveryMode::Both => "both",
+    }
+}
+
 /// Get current application configuration
 ///
 /// This command returns the current configuration. If no configuration is saved,
@@ -312,8 +507,9 @@ pub fn set_config(state: tauri::State<AppState>, config: ConfigDto) -> Result<()
     // Validate configuration
     config.validate()?;
 
-    // Convert and set
-    let app_config = config.to_app_config();
+    // Convert and set (the extra broadcast hosts are persisted by their own commands)
+    let mut app_config = config.to_app_config();
+    app_config.extra_broadcast_hosts = state.get_config().extra_broadcast_hosts;
     state.set_config(app_config);
 
     Ok(())
@@ -332,7 +528,11 @@ pub fn set_config(state: tauri::State<AppState>, config: ConfigDto) -> Result<()
 pub fn reset_config(state: tauri::State<AppState>) -> Result<ConfigDto> {
     tracing::info!("reset_config called");
 
-    let default_config = AppConfig::default();
+    // The extra broadcast hosts are persisted by their own commands and survive a reset
+    let default_config = AppConfig {
+        extra_broadcast_hosts: state.get_config().extra_broadcast_hosts,
+        ..AppConfig::default()
+    };
     state.set_config(default_config.clone());
 
     Ok(ConfigDto::from_app_config(&default_config))
@@ -436,6 +636,19 @@ pub fn set_config_value(state: tauri::State<AppState>, key: String, value: Strin
             let enabled = value == "true";
             state.update_config(|c| c.encryption_enabled = enabled)?;
         }
+        "skip_virtual_interfaces" => {
+            if value != "true" && value != "false" {
+                return Err(NeoLanError::Validation(
+                    "value must be 'true' or 'false'".to_string(),
+                ));
+            }
+            let enabled = value == "true";
+            state.update_config(|c| c.skip_virtual_interfaces = enabled)?;
+        }
+        "excluded_interfaces" => {
+            let names = parse_interface_list(&value);
+            state.update_config(|c| c.excluded_interfaces = names)?;
+        }
         "auto_accept_files" => {
             if value != "true" && value != "false" {
                 return Err(NeoLanError::Validation(
@@ -467,6 +680,63 @@ pub fn set_config_value(state: tauri::State<AppState>, key: String, value: Strin
         "bind_ip" => {
             state.update_config(|c| c.bind_ip = value)?;
         }
+        "discovery_mode" => {
+            let mode = parse_discovery_mode(&value).ok_or_else(|| {
+                NeoLanError::Validation(format!(
+                    "Invalid discovery_mode: {}. Must be one of: broadcast, multicast, both",
+                    value
+                ))
+            })?;
+            state.update_config(|c| c.discovery_mode = mode)?;
+        }
+        "multicast_group" => {
+            let probe = AppConfig {
+                multicast_group: value.clone(),
+                ..AppConfig::default()
+            };
+            probe.multicast_addr()?;
+            state.update_config(|c| c.multicast_group = value)?;
+        }
+        "multicast_ttl" => {
+            let ttl: u32 = value.parse().map_err(|_| {
+                NeoLanError::Validation(format!("Invalid number value: {}", value))
+            })?;
+            if ttl == 0 || ttl > 255 {
+                return Err(NeoLanError::Validation(
+                    "value must be between 1 and 255".to_string(),
+                ));
+            }
+            state.update_config(|c| c.multicast_ttl = ttl)?;
+        }
+        "rate_limit_packets_per_second" => {
+            let rate: u32 = value.parse().map_err(|_| {
+                NeoLanError::Validation(format!("Invalid number value: {}", value))
+            })?;
+            state.update_config(|c| c.rate_limit_packets_per_second = rate)?;
+        }
+        "rate_limit_burst" => {
+            let burst: u32 = value.parse().map_err(|_| {
+                NeoLanError::Validation(format!("Invalid number value: {}", value))
+            })?;
+            if burst == 0 {
+                return Err(NeoLanError::Validation(
+                    "value must be > 0".to_string(),
+                ));
+            }
+            state.update_config(|c| c.rate_limit_burst = burst)?;
+        }
+        "max_parse_errors_per_minute" => {
+            let max: u32 = value.parse().map_err(|_| {
+                NeoLanError::Validation(format!("Invalid number value: {}", value))
+            })?;
+            state.update_config(|c| c.max_parse_errors_per_minute = max)?;
+        }
+        "quarantine_seconds" => {
+            let seconds: u64 = value.parse().map_err(|_| {
+                NeoLanError::Validation(format!("Invalid number value: {}", value))
+            })?;
+            state.update_config(|c| c.quarantine_seconds = seconds)?;
+        }
         "file_save_dir" => {
             state.update_config(|c| c.file_save_dir = value)?;
         }
@@ -479,6 +749,98 @@ pub fn set_config_value(state: tauri::State<AppState>, key: String, value: Strin
     Ok(())
 }
 
+/// Get the extra broadcast hosts
+///
+/// Hosts and CIDR ranges outside the local subnets that receive our presence
+/// announcements by unicast (e.g. peers across a router or VPN).
+///
+/// # Frontend Usage
+/// ```typescript
+/// import { invoke } from "@tauri-apps/api/core";
+/// const hosts = await invoke<string[]>("get_extra_broadcast_hosts");
+/// ```
+#[tauri::command]
+pub fn get_extra_broadcast_hosts(state: tauri::State<AppState>) -> Result<Vec<String>> {
+    tracing::info!("get_extra_broadcast_hosts called");
+
+    Ok(state.get_config().extra_broadcast_hosts)
+}
+
+/// Replace the extra broadcast hosts
+///
+/// The list is validated, saved, and used right away (the new hosts get an
+/// announcement immediately). Returns the stored list.
+///
+/// # Frontend Usage
+/// ```typescript
+/// import { invoke } from "@tauri-apps/api/core";
+/// await invoke("set_extra_broadcast_hosts", { hosts: ["10.8.0.7", "10.8.1.0/24"] });
+/// ```
+#[tauri::command]
+pub async fn set_extra_broadcast_hosts(
+    state: tauri::State<'_, AppState>,
+    hosts: Vec<String>,
+) -> Result<Vec<String>> {
+    tracing::info!("set_extra_broadcast_hosts called with: {:?}", hosts);
+
+    state.set_extra_broadcast_hosts(hosts).await
+}
+
+/// Add a host or CIDR range to the extra broadcast hosts
+///
+/// Adding an entry that's already listed is a no-op. Returns the stored list.
+///
+/// # Frontend Usage
+/// ```typescript
+/// import { invoke } from "@tauri-apps/api/core";
+/// const hosts = await invoke<string[]>("add_extra_broadcast_host", { host: "10.8.0.7" });
+/// ```
+#[tauri::command]
+pub async fn add_extra_broadcast_host(
+    state: tauri::State<'_, AppState>,
+    host: String,
+) -> Result<Vec<String>> {
+    tracing::info!("add_extra_broadcast_host called with: {}", host);
+
+    let host = normalize_host_entry(&host)?;
+    let mut hosts = state.get_config().extra_broadcast_hosts;
+    if !hosts.contains(&host) {
+        hosts.push(host);
+    }
+    state.set_extra_broadcast_hosts(hosts).await
+}
+
+/// Remove a host or CIDR range from the extra broadcast hosts
+///
+/// Returns the stored list.
+///
+/// # Frontend Usage
+/// ```typescript
+/// import { invoke } from "@tauri-apps/api/core";
+/// const hosts = await invoke<string[]>("remove_extra_broadcast_host", { host: "10.8.0.7" });
+/// ```
+#[tauri::command]
+pub async fn remove_extra_broadcast_host(
+    state: tauri::State<'_, AppState>,
+    host: String,
+) -> Result<Vec<String>> {
+    tracing::info!("remove_extra_broadcast_host called with: {}", host);
+
+    let host = normalize_host_entry(&host)?;
+    let mut hosts = state.get_config().extra_broadcast_hosts;
+    let before = hosts.len();
+    hosts.retain(|entry| *entry != host);
+    if hosts.len() == before {
+        return Err(NeoLanError::Validation(format!("Not an extra broadcast host: {}", host)));
+    }
+    state.set_extra_broadcast_hosts(hosts).await
+}
+
+/// Parse a host or CIDR range into its canonical form ("10.8.1.77/24" -> "10.8.1.0/24")
+fn normalize_host_entry(host: &str) -> Result<String> {
+    Ok(host.parse::<HostEntry>()?.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -528,6 +890,60 @@ mod tests {
         config.peer_timeout = 180;
         config.log_level = "invalid".to_string();
         assert!(config.validate().is_err());
+
+        // Invalid multicast group
+        config.log_level = "info".to_string();
+        config.multicast_group = "10.0.0.1".to_string();
+        assert!(config.validate().is_err());
+
+        // Invalid multicast TTL
+        config.multicast_group = "239.192.24.25".to_string();
+        config.multicast_ttl = 256;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_configDto_discovery_mode() {
+        let mut map = HashMap::new();
+        map.insert("discovery_mode".to_string(), "both".to_string());
+        map.insert("multicast_ttl".to_string(), "16".to_string());
+
+        let config = ConfigDto::from_map(&map);
+        assert_eq!(config.discovery_mode, DiscoveryMode::Both);
+        assert_eq!(config.multicast_ttl, 16);
+        assert_eq!(config.to_map().get("discovery_mode"), Some(&"both".to_string()));
+
+        map.insert("excluded_interfaces".to_string(), "docker*, eth1,".to_string());
+        let config = ConfigDto::from_map(&map);
+        assert_eq!(config.excluded_interfaces, vec!["docker*", "eth1"]);
+        assert!(config.skip_virtual_interfaces);
+        assert_eq!(config.to_map().get("excluded_interfaces"), Some(&"docker*,eth1".to_string()));
+
+        // Frontends that predate multicast discovery don't send the new fields
+        let mut json = serde_json::to_value(ConfigDto::default()).unwrap();
+        json.as_object_mut().unwrap().remove("discoveryMode");
+        let config: ConfigDto = serde_json::from_value(json).unwrap();
+        assert_eq!(config.discovery_mode, DiscoveryMode::Broadcast);
+    }
+
+    #[test]
+    fn test_configDto_extra_broadcast_hosts() {
+        let mut map = HashMap::new();
+        map.insert("extra_broadcast_hosts".to_string(), "10.8.0.7, 10.8.1.0/24".to_string());
+
+        let mut config = ConfigDto::from_map(&map);
+        assert_eq!(config.extra_broadcast_hosts, vec!["10.8.0.7", "10.8.1.0/24"]);
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.to_map().get("extra_broadcast_hosts"),
+            Some(&"10.8.0.7,10.8.1.0/24".to_string())
+        );
+
+        config.extra_broadcast_hosts.push("10.0.0.0/8".to_string());
+        assert!(config.validate().is_err());
+
+        assert_eq!(normalize_host_entry(" 10.8.1.77/24").unwrap(), "10.8.1.0/24");
+        assert!(normalize_host_entry("10.8.1").is_err());
     }
 
     #[test]
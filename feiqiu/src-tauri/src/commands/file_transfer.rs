@@ -1,14 +1,53 @@
 // File transfer commands - handle file transfer requests from frontend
+use crate::modules::file_transfer::types::TransferTask;
 use crate::state::AppState;
 use crate::{NeoLanError, Result};
+use std::net::IpAddr;
+use std::path::PathBuf;
 use tauri::State;
 use uuid::Uuid;
 
+/// Default number of tasks per history page
+const DEFAULT_HISTORY_PAGE_SIZE: u64 = 50;
+
+/// Largest number of tasks per history page
+const MAX_HISTORY_PAGE_SIZE: u64 = 500;
+
+/// Send files to a peer
+///
+/// # Arguments
+/// * `peer_ip` - IP address of the target peer
+/// * `paths` - Files to send (one transfer task per file)
+/// * `state` - Application state
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - Task IDs, in the order of `paths`
+/// * `Err(String)` - Send failed
+#[tauri::command]
+pub fn send_files(
+    peer_ip: String,
+    paths: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>> {
+    tracing::info!("Sending {} file(s) to {}", paths.len(), peer_ip);
+
+    let ip: IpAddr = peer_ip.parse().map_err(|_| {
+        NeoLanError::Validation(format!("Invalid IP address: {}", peer_ip))
+    })?;
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+
+    let task_ids = state.send_files(ip, &paths)?;
+    Ok(task_ids.iter().map(Uuid::to_string).collect())
+}
+
 /// Accept a file transfer request
 ///
+/// The file is downloaded in the background into the configured save
+/// directory; follow it with `get_file_transfers`.
+///
 /// # Arguments
 /// * `request_id` - UUID of the pending request (as string)
-/// * `tcp_port` - TCP port to use for data transfer
+/// * `tcp_port` - TCP port to receive on (None or 0: any free port in range)
 /// * `state` - Application state
 ///
 /// # Returns
@@ -17,8 +56,8 @@ use uuid::Uuid;
 #[tauri::command]
 pub fn accept_file_transfer(
     request_id: String,
-    _tcp_port: u16,
-    _state: State<'_, AppState>,
+    tcp_port: Option<u16>,
+    state: State<'_, AppState>,
 ) -> Result<String> {
     tracing::info!("Accepting file transfer request: {}", request_id);
 
@@ -27,22 +66,8 @@ pub fn accept_file_transfer(
         NeoLanError::Validation(format!("Invalid request ID: {}", request_id))
     })?;
 
-    // TODO: Get pending request from a pending requests storage
-    // For now, we need to pass the full request info or store it somewhere
-    // This is a simplified implementation that needs to be enhanced
-
-    // In a complete implementation, we would:
-    // 1. Look up the pending request by ID
-    // 2. Get the FileTransferResponse handler
-    // 3. Call send_response() with accept=true and the tcp_port
-    // 4. Create download task
-    // 5. Return task ID
-
-    // For now, return a placeholder response
-    tracing::warn!("accept_file_transfer not fully implemented - needs pending request storage");
-
-    // Placeholder: would return the actual download task ID
-    Ok(uuid.to_string())
+    let task_id = state.accept_file_transfer(uuid, tcp_port.filter(|port| *port != 0))?;
+    Ok(task_id.to_string())
 }
 
 /// Reject a file transfer request
@@ -57,24 +82,16 @@ pub fn accept_file_transfer(
 #[tauri::command]
 pub fn reject_file_transfer(
     request_id: String,
-    _state: State<'_, AppState>,
+    state: State<'_, AppState>,
 ) -> Result<()> {
     tracing::info!("Rejecting file transfer request: {}", request_id);
 
     // Parse request ID
-    let _uuid = Uuid::parse_str(&request_id).map_err(|_| {
+    let uuid = Uuid::parse_str(&request_id).map_err(|_| {
         NeoLanError::Validation(format!("Invalid request ID: {}", request_id))
     })?;
 
-    // TODO: Similar to accept_file_transfer, we need to:
-    // 1. Look up the pending request
-    // 2. Get the FileTransferResponse handler
-    // 3. Call send_response() with accept=false
-    // 4. Remove pending request from storage
-
-    tracing::warn!("reject_file_transfer not fully implemented - needs pending request storage");
-
-    Ok(())
+    state.reject_file_transfer(uuid)
 }
 
 /// Get all file transfer tasks
@@ -85,11 +102,45 @@ pub fn reject_file_transfer(
 /// # Returns
 /// * `Vec<TaskDto>` - List of all transfer tasks
 #[tauri::command]
-pub fn get_file_transfers(_state: State<'_, AppState>) -> Vec<TaskDto> {
-    // TODO: Return all transfer tasks from FileTransferManager
-    // For now, return empty list
-    tracing::warn!("get_file_transfers not fully implemented");
-    Vec::new()
+pub fn get_file_transfers(state: State<'_, AppState>) -> Vec<TaskDto> {
+    state.get_file_transfers().iter().map(TaskDto::from).collect()
+}
+
+/// Get one page of the transfer history (newest first)
+///
+/// Includes the transfers of earlier runs; finished and unfinished tasks
+/// alike, as last stored.
+///
+/// # Arguments
+/// * `page` - Page number, from 0 (default 0)
+/// * `page_size` - Tasks per page (default 50, at most 500)
+/// * `state` - Application state
+///
+/// # Returns
+/// * `Ok(TransferHistoryDto)` - Tasks of the page and the total number of tasks
+/// * `Err(String)` - Invalid page size, or the query failed
+#[tauri::command]
+pub async fn get_transfer_history(
+    page: Option<u64>,
+    page_size: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<TransferHistoryDto> {
+    let page = page.unwrap_or(0);
+    let page_size = page_size.unwrap_or(DEFAULT_HISTORY_PAGE_SIZE);
+    if page_size == 0 || page_size > MAX_HISTORY_PAGE_SIZE {
+        return Err(NeoLanError::Validation(format!(
+            "Page size must be between 1 and {}",
+            MAX_HISTORY_PAGE_SIZE
+        )));
+    }
+
+    let (tasks, total) = state.get_transfer_history(page, page_size).await?;
+    Ok(TransferHistoryDto {
+        items: tasks.iter().map(TaskDto::from).collect(),
+        total,
+        page,
+        page_size,
+    })
 }
 
 /// Cancel a file transfer task
@@ -102,18 +153,67 @@ pub fn get_file_transfers(_state: State<'_, AppState>) -> Vec<TaskDto> {
 /// * `Ok(())` - Task cancelled successfully
 /// * `Err(String)` - Cancel failed
 #[tauri::command]
-pub fn cancel_file_transfer(task_id: String, _state: State<'_, AppState>) -> Result<()> {
+pub fn cancel_file_transfer(task_id: String, state: State<'_, AppState>) -> Result<()> {
     tracing::info!("Cancelling file transfer task: {}", task_id);
 
-    let _uuid = Uuid::parse_str(&task_id).map_err(|_| {
+    let uuid = Uuid::parse_str(&task_id).map_err(|_| {
+        NeoLanError::Validation(format!("Invalid task ID: {}", task_id))
+    })?;
+
+    state.cancel_file_transfer(uuid)
+}
+
+/// Pause an active file transfer
+///
+/// The transfer stops and its connection is closed; resuming it continues
+/// from where it stopped.
+///
+/// # Arguments
+/// * `task_id` - Task ID to pause
+/// * `state` - Application state
+///
+/// # Returns
+/// * `Ok(())` - Task paused
+/// * `Err(String)` - Unknown task, or not active
+#[tauri::command]
+pub fn pause_file_transfer(task_id: String, state: State<'_, AppState>) -> Result<()> {
+    tracing::info!("Pausing file transfer task: {}", task_id);
+
+    let uuid = Uuid::parse_str(&task_id).map_err(|_| {
         NeoLanError::Validation(format!("Invalid task ID: {}", task_id))
     })?;
 
-    // TODO: Call FileTransferManager::cancel_task()
+    state.pause_file_transfer(uuid)
+}
 
-    tracing::warn!("cancel_file_transfer not fully implemented");
+/// Resume a paused file transfer or an interrupted download
+///
+/// The sender of a download is asked for the rest of the partial file; it
+/// sends the whole file again if its copy doesn't match what we have. A
+/// paused upload continues when its receiver asks for the rest (at once if
+/// it already did); interrupted uploads are resumed by their receiver.
+///
+/// # Arguments
+/// * `task_id` - ID of the paused transfer or paused or failed download task
+/// * `tcp_port` - Optional port to receive on (default: any free port in range)
+/// * `state` - Application state
+///
+/// # Returns
+/// * `Ok(())` - Transfer continuing or download resuming
+/// * `Err(String)` - Unknown task, not resumable, or the request couldn't be sent
+#[tauri::command]
+pub fn resume_file_transfer(
+    task_id: String,
+    tcp_port: Option<u16>,
+    state: State<'_, AppState>,
+) -> Result<()> {
+    tracing::info!("Resuming file transfer task: {}", task_id);
+
+    let uuid = Uuid::parse_str(&task_id).map_err(|_| {
+        NeoLanError::Validation(format!("Invalid task ID: {}", task_id))
+    })?;
 
-    Ok(())
+    state.resume_file_transfer(uuid, tcp_port.filter(|port| *port != 0))
 }
 
 /// Data transfer object for transfer tasks
@@ -147,6 +247,39 @@ pub struct TaskDto {
     pub updated_at: i64,
 }
 
+/// One page of the transfer history
+#[derive(Clone, serde::Serialize)]
+pub struct TransferHistoryDto {
+    #[serde(rename = "items")]
+    pub items: Vec<TaskDto>,
+    #[serde(rename = "total")]
+    pub total: u64,
+    #[serde(rename = "page")]
+    pub page: u64,
+    #[serde(rename = "pageSize")]
+    pub page_size: u64,
+}
+
+impl From<&TransferTask> for TaskDto {
+    fn from(task: &TransferTask) -> Self {
+        Self {
+            id: task.id.to_string(),
+            direction: task.direction.as_str().to_string(),
+            peer_ip: task.peer_ip.to_string(),
+            file_name: task.file_name.clone(),
+            file_size: task.file_size,
+            md5: task.md5.clone(),
+            status: task.status.as_str().to_string(),
+            transferred_bytes: task.transferred_bytes,
+            progress: task.progress(),
+            port: task.port,
+            error: task.error.clone(),
+            created_at: task.created_at.timestamp(),
+            updated_at: task.updated_at.timestamp(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,4 +307,23 @@ mod tests {
         assert!(json.contains("\"direction\":\"upload\""));
         assert!(json.contains("\"progress\":0.5"));
     }
+
+    #[test]
+    fn test_taskdto_from_task() {
+        let mut task = TransferTask::new_download(
+            "192.168.1.100".parse().unwrap(),
+            "test.txt".to_string(),
+            1000,
+            String::new(),
+        );
+        task.mark_active(8001);
+        task.update_progress(250);
+
+        let dto = TaskDto::from(&task);
+        assert_eq!(dto.id, task.id.to_string());
+        assert_eq!(dto.direction, "download");
+        assert_eq!(dto.status, "active");
+        assert_eq!(dto.progress, 0.25);
+        assert_eq!(dto.port, Some(8001));
+    }
 }
@@ -4,6 +4,7 @@
 // All commands are exposed to the frontend via Tauri's invoke system.
 
 use crate::modules::peer::PeerNode;
+use crate::network::NetworkStatsSnapshot;
 use crate::state::AppState;
 use crate::Result;
 use serde::{Deserialize, Serialize};
@@ -46,6 +47,9 @@ pub struct PeerDto {
 
     /// Last seen timestamp (Unix milliseconds)
     pub last_seen: i64,
+
+    /// MAC address (FeiQ clients only, stable across IP changes)
+    pub mac: Option<String>,
 }
 
 impl PeerDto {
@@ -74,6 +78,7 @@ impl PeerDto {
             status,
             display_name,
             last_seen,
+            mac: None,
         }
     }
 
@@ -94,6 +99,7 @@ impl PeerDto {
             status: "offline".to_string(),
             display_name,
             last_seen,
+            mac: None,
         }
     }
 
@@ -110,6 +116,7 @@ impl PeerDto {
             status: node.status.as_str().to_string(),
             display_name: node.display_name(),
             last_seen: system_time_to_millis(node.last_seen),
+            mac: node.mac_address(),
         }
     }
 }
@@ -202,6 +209,28 @@ pub fn get_peer_stats(state: tauri::State<AppState>) -> Result<PeerStats> {
     })
 }
 
+/// Get network statistics
+///
+/// This command returns a snapshot of the network counters since startup:
+/// UDP packets and bytes per IPMsg mode, parse failures per peer, receipt
+/// round-trip times, active TCP transfers with their throughput, and the
+/// receive path's throttled and quarantined packets. The same snapshot is
+/// pushed periodically as the `network-stats` event.
+///
+/// # Frontend Usage
+/// ```typescript
+/// import { invoke } from "@tauri-apps/api/core";
+/// const stats = await invoke<NetworkStatsSnapshot>("get_network_stats");
+/// console.log(`In: ${stats.udp.packetsIn}, out: ${stats.udp.packetsOut}, RTT: ${stats.ackRtt.avgMs} ms`);
+/// console.log(`Throttled: ${stats.flood.throttled}, active transfers: ${stats.tcp.active.length}`);
+/// ```
+#[tauri::command]
+pub fn get_network_stats(state: tauri::State<AppState>) -> Result<NetworkStatsSnapshot> {
+    tracing::info!("get_network_stats called");
+
+    Ok(state.get_network_stats())
+}
+
 /// Peer statistics
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
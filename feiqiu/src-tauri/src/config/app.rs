@@ -3,6 +3,7 @@ use crate::error::{NeoLanError, Result};
 use crate::storage::entities::settings;
 use sea_orm::*;
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
 
 /// 网络配置默认值常量
 impl AppConfig {
@@ -21,6 +22,15 @@ impl AppConfig {
     /// 广播地址（用于 LAN 发现）
     pub const BROADCAST_ADDR: &'static str = "255.255.255.255";
 
+    /// IPv6 链路本地组播地址（用于纯 IPv6 网段的 LAN 发现，ff02::2425）
+    pub const IPV6_MULTICAST_ADDR: std::net::Ipv6Addr = std::net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0x2425);
+
+    /// 默认组播发现地址（组织本地范围 239.192.0.0/14，可跨路由转发）
+    pub const DEFAULT_MULTICAST_GROUP: &'static str = "239.192.24.25";
+
+    /// 默认组播 TTL（允许跨越的路由器跳数）
+    pub const DEFAULT_MULTICAST_TTL: u32 = 8;
+
     /// UDP 接收缓冲区大小（64KB，最大 UDP 包大小）
     pub const UDP_BUFFER_SIZE: usize = 65535;
 
@@ -33,6 +43,18 @@ impl AppConfig {
     /// 默认节点超时时间（秒）
     pub const DEFAULT_PEER_TIMEOUT: u64 = 180;
 
+    /// 默认单个来源每秒允许的数据包数（令牌桶补充速率）
+    pub const DEFAULT_RATE_LIMIT_PACKETS_PER_SECOND: u32 = 100;
+
+    /// 默认单个来源的突发包数（令牌桶容量，可容纳一条完整的分片长消息）
+    pub const DEFAULT_RATE_LIMIT_BURST: u32 = 300;
+
+    /// 默认单个来源每分钟允许的解析失败包数
+    pub const DEFAULT_MAX_PARSE_ERRORS_PER_MINUTE: u32 = 30;
+
+    /// 默认隔离时长（秒）
+    pub const DEFAULT_QUARANTINE_SECONDS: u64 = 300;
+
     /// 默认离线消息保留天数
     pub const DEFAULT_OFFLINE_MESSAGE_RETENTION_DAYS: u32 = 30;
 
@@ -40,6 +62,35 @@ impl AppConfig {
     pub const DEFAULT_LOG_LEVEL: &'static str = "info";
 }
 
+/// 节点发现方式
+///
+/// 广播只能到达本网段；跨子网（启用了组播路由的楼层/VLAN）需要组播
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMode {
+    /// 仅广播（与飞秋/IPMsg 兼容，默认）
+    #[default]
+    Broadcast,
+
+    /// 仅组播
+    Multicast,
+
+    /// 广播 + 组播
+    Both,
+}
+
+impl DiscoveryMode {
+    /// 是否发送广播
+    pub fn uses_broadcast(&self) -> bool {
+        matches!(self, Self::Broadcast | Self::Both)
+    }
+
+    /// 是否使用组播
+    pub fn uses_multicast(&self) -> bool {
+        matches!(self, Self::Multicast | Self::Both)
+    }
+}
+
 /// 应用程序配置
 ///
 /// 存储应用程序的核心配置参数，包括网络设置、用户信息等
@@ -61,6 +112,47 @@ pub struct AppConfig {
     pub tcp_port_start: u16,
     pub tcp_port_end: u16,
 
+    /// 节点发现方式（广播/组播/两者）
+    #[serde(default)]
+    pub discovery_mode: DiscoveryMode,
+
+    /// 组播发现地址（端口使用 udp_port）
+    #[serde(default = "default_multicast_group")]
+    pub multicast_group: String,
+
+    /// 组播 TTL（跨越的路由器跳数，1 表示仅本网段）
+    #[serde(default = "default_multicast_ttl")]
+    pub multicast_ttl: u32,
+
+    /// 广播时跳过虚拟网卡（Docker、虚拟机、VPN 等）
+    #[serde(default = "default_skip_virtual_interfaces")]
+    pub skip_virtual_interfaces: bool,
+
+    /// 广播时排除的网卡名称（末尾 `*` 表示前缀匹配，如 `docker*`）
+    #[serde(default)]
+    pub excluded_interfaces: Vec<String>,
+
+    /// 额外广播地址：其他子网/VPN 的主机或 CIDR 网段（如 `10.8.0.7`、`10.8.1.0/24`），
+    /// 启动时和每次心跳时单播上线通知
+    #[serde(default)]
+    pub extra_broadcast_hosts: Vec<String>,
+
+    /// 单个来源 IP 每秒允许的数据包数，超出的包直接丢弃（0 表示不限速）
+    #[serde(default = "default_rate_limit_packets_per_second")]
+    pub rate_limit_packets_per_second: u32,
+
+    /// 单个来源 IP 可一次性发送的数据包数（令牌桶容量）
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+
+    /// 单个来源 IP 每分钟允许的解析失败包数，超过则隔离（0 表示不限制）
+    #[serde(default = "default_max_parse_errors_per_minute")]
+    pub max_parse_errors_per_minute: u32,
+
+    /// 隔离时长（秒）：持续超速或发送畸形包的来源在此期间的包全部丢弃（0 表示不隔离）
+    #[serde(default = "default_quarantine_seconds")]
+    pub quarantine_seconds: u64,
+
     /// 心跳间隔（秒）
     pub heartbeat_interval: u64,
 
@@ -102,6 +194,22 @@ impl AppConfig {
         Self::BROADCAST_ADDR
     }
 
+    /// 获取组播发现的目标地址（组播地址 + UDP 端口）
+    pub fn multicast_addr(&self) -> Result<SocketAddr> {
+        let group: IpAddr = self.multicast_group.parse().map_err(|_| {
+            NeoLanError::Validation(format!("Invalid multicast group: {}", self.multicast_group))
+        })?;
+
+        if !group.is_multicast() {
+            return Err(NeoLanError::Validation(format!(
+                "Not a multicast address: {}",
+                group
+            )));
+        }
+
+        Ok(SocketAddr::new(group, self.udp_port))
+    }
+
     /// 验证配置的有效性
     pub fn validate(&self) -> Result<()> {
         // 验证 UDP 端口范围
@@ -134,6 +242,24 @@ impl AppConfig {
             return Err(NeoLanError::Validation("Bind IP cannot be empty".to_string()));
         }
 
+        // 验证额外广播地址
+        crate::network::expand_hosts(&self.extra_broadcast_hosts)?;
+
+        // 验证限速设置
+        if self.rate_limit_packets_per_second > 0 && self.rate_limit_burst == 0 {
+            return Err(NeoLanError::Validation(
+                "Rate limit burst must be at least 1".to_string()
+            ));
+        }
+
+        // 验证组播设置
+        self.multicast_addr()?;
+        if self.multicast_ttl == 0 || self.multicast_ttl > 255 {
+            return Err(NeoLanError::Validation(
+                "Multicast TTL must be between 1 and 255".to_string()
+            ));
+        }
+
         Ok(())
     }
 }
@@ -147,6 +273,16 @@ impl Default for AppConfig {
             udp_port: Self::DEFAULT_UDP_PORT,
             tcp_port_start: Self::DEFAULT_TCP_PORT_START,
             tcp_port_end: Self::DEFAULT_TCP_PORT_END,
+            discovery_mode: DiscoveryMode::default(),
+            multicast_group: default_multicast_group(),
+            multicast_ttl: default_multicast_ttl(),
+            skip_virtual_interfaces: default_skip_virtual_interfaces(),
+            excluded_interfaces: Vec::new(),
+            extra_broadcast_hosts: Vec::new(),
+            rate_limit_packets_per_second: default_rate_limit_packets_per_second(),
+            rate_limit_burst: default_rate_limit_burst(),
+            max_parse_errors_per_minute: default_max_parse_errors_per_minute(),
+            quarantine_seconds: default_quarantine_seconds(),
             heartbeat_interval: Self::DEFAULT_HEARTBEAT_INTERVAL,
             peer_timeout: Self::DEFAULT_PEER_TIMEOUT,
             encryption_enabled: false,
@@ -162,17 +298,48 @@ impl Default for AppConfig {
     }
 }
 
+/// 旧版本保存的配置没有组播字段时使用的默认值
+fn default_multicast_group() -> String {
+    AppConfig::DEFAULT_MULTICAST_GROUP.to_string()
+}
+
+fn default_multicast_ttl() -> u32 {
+    AppConfig::DEFAULT_MULTICAST_TTL
+}
+
+fn default_skip_virtual_interfaces() -> bool {
+    true
+}
+
+fn default_rate_limit_packets_per_second() -> u32 {
+    AppConfig::DEFAULT_RATE_LIMIT_PACKETS_PER_SECOND
+}
+
+fn default_rate_limit_burst() -> u32 {
+    AppConfig::DEFAULT_RATE_LIMIT_BURST
+}
+
+fn default_max_parse_errors_per_minute() -> u32 {
+    AppConfig::DEFAULT_MAX_PARSE_ERRORS_PER_MINUTE
+}
+
+fn default_quarantine_seconds() -> u64 {
+    AppConfig::DEFAULT_QUARANTINE_SECONDS
+}
+
 /// 配置存储键名常量
 #[allow(dead_code)]
 mod keys {
     pub const CONFIG: &str = "app_config";
+    pub const EXTRA_BROADCAST_HOSTS: &str = "extra_broadcast_hosts";
 }
 
 /// 配置仓库
 ///
 /// 负责从 settings 表加载和保存配置
 ///
-/// NOTE: Currently not integrated but intended for future database-backed configuration
+/// NOTE: Only the extra broadcast host list is loaded at startup so far; the rest of
+/// the configuration is intended for future database-backed storage
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct ConfigRepository {
@@ -249,6 +416,26 @@ impl ConfigRepository {
         Ok(())
     }
 
+    /// 加载额外广播地址列表
+    ///
+    /// 列表单独保存（不随 app_config 整体保存），未保存过时返回空列表
+    pub async fn load_extra_broadcast_hosts(&self) -> Result<Vec<String>> {
+        match self.get_value(keys::EXTRA_BROADCAST_HOSTS).await? {
+            Some(value) => serde_json::from_str(&value).map_err(|e| {
+                NeoLanError::Config(format!("Failed to parse extra broadcast hosts: {}", e))
+            }),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// 保存额外广播地址列表
+    pub async fn save_extra_broadcast_hosts(&self, hosts: &[String]) -> Result<()> {
+        let value = serde_json::to_string(hosts).map_err(|e| {
+            NeoLanError::Config(format!("Failed to serialize extra broadcast hosts: {}", e))
+        })?;
+        self.set_value(keys::EXTRA_BROADCAST_HOSTS, &value).await
+    }
+
     /// 重置为默认配置
     pub async fn reset_to_default(&self) -> Result<()> {
         self.save_app_config(&AppConfig::default()).await
@@ -406,9 +593,64 @@ mod tests {
         assert!(invalid_config.validate().is_err());
 
         // 测试空的绑定 IP
-        let mut invalid_config = config;
+        let mut invalid_config = config.clone();
         invalid_config.bind_ip = String::new();
         assert!(invalid_config.validate().is_err());
+
+        // 测试非组播地址
+        let mut invalid_config = config.clone();
+        invalid_config.multicast_group = "192.168.1.1".to_string();
+        assert!(invalid_config.validate().is_err());
+
+        // 测试无效的额外广播地址
+        let mut invalid_config = config.clone();
+        invalid_config.extra_broadcast_hosts = vec!["10.8.0.0/8".to_string()];
+        assert!(invalid_config.validate().is_err());
+
+        // 测试限速开启但突发包数为 0
+        let mut invalid_config = config.clone();
+        invalid_config.rate_limit_burst = 0;
+        assert!(invalid_config.validate().is_err());
+        invalid_config.rate_limit_packets_per_second = 0;
+        assert!(invalid_config.validate().is_ok());
+
+        // 测试无效的组播 TTL
+        let mut invalid_config = config;
+        invalid_config.multicast_ttl = 0;
+        assert!(invalid_config.validate().is_err());
+    }
+
+    #[test]
+    fn test_discovery_mode() {
+        let config = AppConfig::default();
+        assert_eq!(config.discovery_mode, DiscoveryMode::Broadcast);
+        assert!(config.discovery_mode.uses_broadcast());
+        assert!(!config.discovery_mode.uses_multicast());
+        assert!(DiscoveryMode::Both.uses_broadcast() && DiscoveryMode::Both.uses_multicast());
+
+        assert_eq!(
+            config.multicast_addr().unwrap(),
+            "239.192.24.25:2425".parse::<SocketAddr>().unwrap()
+        );
+
+        // 旧版本保存的配置没有组播字段，应使用默认值
+        let mut json: serde_json::Value = serde_json::to_value(&config).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("discovery_mode");
+        fields.remove("multicast_group");
+        fields.remove("multicast_ttl");
+        fields.remove("skip_virtual_interfaces");
+        fields.remove("excluded_interfaces");
+        fields.remove("extra_broadcast_hosts");
+        fields.remove("rate_limit_packets_per_second");
+        fields.remove("rate_limit_burst");
+        fields.remove("max_parse_errors_per_minute");
+        fields.remove("quarantine_seconds");
+        let old: AppConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(old, config);
+
+        let json = serde_json::to_string(&DiscoveryMode::Multicast).unwrap();
+        assert_eq!(json, "\"multicast\"");
     }
 
     #[test]
@@ -9,12 +9,13 @@ pub mod state;
 pub mod utils;
 mod error;
 
-// Import Emitter trait for event emission
-use tauri::Emitter;
+// Import Emitter trait for event emission, Manager for state access
+use tauri::{Emitter, Manager};
 use crate::migration::{Migrator, MigratorTrait};
-use crate::network::UdpTransport;
+use crate::network::{PacketCapture, UdpTransport};
 use crate::modules::peer::{PeerManager, discovery::PeerDiscovery};
 use crate::modules::message::handler::MessageHandler;
+use crate::modules::file_transfer::{FileServer, FileTransferManager, FileTransferResponse, TransferStore};
 use crate::modules::peer::manager::MessageRouteRequest;
 use std::thread;
 use std::time::Duration;
@@ -25,14 +26,22 @@ pub use state::AppState;
 pub use state::app_state::{TauriEvent, PeerDiscoveredDto};
 
 // Import Tauri commands from submodules
-use commands::peer::{get_peers, get_online_peers, get_peer_by_ip, get_peer_stats};
+use commands::peer::{get_peers, get_online_peers, get_peer_by_ip, get_peer_stats, get_network_stats};
 use commands::config::{get_config, set_config, reset_config, get_config_value, set_config_value};
+use commands::config::{
+    get_extra_broadcast_hosts, set_extra_broadcast_hosts, add_extra_broadcast_host,
+    remove_extra_broadcast_host,
+};
 use commands::events::poll_events;
 use commands::message::{send_message, send_text_message, get_messages};
-use commands::file_transfer::{accept_file_transfer, reject_file_transfer, get_file_transfers, cancel_file_transfer};
+use commands::file_transfer::{
+    send_files, accept_file_transfer, reject_file_transfer, get_file_transfers, get_transfer_history, cancel_file_transfer,
+    pause_file_transfer, resume_file_transfer,
+};
 use std::sync::mpsc;
 
-
+/// How often the network counters are pushed to the frontend
+const NETWORK_STATS_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tauri::command]
 async fn get_system_info() -> serde_json::Value {
@@ -111,6 +120,11 @@ pub fn run() {
                                 tracing::error!("Failed to emit file-transfer-request event: {}", e);
                             }
                         }
+                        TauriEvent::FileTransferUpdated { .. } => {
+                            if let Err(e) = app_handle.emit("file-transfer-updated", &event) {
+                                tracing::error!("Failed to emit file-transfer-updated event: {}", e);
+                            }
+                        }
                         TauriEvent::PeersDiscovered { .. } => {
                             if let Err(e) = app_handle.emit("peers-discovered", &event) {
                                 tracing::error!("Failed to emit peers-discovered event: {}", e);
@@ -125,6 +139,22 @@ pub fn run() {
                                 tracing::debug!("✅ message-receipt-ack event emitted successfully to frontend");
                             }
                         }
+                        TauriEvent::MessageDeliveryFailed { msg_id, receiver_ip, attempts, .. } => {
+                            tracing::warn!("Message {} to {} not acknowledged after {} attempts", msg_id, receiver_ip, attempts);
+                            if let Err(e) = app_handle.emit("message-delivery-failed", &event) {
+                                tracing::error!("Failed to emit message-delivery-failed event: {}", e);
+                            }
+                        }
+                        TauriEvent::MessageTruncated { .. } => {
+                            if let Err(e) = app_handle.emit("message-truncated", &event) {
+                                tracing::error!("Failed to emit message-truncated event: {}", e);
+                            }
+                        }
+                        TauriEvent::NetworkStats { .. } => {
+                            if let Err(e) = app_handle.emit("network-stats", &event) {
+                                tracing::error!("Failed to emit network-stats event: {}", e);
+                            }
+                        }
                     }
                 }
                 tracing::info!("Event listener task ended");
@@ -155,6 +185,9 @@ pub fn run() {
             }
             tracing::info!("Database migrations completed");
 
+            // Extra broadcast hosts are persisted on their own, ahead of discovery setup
+            tauri::async_runtime::block_on(app_state_for_setup.load_extra_broadcast_hosts());
+
             // Initialize PeerManager
             tracing::info!("Initializing PeerManager...");
 
@@ -162,42 +195,86 @@ pub fn run() {
             let config = app_state_for_setup.get_config();
             let udp_port = config.udp_port;
 
-            // Bind UDP transport for receiving (PeerManager) with retry
-            let udp_recv = match UdpTransport::bind_with_retry(udp_port, 10) {
-                Ok(u) => u,
+            // One UDP transport on the protocol port, shared by discovery, messaging
+            // and file transfer: peers reply to (and know us by) its source port.
+            // The file data listener takes the same port number, where IPMsg peers look for it.
+            let (udp, file_listener) = match UdpTransport::bind_with_tcp_listener(udp_port, 10) {
+                Ok(bound) => bound,
                 Err(e) => {
-                    tracing::error!("Failed to bind UDP transport after retries: {}", e);
+                    tracing::error!("Failed to bind UDP transport and TCP listener after retries: {}", e);
                     return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
                 }
             };
+            let udp = match PacketCapture::from_env() {
+                // Record every UDP datagram when NEOLAN_CAPTURE names a file
+                Some(capture) => udp.with_capture(capture),
+                None => udp,
+            };
+            let udp_sender = udp.sender();
 
-            // Bind UDP transport for sending (MessageHandler) - any available port
-            let udp_send = match UdpTransport::bind(0) {
-                Ok(u) => {
-                    tracing::info!("UDP send transport bound to port {}", u.port());
-                    u
-                }
-                Err(e) => {
-                    tracing::error!("Failed to bind UDP send transport: {}", e);
-                    return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
+            let app_state_arc = std::sync::Arc::new(app_state_for_setup.clone());
+
+            // Initialize file transfer (standard IPMsg attachments + NeoLan JSON requests),
+            // recording every task in the database
+            let transfer_store = app_state_for_setup
+                .get_transfer_repo()
+                .map(|repo| std::sync::Arc::new(TransferStore::new(repo)));
+            let mut file_transfer_manager =
+                FileTransferManager::new(udp_sender.clone(), config.username.clone(), config.hostname.clone())
+                    .with_app_state(app_state_arc.clone());
+            if let Some(ref store) = transfer_store {
+                file_transfer_manager = file_transfer_manager.with_store(store.clone());
+            }
+            let file_transfer_manager = std::sync::Arc::new(file_transfer_manager);
+
+            // Take back the transfers the last run was closed during
+            if let Some(store) = transfer_store {
+                match tauri::async_runtime::block_on(store.load_unfinished()) {
+                    Ok(tasks) => {
+                        file_transfer_manager.restore_tasks(tasks);
+                    }
+                    Err(e) => tracing::warn!("Failed to load unfinished transfers: {:?}", e),
                 }
-            };
+                app_state_for_setup.start_transfer_store(store);
+            }
+            let file_transfer_response = std::sync::Arc::new(FileTransferResponse::new(
+                file_transfer_manager.clone(),
+                config.username.clone(),
+                config.hostname.clone(),
+            ));
+
+            // Serve IPMsg GETFILEDATA requests on TCP (same port number as UDP)
+            let file_server = std::sync::Arc::new(FileServer::new(file_transfer_manager.clone()));
+            let serving = file_server.clone();
+            let handle = thread::spawn(move || {
+                tracing::info!("File data server thread started");
+                serving.serve(file_listener);
+                tracing::info!("File data server thread ended");
+            });
+            // Stopped and joined at shutdown
+            app_state_for_setup.register_file_server(file_server, handle);
 
             // Create channel for routing messages from PeerManager to MessageHandler
             let (message_route_tx, message_route_rx) = mpsc::channel::<MessageRouteRequest>();
 
             // Initialize MessageHandler
             tracing::info!("Initializing MessageHandler...");
-            let app_state_arc = std::sync::Arc::new(app_state_for_setup.clone());
-            let message_handler = MessageHandler::new(udp_send, config.clone())
-                .with_app_state(app_state_arc);
+            let message_handler = match app_state_for_setup.get_message_repo() {
+                Some(repo) => MessageHandler::with_storage(udp_sender, config.clone(), repo),
+                None => MessageHandler::new(udp_sender, config.clone()),
+            }
+            .with_app_state(app_state_arc)
+            .with_file_transfer(file_transfer_response.clone());
+            app_state_for_setup.init_file_transfer(file_transfer_manager, file_transfer_response);
+            // Retransmit unacknowledged messages and record their delivery
+            app_state_for_setup.register_outbox_thread(message_handler.start_outbox());
             app_state_for_setup.init_message_handler(message_handler);
             tracing::info!("MessageHandler initialized");
 
             // Spawn background task to handle routed messages from PeerManager
             let app_state_for_messages = app_state_for_setup.clone();
             let local_ip = config.bind_ip.parse().unwrap_or_else(|_| std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1)));
-            thread::spawn(move || {
+            let router = thread::spawn(move || {
                 tracing::info!("Message handler task started");
                 for route_request in message_route_rx {
                     let sender_ip = route_request.sender.ip();
@@ -211,21 +288,44 @@ pub fn run() {
                 }
                 tracing::info!("Message handler task ended");
             });
+            app_state_for_setup.register_receive_thread("message router", router);
 
-            // Create PeerDiscovery with system defaults
-            let discovery = PeerDiscovery::with_defaults(udp_recv);
+            // Create PeerDiscovery with system defaults, announcing per the configured discovery mode
+            let discovery = PeerDiscovery::with_defaults(udp).with_config(&config);
             tracing::info!("PeerDiscovery created: {}@{}", discovery.username(), discovery.hostname());
 
             // Create PeerManager
             let peer_manager = PeerManager::new(discovery);
             // Connect message routing channel to PeerManager
             peer_manager.set_message_handler_channel(message_route_tx);
+            let discovery = peer_manager.discovery().clone();
             app_state_for_setup.init_peer_manager(peer_manager);
             tracing::info!("PeerManager initialized");
 
+            // Re-announce to the extra broadcast hosts, which never hear our broadcasts
+            // (until shutdown, which joins the thread)
+            app_state_for_setup.register_heartbeat_thread(modules::peer::heartbeat::spawn_extra_host_heartbeat(
+                discovery,
+                config.heartbeat_interval,
+                app_state_for_setup.clone(),
+            ));
+
+            // Feed a capture through the live pipeline when NEOLAN_REPLAY names one
+            if let Some(path) = std::env::var_os(network::capture::REPLAY_ENV) {
+                if let Some(peer_manager) = app_state_for_setup.get_peer_manager() {
+                    thread::spawn(move || match network::capture::read_capture(&path) {
+                        Ok(records) => {
+                            tracing::info!("Replaying {} captured packets from {:?}", records.len(), path);
+                            peer_manager.replay(&records);
+                        }
+                        Err(e) => tracing::error!("Failed to read capture {:?}: {}", path, e),
+                    });
+                }
+            }
+
             // Spawn peer manager in background thread
             let app_state_for_thread = app_state_for_setup.clone();
-            thread::spawn(move || {
+            let listener = thread::spawn(move || {
                 tracing::info!("PeerManager thread started");
 
                 // Start peer discovery (blocking call)
@@ -235,6 +335,17 @@ pub fn run() {
 
                 tracing::info!("PeerManager thread ended");
             });
+            app_state_for_setup.register_receive_thread("peer listener", listener);
+
+            // Push the network counters to the network status card (until shutdown)
+            let app_state_for_stats = app_state_for_setup.clone();
+            thread::spawn(move || loop {
+                thread::sleep(NETWORK_STATS_INTERVAL);
+                if app_state_for_stats.is_shutting_down() {
+                    break;
+                }
+                app_state_for_stats.emit_network_stats();
+            });
 
             // Wait a bit for peers to be discovered
             let app_state_for_discovery = app_state_for_setup.clone();
@@ -253,20 +364,36 @@ pub fn run() {
             get_online_peers,
             get_peer_by_ip,
             get_peer_stats,
+            get_network_stats,
             get_config,
             set_config,
             reset_config,
             get_config_value,
             set_config_value,
+            get_extra_broadcast_hosts,
+            set_extra_broadcast_hosts,
+            add_extra_broadcast_host,
+            remove_extra_broadcast_host,
             poll_events,
             send_message,
             send_text_message,
             get_messages,
+            send_files,
             accept_file_transfer,
             reject_file_transfer,
             get_file_transfers,
+            get_transfer_history,
             cancel_file_transfer,
+            pause_file_transfer,
+            resume_file_transfer,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Tell peers we're leaving (BR_EXIT) instead of letting them time out,
+                // finish handling what was received and store what is still queued
+                app_handle.state::<AppState>().shutdown();
+            }
+        });
 }
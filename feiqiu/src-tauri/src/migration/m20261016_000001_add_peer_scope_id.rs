@@ -0,0 +1,44 @@
+// src-tauri/src/migration/m20261016_000001_add_peer_scope_id.rs
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+#[allow(dead_code)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 添加 peers 表的 IPv6 作用域 ID 列
+        // 链路本地地址 (fe80::/10) 需要接口索引才能回复；IPv4 节点为 NULL
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Peers::Table)
+                    .add_column(ColumnDef::new(Peers::ScopeId).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Peers::Table)
+                    .drop_column(Peers::ScopeId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(dead_code)]
+enum Peers {
+    Table,
+    ScopeId,
+}
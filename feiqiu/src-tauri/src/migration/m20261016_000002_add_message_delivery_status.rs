@@ -0,0 +1,44 @@
+// src-tauri/src/migration/m20261016_000002_add_message_delivery_status.rs
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+#[allow(dead_code)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 添加 messages 表的送达状态列
+        // 发出的消息为 pending / delivered / failed；收到的消息为 NULL
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .add_column(ColumnDef::new(Messages::DeliveryStatus).text().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Messages::Table)
+                    .drop_column(Messages::DeliveryStatus)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(dead_code)]
+enum Messages {
+    Table,
+    DeliveryStatus,
+}
@@ -0,0 +1,64 @@
+// src-tauri/src/migration/m20261016_000003_add_transfer_details.rs
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+#[allow(dead_code)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // 添加 transfers 表恢复传输所需的列
+        // 本地文件路径、失败原因，以及文件所属消息的包 ID / 附件文件 ID
+        // SQLite 每条 ALTER TABLE 只能添加一列
+        let columns = [
+            ColumnDef::new(Transfers::FilePath).text().not_null().default("").to_owned(),
+            ColumnDef::new(Transfers::ErrorMessage).text().null().to_owned(),
+            ColumnDef::new(Transfers::PacketId).big_integer().null().to_owned(),
+            ColumnDef::new(Transfers::FileId).big_integer().null().to_owned(),
+        ];
+
+        for column in columns {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Transfers::Table)
+                        .add_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Transfers::FilePath,
+            Transfers::ErrorMessage,
+            Transfers::PacketId,
+            Transfers::FileId,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Transfers::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(dead_code)]
+enum Transfers {
+    Table,
+    FilePath,
+    ErrorMessage,
+    PacketId,
+    FileId,
+}
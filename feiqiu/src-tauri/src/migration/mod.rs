@@ -3,6 +3,9 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20260105_000001_create_tables;
 mod m20260110_000001_add_composite_indexes;
+mod m20261016_000001_add_peer_scope_id;
+mod m20261016_000002_add_message_delivery_status;
+mod m20261016_000003_add_transfer_details;
 
 #[allow(dead_code)]
 pub struct Migrator;
@@ -13,6 +16,9 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20260105_000001_create_tables::Migration),
             Box::new(m20260110_000001_add_composite_indexes::Migration),
+            Box::new(m20261016_000001_add_peer_scope_id::Migration),
+            Box::new(m20261016_000002_add_message_delivery_status::Migration),
+            Box::new(m20261016_000003_add_transfer_details::Migration),
         ]
     }
 }
@@ -1,22 +1,29 @@
 // File transfer manager - handles file transfer requests and tasks
 use crate::network::{
-    FileSendRequest, ProtocolMessage, UdpTransport, PROTOCOL_VERSION, msg_type,
+    encode_attachments, next_packet_id, serialize_message, FeiqHeader, FileAttachment, FileSendRequest,
+    ProtocolMessage, TextEncoding, TransferControl, TransferSignal, UdpSender, DEFAULT_UDP_PORT,
+    PROTOCOL_VERSION, msg_type,
 };
+use crate::state::app_state::{AppState, TauriEvent};
 use crate::utils::hash;
 use crate::{NeoLanError, Result};
+use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 use uuid::Uuid;
 
-use super::types::{TransferStatus, TransferTask};
+use super::store::{recover_interrupted, TransferStore};
+use super::types::{TransferDirection, TransferStatus, TransferTask};
 
 /// File transfer manager
 ///
 /// Manages file transfer tasks, including sending requests and tracking transfers.
+/// Every change to a task is reported to the frontend (FileTransferUpdated)
+/// and written to the transfer store.
 pub struct FileTransferManager {
-    /// UDP transport for sending requests
-    udp: Arc<UdpTransport>,
+    /// Send handle of the shared UDP transport
+    udp: UdpSender,
 
     /// Transfer tasks (indexed by task ID)
     tasks: Arc<Mutex<Vec<TransferTask>>>,
@@ -26,13 +33,22 @@ pub struct FileTransferManager {
 
     /// Local hostname
     hostname: String,
+
+    /// Application state (for emitting Tauri events)
+    app_state: Option<Arc<AppState>>,
+
+    /// Persistent record of the tasks
+    store: Option<Arc<TransferStore>>,
+
+    /// Control handles of running transfer loops (by task ID)
+    controls: Mutex<HashMap<Uuid, TransferControl>>,
 }
 
 impl FileTransferManager {
     /// Create a new file transfer manager
     ///
     /// # Arguments
-    /// * `udp` - UDP transport for sending requests
+    /// * `udp` - Send handle of the shared UDP transport
     /// * `username` - Local username
     /// * `hostname` - Local hostname
     ///
@@ -40,7 +56,7 @@ impl FileTransferManager {
     /// * `Ok(FileTransferManager)` - Successfully created manager
     /// * `Err(NeoLanError)` - Creation failed
     pub fn new(
-        udp: Arc<UdpTransport>,
+        udp: UdpSender,
         username: String,
         hostname: String,
     ) -> Self {
@@ -51,10 +67,56 @@ impl FileTransferManager {
             tasks: Arc::new(Mutex::new(Vec::new())),
             username,
             hostname,
+            app_state: None,
+            store: None,
+            controls: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Send a file transfer request to a peer
+    /// Set the application state for emitting task events
+    ///
+    /// # Arguments
+    /// * `app_state` - Application state reference
+    pub fn with_app_state(mut self, app_state: Arc<AppState>) -> Self {
+        self.app_state = Some(app_state);
+        self
+    }
+
+    /// Set the store every task change is written to
+    ///
+    /// # Arguments
+    /// * `store` - Transfer store
+    pub fn with_store(mut self, store: Arc<TransferStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Get the address of a peer's IPMsg port
+    ///
+    /// # Returns
+    /// The port the PeerManager last heard the peer from (with its IPv6
+    /// scope), or the IPMsg default port for unknown peers
+    pub fn peer_addr(&self, ip: IpAddr) -> SocketAddr {
+        self.app_state
+            .as_ref()
+            .and_then(|state| state.get_peer(ip))
+            .map(|peer| peer.socket_addr(peer.port))
+            .unwrap_or_else(|| SocketAddr::new(ip, DEFAULT_UDP_PORT))
+    }
+
+    /// Get a peer's display name from the PeerManager (empty for unknown peers)
+    fn peer_name(&self, ip: IpAddr) -> String {
+        self.app_state
+            .as_ref()
+            .and_then(|state| state.get_peer(ip))
+            .map(|peer| peer.display_name())
+            .unwrap_or_default()
+    }
+
+    /// Send a file transfer request to a NeoLan peer (JSON format)
+    ///
+    /// This uses the NeoLan-only `IPMSG_GETFILEDATA` + JSON `FileSendRequest` form.
+    /// FeiQ/IPMsg clients don't understand it; use [`Self::send_attachments`] for them.
     ///
     /// # Arguments
     /// * `path` - Path to the file to send
@@ -111,10 +173,7 @@ impl FileTransferManager {
         };
 
         // Create protocol message
-        let packet_id = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| NeoLanError::Other(format!("Time error: {}", e)))?
-            .as_secs();
+        let packet_id = next_packet_id();
 
         let proto_msg = ProtocolMessage {
             version: PROTOCOL_VERSION as u8,
@@ -125,6 +184,10 @@ impl FileTransferManager {
             content: serde_json::to_string(&request).map_err(|e| {
                 NeoLanError::FileTransfer(format!("Failed to serialize request: {}", e))
             })?,
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: None,
         };
 
         // Serialize and send via UDP
@@ -133,8 +196,7 @@ impl FileTransferManager {
                 NeoLanError::FileTransfer(format!("Failed to serialize message: {}", e))
             })?;
 
-        let addr = SocketAddr::new(target, 2425); // IPMsg default port
-        self.udp.send_to(&msg_bytes, addr)?;
+        self.udp.send_to(&msg_bytes, self.peer_addr(target))?;
 
         tracing::info!(
             "File transfer request sent: {} ({} bytes, MD5: {}) -> {}",
@@ -144,14 +206,15 @@ impl FileTransferManager {
             target
         );
 
-        // Create and store transfer task
+        // Create and store transfer task (the peer's reply names the offer's packet ID)
         let task = TransferTask::new_upload(
             target,
             path.to_path_buf(),
             file_name,
             file_size,
             md5,
-        );
+        )
+        .with_offer(packet_id);
 
         let task_id = task.id;
         self.add_task(task)?;
@@ -161,6 +224,144 @@ impl FileTransferManager {
         Ok(task_id)
     }
 
+    /// Offer files to a peer using the standard IPMsg attachment format
+    ///
+    /// Sends an `IPMSG_SENDMSG | IPMSG_FILEATTACHOPT` message whose attachment
+    /// section lists `fileID:filename:size:mtime:attr:` entries. This is the
+    /// format FeiQ/IPMsg clients understand; they then fetch each file over TCP
+    /// with `IPMSG_GETFILEDATA` (`packetID:fileID:offset`).
+    ///
+    /// # Arguments
+    /// * `paths` - Files to offer (one attachment entry per file)
+    /// * `target` - Target peer IP address
+    /// * `encoding` - Text encoding the peer expects (file names are text too)
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Uuid>)` - Task IDs, one per file, in the order of `paths`
+    /// * `Err(NeoLanError)` - Request failed
+    pub fn send_attachments(
+        &self,
+        paths: &[PathBuf],
+        target: IpAddr,
+        encoding: TextEncoding,
+    ) -> Result<Vec<Uuid>> {
+        tracing::info!(
+            "Sending file attachments: {} file(s) -> {}",
+            paths.len(),
+            target
+        );
+
+        if paths.is_empty() {
+            return Err(NeoLanError::FileTransfer(
+                "No files to send".to_string(),
+            ));
+        }
+
+        let packet_id = next_packet_id();
+
+        // Build attachment entries and the matching upload tasks
+        let mut attachments = Vec::with_capacity(paths.len());
+        let mut tasks = Vec::with_capacity(paths.len());
+
+        for (index, path) in paths.iter().enumerate() {
+            let metadata = std::fs::metadata(path).map_err(|e| {
+                NeoLanError::FileTransfer(format!(
+                    "File not found: {} ({})",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+            if !metadata.is_file() {
+                return Err(NeoLanError::FileTransfer(format!(
+                    "Not a regular file: {}",
+                    path.display()
+                )));
+            }
+
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| {
+                    NeoLanError::FileTransfer(format!(
+                        "Invalid file path: {}",
+                        path.display()
+                    ))
+                })?
+                .to_string_lossy()
+                .to_string();
+
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+
+            let file_id = index as u64;
+            let file_size = metadata.len();
+
+            attachments.push(FileAttachment::regular(
+                file_id,
+                file_name.clone(),
+                file_size,
+                mtime,
+            ));
+
+            // IPMsg attachments carry no checksum, so the offer goes out
+            // without reading the files
+            tasks.push(
+                TransferTask::new_upload(target, path.clone(), file_name, file_size, String::new())
+                    .with_attachment(packet_id, file_id),
+            );
+        }
+
+        let mut options = msg_type::IPMSG_SENDCHECKOPT | msg_type::IPMSG_FILEATTACHOPT;
+        if encoding == TextEncoding::Utf8 {
+            options |= msg_type::IPMSG_UTF8OPT;
+        }
+
+        let proto_msg = ProtocolMessage {
+            version: PROTOCOL_VERSION,
+            packet_id,
+            sender_name: self.username.clone(),
+            sender_host: self.hostname.clone(),
+            msg_type: msg_type::make_command(msg_type::IPMSG_SENDMSG, options),
+            content: String::new(),
+            extensions: vec![encode_attachments(&attachments)],
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: Some(encoding),
+        };
+
+        let msg_bytes =
+            crate::network::serialize_message(&proto_msg).map_err(|e| {
+                NeoLanError::FileTransfer(format!("Failed to serialize message: {}", e))
+            })?;
+
+        self.udp.send_to(&msg_bytes, self.peer_addr(target))?;
+
+        tracing::info!(
+            "File attachments sent: packet_id={}, files={} -> {}",
+            packet_id,
+            attachments.len(),
+            target
+        );
+
+        // Store upload tasks, waiting for the peer's GETFILEDATA requests
+        let mut task_ids = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            task_ids.push(task.id);
+            self.add_task(task)?;
+        }
+
+        Ok(task_ids)
+    }
+
+    /// Send handle of the shared UDP transport
+    pub fn udp(&self) -> &UdpSender {
+        &self.udp
+    }
+
     /// Get all transfer tasks
     ///
     /// # Returns
@@ -186,6 +387,85 @@ impl FileTransferManager {
             .and_then(|tasks| tasks.iter().find(|t| t.id == id).cloned())
     }
 
+    /// Find the outstanding upload task for an IPMsg attachment
+    ///
+    /// # Arguments
+    /// * `peer_ip` - Peer requesting the data
+    /// * `packet_id` - Packet ID of our FILEATTACHOPT message
+    /// * `file_id` - File ID within that message
+    ///
+    /// # Returns
+    /// * `Option<TransferTask>` - Matching upload task, unless it completed
+    ///   or was cancelled (a failed one is requested again to resume it)
+    pub fn find_attachment_upload(
+        &self,
+        peer_ip: IpAddr,
+        packet_id: u64,
+        file_id: u64,
+    ) -> Option<TransferTask> {
+        self.tasks.lock().ok().and_then(|tasks| {
+            tasks
+                .iter()
+                .find(|t| {
+                    t.direction == TransferDirection::Upload
+                        && t.peer_ip == peer_ip
+                        && t.packet_id == Some(packet_id)
+                        && t.file_id == Some(file_id)
+                        && t.is_resumable()
+                })
+                .cloned()
+        })
+    }
+
+    /// Find the upload task a NeoLan reply answers
+    ///
+    /// # Arguments
+    /// * `peer_ip` - Peer that replied
+    /// * `packet_id` - Packet ID of the offer named in the reply (None from
+    ///   older builds: the oldest offer to the peer still waiting is taken)
+    ///
+    /// # Returns
+    /// * `Option<TransferTask>` - Matching pending (JSON offer) upload task
+    pub fn find_offer_upload(&self, peer_ip: IpAddr, packet_id: Option<u64>) -> Option<TransferTask> {
+        self.tasks.lock().ok().and_then(|tasks| {
+            tasks
+                .iter()
+                .filter(|t| {
+                    t.direction == TransferDirection::Upload
+                        && t.peer_ip == peer_ip
+                        && t.status == TransferStatus::Pending
+                        && !t.is_attachment()
+                        && packet_id.is_none_or(|id| t.packet_id == Some(id))
+                })
+                .min_by_key(|t| t.created_at)
+                .cloned()
+        })
+    }
+
+    /// Find the upload task a NeoLan resume request names
+    ///
+    /// # Arguments
+    /// * `peer_ip` - Peer asking to resume
+    /// * `packet_id` - Packet ID of the original offer
+    ///
+    /// # Returns
+    /// * `Option<TransferTask>` - Matching JSON offer upload, unless it
+    ///   completed or was cancelled
+    pub fn find_resumable_upload(&self, peer_ip: IpAddr, packet_id: u64) -> Option<TransferTask> {
+        self.tasks.lock().ok().and_then(|tasks| {
+            tasks
+                .iter()
+                .find(|t| {
+                    t.direction == TransferDirection::Upload
+                        && t.peer_ip == peer_ip
+                        && t.packet_id == Some(packet_id)
+                        && !t.is_attachment()
+                        && t.is_resumable()
+                })
+                .cloned()
+        })
+    }
+
     /// Get tasks by peer IP
     ///
     /// # Arguments
@@ -240,6 +520,7 @@ impl FileTransferManager {
         })?;
 
         if let Some(existing) = tasks.iter_mut().find(|t| t.id == task.id) {
+            self.notify(&task);
             *existing = task;
             Ok(())
         } else {
@@ -250,6 +531,40 @@ impl FileTransferManager {
         }
     }
 
+    /// Update a task unless it has finished meanwhile (e.g. was cancelled)
+    ///
+    /// Used by transfer workers, whose copy of the task may be stale. While
+    /// the task is paused only the end of the transfer (completed or failed)
+    /// is recorded, not its progress.
+    ///
+    /// # Arguments
+    /// * `task` - Updated task
+    ///
+    /// # Returns
+    /// * `Ok(true)` - Task updated
+    /// * `Ok(false)` - Task already finished, or a progress update of a
+    ///   paused task, left unchanged
+    /// * `Err(NeoLanError)` - Task not found
+    pub fn update_running_task(&self, task: TransferTask) -> Result<bool> {
+        let mut tasks = self.tasks.lock().map_err(|_| {
+            NeoLanError::Other("Failed to lock tasks".to_string())
+        })?;
+
+        match tasks.iter_mut().find(|t| t.id == task.id) {
+            Some(existing) if existing.is_finished() => Ok(false),
+            Some(existing) if existing.status == TransferStatus::Paused && !task.is_finished() => Ok(false),
+            Some(existing) => {
+                self.notify(&task);
+                *existing = task;
+                Ok(true)
+            }
+            None => Err(NeoLanError::FileTransfer(format!(
+                "Task not found: {}",
+                task.id
+            ))),
+        }
+    }
+
     /// Cancel a task
     ///
     /// # Arguments
@@ -265,6 +580,10 @@ impl FileTransferManager {
 
         if let Some(task) = tasks.iter_mut().find(|t| t.id == id) {
             task.mark_cancelled();
+            self.notify(task);
+            if let Some(control) = self.lock_controls().get(&id) {
+                control.cancel();
+            }
             tracing::info!("Transfer task cancelled: {}", id);
             Ok(())
         } else {
@@ -275,6 +594,200 @@ impl FileTransferManager {
         }
     }
 
+    /// Pause an active transfer
+    ///
+    /// Its transfer loop stops and its connection is closed (see
+    /// [`TransferControl`]); the transfer continues from the offset reached
+    /// once resumed.
+    ///
+    /// # Arguments
+    /// * `id` - Task ID
+    ///
+    /// # Returns
+    /// * `Ok(())` - Task paused
+    /// * `Err(NeoLanError)` - Task not found, or not active
+    pub fn pause_task(&self, id: Uuid) -> Result<()> {
+        let mut tasks = self.tasks.lock().map_err(|_| {
+            NeoLanError::Other("Failed to lock tasks".to_string())
+        })?;
+
+        let task = tasks.iter_mut().find(|t| t.id == id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!("Task not found: {}", id))
+        })?;
+        if !task.is_active() {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Transfer is {}, not active: {}",
+                task.status.as_str(),
+                id
+            )));
+        }
+
+        task.pause();
+        self.notify(task);
+        if let Some(control) = self.lock_controls().get(&id) {
+            control.pause();
+        }
+        tracing::info!("Transfer task paused: {}", id);
+        Ok(())
+    }
+
+    /// Register the control handle of a task's transfer loop (its worker starting)
+    ///
+    /// The handle starts paused or cancelled if the task was paused or has
+    /// finished meanwhile. Call [`Self::unregister_control`] when the loop ends.
+    /// A handle registered earlier for the task is replaced.
+    pub fn register_control(&self, id: Uuid) -> TransferControl {
+        let tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let signal = match tasks.iter().find(|t| t.id == id) {
+            Some(task) if task.status == TransferStatus::Paused => TransferSignal::Pause,
+            Some(task) if !task.is_finished() => TransferSignal::Run,
+            _ => TransferSignal::Cancel,
+        };
+
+        let control = TransferControl::new(signal);
+        self.lock_controls().insert(id, control.clone());
+        control
+    }
+
+    /// Forget the control handle of a task whose transfer loop ended
+    ///
+    /// Left alone if a newer loop of the task registered its own handle.
+    pub fn unregister_control(&self, id: Uuid, control: &TransferControl) {
+        let mut controls = self.lock_controls();
+        if controls.get(&id).is_some_and(|registered| registered.is_same(control)) {
+            controls.remove(&id);
+        }
+    }
+
+    /// Whether a transfer loop of the task is running (its control handle is registered)
+    pub fn is_transferring(&self, id: Uuid) -> bool {
+        self.lock_controls().contains_key(&id)
+    }
+
+    /// Release pending upload tasks announced in an attachment message
+    ///
+    /// Called when the peer answers with IPMSG_RELEASEFILES for `packet_id`.
+    ///
+    /// # Arguments
+    /// * `peer_ip` - Peer that released the files
+    /// * `packet_id` - Packet ID of our FILEATTACHOPT message
+    ///
+    /// # Returns
+    /// * `usize` - Number of tasks cancelled
+    pub fn release_attachments(&self, peer_ip: IpAddr, packet_id: u64) -> usize {
+        let Ok(mut tasks) = self.tasks.lock() else {
+            return 0;
+        };
+
+        let mut released = 0;
+        for task in tasks.iter_mut().filter(|t| {
+            t.direction == TransferDirection::Upload
+                && t.peer_ip == peer_ip
+                && t.packet_id == Some(packet_id)
+                && t.status == TransferStatus::Pending
+        }) {
+            task.mark_cancelled();
+            self.notify(task);
+            released += 1;
+        }
+
+        if released > 0 {
+            tracing::info!(
+                "Peer {} released {} attachment(s) of packet {}",
+                peer_ip,
+                released,
+                packet_id
+            );
+        }
+        released
+    }
+
+    /// Close every unfinished transfer (at shutdown)
+    ///
+    /// Transfers that got under way (some data transferred) are paused, to
+    /// be resumed after the restart (see [`Self::restore_tasks`]). The
+    /// others are cancelled, and attachments we were going to download are
+    /// released with IPMSG_RELEASEFILES (once per attachment message, unless
+    /// another file of it is kept), so the sender stops offering them.
+    /// Running transfer loops are stopped, and their connections closed.
+    ///
+    /// # Returns
+    /// * `usize` - Number of tasks paused or cancelled
+    pub fn release_all(&self) -> usize {
+        let Ok(mut tasks) = self.tasks.lock() else {
+            return 0;
+        };
+
+        let under_way = |task: &TransferTask| {
+            task.transferred_bytes > 0 && matches!(task.status, TransferStatus::Active | TransferStatus::Paused)
+        };
+        // Attachment messages not to release: a file of them is kept, or they were released already
+        let mut not_released: HashSet<(IpAddr, u64)> = tasks
+            .iter()
+            .filter(|t| under_way(t))
+            .filter_map(|t| t.packet_id.map(|packet_id| (t.peer_ip, packet_id)))
+            .collect();
+
+        let mut paused = 0;
+        let mut cancelled = 0;
+        for task in tasks.iter_mut().filter(|t| !t.is_finished()) {
+            if under_way(task) {
+                task.pause();
+                self.notify(task);
+                paused += 1;
+                continue;
+            }
+
+            let attachment_download = task.direction == TransferDirection::Download && task.is_attachment();
+            if let Some(packet_id) = task.packet_id.filter(|_| attachment_download) {
+                if not_released.insert((task.peer_ip, packet_id)) {
+                    if let Err(e) = self.send_release(task.peer_ip, packet_id) {
+                        tracing::warn!("Failed to release packet {} of {}: {}", packet_id, task.peer_ip, e);
+                    }
+                }
+            }
+            task.mark_cancelled();
+            self.notify(task);
+            cancelled += 1;
+        }
+
+        // Stopped the way their tasks were: paused loops don't mark them failed
+        for (id, control) in self.lock_controls().iter() {
+            match tasks.iter().find(|t| t.id == *id) {
+                Some(task) if task.status == TransferStatus::Paused => control.pause(),
+                _ => control.cancel(),
+            }
+        }
+
+        if paused + cancelled > 0 {
+            tracing::info!(
+                "Paused {} transfer(s) under way, cancelled {} other unfinished transfer(s)",
+                paused,
+                cancelled
+            );
+        }
+        paused + cancelled
+    }
+
+    /// Send IPMSG_RELEASEFILES for an attachment message we won't fetch (further)
+    pub fn send_release(&self, peer_ip: IpAddr, packet_id: u64) -> Result<()> {
+        let proto_msg = ProtocolMessage {
+            version: PROTOCOL_VERSION,
+            packet_id: next_packet_id(),
+            sender_name: self.username.clone(),
+            sender_host: self.hostname.clone(),
+            msg_type: msg_type::IPMSG_RELEASEFILES,
+            content: packet_id.to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: None,
+        };
+
+        let msg_bytes = serialize_message(&proto_msg)?;
+        self.udp.send_to(&msg_bytes, self.peer_addr(peer_ip))
+    }
+
     /// Remove completed/failed/cancelled tasks
     ///
     /// # Returns
@@ -293,20 +806,73 @@ impl FileTransferManager {
         }
     }
 
+    /// Take back the tasks left unfinished when the application last exited
+    ///
+    /// Started downloads whose partial file is still there come back paused;
+    /// the others are marked failed. Call at startup, before any transfer.
+    ///
+    /// # Arguments
+    /// * `tasks` - Unfinished tasks loaded from the transfer store
+    ///
+    /// # Returns
+    /// * `usize` - Number of tasks that can be resumed
+    pub fn restore_tasks(&self, tasks: Vec<TransferTask>) -> usize {
+        let mut resumable = 0;
+        for task in tasks.into_iter().map(recover_interrupted) {
+            if task.status == TransferStatus::Paused {
+                resumable += 1;
+            }
+            if let Err(e) = self.add_task(task) {
+                tracing::warn!("Failed to restore transfer task: {}", e);
+            }
+        }
+
+        if resumable > 0 {
+            tracing::info!("Restored {} interrupted download(s) for resuming", resumable);
+        }
+        resumable
+    }
+
     /// Add a task to the list
     pub fn add_task(&self, task: TransferTask) -> Result<()> {
         let mut tasks = self.tasks.lock().map_err(|_| {
             NeoLanError::Other("Failed to lock tasks".to_string())
         })?;
 
+        self.notify(&task);
         tasks.push(task);
         Ok(())
     }
+
+    fn lock_controls(&self) -> MutexGuard<'_, HashMap<Uuid, TransferControl>> {
+        self.controls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Report a new or changed task to the frontend and the store
+    fn notify(&self, task: &TransferTask) {
+        if let Some(ref store) = self.store {
+            store.save(task, &self.peer_name(task.peer_ip));
+        }
+        if let Some(ref app_state) = self.app_state {
+            app_state.emit_tauri_event(TauriEvent::FileTransferUpdated {
+                task_id: task.id.to_string(),
+                direction: task.direction.as_str().to_string(),
+                peer_ip: task.peer_ip.to_string(),
+                file_name: task.file_name.clone(),
+                file_size: task.file_size,
+                status: task.status.as_str().to_string(),
+                transferred_bytes: task.transferred_bytes,
+                error: task.error.clone(),
+                updated_at: task.updated_at.timestamp(),
+            });
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::network::UdpTransport;
     use std::net::Ipv4Addr;
 
     // Note: These tests require a UDP socket, so they're integration tests
@@ -316,7 +882,7 @@ mod tests {
     #[ignore]
     fn test_send_request() {
         // Create UDP transport
-        let udp = Arc::new(UdpTransport::bind(0).unwrap());
+        let udp = UdpTransport::bind(0).unwrap().sender();
 
         // Create manager
         let manager = FileTransferManager::new(
@@ -340,10 +906,261 @@ mod tests {
         assert!(result.is_ok() || result.is_err()); // Just check it doesn't panic
     }
 
+    #[test]
+    fn test_send_attachments_creates_tasks() {
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = FileTransferManager::new(
+            udp,
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+
+        let dir = std::env::temp_dir();
+        let file1 = dir.join("test_attach_1.txt");
+        let file2 = dir.join("test_attach_2.txt");
+        std::fs::write(&file1, b"first").unwrap();
+        std::fs::write(&file2, b"second file").unwrap();
+
+        let target = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let task_ids = manager
+            .send_attachments(&[file1.clone(), file2.clone()], target, TextEncoding::Gbk)
+            .unwrap();
+
+        std::fs::remove_file(&file1).unwrap();
+        std::fs::remove_file(&file2).unwrap();
+
+        assert_eq!(task_ids.len(), 2);
+        let task1 = manager.get_task(task_ids[0]).unwrap();
+        let task2 = manager.get_task(task_ids[1]).unwrap();
+        assert_eq!(task1.file_id, Some(0));
+        assert_eq!(task2.file_id, Some(1));
+        assert_eq!(task1.packet_id, task2.packet_id);
+        assert_eq!(task2.file_size, 11);
+        assert_eq!(task1.status, TransferStatus::Pending);
+        assert!(task1.md5.is_empty());
+
+        // Peer declines the whole packet
+        let released = manager.release_attachments(target, task1.packet_id.unwrap());
+        assert_eq!(released, 2);
+        assert_eq!(
+            manager.get_task(task_ids[1]).unwrap().status,
+            TransferStatus::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_release_all_at_shutdown() {
+        let capture_path = std::env::temp_dir().join(format!("neolan_release_{}.jsonl", Uuid::new_v4()));
+        let capture = Arc::new(crate::network::PacketCapture::create(&capture_path).unwrap());
+        let udp = UdpTransport::bind(0).unwrap().dry_run().with_capture(capture);
+        let manager = FileTransferManager::new(udp.sender(), "TestUser".to_string(), "test-host".to_string());
+
+        // Two files of one attachment message being downloaded, one upload, one finished download
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        for file_id in 0..2 {
+            let task = TransferTask::new_download(peer, format!("f{}.txt", file_id), 10, String::new())
+                .with_attachment(4242, file_id);
+            manager.add_task(task).unwrap();
+        }
+        manager
+            .add_task(TransferTask::new_upload(peer, PathBuf::from("up.txt"), "up.txt".to_string(), 10, String::new()))
+            .unwrap();
+        let mut done = TransferTask::new_download(peer, "done.txt".to_string(), 10, String::new()).with_attachment(4343, 0);
+        done.mark_completed();
+        manager.add_task(done).unwrap();
+        // A download under way is kept for resuming after the restart, and its message not released
+        let mut started = TransferTask::new_download(peer, "big.iso".to_string(), 10, String::new()).with_attachment(4444, 0);
+        started.mark_active(DEFAULT_UDP_PORT);
+        started.update_progress(4);
+        manager.add_task(started.clone()).unwrap();
+        let started_control = manager.register_control(started.id);
+        let next = TransferTask::new_download(peer, "next.iso".to_string(), 10, String::new()).with_attachment(4444, 1);
+        manager.add_task(next.clone()).unwrap();
+        let next_control = manager.register_control(next.id);
+
+        // Running loops stop the way their tasks do
+        assert_eq!(manager.release_all(), 5);
+        assert_eq!(started_control.signal(), TransferSignal::Pause);
+        assert_eq!(next_control.signal(), TransferSignal::Cancel);
+        assert_eq!(manager.get_tasks_by_status(TransferStatus::Cancelled).len(), 4);
+        assert_eq!(manager.get_tasks_by_status(TransferStatus::Paused).len(), 1);
+        assert_eq!(manager.get_tasks_by_status(TransferStatus::Completed).len(), 1);
+
+        // One RELEASEFILES for the unfinished attachment message
+        let records = crate::network::read_capture(&capture_path).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+        assert_eq!(records.len(), 1);
+        let release = crate::network::parse_message(&records[0].data).unwrap();
+        assert_eq!(release.command().mode(), crate::network::Mode::ReleaseFiles);
+        assert_eq!(release.content, "4242");
+        assert_eq!(records[0].peer, SocketAddr::new(peer, DEFAULT_UDP_PORT));
+    }
+
+    #[test]
+    fn test_offer_and_release_use_known_port() {
+        let capture_path = std::env::temp_dir().join(format!("neolan_peer_port_{}.jsonl", Uuid::new_v4()));
+        let capture = Arc::new(crate::network::PacketCapture::create(&capture_path).unwrap());
+        let udp = UdpTransport::bind(0).unwrap().dry_run().with_capture(capture);
+
+        // A peer the PeerManager heard from on a port other than the default
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        let state = Arc::new(AppState::new(crate::config::AppConfig::default()));
+        state.init_peer_manager(crate::modules::peer::PeerManager::new(
+            crate::modules::peer::discovery::PeerDiscovery::new(
+                UdpTransport::bind(0).unwrap(),
+                "TestUser".to_string(),
+                "test-host".to_string(),
+            ),
+        ));
+        let node = crate::modules::peer::PeerNode::new(peer, 2426);
+        state.get_peer_manager().unwrap().add_peer(node).unwrap();
+        let manager = FileTransferManager::new(udp.sender(), "TestUser".to_string(), "test-host".to_string())
+            .with_app_state(state);
+
+        let file = std::env::temp_dir().join(format!("neolan_peer_port_{}.txt", Uuid::new_v4()));
+        std::fs::write(&file, b"data").unwrap();
+        let task_ids = manager.send_attachments(std::slice::from_ref(&file), peer, TextEncoding::Utf8).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let packet_id = manager.get_task(task_ids[0]).unwrap().packet_id.unwrap();
+        manager.send_release(peer, packet_id).unwrap();
+
+        // Offer and release both go to the port the peer was heard from
+        let records = crate::network::read_capture(&capture_path).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.peer == SocketAddr::new(peer, 2426)));
+        assert_eq!(manager.peer_addr(Ipv4Addr::LOCALHOST.into()).port(), DEFAULT_UDP_PORT);
+    }
+
+    #[test]
+    fn test_find_offer_upload() {
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = FileTransferManager::new(udp, "TestUser".to_string(), "test-host".to_string());
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+
+        let offer = |packet_id| {
+            let task = TransferTask::new_upload(peer, PathBuf::from("a.txt"), "a.txt".to_string(), 1, String::new())
+                .with_offer(packet_id);
+            manager.add_task(task.clone()).unwrap();
+            task.id
+        };
+        let first = offer(1);
+        let second = offer(2);
+        // Attachments are pulled by the peer, never answered
+        manager
+            .add_task(
+                TransferTask::new_upload(peer, PathBuf::from("b.txt"), "b.txt".to_string(), 1, String::new())
+                    .with_attachment(3, 0),
+            )
+            .unwrap();
+
+        assert_eq!(manager.find_offer_upload(peer, Some(2)).unwrap().id, second);
+        assert!(manager.find_offer_upload(peer, Some(3)).is_none());
+        // A reply without the packet ID answers the oldest offer
+        assert_eq!(manager.find_offer_upload(peer, None).unwrap().id, first);
+        assert!(manager.find_offer_upload(IpAddr::V4(Ipv4Addr::LOCALHOST), None).is_none());
+
+        manager.cancel_task(first).unwrap();
+        assert_eq!(manager.find_offer_upload(peer, None).unwrap().id, second);
+    }
+
+    #[test]
+    fn test_tasks_stored_and_restored() {
+        use crate::migration::{Migrator, MigratorTrait};
+        use crate::storage::transfer_repo::TransferRepository;
+
+        // A database file: the store thread and the test use their own connections
+        let db_path = std::env::temp_dir().join(format!("neolan_transfers_{}.db", Uuid::new_v4()));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", db_path.display());
+        let db = rt.block_on(sea_orm::Database::connect(url.clone())).unwrap();
+        rt.block_on(Migrator::up(&db, None)).unwrap();
+        let store = Arc::new(TransferStore::new(TransferRepository::new(db)));
+        let writer = store.start();
+
+        // The peer's name comes from the PeerManager
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        let state = AppState::new(crate::config::AppConfig::default());
+        let peers = crate::modules::peer::PeerManager::new(crate::modules::peer::discovery::PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap().dry_run(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let mut node = crate::modules::peer::PeerNode::new(peer, 2425);
+        node.nickname = Some("张三".to_string());
+        peers.add_peer(node).unwrap();
+        state.init_peer_manager(peers);
+
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = FileTransferManager::new(udp, "TestUser".to_string(), "test-host".to_string())
+            .with_app_state(Arc::new(state))
+            .with_store(store.clone());
+
+        let partial = std::env::temp_dir().join(format!("neolan_restore_{}", Uuid::new_v4()));
+        std::fs::write(&partial, b"part").unwrap();
+        let mut started = TransferTask::new_download(peer, "a.txt".to_string(), 10, String::new());
+        started.file_path = partial.clone();
+        manager.add_task(started.clone()).unwrap();
+        started.mark_active(2425);
+        started.update_progress(4);
+        manager.update_task(started.clone()).unwrap();
+
+        let offer = TransferTask::new_download(peer, "b.txt".to_string(), 10, String::new());
+        manager.add_task(offer.clone()).unwrap();
+        let cancelled = TransferTask::new_download(peer, "c.txt".to_string(), 10, String::new());
+        manager.add_task(cancelled.clone()).unwrap();
+        manager.cancel_task(cancelled.id).unwrap();
+
+        store.close();
+        writer.join().unwrap();
+
+        let (history, total) = rt.block_on(store.history(0, 10)).unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(history.iter().find(|t| t.id == cancelled.id).unwrap().status, TransferStatus::Cancelled);
+        let reader = TransferRepository::new(rt.block_on(sea_orm::Database::connect(url)).unwrap());
+        let record = rt.block_on(reader.find_by_task_id(&offer.id.to_string())).unwrap().unwrap();
+        assert_eq!(record.peer_name, "张三");
+
+        // After a restart: the started download comes back paused, the offer failed
+        let unfinished = rt.block_on(store.load_unfinished()).unwrap();
+        assert_eq!(unfinished.len(), 2);
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let restarted = FileTransferManager::new(udp, "TestUser".to_string(), "test-host".to_string());
+        assert_eq!(restarted.restore_tasks(unfinished), 1);
+
+        let resumed = restarted.get_task(started.id).unwrap();
+        assert_eq!(resumed.status, TransferStatus::Paused);
+        assert_eq!(resumed.transferred_bytes, 4);
+        assert_eq!(restarted.get_task(offer.id).unwrap().status, TransferStatus::Failed);
+
+        std::fs::remove_file(&partial).unwrap();
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_send_attachments_missing_file() {
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = FileTransferManager::new(
+            udp,
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+
+        let missing = std::env::temp_dir().join("test_attach_missing.txt");
+        let result = manager.send_attachments(
+            &[missing],
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            TextEncoding::default(),
+        );
+
+        assert!(result.is_err());
+        assert!(manager.get_tasks().is_empty());
+    }
+
     #[test]
     fn test_task_management() {
         // Create UDP transport
-        let udp = Arc::new(UdpTransport::bind(0).unwrap());
+        let udp = UdpTransport::bind(0).unwrap().sender();
 
         // Create manager
         let manager = FileTransferManager::new(
@@ -390,10 +1207,55 @@ mod tests {
         assert_eq!(manager.get_tasks().len(), 0);
     }
 
+    #[test]
+    fn test_pause_and_cancel_task() {
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = FileTransferManager::new(udp, "TestUser".to_string(), "test-host".to_string());
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+
+        let mut task = TransferTask::new_download(peer, "big.iso".to_string(), 100, String::new());
+        let task_id = task.id;
+        manager.add_task(task.clone()).unwrap();
+        assert!(manager.pause_task(task_id).is_err());
+
+        // Pausing stops the running loop
+        task.mark_active(DEFAULT_UDP_PORT);
+        manager.update_task(task.clone()).unwrap();
+        let control = manager.register_control(task_id);
+        assert!(manager.is_transferring(task_id));
+        manager.pause_task(task_id).unwrap();
+        assert_eq!(control.signal(), TransferSignal::Pause);
+        assert_eq!(manager.get_task(task_id).unwrap().status, TransferStatus::Paused);
+
+        // Progress of the stopping loop is dropped, its end is not
+        task.update_progress(50);
+        assert!(!manager.update_running_task(task.clone()).unwrap());
+        assert_eq!(manager.get_task(task_id).unwrap().status, TransferStatus::Paused);
+        let mut completed = task.clone();
+        completed.mark_completed();
+        assert!(manager.update_running_task(completed.clone()).unwrap());
+        assert_eq!(manager.get_task(task_id).unwrap().status, TransferStatus::Completed);
+        assert!(!manager.update_running_task(task.clone()).unwrap());
+
+        // A loop starting on a paused task stops at once; an older loop ending
+        // leaves the newer one registered
+        manager.update_task(task.clone()).unwrap();
+        manager.pause_task(task_id).unwrap();
+        let newer = manager.register_control(task_id);
+        assert_eq!(newer.signal(), TransferSignal::Pause);
+        manager.unregister_control(task_id, &control);
+        assert!(manager.is_transferring(task_id));
+
+        manager.cancel_task(task_id).unwrap();
+        assert_eq!(newer.signal(), TransferSignal::Cancel);
+        manager.unregister_control(task_id, &newer);
+        assert!(!manager.is_transferring(task_id));
+    }
+
     #[test]
     fn test_get_tasks_by_status() {
         // Create UDP transport
-        let udp = Arc::new(UdpTransport::bind(0).unwrap());
+        let udp = UdpTransport::bind(0).unwrap().sender();
 
         // Create manager
         let manager = FileTransferManager::new(
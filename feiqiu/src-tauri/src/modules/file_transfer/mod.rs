@@ -3,7 +3,21 @@
 pub mod types;
 pub mod manager;
 pub mod response;
+pub mod server;
+pub mod store;
+pub mod upload;
 
 // Re-export commonly used types
 pub use manager::FileTransferManager;
 pub use response::FileTransferResponse;
+pub use server::FileServer;
+pub use store::TransferStore;
+
+/// Timeout for reading and writing file data on a connection (seconds)
+#[cfg(not(test))]
+pub(crate) const IO_TIMEOUT_SECS: u64 = 30;
+#[cfg(test)]
+pub(crate) const IO_TIMEOUT_SECS: u64 = 2;
+
+/// Minimum progress between task updates in the manager (bytes)
+pub(crate) const PROGRESS_UPDATE_BYTES: u64 = 1024 * 1024;
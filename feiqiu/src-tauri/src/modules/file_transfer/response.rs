@@ -1,17 +1,68 @@
 // File transfer response handler - handles incoming file transfer requests
+//
+// Offers wait in a registry until the user answers. Accepting one starts a
+// download worker: NeoLan senders are told the TCP port we listen on and
+// connect to us, IPMsg attachments are fetched from the sender's port with
+// IPMSG_GETFILEDATA (`packetID:fileID:offset`).
+//
+// Interrupted downloads continue from the end of the partial file: IPMsg
+// attachments through the GETFILEDATA offset, NeoLan transfers by repeating
+// the accepting reply with the offset and the MD5 of the bytes we have.
+// Pausing a transfer closes its connection; it is resumed the same way.
 use crate::network::{
-    FileSendRequest, FileSendResponse, ProtocolMessage, PROTOCOL_VERSION, msg_type,
+    canonical_ip, next_packet_id, serialize_message, FeiqHeader, FileAttachment, FileDataRequest,
+    FileSendRequest, FileSendResponse, ProtocolMessage, TcpTransport, TextEncoding, TransferControl,
+    TransferSignal, PROTOCOL_VERSION, msg_type,
 };
 use crate::state::app_state::TauriEvent;
+use crate::utils::hash;
 use crate::{NeoLanError, Result};
 use chrono::Utc;
 use serde_json;
-use std::net::{IpAddr, SocketAddr};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use super::types::TransferTask;
-use super::FileTransferManager;
+use super::types::{TransferDirection, TransferStatus, TransferTask};
+use super::upload::{self, ResumePoint};
+use super::{FileTransferManager, IO_TIMEOUT_SECS, PROGRESS_UPDATE_BYTES};
+
+/// How long an accepted NeoLan sender has to connect (seconds)
+const CONNECT_TIMEOUT_SECS: u64 = 60;
+
+/// Interval between checks for the sender's connection
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most numbered names tried when the offered file name is taken
+const MAX_NAME_ATTEMPTS: usize = 1000;
+
+/// Most attempts in a row to resume a dropped download without receiving anything
+const MAX_RESUME_ATTEMPTS: u32 = 5;
+
+/// Wait before resuming a dropped download
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+
+/// Where an accepted file's data comes from
+enum DownloadSource {
+    /// NeoLan sender connects to this listener (the port in our reply); when
+    /// we asked to resume from `offset`, it first sends the offset it starts
+    /// at (8 bytes, big-endian)
+    Listen { listener: TcpListener, offset: Option<u64> },
+
+    /// IPMsg attachment: connect to the sender and send this GETFILEDATA
+    /// packet (asking for the data from `offset`)
+    Fetch { addr: SocketAddr, packet: Vec<u8>, offset: u64 },
+
+    /// Ask the sender for the rest of the partial file first (see
+    /// [`FileTransferResponse::resume_source`])
+    Resume { tcp_port: Option<u16> },
+}
 
 /// Pending file transfer request
 ///
@@ -34,9 +85,18 @@ pub struct PendingRequest {
     /// File size in bytes
     pub file_size: u64,
 
-    /// MD5 hash
+    /// MD5 hash (empty for IPMsg attachments, which don't carry one)
     pub md5: String,
 
+    /// Packet ID of the announcing message
+    pub packet_id: u64,
+
+    /// IPMsg attachment file ID (None for NeoLan JSON requests)
+    pub file_id: Option<u64>,
+
+    /// Text encoding of the announcing message (replies use the same one)
+    pub encoding: Option<TextEncoding>,
+
     /// Request timestamp
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
@@ -53,6 +113,13 @@ pub struct FileTransferResponse {
 
     /// Local hostname
     hostname: String,
+
+    /// Requests waiting for the user to accept or reject them (by request ID)
+    pending: Mutex<HashMap<Uuid, PendingRequest>>,
+
+    /// Resume requests for paused uploads (receiver's address and part),
+    /// answered once the upload is resumed
+    parked: Mutex<HashMap<Uuid, (SocketAddr, ResumePoint)>>,
 }
 
 impl FileTransferResponse {
@@ -71,40 +138,34 @@ impl FileTransferResponse {
             manager,
             username,
             hostname,
+            pending: Mutex::new(HashMap::new()),
+            parked: Mutex::new(HashMap::new()),
         }
     }
 
     /// Handle an incoming file transfer request
     ///
+    /// The request is kept until it is accepted or rejected.
+    ///
     /// # Arguments
     /// * `proto_msg` - Protocol message containing the request
+    /// * `file_request` - File offer decoded from the message content
     /// * `sender_ip` - Sender's IP address
     ///
     /// # Returns
-    /// * `Ok(PendingRequest)` - Request parsed successfully
-    /// * `Err(NeoLanError)` - Parsing failed
-    ///
-    /// # Process
-    /// 1. Parse FileSendRequest from message content
-    /// 2. Create PendingRequest
-    /// 3. Return for user confirmation (via Tauri event)
+    /// * `PendingRequest` - Request awaiting user confirmation (via Tauri event)
     pub fn handle_incoming_request(
         &self,
         proto_msg: &ProtocolMessage,
+        file_request: &FileSendRequest,
         sender_ip: IpAddr,
-    ) -> Result<PendingRequest> {
+    ) -> PendingRequest {
         tracing::info!(
             "File transfer request from {}: {}",
             proto_msg.sender_name,
             sender_ip
         );
 
-        // Parse FileSendRequest from JSON content
-        let file_request: FileSendRequest = serde_json::from_str(&proto_msg.content)
-            .map_err(|e| {
-                NeoLanError::Protocol(format!("Failed to parse file request: {}", e))
-            })?;
-
         tracing::info!(
             "File request: name={}, size={}, md5={}",
             file_request.name,
@@ -113,17 +174,195 @@ impl FileTransferResponse {
         );
 
         // Create pending request
-        let request = PendingRequest {
+        let pending = PendingRequest {
             id: Uuid::new_v4(),
             sender_ip,
             sender_name: proto_msg.sender_name.clone(),
             file_name: file_request.name.clone(),
             file_size: file_request.size,
             md5: file_request.md5.clone(),
+            packet_id: proto_msg.packet_id,
+            file_id: None,
+            encoding: proto_msg.encoding,
             created_at: Utc::now(),
         };
 
-        Ok(request)
+        self.lock_pending().insert(pending.id, pending.clone());
+        pending
+    }
+
+    /// Handle incoming file attachments (IPMSG_SENDMSG | IPMSG_FILEATTACHOPT)
+    ///
+    /// # Arguments
+    /// * `proto_msg` - Protocol message carrying the attachment section
+    /// * `attachments` - Attachment entries decoded from the message
+    /// * `sender_ip` - Sender's IP address
+    ///
+    /// # Returns
+    /// * `Vec<PendingRequest>` - One pending request per regular file (each
+    ///   kept until it is accepted or rejected)
+    ///
+    /// # Note
+    /// Directory attachments (IPMSG_GETDIRFILES) are not supported yet and are skipped.
+    pub fn handle_incoming_attachments(
+        &self,
+        proto_msg: &ProtocolMessage,
+        attachments: Vec<FileAttachment>,
+        sender_ip: IpAddr,
+    ) -> Vec<PendingRequest> {
+        tracing::info!(
+            "File attachments from {} ({}): {} entr(ies), packet_id={}",
+            proto_msg.sender_name,
+            sender_ip,
+            attachments.len(),
+            proto_msg.packet_id
+        );
+
+        let pending: Vec<PendingRequest> = attachments
+            .into_iter()
+            .filter(|attachment| {
+                if attachment.is_regular() {
+                    true
+                } else {
+                    tracing::warn!(
+                        "Skipping unsupported attachment (attr=0x{:x}): {}",
+                        attachment.attr,
+                        attachment.name
+                    );
+                    false
+                }
+            })
+            .map(|attachment| PendingRequest {
+                id: Uuid::new_v4(),
+                sender_ip,
+                sender_name: proto_msg.sender_name.clone(),
+                file_name: attachment.name,
+                file_size: attachment.size,
+                md5: String::new(),
+                packet_id: proto_msg.packet_id,
+                file_id: Some(attachment.file_id),
+                encoding: proto_msg.encoding,
+                created_at: Utc::now(),
+            })
+            .collect();
+
+        let mut registry = self.lock_pending();
+        for request in &pending {
+            registry.insert(request.id, request.clone());
+        }
+        pending
+    }
+
+    /// Requests waiting for the user's answer
+    pub fn pending_requests(&self) -> Vec<PendingRequest> {
+        self.lock_pending().values().cloned().collect()
+    }
+
+    /// Accept a pending request and start downloading the file
+    ///
+    /// The file is saved in `save_dir` under the offered name (see
+    /// [`Self::reserve_save_path`]) and received on a worker thread, which
+    /// keeps the download task up to date. A request that can't be set up
+    /// stays pending.
+    ///
+    /// # Arguments
+    /// * `request_id` - ID of the pending request
+    /// * `save_dir` - Directory to save the file in
+    /// * `tcp_port` - Port to listen on for a NeoLan sender (None: a free
+    ///   port in the configured range); unused for IPMsg attachments
+    ///
+    /// # Returns
+    /// * `Ok(Uuid)` - ID of the download task
+    /// * `Err(NeoLanError)` - Unknown request, or the download couldn't be set up
+    pub fn accept(self: &Arc<Self>, request_id: Uuid, save_dir: &Path, tcp_port: Option<u16>) -> Result<Uuid> {
+        let request = self.take_pending(request_id)?;
+
+        match self.start_download(&request, save_dir, tcp_port) {
+            Ok(task_id) => Ok(task_id),
+            Err(e) => {
+                self.lock_pending().insert(request.id, request);
+                Err(e)
+            }
+        }
+    }
+
+    /// Reject a pending request
+    ///
+    /// The sender is told: a rejecting reply for NeoLan requests,
+    /// IPMSG_RELEASEFILES for IPMsg attachments (once no other file of the
+    /// attachment message is pending or downloading).
+    ///
+    /// # Arguments
+    /// * `request_id` - ID of the pending request
+    ///
+    /// # Returns
+    /// * `Ok(())` - Request rejected
+    /// * `Err(NeoLanError)` - Unknown request, or the reply couldn't be sent
+    pub fn reject(&self, request_id: Uuid) -> Result<()> {
+        let request = self.take_pending(request_id)?;
+        if request.file_id.is_some() && self.attachment_in_use(request.sender_ip, request.packet_id) {
+            return Ok(());
+        }
+        self.send_response(&request, false, None, self.manager.udp())
+    }
+
+    /// Cancel an unfinished transfer task
+    ///
+    /// A running download is disconnected (its worker removes the partial
+    /// file, as we do for a paused one) and the sender is told we won't take
+    /// the file: IPMSG_RELEASEFILES
+    /// once no other file of the attachment message is pending or
+    /// downloading, a rejecting reply for NeoLan transfers.
+    ///
+    /// # Arguments
+    /// * `task_id` - ID of the transfer task
+    ///
+    /// # Returns
+    /// * `Ok(())` - Task cancelled
+    /// * `Err(NeoLanError)` - Unknown or finished task, or the sender couldn't be told
+    pub fn cancel(&self, task_id: Uuid) -> Result<()> {
+        let task = self.manager.get_task(task_id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!("Task not found: {}", task_id))
+        })?;
+        if task.is_finished() {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Transfer already finished: {}",
+                task_id
+            )));
+        }
+
+        self.manager.cancel_task(task_id)?;
+        if task.direction != TransferDirection::Download {
+            self.lock_parked().remove(&task_id);
+            return Ok(());
+        }
+
+        if task.status == TransferStatus::Paused {
+            let _ = std::fs::remove_file(&task.file_path);
+        }
+
+        match task.packet_id {
+            Some(packet_id) if task.is_attachment() => {
+                if self.attachment_in_use(task.peer_ip, packet_id) {
+                    Ok(())
+                } else {
+                    self.manager.send_release(task.peer_ip, packet_id)
+                }
+            }
+            packet_id => {
+                let response = FileSendResponse {
+                    accept: false,
+                    port: None,
+                    packet_id,
+                    offset: None,
+                    prefix_md5: None,
+                };
+                let content = serde_json::to_string(&response).map_err(|e| {
+                    NeoLanError::FileTransfer(format!("Failed to serialize response: {}", e))
+                })?;
+                self.send_release_files(task.peer_ip, content, None, self.manager.udp())
+            }
+        }
     }
 
     /// Send a file transfer response (accept or reject)
@@ -132,7 +371,7 @@ impl FileTransferResponse {
     /// * `request` - The pending request to respond to
     /// * `accept` - true to accept, false to reject
     /// * `tcp_port` - TCP port for data transfer (only if accept = true)
-    /// * `udp` - UDP transport for sending the response
+    /// * `udp` - Send handle of the shared UDP transport
     ///
     /// # Returns
     /// * `Ok(())` - Response sent successfully
@@ -142,36 +381,38 @@ impl FileTransferResponse {
         request: &PendingRequest,
         accept: bool,
         tcp_port: Option<u16>,
-        udp: &crate::network::UdpTransport,
+        udp: &crate::network::UdpSender,
     ) -> Result<()> {
-        // Create response
-        let response = FileSendResponse {
-            accept,
-            port: if accept { tcp_port } else { None },
-        };
-
-        let content = serde_json::to_string(&response)
-            .map_err(|e| NeoLanError::FileTransfer(format!("Failed to serialize response: {}", e)))?;
-
-        // Create protocol message
-        let packet_id = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map_err(|e| NeoLanError::Other(format!("Time error: {}", e)))?
-            .as_secs();
+        // Standard IPMsg attachments: accepting means fetching the data over TCP
+        // (IPMSG_GETFILEDATA), so there is nothing to send over UDP. Rejecting
+        // releases the files with IPMSG_RELEASEFILES carrying the packet ID.
+        let content = if request.file_id.is_some() {
+            if accept {
+                tracing::info!(
+                    "File attachment ACCEPTED: {} (packet_id={}, file_id={:?}) <- {}",
+                    request.file_name,
+                    request.packet_id,
+                    request.file_id,
+                    request.sender_ip
+                );
+                return Ok(());
+            }
+            request.packet_id.to_string()
+        } else {
+            // Create response
+            let response = FileSendResponse {
+                accept,
+                port: if accept { tcp_port } else { None },
+                packet_id: Some(request.packet_id),
+                offset: None,
+                prefix_md5: None,
+            };
 
-        let proto_msg = ProtocolMessage {
-            version: PROTOCOL_VERSION as u8,
-            packet_id,
-            sender_name: self.username.clone(),
-            sender_host: self.hostname.clone(),
-            msg_type: msg_type::IPMSG_RELEASEFILES,
-            content,
+            serde_json::to_string(&response)
+                .map_err(|e| NeoLanError::FileTransfer(format!("Failed to serialize response: {}", e)))?
         };
 
-        // Serialize and send via UDP
-        let msg_bytes = crate::network::serialize_message(&proto_msg)?;
-        let addr = SocketAddr::new(request.sender_ip, 2425);
-        udp.send_to(&msg_bytes, addr)?;
+        self.send_release_files(request.sender_ip, content, request.encoding, udp)?;
 
         if accept {
             tracing::info!(
@@ -191,6 +432,261 @@ impl FileTransferResponse {
         Ok(())
     }
 
+    /// Send an IPMSG_RELEASEFILES packet (the reply to a file offer)
+    fn send_release_files(
+        &self,
+        peer_ip: IpAddr,
+        content: String,
+        encoding: Option<TextEncoding>,
+        udp: &crate::network::UdpSender,
+    ) -> Result<()> {
+        let proto_msg = ProtocolMessage {
+            version: PROTOCOL_VERSION,
+            packet_id: next_packet_id(),
+            sender_name: self.username.clone(),
+            sender_host: self.hostname.clone(),
+            msg_type: msg_type::IPMSG_RELEASEFILES,
+            content,
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding,
+        };
+
+        // Serialize and send via UDP
+        let msg_bytes = serialize_message(&proto_msg)?;
+        udp.send_to(&msg_bytes, self.manager.peer_addr(peer_ip))
+    }
+
+    /// Handle a NeoLan reply to one of our file offers
+    ///
+    /// The reply is matched to its upload task by the offer's packet ID (see
+    /// [`FileTransferManager::find_offer_upload`]). Accepted: the file is
+    /// pushed to the port in the reply on a worker thread. Rejected: the task
+    /// is cancelled. Replies carrying an offset resume an interrupted upload
+    /// instead (see [`Self::handle_resume`]).
+    ///
+    /// # Arguments
+    /// * `response` - The reply
+    /// * `sender` - Address of the peer (the port is replaced by the reply's)
+    ///
+    /// # Returns
+    /// * `Ok(Uuid)` - ID of the upload task
+    /// * `Err(NeoLanError)` - No offer to the peer is waiting for a reply
+    pub fn handle_reply(&self, response: &FileSendResponse, sender: SocketAddr) -> Result<Uuid> {
+        let peer_ip = canonical_ip(sender.ip());
+        if let Some(offset) = response.offset {
+            return self.handle_resume(response, offset, sender);
+        }
+
+        let mut task = self.manager.find_offer_upload(peer_ip, response.packet_id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!(
+                "No file offer to {} waiting for a reply (packet_id={:?})",
+                peer_ip, response.packet_id
+            ))
+        })?;
+
+        match (response.accept, response.port) {
+            (true, Some(port)) => {
+                task.mark_active(port);
+                if self.manager.update_running_task(task.clone())? {
+                    let mut addr = sender;
+                    addr.set_port(port);
+                    tracing::info!("File transfer ACCEPTED by {}: {} -> port {}", peer_ip, task.file_name, port);
+                    upload::spawn_upload(self.manager.clone(), task.clone(), addr, None);
+                }
+            }
+            (true, None) => {
+                task.mark_failed("Peer accepted without a port".to_string());
+                self.manager.update_running_task(task.clone())?;
+            }
+            (false, _) => {
+                tracing::info!("File transfer REJECTED by {}: {}", peer_ip, task.file_name);
+                self.manager.cancel_task(task.id)?;
+            }
+        }
+
+        Ok(task.id)
+    }
+
+    /// Resume an upload the receiver has part of
+    ///
+    /// The upload must not be running any more: while its old connection
+    /// hasn't failed yet on our side, the request is refused and the receiver
+    /// asks again. The request for an upload we paused is kept until the
+    /// upload is resumed (see [`Self::resume`]).
+    fn handle_resume(&self, response: &FileSendResponse, offset: u64, sender: SocketAddr) -> Result<Uuid> {
+        let peer_ip = canonical_ip(sender.ip());
+        let (Some(packet_id), Some(port), Some(prefix_md5)) =
+            (response.packet_id, response.port, response.prefix_md5.clone())
+        else {
+            return Err(NeoLanError::Protocol(format!(
+                "Incomplete resume request from {}: {:?}",
+                peer_ip, response
+            )));
+        };
+
+        let mut task = self.manager.find_resumable_upload(peer_ip, packet_id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!(
+                "No upload to {} to resume (packet_id={})",
+                peer_ip, packet_id
+            ))
+        })?;
+        if self.manager.is_transferring(task.id) {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Upload of {} to {} is still running",
+                task.file_name, peer_ip
+            )));
+        }
+
+        let mut addr = sender;
+        addr.set_port(port);
+        let resume = ResumePoint { offset, prefix_md5 };
+        if task.status == TransferStatus::Paused {
+            tracing::info!(
+                "{} asks to resume paused upload {} from byte {}, kept until it is resumed",
+                peer_ip,
+                task.file_name,
+                offset
+            );
+            self.lock_parked().insert(task.id, (addr, resume));
+            return Ok(task.id);
+        }
+
+        task.mark_active(port);
+        self.manager.update_task(task.clone())?;
+        tracing::info!(
+            "File transfer RESUMED by {}: {} from byte {} -> port {}",
+            peer_ip,
+            task.file_name,
+            offset,
+            port
+        );
+        upload::spawn_upload(self.manager.clone(), task.clone(), addr, Some(resume));
+
+        Ok(task.id)
+    }
+
+    /// Continue a paused transfer, or resume an interrupted download
+    ///
+    /// The sender of a download is asked for the rest of the partial file
+    /// on a worker thread (hashing the part we have may take a while).
+    /// Uploads continue when their receiver asks for the rest: at once if it
+    /// did while the upload was paused, else at its next request (failed
+    /// uploads need no resuming on our side).
+    ///
+    /// # Arguments
+    /// * `task_id` - ID of the paused transfer or paused or failed download task
+    /// * `tcp_port` - Port to listen on for a NeoLan sender (None: a free
+    ///   port in the configured range); unused for uploads and IPMsg attachments
+    ///
+    /// # Returns
+    /// * `Ok(())` - Transfer resuming
+    /// * `Err(NeoLanError)` - Unknown task, not paused (or failed, for a
+    ///   download), or the partial file is gone
+    pub fn resume(self: &Arc<Self>, task_id: Uuid, tcp_port: Option<u16>) -> Result<()> {
+        let mut task = self.manager.get_task(task_id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!("Task not found: {}", task_id))
+        })?;
+
+        if task.direction != TransferDirection::Download {
+            return self.resume_upload(task);
+        }
+        if !matches!(task.status, TransferStatus::Paused | TransferStatus::Failed) {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Transfer is {}, not paused or failed: {}",
+                task.status.as_str(),
+                task_id
+            )));
+        }
+        if task.packet_id.is_none() || !task.file_path.is_file() {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Nothing to resume {} from: {}",
+                task.file_name,
+                task.file_path.display()
+            )));
+        }
+
+        task.resume();
+        self.manager.update_task(task.clone())?;
+        tracing::info!("Resuming download of {} from {} (task {})", task.file_name, task.peer_ip, task_id);
+
+        let this = self.clone();
+        thread::spawn(move || this.run_download(task, DownloadSource::Resume { tcp_port }));
+
+        Ok(())
+    }
+
+    /// Pause an active transfer
+    ///
+    /// Its transfer loop stops and the connection is closed (see
+    /// [`FileTransferManager::pause_task`]); a download not connected yet
+    /// stops waiting for the sender. The transfer is resumed from the
+    /// offset reached (see [`Self::resume`]).
+    ///
+    /// # Arguments
+    /// * `task_id` - ID of the transfer task
+    ///
+    /// # Returns
+    /// * `Ok(())` - Task paused
+    /// * `Err(NeoLanError)` - Unknown task, or not active
+    pub fn pause(&self, task_id: Uuid) -> Result<()> {
+        self.manager.pause_task(task_id)
+    }
+
+    /// Continue a paused upload (see [`Self::resume`])
+    fn resume_upload(&self, mut task: TransferTask) -> Result<()> {
+        if task.status != TransferStatus::Paused {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Upload is {}, not paused (the receiver asks for the rest): {}",
+                task.status.as_str(),
+                task.id
+            )));
+        }
+
+        task.resume();
+        let Some((addr, resume)) = self.lock_parked().remove(&task.id) else {
+            self.manager.update_task(task.clone())?;
+            tracing::info!("Upload of {} resumed, waiting for {} to ask for the rest", task.file_name, task.peer_ip);
+            return Ok(());
+        };
+
+        task.mark_active(addr.port());
+        self.manager.update_task(task.clone())?;
+        tracing::info!(
+            "Resuming upload of {} to {} from byte {}",
+            task.file_name,
+            addr,
+            resume.offset
+        );
+        upload::spawn_upload(self.manager.clone(), task, addr, Some(resume));
+        Ok(())
+    }
+
+    /// Handle an IPMSG_RELEASEFILES notification
+    ///
+    /// Standard IPMsg peers send the packet ID of our attachment message when
+    /// they decline (or are done with) the offered files. Pending upload tasks
+    /// for that packet are cancelled.
+    ///
+    /// # Arguments
+    /// * `packet_id` - Packet ID of the released attachment message
+    /// * `sender_ip` - Sender's IP address
+    ///
+    /// # Returns
+    /// * `usize` - Number of upload tasks released
+    pub fn handle_release(&self, packet_id: u64, sender_ip: IpAddr) -> usize {
+        self.manager.release_attachments(sender_ip, packet_id)
+    }
+
+    /// Close every unfinished transfer (at shutdown)
+    ///
+    /// See [`FileTransferManager::release_all`]; the partial files of
+    /// paused downloads are kept.
+    pub fn release_all(&self) -> usize {
+        self.manager.release_all()
+    }
+
     /// Create a download task when request is accepted
     ///
     /// # Arguments
@@ -199,13 +695,17 @@ impl FileTransferResponse {
     /// # Returns
     /// * `Uuid` - Task ID for tracking
     pub fn create_download_task(&self, request: &PendingRequest) -> Uuid {
-        let task = TransferTask::new_download(
+        let mut task = TransferTask::new_download(
             request.sender_ip,
             request.file_name.clone(),
             request.file_size,
             request.md5.clone(),
         );
 
+        if let Some(file_id) = request.file_id {
+            task = task.with_attachment(request.packet_id, file_id);
+        }
+
         let task_id = task.id;
 
         // Add to manager
@@ -234,17 +734,467 @@ impl FileTransferResponse {
             created_at: request.created_at.timestamp(),
         }
     }
+
+    /// Set up the download of an accepted request and start its worker
+    fn start_download(self: &Arc<Self>, request: &PendingRequest, save_dir: &Path, tcp_port: Option<u16>) -> Result<Uuid> {
+        let file_path = Self::reserve_save_path(save_dir, &request.file_name)?;
+        let mut task = TransferTask::new_download(
+            request.sender_ip,
+            request.file_name.clone(),
+            request.file_size,
+            request.md5.clone(),
+        );
+        task.file_path = file_path.clone();
+
+        let source = match request.file_id {
+            Some(file_id) => {
+                // IPMsg serves file data on the TCP port of the same number as its UDP port
+                let addr = self.manager.peer_addr(request.sender_ip);
+                task = task.with_attachment(request.packet_id, file_id);
+                task.mark_active(addr.port());
+                self.file_data_request(request.packet_id, file_id, 0, request.encoding)
+                    .map(|packet| DownloadSource::Fetch {
+                        addr,
+                        packet,
+                        offset: 0,
+                    })
+            }
+            None => {
+                task = task.with_offer(request.packet_id);
+                match tcp_port {
+                    Some(port) => TcpTransport::bind(port).map(|listener| (listener, port)),
+                    None => TcpTransport::bind_available(),
+                }
+                .and_then(|(listener, port)| {
+                    self.send_response(request, true, Some(port), self.manager.udp())?;
+                    task.mark_active(port);
+                    Ok(DownloadSource::Listen { listener, offset: None })
+                })
+            }
+        };
+        let source = match source.and_then(|source| self.manager.add_task(task.clone()).map(|_| source)) {
+            Ok(source) => source,
+            Err(e) => {
+                let _ = std::fs::remove_file(&file_path);
+                return Err(e);
+            }
+        };
+
+        let task_id = task.id;
+        tracing::info!(
+            "Downloading {} ({} bytes) from {} to {} (task {})",
+            request.file_name,
+            request.file_size,
+            request.sender_ip,
+            file_path.display(),
+            task_id
+        );
+
+        let this = self.clone();
+        thread::spawn(move || this.run_download(task, source));
+
+        Ok(task_id)
+    }
+
+    /// Ask the sender for the rest of a download's partial file
+    ///
+    /// IPMsg attachments are fetched from the partial file's length. NeoLan
+    /// senders get an accepting reply naming that offset and the MD5 of the
+    /// bytes before it; they start over if their file differs. The task is
+    /// marked active on the new port.
+    fn resume_source(&self, task: &mut TransferTask, tcp_port: Option<u16>) -> Result<DownloadSource> {
+        let packet_id = task.packet_id.ok_or_else(|| {
+            NeoLanError::FileTransfer(format!("Offer of download {} is unknown", task.id))
+        })?;
+        let offset = std::fs::metadata(&task.file_path)
+            .map_err(|e| {
+                NeoLanError::FileTransfer(format!("Partial file {} is gone: {}", task.file_path.display(), e))
+            })?
+            .len()
+            .min(task.file_size);
+
+        if let Some(file_id) = task.file_id {
+            let packet = self.file_data_request(packet_id, file_id, offset, None)?;
+            let addr = self.manager.peer_addr(task.peer_ip);
+            task.mark_active(addr.port());
+            return Ok(DownloadSource::Fetch {
+                addr,
+                packet,
+                offset,
+            });
+        }
+
+        let prefix_md5 = hash::calculate_prefix_md5(&task.file_path, offset)?;
+        let (listener, port) = match tcp_port {
+            Some(port) => TcpTransport::bind(port).map(|listener| (listener, port)),
+            None => TcpTransport::bind_available(),
+        }?;
+
+        let response = FileSendResponse {
+            accept: true,
+            port: Some(port),
+            packet_id: Some(packet_id),
+            offset: Some(offset),
+            prefix_md5: Some(prefix_md5),
+        };
+        let content = serde_json::to_string(&response).map_err(|e| {
+            NeoLanError::FileTransfer(format!("Failed to serialize response: {}", e))
+        })?;
+        self.send_release_files(task.peer_ip, content, None, self.manager.udp())?;
+
+        tracing::info!(
+            "Asked {} to resume {} from byte {} (port {})",
+            task.peer_ip,
+            task.file_name,
+            offset,
+            port
+        );
+        task.mark_active(port);
+        Ok(DownloadSource::Listen { listener, offset: Some(offset) })
+    }
+
+    /// IPMSG_GETFILEDATA packet asking the sender for an attachment from
+    /// `offset` (sent over TCP)
+    fn file_data_request(
+        &self,
+        packet_id: u64,
+        file_id: u64,
+        offset: u64,
+        encoding: Option<TextEncoding>,
+    ) -> Result<Vec<u8>> {
+        let data_request = FileDataRequest {
+            packet_id,
+            file_id,
+            offset,
+        };
+
+        serialize_message(&ProtocolMessage {
+            version: PROTOCOL_VERSION,
+            packet_id: next_packet_id(),
+            sender_name: self.username.clone(),
+            sender_host: self.hostname.clone(),
+            msg_type: msg_type::IPMSG_GETFILEDATA,
+            content: data_request.encode(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding,
+        })
+    }
+
+    /// Create an empty file for a download in `save_dir`
+    ///
+    /// The offered name is cut down to its last path component, so a peer
+    /// can't write outside `save_dir`. Existing files are never overwritten:
+    /// `name (1).ext`, `name (2).ext`, ... are tried instead.
+    fn reserve_save_path(save_dir: &Path, file_name: &str) -> Result<PathBuf> {
+        std::fs::create_dir_all(save_dir).map_err(|e| {
+            NeoLanError::FileTransfer(format!("Failed to create {}: {}", save_dir.display(), e))
+        })?;
+
+        let name = file_name
+            .rsplit(['/', '\\'])
+            .next()
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .unwrap_or("unnamed");
+        let (stem, extension) = match name.rfind('.') {
+            Some(dot) if dot > 0 => name.split_at(dot),
+            _ => (name, ""),
+        };
+
+        for attempt in 0..MAX_NAME_ATTEMPTS {
+            let path = if attempt == 0 {
+                save_dir.join(name)
+            } else {
+                save_dir.join(format!("{} ({}){}", stem, attempt, extension))
+            };
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(path),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(NeoLanError::FileTransfer(format!(
+                        "Failed to create {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+
+        Err(NeoLanError::FileTransfer(format!(
+            "Too many files named {} in {}",
+            name,
+            save_dir.display()
+        )))
+    }
+
+    /// Receive the file of a download task (worker thread)
+    ///
+    /// # Process
+    /// 1. Get a connection to the sender (see [`DownloadSource`])
+    /// 2. Receive the file, publishing progress every PROGRESS_UPDATE_BYTES
+    ///    (pausing or cancelling the download closes the connection)
+    /// 3. When the connection drops otherwise, resume from the end of the partial file
+    ///    after RESUME_DELAY (up to MAX_RESUME_ATTEMPTS times in a row
+    ///    without receiving anything)
+    /// 4. Check the MD5, when the offer carried one
+    /// 5. Mark the task completed or failed (keeping the partial file to
+    ///    resume later); if it was cancelled meanwhile, remove the partial
+    ///    file instead, if it was paused (stopping the loop) keep it
+    fn run_download(&self, mut task: TransferTask, mut source: DownloadSource) {
+        let task_id = task.id;
+        let control = self.manager.register_control(task_id);
+        let mut attempts = 0;
+
+        let result = loop {
+            let before = task.transferred_bytes;
+            let error = match self.receive(&mut task, source, &control) {
+                Ok(end) if end == task.file_size => break Self::verify_download(&task, end),
+                Ok(end) => NeoLanError::FileTransfer(format!(
+                    "Connection closed after {} of {} bytes",
+                    end, task.file_size
+                )),
+                Err(e) => e,
+            };
+
+            attempts = if task.transferred_bytes > before { 1 } else { attempts + 1 };
+            if attempts > MAX_RESUME_ATTEMPTS || !self.is_running(task_id) {
+                break Err(error);
+            }
+            tracing::warn!(
+                "Download of {} interrupted at byte {} ({}), resuming in {}s",
+                task.file_name,
+                task.transferred_bytes,
+                error,
+                RESUME_DELAY.as_secs()
+            );
+            thread::sleep(RESUME_DELAY);
+            if !self.is_running(task_id) {
+                break Err(error);
+            }
+            source = DownloadSource::Resume { tcp_port: None };
+        };
+        self.manager.unregister_control(task_id, &control);
+
+        if result.is_err() && control.signal() == TransferSignal::Pause {
+            tracing::info!("Download paused: {} <- {}", task.file_name, task.peer_ip);
+            return;
+        }
+        match result {
+            Ok(()) => task.mark_completed(),
+            Err(e) => task.mark_failed(e.to_string()),
+        }
+
+        match self.manager.update_running_task(task.clone()) {
+            Ok(true) if task.error.is_none() => {
+                tracing::info!("✅ Download completed: {} <- {}", task.file_name, task.peer_ip);
+            }
+            Ok(true) => {
+                tracing::warn!(
+                    "Download of {} from {} failed: {}",
+                    task.file_name,
+                    task.peer_ip,
+                    task.error.as_deref().unwrap_or_default()
+                );
+            }
+            Ok(false) => {
+                tracing::info!("Download cancelled: {} <- {}", task.file_name, task.peer_ip);
+                let _ = std::fs::remove_file(&task.file_path);
+            }
+            Err(e) => tracing::warn!("Failed to update download task {}: {}", task_id, e),
+        }
+    }
+
+    /// Receive (the rest of) the file over one connection
+    ///
+    /// The connection is attached to the control handle, which closes it
+    /// when the download is paused or cancelled.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - Position in the file the data ended at
+    /// * `Err(NeoLanError)` - No connection, or it failed
+    fn receive(&self, task: &mut TransferTask, source: DownloadSource, control: &TransferControl) -> Result<u64> {
+        let source = match source {
+            DownloadSource::Resume { tcp_port } => {
+                let source = self.resume_source(task, tcp_port)?;
+                if !self.manager.update_running_task(task.clone())? {
+                    return Err(NeoLanError::FileTransfer("Download stopped".to_string()));
+                }
+                source
+            }
+            source => source,
+        };
+
+        let (stream, start) = Self::connect_source(&self.manager, task, source)?;
+        control.attach(&stream)?;
+
+        let file_path = task.file_path.clone();
+        let file_size = task.file_size;
+        task.update_progress(start);
+        let mut last_reported = start;
+        let result = TcpTransport::receive_file_from_offset(
+            stream,
+            &file_path,
+            start,
+            file_size,
+            Some(control),
+            Some(|position, _total| {
+                task.update_progress(position);
+                if position - last_reported >= PROGRESS_UPDATE_BYTES {
+                    last_reported = position;
+                    let _ = self.manager.update_running_task(task.clone());
+                }
+            }),
+        );
+
+        result.map(|received| start + received)
+    }
+
+    /// Open the data connection of a download
+    ///
+    /// # Returns
+    /// * `Ok((TcpStream, u64))` - The connection and the offset its data starts at
+    fn connect_source(manager: &FileTransferManager, task: &TransferTask, source: DownloadSource) -> Result<(TcpStream, u64)> {
+        match source {
+            DownloadSource::Listen { listener, offset } => {
+                let mut stream = Self::wait_for_sender(manager, task, listener)?;
+                TcpTransport::set_read_timeout(&stream, IO_TIMEOUT_SECS)?;
+
+                let Some(offset) = offset else {
+                    return Ok((stream, 0));
+                };
+                let mut header = [0u8; 8];
+                stream.read_exact(&mut header).map_err(|e| {
+                    NeoLanError::FileTransfer(format!("Failed to read resume offset: {}", e))
+                })?;
+                match u64::from_be_bytes(header) {
+                    start if start == 0 || start == offset => Ok((stream, start)),
+                    start => Err(NeoLanError::Protocol(format!(
+                        "Sender resumed from byte {}, asked for {}",
+                        start, offset
+                    ))),
+                }
+            }
+            DownloadSource::Fetch { addr, packet, offset } => {
+                let mut stream = TcpTransport::connect(addr)?;
+                stream.write_all(&packet).map_err(|e| {
+                    NeoLanError::FileTransfer(format!("Failed to send file data request: {}", e))
+                })?;
+                TcpTransport::set_read_timeout(&stream, IO_TIMEOUT_SECS)?;
+                Ok((stream, offset))
+            }
+            DownloadSource::Resume { .. } => Err(NeoLanError::FileTransfer(
+                "Download to resume has no connection yet".to_string(),
+            )),
+        }
+    }
+
+    /// Whether a task is still active (not paused, cancelled or finished)
+    fn is_running(&self, task_id: Uuid) -> bool {
+        self.manager.get_task(task_id).is_some_and(|task| task.is_active())
+    }
+
+    /// Wait for the NeoLan sender to connect to the port in our reply
+    ///
+    /// Connections from other hosts are refused. Gives up after
+    /// CONNECT_TIMEOUT_SECS, or as soon as the task is paused or cancelled.
+    fn wait_for_sender(manager: &FileTransferManager, task: &TransferTask, listener: TcpListener) -> Result<TcpStream> {
+        listener.set_nonblocking(true).map_err(NeoLanError::Network)?;
+        let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS);
+
+        loop {
+            match listener.accept() {
+                Ok((stream, addr)) if canonical_ip(addr.ip()) == task.peer_ip => {
+                    stream.set_nonblocking(false).map_err(NeoLanError::Network)?;
+                    return Ok(stream);
+                }
+                Ok((_, addr)) => {
+                    tracing::warn!("Refusing file data connection from {} (expected {})", addr, task.peer_ip);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(NeoLanError::Network(e)),
+            }
+
+            if !manager.get_task(task.id).is_some_and(|current| current.is_active()) {
+                return Err(NeoLanError::FileTransfer("Download stopped".to_string()));
+            }
+            if Instant::now() >= deadline {
+                return Err(NeoLanError::FileTransfer(format!(
+                    "{} did not connect within {}s",
+                    task.peer_ip, CONNECT_TIMEOUT_SECS
+                )));
+            }
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+    }
+
+    /// Check a received file against the offer
+    fn verify_download(task: &TransferTask, received: u64) -> Result<()> {
+        if received != task.file_size {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Connection closed after {} of {} bytes",
+                received, task.file_size
+            )));
+        }
+
+        if !task.md5.is_empty() {
+            let md5 = hash::calculate_file_md5(&task.file_path)?;
+            if !md5.eq_ignore_ascii_case(&task.md5) {
+                return Err(NeoLanError::FileTransfer(format!(
+                    "MD5 mismatch: expected {}, got {}",
+                    task.md5, md5
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether another file of an attachment message is still pending or
+    /// downloading (IPMSG_RELEASEFILES would release it too)
+    fn attachment_in_use(&self, peer_ip: IpAddr, packet_id: u64) -> bool {
+        let pending = self.lock_pending().values().any(|request| {
+            request.sender_ip == peer_ip && request.packet_id == packet_id && request.file_id.is_some()
+        });
+
+        pending
+            || self.manager.get_tasks_by_peer(peer_ip).iter().any(|task| {
+                task.direction == TransferDirection::Download
+                    && task.packet_id == Some(packet_id)
+                    && !task.is_finished()
+            })
+    }
+
+    /// Remove a pending request (it is being answered)
+    fn take_pending(&self, request_id: Uuid) -> Result<PendingRequest> {
+        self.lock_pending().remove(&request_id).ok_or_else(|| {
+            NeoLanError::FileTransfer(format!("No pending file transfer request: {}", request_id))
+        })
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, HashMap<Uuid, PendingRequest>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_parked(&self) -> MutexGuard<'_, HashMap<Uuid, (SocketAddr, ResumePoint)>> {
+        self.parked.lock().unwrap_or_else(|e| e.into_inner())
+    }
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use super::super::types::TransferDirection;
+    use super::super::types::{TransferDirection, TransferStatus};
+    use crate::network::DEFAULT_UDP_PORT;
+    use md5::Digest;
     use std::net::Ipv4Addr;
 
     #[test]
     fn test_handle_incoming_request() {
-        let udp = Arc::new(crate::network::UdpTransport::bind(0).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
         let manager = Arc::new(FileTransferManager::new(
             udp.clone(),
             "TestUser".to_string(),
@@ -271,24 +1221,84 @@ mod tests {
             sender_host: "alice-pc".to_string(),
             msg_type: msg_type::IPMSG_GETFILEDATA,
             content: serde_json::to_string(&file_request).unwrap(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let sender_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
 
         // Handle request
-        let result = handler.handle_incoming_request(&proto_msg, sender_ip);
+        let pending = handler.handle_incoming_request(&proto_msg, &file_request, sender_ip);
 
-        assert!(result.is_ok());
-        let pending = result.unwrap();
         assert_eq!(pending.file_name, "test.txt");
         assert_eq!(pending.file_size, 1024);
         assert_eq!(pending.sender_ip, sender_ip);
         assert_eq!(pending.sender_name, "Alice");
     }
 
+    #[test]
+    fn test_handle_incoming_attachments() {
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
+        let manager = Arc::new(FileTransferManager::new(
+            udp,
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+
+        let handler = FileTransferResponse::new(
+            manager.clone(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+
+        let attachments = vec![
+            crate::network::FileAttachment::regular(0, "a.txt".to_string(), 100, 0x65000000),
+            crate::network::FileAttachment {
+                file_id: 1,
+                name: "photos".to_string(),
+                size: 0,
+                mtime: 0x65000000,
+                attr: msg_type::IPMSG_FILE_DIR,
+            },
+            crate::network::FileAttachment::regular(2, "b.bin".to_string(), 4096, 0x65000000),
+        ];
+
+        let proto_msg = ProtocolMessage {
+            version: 1,
+            packet_id: 777,
+            sender_name: "Alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: msg_type::make_command(msg_type::IPMSG_SENDMSG, msg_type::IPMSG_FILEATTACHOPT),
+            content: String::new(),
+            extensions: vec![crate::network::encode_attachments(&attachments)],
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+
+        let sender_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        let pending = handler.handle_incoming_attachments(&proto_msg, attachments, sender_ip);
+
+        // Directory entry is skipped
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].file_name, "a.txt");
+        assert_eq!(pending[0].file_id, Some(0));
+        assert_eq!(pending[1].file_name, "b.bin");
+        assert_eq!(pending[1].file_id, Some(2));
+        assert_eq!(pending[1].packet_id, 777);
+
+        // Download task keeps the attachment identifiers
+        let task_id = handler.create_download_task(&pending[1]);
+        let task = manager.get_task(task_id).unwrap();
+        assert_eq!(task.packet_id, Some(777));
+        assert_eq!(task.file_id, Some(2));
+    }
+
     #[test]
     fn test_send_accept_response() {
-        let udp = Arc::new(crate::network::UdpTransport::bind(0).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
         let manager = Arc::new(FileTransferManager::new(
             udp.clone(),
             "TestUser".to_string(),
@@ -308,6 +1318,9 @@ mod tests {
             file_name: "test.txt".to_string(),
             file_size: 1024,
             md5: "abc123".to_string(),
+            packet_id: 1,
+            file_id: None,
+            encoding: None,
             created_at: Utc::now(),
         };
 
@@ -320,7 +1333,7 @@ mod tests {
 
     #[test]
     fn test_send_reject_response() {
-        let udp = Arc::new(crate::network::UdpTransport::bind(0).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
         let manager = Arc::new(FileTransferManager::new(
             udp.clone(),
             "TestUser".to_string(),
@@ -340,6 +1353,9 @@ mod tests {
             file_name: "test.txt".to_string(),
             file_size: 1024,
             md5: "abc123".to_string(),
+            packet_id: 1,
+            file_id: None,
+            encoding: None,
             created_at: Utc::now(),
         };
 
@@ -352,7 +1368,7 @@ mod tests {
 
     #[test]
     fn test_to_event() {
-        let udp = Arc::new(crate::network::UdpTransport::bind(0).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
         let manager = Arc::new(FileTransferManager::new(
             udp,
             "TestUser".to_string(),
@@ -372,6 +1388,9 @@ mod tests {
             file_name: "test.txt".to_string(),
             file_size: 1024,
             md5: "abc123".to_string(),
+            packet_id: 1,
+            file_id: None,
+            encoding: None,
             created_at: Utc::now(),
         };
 
@@ -400,7 +1419,7 @@ mod tests {
 
     #[test]
     fn test_create_download_task() {
-        let udp = Arc::new(crate::network::UdpTransport::bind(0).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().sender();
         let manager = Arc::new(FileTransferManager::new(
             udp,
             "TestUser".to_string(),
@@ -420,6 +1439,9 @@ mod tests {
             file_name: "test.txt".to_string(),
             file_size: 1024,
             md5: "abc123".to_string(),
+            packet_id: 1,
+            file_id: None,
+            encoding: None,
             created_at: Utc::now(),
         };
 
@@ -432,4 +1454,482 @@ mod tests {
         assert_eq!(task.direction, TransferDirection::Download);
         assert_eq!(task.file_name, "test.txt");
     }
+
+    /// Handler whose UDP packets are captured (not sent) to `capture_path`
+    fn capturing_handler(capture_path: &Path) -> (Arc<FileTransferResponse>, Arc<FileTransferManager>) {
+        let capture = Arc::new(crate::network::PacketCapture::create(capture_path).unwrap());
+        let udp = crate::network::UdpTransport::bind(0).unwrap().dry_run().with_capture(capture);
+        let manager = Arc::new(FileTransferManager::new(
+            udp.sender(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let handler = FileTransferResponse::new(manager.clone(), "TestUser".to_string(), "test-host".to_string());
+        (Arc::new(handler), manager)
+    }
+
+    fn offer(handler: &FileTransferResponse, name: &str, data: &[u8]) -> PendingRequest {
+        let file_request = FileSendRequest {
+            name: name.to_string(),
+            size: data.len() as u64,
+            md5: format!("{:x}", md5::Md5::digest(data)),
+        };
+        let proto_msg = ProtocolMessage {
+            version: 1,
+            packet_id: 42,
+            sender_name: "Alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: msg_type::IPMSG_GETFILEDATA,
+            content: serde_json::to_string(&file_request).unwrap(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+        handler.handle_incoming_request(&proto_msg, &file_request, IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+
+    fn wait_until_finished(manager: &FileTransferManager, task_id: Uuid) -> TransferTask {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let task = manager.get_task(task_id).unwrap();
+            if task.is_finished() || Instant::now() >= deadline {
+                return task;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_accept_receives_file() {
+        let dir = std::env::temp_dir().join(format!("neolan_accept_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        let data = b"Hello, File Transfer!".repeat(100);
+
+        let request = offer(&handler, "hello.txt", &data);
+        assert_eq!(handler.pending_requests().len(), 1);
+        let task_id = handler.accept(request.id, &dir, None).unwrap();
+        assert!(handler.pending_requests().is_empty());
+        assert!(handler.accept(request.id, &dir, None).is_err());
+
+        // The reply tells the sender where to connect
+        let records = crate::network::read_capture(&capture_path).unwrap();
+        let reply = crate::network::parse_message(&records[0].data).unwrap();
+        let response: FileSendResponse = serde_json::from_str(&reply.content).unwrap();
+        let task = manager.get_task(task_id).unwrap();
+        assert!(response.accept);
+        assert_eq!(response.port, task.port);
+
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, task.port.unwrap())).unwrap();
+        stream.write_all(&data).unwrap();
+        drop(stream);
+
+        let task = wait_until_finished(&manager, task_id);
+        assert_eq!(task.status, TransferStatus::Completed, "{:?}", task.error);
+        assert_eq!(task.file_path, dir.join("hello.txt"));
+        assert_eq!(std::fs::read(&task.file_path).unwrap(), data);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    #[test]
+    fn test_reject_and_cancel_notify_sender() {
+        let dir = std::env::temp_dir().join(format!("neolan_cancel_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+
+        assert!(handler.reject(Uuid::new_v4()).is_err());
+        let rejected = offer(&handler, "no.txt", b"no");
+        handler.reject(rejected.id).unwrap();
+        assert!(handler.pending_requests().is_empty());
+
+        // Cancelled while waiting for the sender to connect
+        let accepted = offer(&handler, "later.txt", b"later");
+        let task_id = handler.accept(accepted.id, &dir, None).unwrap();
+        handler.cancel(task_id).unwrap();
+        assert!(handler.cancel(task_id).is_err());
+
+        let task = wait_until_finished(&manager, task_id);
+        assert_eq!(task.status, TransferStatus::Cancelled);
+        thread::sleep(ACCEPT_POLL_INTERVAL * 3);
+        assert!(!task.file_path.exists());
+
+        // Reject, accept, cancel: the last reply turns the sender down again
+        let records = crate::network::read_capture(&capture_path).unwrap();
+        assert_eq!(records.len(), 3);
+        let replies: Vec<FileSendResponse> = records
+            .iter()
+            .map(|record| serde_json::from_str(&crate::network::parse_message(&record.data).unwrap().content).unwrap())
+            .collect();
+        assert!(!replies[0].accept);
+        assert!(replies[1].accept);
+        assert!(!replies[2].accept);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    #[test]
+    fn test_reserve_save_path() {
+        let dir = std::env::temp_dir().join(format!("neolan_save_{}", Uuid::new_v4()));
+
+        let first = FileTransferResponse::reserve_save_path(&dir, "report.pdf").unwrap();
+        let second = FileTransferResponse::reserve_save_path(&dir, "report.pdf").unwrap();
+        assert_eq!(first, dir.join("report.pdf"));
+        assert_eq!(second, dir.join("report (1).pdf"));
+
+        // Offered names can't leave the directory
+        let escaped = FileTransferResponse::reserve_save_path(&dir, "../../etc/passwd").unwrap();
+        assert_eq!(escaped, dir.join("passwd"));
+        let windows = FileTransferResponse::reserve_save_path(&dir, "C:\\Users\\bob\\notes.txt").unwrap();
+        assert_eq!(windows, dir.join("notes.txt"));
+        assert_eq!(FileTransferResponse::reserve_save_path(&dir, "..").unwrap(), dir.join("unnamed"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reply_drives_upload() {
+        let dir = std::env::temp_dir().join(format!("neolan_upload_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("up.bin");
+        let data = vec![7u8; 3000];
+        std::fs::write(&path, &data).unwrap();
+
+        let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let upload = |packet_id| {
+            let task = TransferTask::new_upload(peer, path.clone(), "up.bin".to_string(), 3000, String::new())
+                .with_offer(packet_id);
+            manager.add_task(task.clone()).unwrap();
+            task.id
+        };
+        let accepted = upload(10);
+        let rejected = upload(11);
+        let sender = SocketAddr::new(peer, DEFAULT_UDP_PORT);
+
+        // The receiver listens; its reply names the offer and the port
+        let receiver = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let reply = FileSendResponse {
+            accept: true,
+            port: Some(port),
+            packet_id: Some(10),
+            offset: None,
+            prefix_md5: None,
+        };
+        assert_eq!(handler.handle_reply(&reply, sender).unwrap(), accepted);
+
+        let (mut stream, _) = receiver.accept().unwrap();
+        let mut received = Vec::new();
+        std::io::Read::read_to_end(&mut stream, &mut received).unwrap();
+        assert_eq!(received, data);
+        assert_eq!(wait_until_finished(&manager, accepted).status, TransferStatus::Completed);
+
+        let reply = FileSendResponse {
+            accept: false,
+            port: None,
+            packet_id: Some(11),
+            offset: None,
+            prefix_md5: None,
+        };
+        assert_eq!(handler.handle_reply(&reply, sender).unwrap(), rejected);
+        assert_eq!(manager.get_task(rejected).unwrap().status, TransferStatus::Cancelled);
+
+        // Nothing left to answer
+        assert!(handler.handle_reply(&reply, sender).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    fn md5_of(data: &[u8]) -> String {
+        format!("{:x}", md5::Md5::digest(data))
+    }
+
+    /// Wait for the `index`th captured packet and decode it as a reply
+    fn captured_reply(capture_path: &Path, index: usize) -> FileSendResponse {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let records = crate::network::read_capture(capture_path).unwrap();
+            if let Some(record) = records.get(index) {
+                return serde_json::from_str(&crate::network::parse_message(&record.data).unwrap().content).unwrap();
+            }
+            assert!(Instant::now() < deadline, "no packet {} captured", index);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Act as the NeoLan sender answering a resume request
+    fn send_rest(request: &FileSendResponse, data: &[u8]) {
+        let offset = request.offset.unwrap();
+        assert_eq!(request.prefix_md5.as_deref(), Some(md5_of(&data[..offset as usize]).as_str()));
+
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, request.port.unwrap())).unwrap();
+        stream.write_all(&offset.to_be_bytes()).unwrap();
+        stream.write_all(&data[offset as usize..]).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_download_resumes() {
+        let dir = std::env::temp_dir().join(format!("neolan_resume_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+
+        let request = offer(&handler, "image.vmdk", &data);
+        let task_id = handler.accept(request.id, &dir, None).unwrap();
+        let port = manager.get_task(task_id).unwrap().port.unwrap();
+
+        // The connection drops after 1000 bytes
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(&data[..1000]).unwrap();
+        drop(stream);
+
+        // The receiver asks for the rest, naming what it has
+        let resume = captured_reply(&capture_path, 1);
+        assert!(resume.accept);
+        assert_eq!(resume.packet_id, Some(42));
+        assert_eq!(resume.offset, Some(1000));
+        send_rest(&resume, &data);
+
+        let task = wait_until_finished(&manager, task_id);
+        assert_eq!(task.status, TransferStatus::Completed, "{:?}", task.error);
+        assert_eq!(std::fs::read(&task.file_path).unwrap(), data);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    #[test]
+    fn test_paused_download_resumed() {
+        let dir = std::env::temp_dir().join(format!("neolan_paused_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        let data: Vec<u8> = (0..(PROGRESS_UPDATE_BYTES as u32 * 2)).map(|i| (i % 253) as u8).collect();
+        let half = PROGRESS_UPDATE_BYTES as usize + 4096;
+
+        let request = offer(&handler, "big.iso", &data);
+        let task_id = handler.accept(request.id, &dir, None).unwrap();
+        let port = manager.get_task(task_id).unwrap().port.unwrap();
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(&data[..half]).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.get_task(task_id).unwrap().transferred_bytes == 0 {
+            assert!(Instant::now() < deadline, "no progress reported");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // Closed at shutdown: the download is paused and its partial file kept
+        assert_eq!(handler.release_all(), 1);
+        let task = manager.get_task(task_id).unwrap();
+        assert_eq!(task.status, TransferStatus::Paused);
+        thread::sleep(ACCEPT_POLL_INTERVAL * 3);
+        assert!(task.file_path.is_file());
+        drop(stream);
+
+        handler.resume(task_id, None).unwrap();
+        assert!(handler.resume(task_id, None).is_err());
+        let resume = captured_reply(&capture_path, 1);
+        assert!(resume.offset.unwrap() >= PROGRESS_UPDATE_BYTES);
+        send_rest(&resume, &data);
+
+        let task = wait_until_finished(&manager, task_id);
+        assert_eq!(task.status, TransferStatus::Completed, "{:?}", task.error);
+        assert_eq!(std::fs::read(&task.file_path).unwrap(), data);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    #[test]
+    fn test_pause_outlasts_io_timeout() {
+        let dir = std::env::temp_dir().join(format!("neolan_pause_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        let data: Vec<u8> = (0..(PROGRESS_UPDATE_BYTES as u32 * 2)).map(|i| (i % 253) as u8).collect();
+        let half = PROGRESS_UPDATE_BYTES as usize + 4096;
+
+        let request = offer(&handler, "held.iso", &data);
+        let task_id = handler.accept(request.id, &dir, None).unwrap();
+        let port = manager.get_task(task_id).unwrap().port.unwrap();
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(&data[..half]).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.get_task(task_id).unwrap().transferred_bytes == 0 {
+            assert!(Instant::now() < deadline, "no progress reported");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // Paused: the connection is closed rather than left to time out
+        handler.pause(task_id).unwrap();
+        assert!(handler.pause(task_id).is_err());
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buffer = [0u8; 16];
+        assert!(stream.read(&mut buffer).map_or(true, |n| n == 0));
+        drop(stream);
+
+        thread::sleep(Duration::from_secs(IO_TIMEOUT_SECS + 1));
+        let task = manager.get_task(task_id).unwrap();
+        assert_eq!(task.status, TransferStatus::Paused);
+        assert!(task.file_path.is_file());
+        assert_eq!(crate::network::read_capture(&capture_path).unwrap().len(), 1);
+
+        // Resumed from the partial file
+        handler.resume(task_id, None).unwrap();
+        let resume = captured_reply(&capture_path, 1);
+        assert!(resume.offset.unwrap() >= PROGRESS_UPDATE_BYTES);
+        send_rest(&resume, &data);
+
+        let task = wait_until_finished(&manager, task_id);
+        assert_eq!(task.status, TransferStatus::Completed, "{:?}", task.error);
+        assert_eq!(std::fs::read(&task.file_path).unwrap(), data);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    /// Read until the peer closes the connection (or resets it)
+    fn read_until_closed(stream: &mut TcpStream) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 64 * 1024];
+        while let Ok(n @ 1..) = stream.read(&mut buffer) {
+            received.extend_from_slice(&buffer[..n]);
+        }
+        received
+    }
+
+    #[test]
+    fn test_paused_upload_resumed_by_receiver() {
+        let dir = std::env::temp_dir().join(format!("neolan_pause_upload_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("big.bin");
+        let data: Vec<u8> = (0..32 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let sender = SocketAddr::new(peer, DEFAULT_UDP_PORT);
+        let task = TransferTask::new_upload(peer, path.clone(), "big.bin".to_string(), data.len() as u64, String::new())
+            .with_offer(30);
+        manager.add_task(task.clone()).unwrap();
+
+        let receiver = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+        let reply = |offset: Option<u64>, prefix_md5: Option<String>| FileSendResponse {
+            accept: true,
+            port: Some(port),
+            packet_id: Some(30),
+            offset,
+            prefix_md5,
+        };
+        handler.handle_reply(&reply(None, None), sender).unwrap();
+
+        // The receiver stops reading: the sender blocks writing until paused
+        let (mut stream, _) = receiver.accept().unwrap();
+        let mut received = vec![0u8; 1000];
+        stream.read_exact(&mut received).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        handler.pause(task.id).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while manager.is_transferring(task.id) {
+            assert!(Instant::now() < deadline, "upload still blocked");
+            thread::sleep(Duration::from_millis(20));
+        }
+        received.extend(read_until_closed(&mut stream));
+        let offset = received.len() as u64;
+        assert!(offset < data.len() as u64);
+        assert_eq!(received[..], data[..received.len()]);
+
+        // Asking for the rest while paused: kept until resumed, past the I/O timeout
+        assert_eq!(
+            handler.handle_reply(&reply(Some(offset), Some(md5_of(&received))), sender).unwrap(),
+            task.id
+        );
+        thread::sleep(Duration::from_secs(IO_TIMEOUT_SECS + 1));
+        receiver.set_nonblocking(true).unwrap();
+        assert_eq!(receiver.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+        receiver.set_nonblocking(false).unwrap();
+        assert_eq!(manager.get_task(task.id).unwrap().status, TransferStatus::Paused);
+
+        handler.resume(task.id, None).unwrap();
+        assert!(handler.resume(task.id, None).is_err());
+        let (mut stream, _) = receiver.accept().unwrap();
+        let rest = read_until_closed(&mut stream);
+        assert_eq!(rest[..8], offset.to_be_bytes());
+        assert_eq!(rest[8..], data[offset as usize..]);
+        assert_eq!(wait_until_finished(&manager, task.id).status, TransferStatus::Completed);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
+
+    #[test]
+    fn test_resume_request_drives_upload() {
+        let dir = std::env::temp_dir().join(format!("neolan_reupload_{}", Uuid::new_v4()));
+        let capture_path = dir.with_extension("jsonl");
+        let (handler, manager) = capturing_handler(&capture_path);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("up.bin");
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let sender = SocketAddr::new(peer, DEFAULT_UDP_PORT);
+        let mut task = TransferTask::new_upload(peer, path.clone(), "up.bin".to_string(), 3000, String::new())
+            .with_offer(20);
+        task.mark_active(DEFAULT_UDP_PORT);
+        task.update_progress(1000);
+        manager.add_task(task.clone()).unwrap();
+        assert!(handler.resume(task.id, None).is_err());
+
+        let receiver = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let request = |offset: u64, prefix_md5: String| FileSendResponse {
+            accept: true,
+            port: Some(receiver.local_addr().unwrap().port()),
+            packet_id: Some(20),
+            offset: Some(offset),
+            prefix_md5: Some(prefix_md5),
+        };
+        let receive = || {
+            let (mut stream, _) = receiver.accept().unwrap();
+            let mut received = Vec::new();
+            std::io::Read::read_to_end(&mut stream, &mut received).unwrap();
+            received
+        };
+
+        // Refused while the old connection is still running
+        let running = manager.register_control(task.id);
+        assert!(handler.handle_reply(&request(1000, md5_of(&data[..1000])), sender).is_err());
+        manager.unregister_control(task.id, &running);
+        task.mark_failed("Connection reset".to_string());
+        manager.update_task(task.clone()).unwrap();
+
+        // Different bytes at the receiver: the sender starts over
+        assert_eq!(handler.handle_reply(&request(1000, md5_of(b"other")), sender).unwrap(), task.id);
+        let received = receive();
+        assert_eq!(received[..8], 0u64.to_be_bytes());
+        assert_eq!(&received[8..], &data[..]);
+        wait_until_finished(&manager, task.id);
+
+        // Failed again: this time the receiver's bytes match
+        manager.update_task(task.clone()).unwrap();
+        assert_eq!(handler.handle_reply(&request(1000, md5_of(&data[..1000])), sender).unwrap(), task.id);
+        let received = receive();
+        assert_eq!(received[..8], 1000u64.to_be_bytes());
+        assert_eq!(&received[8..], &data[1000..]);
+        assert_eq!(wait_until_finished(&manager, task.id).status, TransferStatus::Completed);
+
+        // Completed uploads aren't resumed
+        assert!(handler.handle_reply(&request(1000, md5_of(&data[..1000])), sender).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&capture_path).unwrap();
+    }
 }
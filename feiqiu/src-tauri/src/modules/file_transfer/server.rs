@@ -0,0 +1,473 @@
+// File data server - serves IPMsg GETFILEDATA requests over TCP
+//
+// In IPMsg, the receiver of a FILEATTACHOPT message opens a TCP connection to
+// the sender's IPMsg port (2425) and sends an IPMSG_GETFILEDATA packet whose
+// content is `packetID:fileID:offset`. The sender answers with the raw file
+// bytes from that offset and closes the connection.
+use crate::network::{canonical_ip, msg_type, parse_message, FileDataRequest, TcpTransport, TransferSignal};
+use crate::{NeoLanError, Result};
+use std::io::{ErrorKind, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::types::TransferStatus;
+use super::{FileTransferManager, IO_TIMEOUT_SECS, PROGRESS_UPDATE_BYTES};
+
+/// Maximum size of a GETFILEDATA request packet
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Wait for more of a request that parses but doesn't end with a separator
+const REQUEST_SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Most connections served at once (further ones are closed right away)
+const MAX_CONNECTIONS: usize = 32;
+
+/// Interval between checks for connections and for the stop flag
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// File data server
+///
+/// Accepts TCP connections on the IPMsg port and streams the requested
+/// attachment to the peer, updating the matching upload task.
+pub struct FileServer {
+    /// File transfer manager (for looking up upload tasks)
+    manager: Arc<FileTransferManager>,
+
+    /// Set to make `serve` return
+    stop: AtomicBool,
+
+    /// Connections being served
+    connections: Arc<AtomicUsize>,
+}
+
+impl FileServer {
+    /// Create a new file data server
+    ///
+    /// # Arguments
+    /// * `manager` - File transfer manager holding the upload tasks
+    pub fn new(manager: Arc<FileTransferManager>) -> Self {
+        Self {
+            manager,
+            stop: AtomicBool::new(false),
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Accept connections and serve file data requests (blocking until
+    /// [`Self::stop`] is called)
+    ///
+    /// Each connection is handled on its own thread, at most MAX_CONNECTIONS
+    /// at once. Connections being served when the server stops run on (they
+    /// are stopped with their transfers).
+    ///
+    /// # Arguments
+    /// * `listener` - TCP listener bound to the IPMsg port
+    pub fn serve(&self, listener: TcpListener) {
+        tracing::info!(
+            "📁 File data server listening on {:?}",
+            listener.local_addr().ok()
+        );
+        if let Err(e) = listener.set_nonblocking(true) {
+            tracing::error!("Failed to set up file data listener: {}", e);
+            return;
+        }
+
+        while !self.stop.load(Ordering::SeqCst) {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    if e.kind() != ErrorKind::WouldBlock {
+                        tracing::error!("Failed to accept file data connection: {}", e);
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+            };
+
+            if self.connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                tracing::warn!(
+                    "Refusing file data connection from {}: already serving {}",
+                    peer,
+                    MAX_CONNECTIONS
+                );
+                continue;
+            }
+            if let Err(e) = stream.set_nonblocking(false) {
+                tracing::warn!("Failed to set up file data connection from {}: {}", peer, e);
+                continue;
+            }
+
+            self.connections.fetch_add(1, Ordering::SeqCst);
+            let manager = self.manager.clone();
+            let connections = self.connections.clone();
+            thread::spawn(move || {
+                if let Err(e) = Self::handle_connection(&manager, stream) {
+                    tracing::warn!("File data request from {} failed: {}", peer, e);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+
+        tracing::info!("📁 File data server stopped");
+    }
+
+    /// Make [`Self::serve`] return (within ACCEPT_POLL_INTERVAL)
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Handle a single GETFILEDATA connection
+    ///
+    /// # Process
+    /// 1. Read and parse the IPMSG_GETFILEDATA packet
+    /// 2. Match `packetID:fileID` to an outstanding upload task for this peer
+    /// 3. Stream the file from the requested offset
+    /// 4. Mark the task completed or failed (a paused one is left paused)
+    fn handle_connection(manager: &FileTransferManager, mut stream: TcpStream) -> Result<()> {
+        let peer_addr = stream.peer_addr().map_err(NeoLanError::Network)?;
+        TcpTransport::set_read_timeout(&stream, IO_TIMEOUT_SECS)?;
+        TcpTransport::set_write_timeout(&stream, IO_TIMEOUT_SECS)?;
+
+        let request = Self::read_request(&mut stream)?;
+        // Match the canonical IPs the UDP side recorded on the task
+        let peer_ip = canonical_ip(peer_addr.ip());
+
+        tracing::info!(
+            "📥 GETFILEDATA from {}: packet_id={}, file_id={}, offset={}",
+            peer_ip,
+            request.packet_id,
+            request.file_id,
+            request.offset
+        );
+
+        let mut task = manager
+            .find_attachment_upload(peer_ip, request.packet_id, request.file_id)
+            .ok_or_else(|| {
+                NeoLanError::FileTransfer(format!(
+                    "No upload task for packet_id={}, file_id={} from {}",
+                    request.packet_id, request.file_id, peer_ip
+                ))
+            })?;
+
+        if task.status == TransferStatus::Paused {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Upload of {} to {} is paused",
+                task.file_name, peer_ip
+            )));
+        }
+        if request.offset > task.file_size {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Requested offset {} is beyond file size {}",
+                request.offset, task.file_size
+            )));
+        }
+
+        task.mark_active(peer_addr.port());
+        task.update_progress(request.offset);
+        manager.update_task(task.clone())?;
+
+        // Stream the file, publishing progress to the manager every PROGRESS_UPDATE_BYTES
+        // (the control handle lets it be paused and cancelled meanwhile)
+        let file_path = task.file_path.clone();
+        let control = manager.register_control(task.id);
+        let mut last_reported = request.offset;
+        let result = control.attach(&stream).and_then(|_| {
+            TcpTransport::send_file_from_offset(
+                stream,
+                &file_path,
+                request.offset,
+                Some(&control),
+                Some(|position, _total| {
+                    task.update_progress(position);
+                    if position - last_reported >= PROGRESS_UPDATE_BYTES {
+                        last_reported = position;
+                        let _ = manager.update_running_task(task.clone());
+                    }
+                }),
+            )
+        });
+        manager.unregister_control(task.id, &control);
+
+        match result {
+            Ok(sent) if request.offset + sent == task.file_size => {
+                task.mark_completed();
+                manager.update_running_task(task.clone())?;
+                tracing::info!(
+                    "✅ Upload completed: {} ({} bytes sent) -> {}",
+                    task.file_name,
+                    sent,
+                    peer_ip
+                );
+                Ok(())
+            }
+            Ok(sent) => {
+                let error = format!(
+                    "File changed since it was offered: sent up to {} of {} bytes",
+                    request.offset + sent,
+                    task.file_size
+                );
+                task.mark_failed(error.clone());
+                manager.update_running_task(task)?;
+                Err(NeoLanError::FileTransfer(error))
+            }
+            Err(_) if control.signal() == TransferSignal::Pause => {
+                tracing::info!("Upload paused: {} -> {}", task.file_name, peer_ip);
+                Ok(())
+            }
+            Err(e) => {
+                task.mark_failed(e.to_string());
+                manager.update_running_task(task)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Read the IPMSG_GETFILEDATA packet from the stream
+    ///
+    /// The packet may arrive in pieces: reading goes on until it parses and
+    /// ends with a separator (`:` or `\0`), or parses and nothing more comes
+    /// within REQUEST_SETTLE_TIME. Fails beyond MAX_REQUEST_SIZE bytes, when
+    /// the read times out or the connection closes first.
+    fn read_request(stream: &mut TcpStream) -> Result<FileDataRequest> {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; MAX_REQUEST_SIZE];
+        // Request parsed from data that may go on
+        let mut parsed = None;
+
+        loop {
+            let n = match stream.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if parsed.is_some() && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => 0,
+                Err(e) => return Err(NeoLanError::Network(e)),
+            };
+            if n == 0 {
+                return parsed.ok_or_else(|| {
+                    NeoLanError::Protocol(format!(
+                        "Connection closed before the file data request was complete ({} bytes)",
+                        data.len()
+                    ))
+                });
+            }
+
+            data.extend_from_slice(&buffer[..n]);
+            if data.len() > MAX_REQUEST_SIZE {
+                return Err(NeoLanError::Protocol(format!(
+                    "File data request longer than {} bytes",
+                    MAX_REQUEST_SIZE
+                )));
+            }
+
+            parsed = match Self::parse_request(&data) {
+                Ok(request) if data.ends_with(b":") || data.ends_with(b"\0") => return Ok(request),
+                Ok(request) => Some(request),
+                Err(_) => None,
+            };
+            let timeout = match parsed {
+                Some(_) => REQUEST_SETTLE_TIME,
+                None => Duration::from_secs(IO_TIMEOUT_SECS),
+            };
+            stream.set_read_timeout(Some(timeout)).map_err(NeoLanError::Network)?;
+        }
+    }
+
+    /// Parse a complete IPMSG_GETFILEDATA packet
+    fn parse_request(data: &[u8]) -> Result<FileDataRequest> {
+        let proto_msg = parse_message(data)?;
+        let mode = msg_type::get_mode(proto_msg.msg_type) as u32;
+        if mode != msg_type::IPMSG_GETFILEDATA {
+            return Err(NeoLanError::Protocol(format!(
+                "Unexpected command on file data connection: mode=0x{:02x}",
+                mode
+            )));
+        }
+
+        FileDataRequest::parse(&proto_msg.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::file_transfer::types::{TransferStatus, TransferTask};
+    use crate::network::{serialize_message, ProtocolMessage, UdpTransport};
+    use std::io::Write;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    fn create_server() -> (Arc<FileTransferManager>, SocketAddr, Arc<FileServer>, thread::JoinHandle<()>) {
+        let udp = UdpTransport::bind(0).unwrap().sender();
+        let manager = Arc::new(FileTransferManager::new(
+            udp,
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(FileServer::new(manager.clone()));
+        let serving = server.clone();
+        let handle = thread::spawn(move || serving.serve(listener));
+
+        (manager, addr, server, handle)
+    }
+
+    fn add_upload(manager: &FileTransferManager, path: &std::path::Path, size: u64, packet_id: u64) -> uuid::Uuid {
+        let task = TransferTask::new_upload(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            path.to_path_buf(),
+            "upload.txt".to_string(),
+            size,
+            String::new(),
+        )
+        .with_attachment(packet_id, 1);
+        manager.add_task(task.clone()).unwrap();
+        task.id
+    }
+
+    fn request_bytes(packet_id: u64, file_id: u64, offset: u64) -> Vec<u8> {
+        let request = FileDataRequest {
+            packet_id,
+            file_id,
+            offset,
+        };
+        serialize_message(&ProtocolMessage {
+            version: 1,
+            packet_id: 9,
+            sender_name: "bob".to_string(),
+            sender_host: "bob-pc".to_string(),
+            msg_type: msg_type::IPMSG_GETFILEDATA,
+            content: request.encode(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_serve_file_from_offset() {
+        let (manager, addr, _server, _handle) = create_server();
+
+        let test_file = std::env::temp_dir().join("test_file_server.txt");
+        std::fs::write(&test_file, b"Hello, FeiQ peer!").unwrap();
+
+        let task = TransferTask::new_upload(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            test_file.clone(),
+            "test_file_server.txt".to_string(),
+            17,
+            "md5".to_string(),
+        )
+        .with_attachment(0x1000, 1);
+        let task_id = task.id;
+        manager.add_task(task).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request_bytes(0x1000, 1, 7)).unwrap();
+
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"FeiQ peer!");
+
+        // Task state is updated after the connection is closed
+        thread::sleep(Duration::from_millis(100));
+        let task = manager.get_task(task_id).unwrap();
+        assert_eq!(task.status, TransferStatus::Completed);
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_request_is_closed() {
+        let (_manager, addr, _server, _handle) = create_server();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request_bytes(0x2000, 0, 0)).unwrap();
+
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn test_request_in_pieces() {
+        let (manager, addr, _server, _handle) = create_server();
+        let test_file = std::env::temp_dir().join(format!("test_file_server_{}.txt", uuid::Uuid::new_v4()));
+        let data: Vec<u8> = (0..64u8).collect();
+        std::fs::write(&test_file, &data).unwrap();
+        let task_id = add_upload(&manager, &test_file, 64, 0x3000);
+
+        // Offset 0x10 split after its first digit: `...:1` parses, but more follows
+        let request = request_bytes(0x3000, 1, 0x10);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request[..request.len() - 2]).unwrap();
+        thread::sleep(REQUEST_SETTLE_TIME / 4);
+        stream.write_all(&request[request.len() - 2..]).unwrap();
+
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, data[0x10..]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(manager.get_task(task_id).unwrap().status, TransferStatus::Completed);
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
+    #[test]
+    fn test_short_file_fails_upload() {
+        let (manager, addr, _server, _handle) = create_server();
+        let test_file = std::env::temp_dir().join(format!("test_file_server_{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&test_file, b"shrunk").unwrap();
+        let task_id = add_upload(&manager, &test_file, 100, 0x4000);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request_bytes(0x4000, 1, 0)).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"shrunk");
+
+        thread::sleep(Duration::from_millis(100));
+        let task = manager.get_task(task_id).unwrap();
+        assert_eq!(task.status, TransferStatus::Failed);
+        assert!(task.error.unwrap().contains("6 of 100"));
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
+    #[test]
+    fn test_connection_cap_and_stop() {
+        let (_manager, addr, server, handle) = create_server();
+
+        // Connections that never send their request take every slot
+        let idle: Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| TcpStream::connect(addr).unwrap()).collect();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while server.connections.load(Ordering::SeqCst) < MAX_CONNECTIONS {
+            assert!(std::time::Instant::now() < deadline, "connections not accepted");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        // One more is closed right away
+        let mut refused = TcpStream::connect(addr).unwrap();
+        refused.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buffer = [0u8; 1];
+        assert_eq!(refused.read(&mut buffer).unwrap(), 0);
+
+        // Closing them frees the slots
+        drop(idle);
+        while server.connections.load(Ordering::SeqCst) > 0 {
+            assert!(std::time::Instant::now() < deadline, "connections not released");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        server.stop();
+        let deadline = std::time::Instant::now() + ACCEPT_POLL_INTERVAL * 10;
+        while !handle.is_finished() {
+            assert!(std::time::Instant::now() < deadline, "server still serving");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Transfer store - writes every task change through to the `transfers` table
+//
+// Task changes are reported by the manager while it holds its task lock,
+// often from transfer worker threads, so they are queued and written by one
+// store thread (in order) instead of blocking on the database there.
+use crate::storage::transfer_repo::{TransferModel, TransferRepository};
+use crate::{NeoLanError, Result};
+use chrono::Utc;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use uuid::Uuid;
+
+use super::types::{TransferDirection, TransferStatus, TransferTask};
+
+/// Persistent record of transfer tasks
+pub struct TransferStore {
+    /// Repository of the `transfers` table
+    repo: TransferRepository,
+
+    /// Queue of task changes (with the peer's name) to write (None once closed)
+    queue: Mutex<Option<Sender<(TransferTask, String)>>>,
+
+    /// Receiving end of the queue, until the store thread takes it
+    queued: Mutex<Option<Receiver<(TransferTask, String)>>>,
+}
+
+impl TransferStore {
+    /// Create a store writing to `repo`
+    ///
+    /// Changes are queued until [`Self::start`] runs the store thread.
+    pub fn new(repo: TransferRepository) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            repo,
+            queue: Mutex::new(Some(tx)),
+            queued: Mutex::new(Some(rx)),
+        }
+    }
+
+    /// Queue a new or changed task for writing
+    ///
+    /// # Arguments
+    /// * `task` - The task
+    /// * `peer_name` - Display name of the peer (empty keeps the stored one)
+    pub fn save(&self, task: &TransferTask, peer_name: &str) {
+        let queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        match queue.as_ref() {
+            Some(tx) if tx.send((task.clone(), peer_name.to_string())).is_ok() => {}
+            _ => tracing::warn!("Transfer store closed, change of task {} not stored", task.id),
+        }
+    }
+
+    /// Start the store thread
+    ///
+    /// It writes the queued changes in order and ends once the store is
+    /// closed and the queue drained. Call once.
+    pub fn start(&self) -> thread::JoinHandle<()> {
+        let rx = self.queued.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let repo = self.repo.clone();
+
+        thread::spawn(move || {
+            let Some(rx) = rx else {
+                tracing::error!("Transfer store thread started twice");
+                return;
+            };
+            let rt = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(rt) => rt,
+                Err(e) => {
+                    tracing::error!("Failed to create transfer store runtime, transfers won't be stored: {}", e);
+                    return;
+                }
+            };
+
+            for (task, peer_name) in rx {
+                let model = TransferModel { peer_name, ..TransferModel::from(&task) };
+                if let Err(e) = rt.block_on(repo.save(&model)) {
+                    tracing::warn!("Failed to store transfer task {}: {:?}", task.id, e);
+                }
+            }
+        })
+    }
+
+    /// Stop accepting changes; the store thread ends after writing the queued ones
+    pub fn close(&self) {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    /// Load the tasks left unfinished when the application last exited
+    ///
+    /// Records that can't be read back are logged and skipped.
+    pub async fn load_unfinished(&self) -> Result<Vec<TransferTask>> {
+        let models = self.repo.find_unfinished().await?;
+        Ok(Self::into_tasks(models))
+    }
+
+    /// Get one page of the transfer history, newest first
+    ///
+    /// # Arguments
+    /// * `page` - Page number (from 0)
+    /// * `page_size` - Tasks per page
+    ///
+    /// # Returns
+    /// * `Ok((Vec<TransferTask>, u64))` - Tasks of the page and the total number of tasks
+    /// * `Err(NeoLanError)` - Query failed
+    pub async fn history(&self, page: u64, page_size: u64) -> Result<(Vec<TransferTask>, u64)> {
+        let models = self.repo.find_page(page, page_size).await?;
+        let total = self.repo.count().await?;
+        Ok((Self::into_tasks(models), total))
+    }
+
+    fn into_tasks(models: Vec<TransferModel>) -> Vec<TransferTask> {
+        models
+            .into_iter()
+            .filter_map(|model| match TransferTask::try_from(model) {
+                Ok(task) => Some(task),
+                Err(e) => {
+                    tracing::warn!("Skipping stored transfer: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// The record of a task, without the peer's name (tasks only know the IP)
+impl From<&TransferTask> for TransferModel {
+    fn from(task: &TransferTask) -> Self {
+        let updated_at = task.updated_at.naive_utc();
+        Self {
+            id: 0,
+            task_id: task.id.to_string(),
+            direction: task.direction.as_str().to_string(),
+            file_name: task.file_name.clone(),
+            file_size: task.file_size as i64,
+            file_md5: task.md5.clone(),
+            peer_ip: task.peer_ip.to_string(),
+            peer_name: String::new(),
+            status: task.status.as_str().to_string(),
+            transferred_size: task.transferred_bytes as i64,
+            started_at: (task.status != TransferStatus::Pending).then_some(updated_at),
+            completed_at: task.is_finished().then_some(updated_at),
+            created_at: task.created_at.naive_utc(),
+            file_path: task.file_path.to_string_lossy().to_string(),
+            error_message: task.error.clone(),
+            packet_id: task.packet_id.map(|id| id as i64),
+            file_id: task.file_id.map(|id| id as i64),
+        }
+    }
+}
+
+impl TryFrom<TransferModel> for TransferTask {
+    type Error = NeoLanError;
+
+    fn try_from(model: TransferModel) -> Result<Self> {
+        let invalid = |what: &str, value: &str| {
+            NeoLanError::Storage(format!("Invalid {} of transfer {}: {}", what, model.task_id, value))
+        };
+
+        let id = Uuid::parse_str(&model.task_id).map_err(|_| invalid("task ID", &model.task_id))?;
+        let direction = TransferDirection::parse(&model.direction).ok_or_else(|| invalid("direction", &model.direction))?;
+        let status = TransferStatus::parse(&model.status).ok_or_else(|| invalid("status", &model.status))?;
+        let peer_ip = model.peer_ip.parse().map_err(|_| invalid("peer IP", &model.peer_ip))?;
+
+        let created_at = model.created_at.and_utc();
+        let updated_at = model.completed_at.or(model.started_at).map_or(created_at, |t| t.and_utc());
+
+        Ok(Self {
+            id,
+            direction,
+            peer_ip,
+            file_path: PathBuf::from(model.file_path),
+            file_name: model.file_name,
+            file_size: model.file_size.max(0) as u64,
+            md5: model.file_md5,
+            status,
+            transferred_bytes: model.transferred_size.max(0) as u64,
+            port: None,
+            created_at,
+            updated_at,
+            error: model.error_message,
+            packet_id: model.packet_id.map(|id| id as u64),
+            file_id: model.file_id.map(|id| id as u64),
+        })
+    }
+}
+
+/// Decide what becomes of a task the application was closed during
+///
+/// A download that had started and whose partial file is still there is
+/// kept paused, to be resumed. Anything else is failed: offers the peer has
+/// forgotten meanwhile, downloads without data, and uploads (failed uploads
+/// are still served to a receiver resuming them; paused ones are not).
+pub fn recover_interrupted(mut task: TransferTask) -> TransferTask {
+    let resumable = task.direction == TransferDirection::Download
+        && matches!(task.status, TransferStatus::Active | TransferStatus::Paused)
+        && task.file_path.is_file();
+
+    if resumable {
+        task.status = TransferStatus::Paused;
+        task.updated_at = Utc::now();
+    } else {
+        task.mark_failed("Interrupted: the application was closed during the transfer".to_string());
+    }
+    task
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_model_round_trip() {
+        let mut task = TransferTask::new_download(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
+            "file.txt".to_string(),
+            1024,
+            "abc123".to_string(),
+        )
+        .with_attachment(7, 1);
+        task.file_path = PathBuf::from("/tmp/file.txt");
+        task.mark_active(2425);
+        task.update_progress(512);
+
+        let model = TransferModel::from(&task);
+        assert_eq!(model.status, "active");
+        assert!(model.started_at.is_some());
+        assert!(model.completed_at.is_none());
+
+        let restored = TransferTask::try_from(model).unwrap();
+        assert_eq!(restored.id, task.id);
+        assert_eq!(restored.direction, TransferDirection::Download);
+        assert_eq!(restored.status, TransferStatus::Active);
+        assert_eq!(restored.transferred_bytes, 512);
+        assert_eq!(restored.file_path, task.file_path);
+        assert_eq!((restored.packet_id, restored.file_id), (Some(7), Some(1)));
+
+        let mut broken = TransferModel::from(&task);
+        broken.status = "transferring".to_string();
+        assert!(TransferTask::try_from(broken).is_err());
+    }
+
+    #[test]
+    fn test_recover_interrupted() {
+        let partial = std::env::temp_dir().join(format!("neolan_partial_{}", Uuid::new_v4()));
+        std::fs::write(&partial, b"part").unwrap();
+
+        let peer = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100));
+        let mut download = TransferTask::new_download(peer, "file.txt".to_string(), 1024, String::new());
+        download.file_path = partial.clone();
+        download.mark_active(2425);
+        assert_eq!(recover_interrupted(download.clone()).status, TransferStatus::Paused);
+
+        std::fs::remove_file(&partial).unwrap();
+        assert_eq!(recover_interrupted(download).status, TransferStatus::Failed);
+
+        let offer = TransferTask::new_download(peer, "file.txt".to_string(), 1024, String::new());
+        assert_eq!(recover_interrupted(offer).status, TransferStatus::Failed);
+
+        // Uploads wait for the receiver to resume them
+        std::fs::write(&partial, b"whole").unwrap();
+        let mut upload = TransferTask::new_upload(peer, partial.clone(), "file.txt".to_string(), 1024, String::new());
+        upload.mark_active(2425);
+        let upload = recover_interrupted(upload);
+        assert_eq!(upload.status, TransferStatus::Failed);
+        assert!(upload.error.is_some() && upload.is_resumable());
+        std::fs::remove_file(&partial).unwrap();
+    }
+}
@@ -28,7 +28,7 @@ pub struct TransferTask {
     /// File size in bytes
     pub file_size: u64,
 
-    /// MD5 hash (hex string)
+    /// MD5 hash (hex string, empty for IPMsg attachments, which carry none)
     pub md5: String,
 
     /// Current transfer status
@@ -48,6 +48,15 @@ pub struct TransferTask {
 
     /// Error message (if failed)
     pub error: Option<String>,
+
+    /// Packet ID of the message offering the file: the IPMsg FILEATTACHOPT
+    /// message, or the NeoLan JSON offer (which replies name)
+    #[serde(default)]
+    pub packet_id: Option<u64>,
+
+    /// IPMsg attachment file ID within that packet
+    #[serde(default)]
+    pub file_id: Option<u64>,
 }
 
 /// Transfer direction
@@ -76,6 +85,52 @@ pub enum TransferStatus {
     Cancelled,
 }
 
+impl TransferDirection {
+    /// Get direction as string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Upload => "upload",
+            Self::Download => "download",
+        }
+    }
+
+    /// Parse a direction string (as produced by [`Self::as_str`])
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "upload" => Some(Self::Upload),
+            "download" => Some(Self::Download),
+            _ => None,
+        }
+    }
+}
+
+impl TransferStatus {
+    /// Get status as string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Active => "active",
+            Self::Paused => "paused",
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// Parse a status string (as produced by [`Self::as_str`])
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "pending" => Some(Self::Pending),
+            "active" => Some(Self::Active),
+            "paused" => Some(Self::Paused),
+            "completed" => Some(Self::Completed),
+            "failed" => Some(Self::Failed),
+            "cancelled" => Some(Self::Cancelled),
+            _ => None,
+        }
+    }
+}
+
 impl TransferTask {
     /// Create a new upload task
     pub fn new_upload(
@@ -100,6 +155,8 @@ impl TransferTask {
             created_at: now,
             updated_at: now,
             error: None,
+            packet_id: None,
+            file_id: None,
         }
     }
 
@@ -125,9 +182,32 @@ impl TransferTask {
             created_at: now,
             updated_at: now,
             error: None,
+            packet_id: None,
+            file_id: None,
         }
     }
 
+    /// Attach IPMsg attachment identifiers (packet ID + file ID)
+    ///
+    /// Used for transfers announced with IPMSG_FILEATTACHOPT, where the
+    /// receiver requests the data with `packetID:fileID:offset`.
+    pub fn with_attachment(mut self, packet_id: u64, file_id: u64) -> Self {
+        self.packet_id = Some(packet_id);
+        self.file_id = Some(file_id);
+        self
+    }
+
+    /// Record the packet ID of the NeoLan JSON offer
+    pub fn with_offer(mut self, packet_id: u64) -> Self {
+        self.packet_id = Some(packet_id);
+        self
+    }
+
+    /// Check if this task uses the standard IPMsg attachment format
+    pub fn is_attachment(&self) -> bool {
+        self.packet_id.is_some() && self.file_id.is_some()
+    }
+
     /// Get transfer progress (0.0 to 1.0)
     pub fn progress(&self) -> f64 {
         if self.file_size == 0 {
@@ -148,10 +228,11 @@ impl TransferTask {
         self.updated_at = Utc::now();
     }
 
-    /// Mark as active
+    /// Mark as active (clears the error of an earlier attempt)
     pub fn mark_active(&mut self, port: u16) {
         self.status = TransferStatus::Active;
         self.port = Some(port);
+        self.error = None;
         self.updated_at = Utc::now();
     }
 
@@ -183,10 +264,11 @@ impl TransferTask {
         }
     }
 
-    /// Resume transfer
+    /// Resume a paused transfer, or retry a failed one
     pub fn resume(&mut self) {
-        if self.status == TransferStatus::Paused {
+        if matches!(self.status, TransferStatus::Paused | TransferStatus::Failed) {
             self.status = TransferStatus::Active;
+            self.error = None;
             self.updated_at = Utc::now();
         }
     }
@@ -199,6 +281,14 @@ impl TransferTask {
         )
     }
 
+    /// Check if the peer may still ask for (the rest of) the file
+    ///
+    /// True unless the transfer completed or was cancelled: failed and
+    /// interrupted transfers are continued from where they stopped.
+    pub fn is_resumable(&self) -> bool {
+        !matches!(self.status, TransferStatus::Completed | TransferStatus::Cancelled)
+    }
+
     /// Check if transfer is active (not paused and not finished)
     pub fn is_active(&self) -> bool {
         self.status == TransferStatus::Active
@@ -241,6 +331,23 @@ mod tests {
         assert_eq!(task.status, TransferStatus::Pending);
     }
 
+    #[test]
+    fn test_with_attachment() {
+        let task = TransferTask::new_upload(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
+            PathBuf::from("/test/file.txt"),
+            "file.txt".to_string(),
+            1024,
+            "abc123".to_string(),
+        );
+        assert!(!task.is_attachment());
+
+        let task = task.with_attachment(1234, 2);
+        assert!(task.is_attachment());
+        assert_eq!(task.packet_id, Some(1234));
+        assert_eq!(task.file_id, Some(2));
+    }
+
     #[test]
     fn test_transfer_progress() {
         let mut task = TransferTask::new_upload(
@@ -321,6 +428,15 @@ mod tests {
 
         task.resume();
         assert_eq!(task.status, TransferStatus::Active);
+
+        task.mark_failed("Connection reset".to_string());
+        task.resume();
+        assert_eq!(task.status, TransferStatus::Active);
+        assert!(task.error.is_none());
+
+        task.mark_cancelled();
+        task.resume();
+        assert_eq!(task.status, TransferStatus::Cancelled);
     }
 
     #[test]
@@ -339,6 +455,25 @@ mod tests {
         assert!(task.is_finished());
     }
 
+    #[test]
+    fn test_is_resumable() {
+        let mut task = TransferTask::new_upload(
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
+            PathBuf::from("/test/file.txt"),
+            "file.txt".to_string(),
+            1024,
+            "abc123".to_string(),
+        );
+
+        assert!(task.is_resumable());
+
+        task.mark_failed("Connection lost".to_string());
+        assert!(task.is_resumable());
+
+        task.mark_cancelled();
+        assert!(!task.is_resumable());
+    }
+
     #[test]
     fn test_is_active() {
         let mut task = TransferTask::new_upload(
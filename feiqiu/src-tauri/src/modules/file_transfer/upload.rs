@@ -0,0 +1,147 @@
+// Upload worker - pushes a file to a NeoLan peer that accepted our offer
+//
+// NeoLan receivers answer a JSON offer with the TCP port they listen on; the
+// sender connects there and streams the whole file. A receiver resuming an
+// interrupted transfer names the offset it has and the MD5 of those bytes;
+// the sender then starts the connection with the offset it actually starts
+// at. (IPMsg clients pull attachments from us instead, see `server`.)
+use crate::network::{TcpTransport, TransferControl, TransferSignal};
+use crate::utils::hash;
+use crate::{NeoLanError, Result};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+
+use super::types::TransferTask;
+use super::{FileTransferManager, IO_TIMEOUT_SECS, PROGRESS_UPDATE_BYTES};
+
+/// Part of the file a receiver already has, from its resume request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Bytes the receiver has
+    pub offset: u64,
+
+    /// MD5 of those bytes
+    pub prefix_md5: String,
+}
+
+/// Spawn the worker pushing an accepted upload's file to the receiver
+///
+/// A control handle is registered with the manager first, so the upload can
+/// be paused (closing the connection, see [`super::FileTransferResponse::pause`])
+/// and cancelled. The task is marked completed or failed when the worker
+/// ends, unless it was paused or cancelled meanwhile.
+///
+/// # Arguments
+/// * `manager` - File transfer manager holding the task
+/// * `task` - The accepted upload task
+/// * `addr` - Address the receiver listens on (port from its reply)
+/// * `resume` - Part the receiver already has, when it resumes the transfer
+pub fn spawn_upload(
+    manager: Arc<FileTransferManager>,
+    task: TransferTask,
+    addr: SocketAddr,
+    resume: Option<ResumePoint>,
+) -> thread::JoinHandle<()> {
+    let control = manager.register_control(task.id);
+    thread::spawn(move || run_upload(&manager, task, addr, resume, control))
+}
+
+/// Offset to resume from: the receiver's, if its bytes match our file, else 0
+fn resume_offset(task: &TransferTask, resume: &ResumePoint) -> u64 {
+    if resume.offset > task.file_size {
+        tracing::warn!(
+            "Resume offset {} of {} is beyond its end, sending it again",
+            resume.offset,
+            task.file_name
+        );
+        return 0;
+    }
+
+    match hash::calculate_prefix_md5(&task.file_path, resume.offset) {
+        Ok(md5) if md5.eq_ignore_ascii_case(&resume.prefix_md5) => resume.offset,
+        Ok(_) => {
+            tracing::warn!("Received part of {} differs from the file, sending it again", task.file_name);
+            0
+        }
+        Err(e) => {
+            tracing::warn!("Failed to check received part of {}, sending it again: {}", task.file_name, e);
+            0
+        }
+    }
+}
+
+/// Connect to the receiver and stream the file (worker thread)
+fn run_upload(
+    manager: &FileTransferManager,
+    mut task: TransferTask,
+    addr: SocketAddr,
+    resume: Option<ResumePoint>,
+    control: TransferControl,
+) {
+    let file_path = task.file_path.clone();
+    let start = resume.as_ref().map_or(0, |resume| resume_offset(&task, resume));
+    task.update_progress(start);
+
+    let result = TcpTransport::connect(addr).and_then(|mut stream| {
+        control.attach(&stream)?;
+        TcpTransport::set_write_timeout(&stream, IO_TIMEOUT_SECS)?;
+
+        if resume.is_some() {
+            stream.write_all(&start.to_be_bytes()).map_err(|e| {
+                NeoLanError::FileTransfer(format!("Failed to send resume offset: {}", e))
+            })?;
+        }
+
+        let mut last_reported = start;
+        TcpTransport::send_file_from_offset(
+            stream,
+            &file_path,
+            start,
+            Some(&control),
+            Some(|position, _total| {
+                task.update_progress(position);
+                if position - last_reported >= PROGRESS_UPDATE_BYTES {
+                    last_reported = position;
+                    let _ = manager.update_running_task(task.clone());
+                }
+            }),
+        )
+    });
+    manager.unregister_control(task.id, &control);
+
+    if result.is_err() && control.signal() == TransferSignal::Pause {
+        tracing::info!("Upload paused: {} -> {}", task.file_name, addr);
+        return;
+    }
+    finish_upload(manager, &mut task, addr, result.map(|sent| start + sent));
+}
+
+/// Record how an upload ended
+fn finish_upload(manager: &FileTransferManager, task: &mut TransferTask, addr: SocketAddr, result: Result<u64>) {
+    match result {
+        Ok(end) if end == task.file_size => task.mark_completed(),
+        Ok(end) => task.mark_failed(format!(
+            "File changed since it was offered: sent up to {} of {} bytes",
+            end, task.file_size
+        )),
+        Err(e) => task.mark_failed(e.to_string()),
+    }
+
+    match manager.update_running_task(task.clone()) {
+        Ok(true) if task.error.is_none() => {
+            tracing::info!("✅ Upload completed: {} -> {}", task.file_name, addr);
+        }
+        Ok(true) => {
+            tracing::warn!(
+                "Upload of {} to {} failed: {}",
+                task.file_name,
+                addr,
+                task.error.as_deref().unwrap_or_default()
+            );
+        }
+        Ok(false) => tracing::info!("Upload cancelled: {} -> {}", task.file_name, addr),
+        Err(e) => tracing::warn!("Failed to update upload task {}: {}", task.id, e),
+    }
+}
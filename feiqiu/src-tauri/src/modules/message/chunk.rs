@@ -0,0 +1,304 @@
+// Chunked messages - long text split across several datagrams
+//
+// IPMsg clients read a message from a single UDP datagram and older ones
+// (FeiQ included) use an 8 KiB receive buffer, so longer text never arrives
+// whole. NeoLan peers advertise IPMSG_CAPCHUNKOPT on their presence packets;
+// text for them is split into IPMSG_CHUNKOPT pieces, each sent and
+// acknowledged like an ordinary message, and joined again on arrival. Other
+// peers get the text truncated to what fits in one datagram.
+
+use crate::network::{canonical_ip, msg_type, ProtocolMessage, TextEncoding, MAX_CONTENT_SIZE};
+use crate::{NeoLanError, Result};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Largest text (encoded bytes) sent in one datagram
+///
+/// 8 KiB, the receive buffer of classic IPMsg clients, less room for the
+/// header (FeiQ prefix, user and host names, chunk fields).
+pub const MAX_DATAGRAM_TEXT: usize = 7 * 1024;
+
+/// Most chunks one message may be split into
+///
+/// A joined message is held to the same size limit as a single packet.
+pub const MAX_CHUNKS: usize = MAX_CONTENT_SIZE / MAX_DATAGRAM_TEXT;
+
+/// How long the chunks of one message may take to arrive
+///
+/// Longer than the sender's retransmission schedule, so a chunk that needed
+/// every retry still completes the message.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Most messages reassembled at once (the oldest is dropped beyond that)
+const MAX_PARTIAL_MESSAGES: usize = 64;
+
+/// Split text into pieces of at most `max_bytes` encoded bytes
+///
+/// Pieces end on character boundaries; a character wider than `max_bytes`
+/// gets a piece of its own.
+pub fn split_text(text: &str, max_bytes: usize, encoding: TextEncoding) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+
+    for (offset, char_size) in char_sizes(text, encoding) {
+        if size + char_size > max_bytes && offset > start {
+            pieces.push(&text[start..offset]);
+            start = offset;
+            size = 0;
+        }
+        size += char_size;
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+
+    pieces
+}
+
+/// Cut text down to at most `max_bytes` encoded bytes (on a character boundary)
+pub fn truncate_text(text: &str, max_bytes: usize, encoding: TextEncoding) -> &str {
+    let mut size = 0;
+    for (offset, char_size) in char_sizes(text, encoding) {
+        size += char_size;
+        if size > max_bytes {
+            return &text[..offset];
+        }
+    }
+    text
+}
+
+/// Byte offset and encoded size of each character of `text`
+fn char_sizes(text: &str, encoding: TextEncoding) -> impl Iterator<Item = (usize, usize)> + '_ {
+    text.char_indices().map(move |(offset, c)| {
+        let size = match encoding {
+            TextEncoding::Utf8 => c.len_utf8(),
+            _ => encoding.encode(c.encode_utf8(&mut [0u8; 4])).len(),
+        };
+        (offset, size)
+    })
+}
+
+/// Chunks of one message received so far
+struct PartialMessage {
+    /// Text of each chunk, by index
+    chunks: Vec<Option<String>>,
+
+    /// Number of chunks received
+    received: usize,
+
+    /// First chunk that arrived (sender fields of the joined message)
+    template: ProtocolMessage,
+
+    /// When the first chunk arrived
+    started: Instant,
+}
+
+/// Joins chunked messages back together
+///
+/// Partial messages are keyed by sender and message ID, and dropped when
+/// they don't complete within [`REASSEMBLY_TIMEOUT`].
+#[derive(Default)]
+pub struct Reassembler {
+    partial: Mutex<HashMap<(IpAddr, u64), PartialMessage>>,
+}
+
+impl Reassembler {
+    /// Create an empty reassembler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a received chunk
+    ///
+    /// # Arguments
+    /// * `sender_ip` - Sender of the chunk
+    /// * `chunk` - The chunk packet (IPMSG_SENDMSG | IPMSG_CHUNKOPT)
+    /// * `message_id` / `index` / `count` / `text` - Decoded chunk fields
+    ///
+    /// # Returns
+    /// * `Ok(Some(ProtocolMessage))` - The chunk completed the message: an
+    ///   ordinary IPMSG_SENDMSG under the message ID, with the joined text
+    /// * `Ok(None)` - More chunks are needed (or the chunk was a repeat)
+    /// * `Err(NeoLanError::Protocol)` - Index or count out of range, or a count
+    ///   that disagrees with earlier chunks
+    pub fn insert(
+        &self,
+        sender_ip: IpAddr,
+        chunk: &ProtocolMessage,
+        message_id: u64,
+        index: usize,
+        count: usize,
+        text: String,
+    ) -> Result<Option<ProtocolMessage>> {
+        if count == 0 || count > MAX_CHUNKS || index >= count {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid chunk {}/{} of message {} (at most {} chunks)",
+                index, count, message_id, MAX_CHUNKS
+            )));
+        }
+
+        let now = Instant::now();
+        let key = (canonical_ip(sender_ip), message_id);
+        let mut partial = self.lock_partial();
+        Self::expire(&mut partial, now);
+
+        if !partial.contains_key(&key) && partial.len() >= MAX_PARTIAL_MESSAGES {
+            let oldest = partial.iter().min_by_key(|(_, message)| message.started).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                tracing::warn!("Too many partial messages, dropping message {} from {}", oldest.1, oldest.0);
+                partial.remove(&oldest);
+            }
+        }
+
+        let message = partial.entry(key).or_insert_with(|| PartialMessage {
+            chunks: vec![None; count],
+            received: 0,
+            template: chunk.clone(),
+            started: now,
+        });
+        if message.chunks.len() != count {
+            return Err(NeoLanError::Protocol(format!(
+                "Chunk {} of message {} says {} chunks, earlier ones said {}",
+                index,
+                message_id,
+                count,
+                message.chunks.len()
+            )));
+        }
+
+        if message.chunks[index].is_none() {
+            message.chunks[index] = Some(text);
+            message.received += 1;
+        }
+        if message.received < count {
+            return Ok(None);
+        }
+
+        let Some(message) = partial.remove(&key) else {
+            return Ok(None);
+        };
+        let mut complete = message.template;
+        complete.packet_id = message_id;
+        complete.msg_type &= !(msg_type::IPMSG_CHUNKOPT | msg_type::IPMSG_RETRYOPT);
+        complete.content = message.chunks.into_iter().flatten().collect();
+        complete.extensions = Vec::new();
+        complete.raw_extensions = Vec::new();
+
+        Ok(Some(complete))
+    }
+
+    /// Number of messages still missing chunks
+    pub fn pending_count(&self) -> usize {
+        self.lock_partial().len()
+    }
+
+    /// Drop partial messages whose chunks stopped arriving
+    fn expire(partial: &mut HashMap<(IpAddr, u64), PartialMessage>, now: Instant) {
+        partial.retain(|(sender_ip, message_id), message| {
+            let alive = now.duration_since(message.started) < REASSEMBLY_TIMEOUT;
+            if !alive {
+                tracing::warn!(
+                    "Message {} from {} incomplete after {:?} ({}/{} chunks), dropped",
+                    message_id,
+                    sender_ip,
+                    REASSEMBLY_TIMEOUT,
+                    message.received,
+                    message.chunks.len()
+                );
+            }
+            alive
+        });
+    }
+
+    fn lock_partial(&self) -> std::sync::MutexGuard<'_, HashMap<(IpAddr, u64), PartialMessage>> {
+        self.partial.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Command, Mode, Opt};
+
+    fn chunk(packet_id: u64) -> ProtocolMessage {
+        ProtocolMessage {
+            version: 1,
+            packet_id,
+            sender_name: "alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: Command::new(Mode::SendMsg)
+                .with_all(&[Opt::SendCheck, Opt::Chunk])
+                .unwrap()
+                .into(),
+            content: String::new(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: Some(TextEncoding::Utf8),
+        }
+    }
+
+    #[test]
+    fn test_split_text() {
+        assert_eq!(split_text("abcdefg", 3, TextEncoding::Utf8), vec!["abc", "def", "g"]);
+        assert_eq!(split_text("abc", 3, TextEncoding::Utf8), vec!["abc"]);
+        assert!(split_text("", 3, TextEncoding::Utf8).is_empty());
+
+        // Pieces end on character boundaries: 3 bytes per character in UTF-8, 2 in GBK
+        assert_eq!(split_text("你好世界", 7, TextEncoding::Utf8), vec!["你好", "世界"]);
+        assert_eq!(split_text("你好世界", 7, TextEncoding::Gbk), vec!["你好世", "界"]);
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("hello", 10, TextEncoding::Utf8), "hello");
+        assert_eq!(truncate_text("hello", 3, TextEncoding::Utf8), "hel");
+        assert_eq!(truncate_text("你好世界", 7, TextEncoding::Utf8), "你好");
+        assert_eq!(truncate_text("你好世界", 7, TextEncoding::Gbk), "你好世");
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let reassembler = Reassembler::new();
+        let sender: IpAddr = "192.168.1.20".parse().unwrap();
+
+        let first = reassembler.insert(sender, &chunk(501), 500, 2, 3, "!".to_string()).unwrap();
+        assert!(first.is_none());
+        // A repeated chunk changes nothing
+        assert!(reassembler.insert(sender, &chunk(501), 500, 2, 3, "!".to_string()).unwrap().is_none());
+        assert!(reassembler.insert(sender, &chunk(500), 500, 0, 3, "hello".to_string()).unwrap().is_none());
+        assert_eq!(reassembler.pending_count(), 1);
+
+        let complete = reassembler
+            .insert(sender, &chunk(502), 500, 1, 3, ", world".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(complete.packet_id, 500);
+        assert_eq!(complete.content, "hello, world!");
+        assert!(!complete.command().has(Opt::Chunk));
+        assert!(complete.command().has(Opt::SendCheck));
+        assert_eq!(reassembler.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_reassembly_rejects_bad_chunks() {
+        let reassembler = Reassembler::new();
+        let sender: IpAddr = "192.168.1.20".parse().unwrap();
+
+        assert!(reassembler.insert(sender, &chunk(1), 1, 3, 3, String::new()).is_err());
+        assert!(reassembler.insert(sender, &chunk(1), 1, 0, 0, String::new()).is_err());
+        assert!(reassembler.insert(sender, &chunk(1), 1, 0, MAX_CHUNKS + 1, String::new()).is_err());
+        assert!(reassembler.insert(sender, &chunk(1), 1, 0, 256, String::new()).is_err());
+
+        reassembler.insert(sender, &chunk(1), 1, 0, 3, "a".to_string()).unwrap();
+        assert!(reassembler.insert(sender, &chunk(2), 1, 1, 4, "b".to_string()).is_err());
+
+        // Same message ID from another host is another message
+        let other: IpAddr = "192.168.1.21".parse().unwrap();
+        assert!(reassembler.insert(other, &chunk(1), 1, 0, 2, "x".to_string()).unwrap().is_none());
+        assert_eq!(reassembler.pending_count(), 2);
+    }
+}
@@ -1,7 +1,10 @@
 // Message handler - handles sending and receiving messages
 //
 // This module provides the MessageHandler which:
-// - Sends text messages to peers via UDP
+// - Sends text messages to peers via UDP, retransmitting them until the
+//   receiver acknowledges (see `outbox`)
+// - Splits text too long for one datagram into chunks for NeoLan peers, and
+//   joins received chunks back together (see `chunk`)
 // - Serializes messages to IPMsg protocol format
 // - Receives and routes incoming messages by type
 // - Stores text messages to database
@@ -9,18 +12,22 @@
 // - Emits Tauri events for received messages
 
 use crate::config::AppConfig;
+use crate::modules::message::chunk::{split_text, truncate_text, Reassembler, MAX_CHUNKS, MAX_DATAGRAM_TEXT};
+use crate::modules::message::outbox::{Outbox, OutboxEvent, RetryPolicy};
 use crate::modules::message::types::{Message, MessageType};
 use crate::modules::peer::types::PeerInfo;
-use crate::network::{serialize_message, msg_type, ProtocolMessage};
-use crate::network::udp::UdpTransport;
-use crate::storage::message_repo::{MessageRepository, MessageModel};
+use crate::network::{
+    msg_type, next_packet_id, serialize_message, FileAttachment, FileSendRequest, FileSendResponse, HostListEntry,
+    Mode, NetworkStats, Opt, Payload, ProtocolMessage, RecentPackets, TextEncoding,
+};
+use crate::network::udp::UdpSender;
+use crate::storage::message_repo::{MessageRepository, MessageModel, DELIVERY_PENDING};
 use crate::state::AppState;
 use crate::modules::file_transfer::FileTransferResponse;
 use crate::state::app_state::TauriEvent;
 use crate::{NeoLanError, Result};
 use chrono::Utc;
 use std::net::{IpAddr, SocketAddr};
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::instrument;
 
@@ -29,14 +36,14 @@ use tracing::instrument;
 /// Handles sending and receiving messages over UDP using the IPMsg protocol.
 /// Manages packet ID generation, message serialization, and message routing.
 pub struct MessageHandler {
-    /// UDP transport for sending messages
-    udp: UdpTransport,
+    /// Send handle of the shared protocol-port socket
+    udp: UdpSender,
 
     /// Application configuration (username, hostname, etc.)
     config: AppConfig,
 
-    /// Atomic counter for generating packet IDs
-    packet_id_counter: Arc<AtomicU64>,
+    /// Recently handled inbound packets (duplicate suppression)
+    recent_packets: RecentPackets,
 
     /// Message repository for database storage (optional)
     message_repo: Option<MessageRepository>,
@@ -46,13 +53,19 @@ pub struct MessageHandler {
 
     /// File transfer response handler (optional)
     file_transfer: Option<Arc<FileTransferResponse>>,
+
+    /// Sent messages awaiting their IPMSG_RECVMSG receipt
+    outbox: Outbox,
+
+    /// Chunked messages being received
+    reassembler: Reassembler,
 }
 
 impl MessageHandler {
     /// Create a new message handler
     ///
     /// # Arguments
-    /// * `udp` - UDP transport for sending messages
+    /// * `udp` - Send handle of the shared UDP transport
     /// * `config` - Application configuration
     ///
     /// # Returns
@@ -65,37 +78,41 @@ impl MessageHandler {
     /// # use neolan_lib::config::AppConfig;
     /// let udp = UdpTransport::bind(2425)?;
     /// let config = AppConfig::default();
-    /// let handler = MessageHandler::new(udp, config);
+    /// let handler = MessageHandler::new(udp.sender(), config);
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn new(udp: UdpTransport, config: AppConfig) -> Self {
+    pub fn new(udp: UdpSender, config: AppConfig) -> Self {
         Self {
+            outbox: Outbox::new(udp.clone(), RetryPolicy::default()),
             udp,
             config,
-            packet_id_counter: Arc::new(AtomicU64::new(1)),
+            recent_packets: RecentPackets::default(),
             message_repo: None,
             app_state: None,
             file_transfer: None,
+            reassembler: Reassembler::new(),
         }
     }
 
     /// Create a new message handler with database storage
     ///
     /// # Arguments
-    /// * `udp` - UDP transport for sending messages
+    /// * `udp` - Send handle of the shared UDP transport
     /// * `config` - Application configuration
     /// * `message_repo` - Message repository for database storage
     ///
     /// # Returns
     /// A new MessageHandler instance with database storage enabled
-    pub fn with_storage(udp: UdpTransport, config: AppConfig, message_repo: MessageRepository) -> Self {
+    pub fn with_storage(udp: UdpSender, config: AppConfig, message_repo: MessageRepository) -> Self {
         Self {
+            outbox: Outbox::new(udp.clone(), RetryPolicy::default()),
             udp,
             config,
-            packet_id_counter: Arc::new(AtomicU64::new(1)),
+            recent_packets: RecentPackets::default(),
             message_repo: Some(message_repo),
             app_state: None,
             file_transfer: None,
+            reassembler: Reassembler::new(),
         }
     }
 
@@ -117,14 +134,123 @@ impl MessageHandler {
         self
     }
 
+    /// Set the retransmission schedule of unacknowledged messages
+    ///
+    /// # Arguments
+    /// * `policy` - Retransmission schedule (replaces the default)
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.outbox = Outbox::new(self.udp.clone(), policy);
+        self
+    }
+
+    /// Start the outbox thread
+    ///
+    /// Retransmits unacknowledged messages, stores sent messages and records
+    /// their delivery (database and Tauri events). Call once, after the
+    /// builder methods.
+    pub fn start_outbox(&self) -> std::thread::JoinHandle<()> {
+        let outbox = self.outbox.clone();
+        let message_repo = self.message_repo.clone();
+        let app_state = self.app_state.clone();
+
+        std::thread::spawn(move || {
+            let rt = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(rt) => Some(rt),
+                Err(e) => {
+                    tracing::error!("Failed to create outbox runtime, delivery won't be stored: {}", e);
+                    None
+                }
+            };
+
+            outbox.run(|event| {
+                Self::record_delivery(event, message_repo.as_ref(), rt.as_ref(), app_state.as_deref())
+            });
+        })
+    }
+
+    /// Wind down for application exit
+    ///
+    /// Closes unfinished file transfers (those under way are paused to resume
+    /// after the restart, attachments not started on are released with
+    /// IPMSG_RELEASEFILES) and stops the outbox thread, which first stores the
+    /// delivery state changes still queued and marks the messages still
+    /// awaiting a receipt as failed. Call after incoming messages stopped
+    /// being routed here, so their receipts are recorded too.
+    pub fn shutdown(&self) {
+        if let Some(ref file_transfer) = self.file_transfer {
+            file_transfer.release_all();
+        }
+        self.outbox.stop();
+    }
+
+    /// Record a delivery state change of a sent message
+    ///
+    /// # Arguments
+    /// * `event` - Delivery state change
+    /// * `repo` - Message repository (nothing is stored without it)
+    /// * `rt` - Runtime for the database calls
+    /// * `app_state` - Application state for emitting events
+    fn record_delivery(
+        event: OutboxEvent,
+        repo: Option<&MessageRepository>,
+        rt: Option<&tokio::runtime::Runtime>,
+        app_state: Option<&AppState>,
+    ) {
+        let store = |what: &str, result: Result<()>| {
+            if let Err(e) = result {
+                tracing::warn!("Failed to record {}: {:?}", what, e);
+            }
+        };
+
+        match event {
+            OutboxEvent::Sent(record) => {
+                if let (Some(repo), Some(rt)) = (repo, rt) {
+                    store("sent message", rt.block_on(repo.insert(&record)).map(|_| ()));
+                }
+            }
+            OutboxEvent::Delivered { packet_id, .. } => {
+                if let (Some(repo), Some(rt)) = (repo, rt) {
+                    store("delivery", rt.block_on(repo.mark_as_delivered(&packet_id.to_string())));
+                }
+            }
+            OutboxEvent::Failed { packet_id, peer_ip, attempts } => {
+                if let (Some(repo), Some(rt)) = (repo, rt) {
+                    store("failed delivery", rt.block_on(repo.mark_as_failed(&packet_id.to_string())));
+                }
+
+                if let Some(app_state) = app_state {
+                    app_state.emit_tauri_event(TauriEvent::MessageDeliveryFailed {
+                        msg_id: packet_id.to_string(),
+                        receiver_ip: peer_ip.to_string(),
+                        attempts,
+                        failed_at: Utc::now().timestamp_millis(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Get the outbox of unacknowledged messages
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
     /// Send a text message to a target peer
     ///
+    /// The message requests a receipt (IPMSG_SENDCHECKOPT) and is
+    /// retransmitted until one arrives (see [`start_outbox`](Self::start_outbox)).
+    ///
+    /// Text longer than one datagram ([`MAX_DATAGRAM_TEXT`] encoded bytes) is
+    /// sent in chunks to peers that advertise IPMSG_CAPCHUNKOPT. Other peers
+    /// get it truncated, reported with a `MessageTruncated` event.
+    ///
     /// # Arguments
     /// * `target_ip` - IP address of the target peer
     /// * `content` - Text content to send
     ///
     /// # Returns
-    /// * `Ok(())` - Message sent successfully
+    /// * `Ok(u64)` - Message sent successfully, returns its packet ID (of the
+    ///   first chunk when chunked)
     /// * `Err(NeoLanError)` - Send failed
     ///
     /// # Examples
@@ -135,13 +261,13 @@ impl MessageHandler {
     /// # use std::net::IpAddr;
     /// let udp = UdpTransport::bind(2425)?;
     /// let config = AppConfig::default();
-    /// let handler = MessageHandler::new(udp, config);
+    /// let handler = MessageHandler::new(udp.sender(), config);
     /// let target_ip = "192.168.1.100".parse::<IpAddr>().unwrap();
     /// handler.send_text_message(target_ip, "Hello, World!")?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     #[instrument(skip(self), fields(target_ip = %target_ip, content_len = content.len()))]
-    pub fn send_text_message(&self, target_ip: IpAddr, content: &str) -> Result<()> {
+    pub fn send_text_message(&self, target_ip: IpAddr, content: &str) -> Result<u64> {
         tracing::info!(
             "Sending text message to {}: {}",
             target_ip,
@@ -155,8 +281,105 @@ impl MessageHandler {
             ));
         }
 
-        // Create target peer info (use configured UDP port)
-        let target_peer = PeerInfo::new(target_ip, self.config.udp_port, None);
+        // Text that doesn't fit in one datagram: chunks for NeoLan peers, truncated for others
+        let encoding = self.peer_encoding(target_ip);
+        let original_size = encoding.encode(content).len();
+        if original_size > MAX_DATAGRAM_TEXT && self.peer_supports_chunks(target_ip) {
+            return self.send_chunked_text(target_ip, content, encoding);
+        }
+        let text = truncate_text(content, MAX_DATAGRAM_TEXT, encoding);
+
+        // Convert to protocol message with SENDCHECKOPT flag
+        // This tells the receiver to send back an IPMSG_RECVMSG acknowledgment
+        let packet_id = self.next_packet_id();
+        let mut proto_msg = self.text_packet(target_ip, packet_id, text.to_string(), &[Opt::SendCheck])?;
+        Self::apply_encoding(&mut proto_msg, encoding)?;
+
+        // Serialize to bytes
+        let bytes = serialize_message(&proto_msg)?;
+
+        // Resend until the receiver acknowledges (tracked first: the receipt may beat `send_to` back)
+        let target_addr = self.peer_addr(target_ip);
+        let record = self.sent_record(packet_id, target_ip, proto_msg.msg_type, text);
+        self.outbox.track(&proto_msg, target_addr, record)?;
+
+        // Send via UDP
+        if let Err(e) = self.udp.send_to(&bytes, target_addr) {
+            self.outbox.untrack(packet_id);
+            return Err(e);
+        }
+
+        if text.len() < content.len() {
+            self.report_truncation(packet_id, target_ip, original_size, encoding.encode(text).len());
+        }
+
+        tracing::debug!("Message sent successfully to {}", target_ip);
+        Ok(packet_id)
+    }
+
+    /// Send text too long for one datagram in chunks (IPMSG_CHUNKOPT)
+    ///
+    /// Every chunk requests its own receipt; the outbox tracks them as one
+    /// message, identified by the first chunk's packet ID.
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - Packet ID of the first chunk
+    /// * `Err(NeoLanError::Validation)` - The text needs more than [`MAX_CHUNKS`] chunks
+    fn send_chunked_text(&self, target_ip: IpAddr, content: &str, encoding: TextEncoding) -> Result<u64> {
+        let pieces = split_text(content, MAX_DATAGRAM_TEXT, encoding);
+        if pieces.len() > MAX_CHUNKS {
+            return Err(NeoLanError::Validation(format!(
+                "Message too long: {} chunks needed (at most {})",
+                pieces.len(),
+                MAX_CHUNKS
+            )));
+        }
+
+        // Build every chunk before sending any, so a failure leaves no stray chunks
+        let message_id = self.next_packet_id();
+        let mut chunks = Vec::with_capacity(pieces.len());
+        let mut packets = Vec::with_capacity(pieces.len());
+        for (index, text) in pieces.iter().enumerate() {
+            let packet_id = if index == 0 { message_id } else { self.next_packet_id() };
+            let payload = Payload::Chunk {
+                message_id,
+                index,
+                count: pieces.len(),
+                text: text.to_string(),
+            };
+            let mut chunk = self.text_packet(target_ip, packet_id, payload.encode().0, &[Opt::SendCheck, Opt::Chunk])?;
+            Self::apply_encoding(&mut chunk, encoding)?;
+            packets.push(serialize_message(&chunk)?);
+            chunks.push(chunk);
+        }
+
+        // Stored as the whole text under an ordinary SENDMSG command
+        let target_addr = self.peer_addr(target_ip);
+        let msg_type = chunks[0].msg_type & !msg_type::IPMSG_CHUNKOPT;
+        let record = self.sent_record(message_id, target_ip, msg_type, content);
+        self.outbox.track_chunks(&chunks, target_addr, record)?;
+
+        for bytes in &packets {
+            if let Err(e) = self.udp.send_to(bytes, target_addr) {
+                self.outbox.untrack(message_id);
+                return Err(e);
+            }
+        }
+
+        tracing::debug!("Message sent to {} in {} chunks", target_ip, chunks.len());
+        Ok(message_id)
+    }
+
+    /// Build an IPMSG_SENDMSG packet for `target_ip`
+    ///
+    /// # Arguments
+    /// * `target_ip` - IP address of the target peer
+    /// * `packet_id` - Packet ID from the shared allocator
+    /// * `content` - Packet content
+    /// * `options` - Send options (IPMSG_SENDCHECKOPT, ...)
+    fn text_packet(&self, target_ip: IpAddr, packet_id: u64, content: String, options: &[Opt]) -> Result<ProtocolMessage> {
+        // Create target peer info
+        let target_peer = PeerInfo::new(target_ip, self.peer_addr(target_ip).port(), None);
 
         // Create sender peer info (local)
         let sender_peer = PeerInfo::new(
@@ -167,34 +390,57 @@ impl MessageHandler {
             Some(self.config.username.clone()),
         );
 
-        // Create application layer message with proper packet ID from counter
         let message = Message {
             id: uuid::Uuid::new_v4(),
-            packet_id: self.next_packet_id().to_string(),
+            packet_id: packet_id.to_string(),
             sender: sender_peer,
             receiver: target_peer,
             msg_type: MessageType::Text,
-            content: content.to_string(),
+            content,
             timestamp: chrono::Utc::now(),
         };
 
-        // Convert to protocol message with SENDCHECKOPT flag
-        // This tells the receiver to send back an IPMSG_RECVMSG acknowledgment
-        let proto_msg = message.to_protocol_with_options(
-            &self.config.username,
-            &self.config.hostname,
-            msg_type::IPMSG_SENDCHECKOPT, // Request acknowledgment
-        );
+        message.to_protocol_with_options(&self.config.username, &self.config.hostname, options)
+    }
 
-        // Serialize to bytes
-        let bytes = serialize_message(&proto_msg)?;
+    /// Database record of a message we sent, pending until acknowledged
+    fn sent_record(&self, packet_id: u64, target_ip: IpAddr, msg_type: u32, content: &str) -> MessageModel {
+        let now = Utc::now().naive_utc();
+        MessageModel {
+            id: 0, // Auto-increment
+            msg_id: packet_id.to_string(),
+            sender_ip: self.config.bind_ip.clone(),
+            sender_name: self.config.username.clone(),
+            receiver_ip: target_ip.to_string(),
+            msg_type: msg_type as i32,
+            content: content.to_string(),
+            is_encrypted: false,
+            is_offline: true, // Until acknowledged
+            sent_at: now,
+            received_at: None,
+            created_at: now,
+            delivery_status: Some(DELIVERY_PENDING.to_string()),
+        }
+    }
 
-        // Send via UDP (use configured UDP port)
-        let target_addr = SocketAddr::new(target_ip, self.config.udp_port);
-        self.udp.send_to(&bytes, target_addr)?;
+    /// Tell the frontend a message was cut to fit one datagram
+    fn report_truncation(&self, packet_id: u64, target_ip: IpAddr, original_size: usize, sent_size: usize) {
+        tracing::warn!(
+            "Peer {} can't take chunked messages: message {} truncated from {} to {} bytes",
+            target_ip,
+            packet_id,
+            original_size,
+            sent_size
+        );
 
-        tracing::debug!("Message sent successfully to {}", target_ip);
-        Ok(())
+        if let Some(ref app_state) = self.app_state {
+            app_state.emit_tauri_event(TauriEvent::MessageTruncated {
+                msg_id: packet_id.to_string(),
+                receiver_ip: target_ip.to_string(),
+                original_size,
+                sent_size,
+            });
+        }
     }
 
     /// Send a message to a target peer (generic method)
@@ -216,7 +462,7 @@ impl MessageHandler {
         tracing::debug!("Sending {:?} message to {}", msg_type, target_ip);
 
         // Create target and sender peer info
-        let target_peer = PeerInfo::new(target_ip, AppConfig::DEFAULT_UDP_PORT, None);
+        let target_peer = PeerInfo::new(target_ip, self.peer_addr(target_ip).port(), None);
         let sender_peer = PeerInfo::new(
             self.config.bind_ip.parse().map_err(|_| {
                 NeoLanError::Config(format!("Invalid bind IP: {}", self.config.bind_ip))
@@ -237,44 +483,78 @@ impl MessageHandler {
         };
 
         // Convert and send
-        let proto_msg = message.to_protocol(&self.config.username, &self.config.hostname);
+        let mut proto_msg = message.to_protocol(&self.config.username, &self.config.hostname);
+        Self::apply_encoding(&mut proto_msg, self.peer_encoding(target_ip))?;
         let bytes = serialize_message(&proto_msg)?;
-        let target_addr = SocketAddr::new(target_ip, AppConfig::DEFAULT_UDP_PORT);
+        let target_addr = self.peer_addr(target_ip);
         self.udp.send_to(&bytes, target_addr)?;
 
         Ok(())
     }
 
-    /// Get the next packet ID
+    /// Get the next packet ID (from the process-wide allocator)
     ///
     /// # Returns
     /// A monotonically increasing packet ID
     fn next_packet_id(&self) -> u64 {
-        self.packet_id_counter.fetch_add(1, Ordering::SeqCst)
+        next_packet_id()
     }
 
-    /// Get a reference to the UDP transport
+    /// Get the text encoding a peer expects
     ///
     /// # Returns
-    /// Reference to the inner UDP transport
-    pub fn udp(&self) -> &UdpTransport {
-        &self.udp
+    /// The encoding learned by the PeerManager, or UTF-8 for unknown peers
+    fn peer_encoding(&self, ip: IpAddr) -> TextEncoding {
+        self.app_state
+            .as_ref()
+            .and_then(|state| state.get_peer(ip))
+            .map(|peer| peer.encoding)
+            .unwrap_or_default()
     }
 
-    /// Get the current packet ID counter value
+    /// Check if a peer reassembles chunked messages
     ///
     /// # Returns
-    /// Current packet ID counter value
-    pub fn packet_id_counter(&self) -> u64 {
-        self.packet_id_counter.load(Ordering::SeqCst)
+    /// What the PeerManager learned from its presence packets, or `false`
+    /// for unknown peers
+    fn peer_supports_chunks(&self, ip: IpAddr) -> bool {
+        self.app_state
+            .as_ref()
+            .and_then(|state| state.get_peer(ip))
+            .is_some_and(|peer| peer.supports_chunks)
     }
 
-    /// Reset the packet ID counter to a specific value
+    /// Get the address of a peer's IPMsg port
     ///
-    /// # Arguments
-    /// * `value` - New counter value
-    pub fn reset_packet_id_counter(&self, value: u64) {
-        self.packet_id_counter.store(value, Ordering::SeqCst);
+    /// # Returns
+    /// The port the PeerManager last heard the peer from (with its IPv6
+    /// scope), or the IPMsg default port for unknown peers
+    fn peer_addr(&self, ip: IpAddr) -> SocketAddr {
+        self.app_state
+            .as_ref()
+            .and_then(|state| state.get_peer(ip))
+            .map(|peer| peer.socket_addr(peer.port))
+            .unwrap_or_else(|| SocketAddr::new(ip, AppConfig::DEFAULT_UDP_PORT))
+    }
+
+    /// Encode an outbound message with the given encoding
+    ///
+    /// UTF-8 packets also carry `IPMSG_UTF8OPT` so IPMsg clients don't
+    /// fall back to their local code page.
+    fn apply_encoding(proto_msg: &mut ProtocolMessage, encoding: TextEncoding) -> Result<()> {
+        proto_msg.encoding = Some(encoding);
+        if encoding == TextEncoding::Utf8 {
+            proto_msg.msg_type = proto_msg.command().with(Opt::Utf8)?.into();
+        }
+        Ok(())
+    }
+
+    /// Get the UDP send handle
+    ///
+    /// # Returns
+    /// Reference to the send handle messages go out through
+    pub fn udp(&self) -> &UdpSender {
+        &self.udp
     }
 
     // ==================== Message Receiving ====================
@@ -299,150 +579,210 @@ impl MessageHandler {
     /// - IPMSG_BR_EXIT (0x00000002) → Should be handled by PeerManager
     /// - IPMSG_ANSENTRY (0x00000003) → Should be handled by HeartbeatMonitor
     /// - Other types → Logged and ignored
-    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip, msg_type = %msg_type::get_mode(proto_msg.msg_type)))]
+    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip, msg_type = %proto_msg.command().mode().name()))]
     pub fn handle_incoming_message(
         &self,
         proto_msg: &ProtocolMessage,
         sender_ip: IpAddr,
         local_ip: IpAddr,
     ) -> Result<()> {
-        let mode = msg_type::get_mode(proto_msg.msg_type) as u32;
+        let command = proto_msg.command();
+        let mode = command.mode();
+
+        tracing::debug!("Handling incoming message: {}, from={}", command, sender_ip);
+
+        // Retransmission of a packet we already handled: acknowledge it again
+        // (our first RECVMSG may have been lost) but don't store / emit it twice
+        if self.recent_packets.check_and_insert(sender_ip, proto_msg.packet_id) {
+            tracing::debug!(
+                "🔁 Duplicate {} from {} (packet_id={}) - not processed again",
+                mode.name(),
+                sender_ip,
+                proto_msg.packet_id
+            );
+            if mode == Mode::SendMsg {
+                self.acknowledge(proto_msg, sender_ip)?;
+            }
+            return Ok(());
+        }
 
-        tracing::debug!(
-            "Handling incoming message: mode={}, from={}",
-            msg_type::get_mode(proto_msg.msg_type),
-            sender_ip
-        );
+        let result = self.dispatch(proto_msg, sender_ip, local_ip);
+        if result.is_err() {
+            // Let a retransmission try again
+            self.recent_packets.forget(sender_ip, proto_msg.packet_id);
+        }
+        result
+    }
 
-        match mode {
+    /// Decode a message's payload and pass it to the matching handler
+    fn dispatch(
+        &self,
+        proto_msg: &ProtocolMessage,
+        sender_ip: IpAddr,
+        local_ip: IpAddr,
+    ) -> Result<()> {
+        let mode = proto_msg.command().mode();
+
+        // Decode the content once; handlers get typed fields
+        let payload = Payload::decode(proto_msg).inspect_err(|e| {
+            tracing::warn!("⚠️ Malformed {} payload from {}: {}", mode.name(), sender_ip, e);
+            NetworkStats::global().record_parse_failure(sender_ip);
+        })?;
+
+        match payload {
             // ========== Text Messages ==========
             // IPMSG_SENDMSG: 发送消息
-            msg_type::IPMSG_SENDMSG => {
+            Payload::Text { attachments, .. } => {
                 tracing::debug!("📨 [handle_incoming_message] Routing to handle_text_message");
-                self.handle_text_message(proto_msg, sender_ip, local_ip)?;
+                self.handle_text_message(proto_msg, attachments, sender_ip, local_ip)?;
+            }
+
+            // IPMSG_SENDMSG | IPMSG_CHUNKOPT: 长消息的一个分片（NeoLan 扩展）
+            Payload::Chunk { message_id, index, count, text } => {
+                let complete = self.reassembler.insert(sender_ip, proto_msg, message_id, index, count, text)?;
+                self.handle_chunk(proto_msg, complete, sender_ip, local_ip)?;
             }
 
             // IPMSG_RECVMSG: 接收确认（对方已收到消息）
-            msg_type::IPMSG_RECVMSG => {
+            Payload::RecvMsg { original_packet_id } => {
                 tracing::debug!("📨 [handle_incoming_message] Routing to handle_recv_msg");
-                self.handle_recv_msg(proto_msg, sender_ip)?;
+                self.handle_recv_msg(proto_msg, original_packet_id, sender_ip)?;
             }
 
             // ========== Message Read/Delete Status ==========
             // IPMSG_READMSG: 消息已读
-            msg_type::IPMSG_READMSG => {
-                self.handle_read_msg(proto_msg, sender_ip)?;
+            Payload::ReadMsg { original_packet_id } => {
+                self.handle_read_msg(original_packet_id, sender_ip)?;
             }
 
             // IPMSG_DELMSG: 删除消息
-            msg_type::IPMSG_DELMSG => {
-                self.handle_del_msg(proto_msg, sender_ip)?;
+            Payload::DelMsg { original_packet_id } => {
+                self.handle_del_msg(original_packet_id, sender_ip)?;
             }
 
             // IPMSG_ANSREADMSG: 对已读消息的应答
-            msg_type::IPMSG_ANSREADMSG => {
-                self.handle_answer_read_msg(proto_msg, sender_ip)?;
+            Payload::AnsReadMsg { original_packet_id } => {
+                self.handle_answer_read_msg(original_packet_id, sender_ip)?;
             }
 
             // ========== Peer Discovery Messages ==========
+            // IPMSG_BR_ABSENCE: 广播缺席状态
+            Payload::Entry { nickname, .. } if mode == Mode::BrAbsence => {
+                tracing::info!("🏖️ Absence status broadcast from {} ({})", sender_ip, nickname);
+                // TODO: Update peer absence status in PeerManager
+            }
+
             // These should be handled by PeerManager through its own discovery callback
-            msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_BR_EXIT | msg_type::IPMSG_ANSENTRY => {
+            Payload::Entry { .. } => {
                 tracing::debug!(
-                    "📢 Peer discovery message (mode={}), delegating to PeerManager",
-                    mode
+                    "📢 Peer discovery message ({}), delegating to PeerManager",
+                    mode.name()
                 );
                 // PeerManager will handle these through its own discovery callback
                 // No action needed here - the message is already logged
             }
 
-            // IPMSG_BR_ABSENCE: 广播缺席状态
-            msg_type::IPMSG_BR_ABSENCE => {
-                tracing::info!("🏖️ Absence status broadcast from {}", sender_ip);
-                // TODO: Update peer absence status in PeerManager
-            }
-
             // ========== Peer List Management ==========
             // IPMSG_BR_ISGETLIST: 请求是否需要列表
-            msg_type::IPMSG_BR_ISGETLIST | msg_type::IPMSG_BR_ISGETLIST2 => {
+            Payload::IsGetList => {
                 tracing::info!("📋 Peer list request from {}", sender_ip);
                 // TODO: Send response with IPMSG_OKGETLIST
             }
 
             // IPMSG_OKGETLIST: 同意发送列表
-            msg_type::IPMSG_OKGETLIST => {
+            Payload::OkGetList => {
                 tracing::info!("✅ Peer list approval from {}", sender_ip);
                 // TODO: Proceed to send IPMSG_GETLIST
             }
 
             // IPMSG_GETLIST: 请求列表
-            msg_type::IPMSG_GETLIST => {
-                tracing::info!("📋 Get list request from {}", sender_ip);
+            Payload::GetList { start } => {
+                tracing::info!("📋 Get list request from {} (start={})", sender_ip, start);
                 // TODO: Send peer list with IPMSG_ANSLIST
             }
 
             // IPMSG_ANSLIST: 返回列表
-            msg_type::IPMSG_ANSLIST => {
-                self.handle_peer_list_response(proto_msg, sender_ip)?;
+            Payload::AnsList { start, entries } => {
+                self.handle_peer_list_response(start, &entries, sender_ip)?;
             }
 
             // ========== User Information ==========
             // IPMSG_GETINFO: 请求用户信息
-            msg_type::IPMSG_GETINFO => {
+            Payload::GetInfo => {
                 tracing::info!("ℹ️ User info request from {}", sender_ip);
                 // TODO: Send user info with IPMSG_SENDINFO
             }
 
             // IPMSG_SENDINFO: 发送用户信息
-            msg_type::IPMSG_SENDINFO => {
-                self.handle_user_info(proto_msg, sender_ip)?;
+            Payload::SendInfo { version } => {
+                self.handle_user_info(&version, sender_ip)?;
             }
 
             // ========== Absence Information ==========
             // IPMSG_GETABSENCEINFO: 请求缺席信息
-            msg_type::IPMSG_GETABSENCEINFO => {
+            Payload::GetAbsenceInfo => {
                 tracing::info!("🏖️ Absence info request from {}", sender_ip);
                 // TODO: Send absence info with IPMSG_SENDABSENCEINFO
             }
 
             // IPMSG_SENDABSENCEINFO: 发送缺席信息
-            msg_type::IPMSG_SENDABSENCEINFO => {
-                self.handle_absence_info(proto_msg, sender_ip)?;
+            Payload::AbsenceInfo { text } => {
+                self.handle_absence_info(&text, sender_ip)?;
             }
 
             // ========== File Transfer ==========
-            // IPMSG_GETFILEDATA: 请求文件数据（文件传输）
-            msg_type::IPMSG_GETFILEDATA => {
-                self.handle_file_transfer_request(proto_msg, sender_ip)?;
+            // IPMSG_GETFILEDATA: 请求文件数据（NeoLan JSON 文件请求）
+            Payload::FileOffer(file_request) => {
+                self.handle_file_transfer_request(proto_msg, &file_request, sender_ip)?;
+            }
+
+            // IPMSG_GETFILEDATA: 标准 IPMsg 文件数据请求（应通过 TCP 发送）
+            Payload::GetFileData(request) => {
+                tracing::warn!(
+                    "⚠️ IPMSG_GETFILEDATA over UDP from {} ignored (expected on TCP): {:?}",
+                    sender_ip,
+                    request
+                );
             }
 
             // IPMSG_RELEASEFILES: 释放文件资源
-            msg_type::IPMSG_RELEASEFILES => {
-                self.handle_release_files(proto_msg, sender_ip)?;
+            Payload::ReleaseFiles { packet_id } => {
+                self.handle_release_files(packet_id, sender_ip)?;
+            }
+
+            // IPMSG_RELEASEFILES: NeoLan JSON 应答
+            Payload::FileReply(response) => {
+                self.handle_file_reply(&response, sender_ip);
             }
 
             // IPMSG_GETDIRFILES: 请求目录文件列表
-            msg_type::IPMSG_GETDIRFILES => {
-                tracing::info!("📁 Directory file list request from {}", sender_ip);
+            Payload::GetDirFiles { packet_id, file_id } => {
+                tracing::info!(
+                    "📁 Directory file list request from {}: packet_id={}, file_id={}",
+                    sender_ip,
+                    packet_id,
+                    file_id
+                );
                 // TODO: Handle directory file list request
             }
 
             // ========== Encryption ==========
             // IPMSG_GETPUBKEY: 请求公钥
-            msg_type::IPMSG_GETPUBKEY => {
-                tracing::info!("🔑 Public key request from {}", sender_ip);
+            Payload::GetPubKey { caps } => {
+                tracing::info!("🔑 Public key request from {} (caps=0x{:x})", sender_ip, caps);
                 // TODO: Send public key with IPMSG_ANSPUBKEY
             }
 
             // IPMSG_ANSPUBKEY: 应答公钥
-            msg_type::IPMSG_ANSPUBKEY => {
-                self.handle_public_key_response(proto_msg, sender_ip)?;
+            Payload::PubKey { caps, exponent, modulus } => {
+                self.handle_public_key_response(caps, exponent, &modulus, sender_ip)?;
             }
 
             // ========== Unknown Message Types ==========
-            _ => {
+            Payload::NoOperation | Payload::Unknown { .. } => {
                 tracing::warn!(
                     "⚠️ Unhandled message type: mode=0x{:02x} from {}, content={}",
-                    mode,
+                    mode.as_u8(),
                     sender_ip,
                     proto_msg.content.chars().take(50).collect::<String>()
                 );
@@ -458,12 +798,14 @@ impl MessageHandler {
     ///
     /// # Arguments
     /// * `proto_msg` - Protocol message
+    /// * `attachments` - Decoded file attachments (empty without IPMSG_FILEATTACHOPT)
     /// * `sender_ip` - Sender's IP address
     /// * `local_ip` - Local IP address (receiver)
-    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip, sender_name = %proto_msg.sender_name, packet_id = %proto_msg.packet_id))]
+    #[instrument(skip(self, proto_msg, attachments), fields(sender_ip = %sender_ip, sender_name = %proto_msg.sender_name, packet_id = %proto_msg.packet_id))]
     fn handle_text_message(
         &self,
         proto_msg: &ProtocolMessage,
+        attachments: Vec<FileAttachment>,
         sender_ip: IpAddr,
         local_ip: IpAddr,
     ) -> Result<()> {
@@ -475,6 +817,122 @@ impl MessageHandler {
             proto_msg.content.chars().take(100).collect::<String>()
         );
 
+        // Standard IPMsg file attachments (IPMSG_FILEATTACHOPT): text\0attachments\0
+        let has_attachments = !attachments.is_empty();
+        if has_attachments {
+            self.handle_file_attachments(proto_msg, attachments, sender_ip);
+        }
+
+        if has_attachments && proto_msg.content.is_empty() {
+            tracing::debug!("📎 Attachment-only message - no text to store");
+        } else {
+            self.store_and_emit_text(proto_msg, sender_ip, local_ip)?;
+        }
+
+        self.acknowledge(proto_msg, sender_ip)
+    }
+
+    /// Handle one chunk of a long message (IPMSG_SENDMSG | IPMSG_CHUNKOPT)
+    ///
+    /// Each chunk is acknowledged on its own; the text is stored and shown
+    /// once the last chunk arrives.
+    ///
+    /// # Arguments
+    /// * `proto_msg` - The chunk
+    /// * `complete` - The whole message, if this chunk completed it
+    /// * `sender_ip` - Sender's IP address
+    /// * `local_ip` - Local IP address (receiver)
+    fn handle_chunk(
+        &self,
+        proto_msg: &ProtocolMessage,
+        complete: Option<ProtocolMessage>,
+        sender_ip: IpAddr,
+        local_ip: IpAddr,
+    ) -> Result<()> {
+        if let Some(ref message) = complete {
+            tracing::info!(
+                "💬 Chunked message {} from {} complete ({} bytes)",
+                message.packet_id,
+                sender_ip,
+                message.content.len()
+            );
+            self.store_and_emit_text(message, sender_ip, local_ip)?;
+        }
+
+        self.acknowledge(proto_msg, sender_ip)
+    }
+
+    /// Send an IPMSG_RECVMSG acknowledgment if the message has SENDCHECKOPT
+    ///
+    /// This tells the sender that we received their message. Called again for
+    /// retransmitted duplicates, since the first acknowledgment may have been lost.
+    ///
+    /// # Arguments
+    /// * `proto_msg` - Received text message
+    /// * `sender_ip` - Sender's IP address
+    fn acknowledge(&self, proto_msg: &ProtocolMessage, sender_ip: IpAddr) -> Result<()> {
+        if proto_msg.command().has(Opt::SendCheck) {
+            let ack_packet_id = self.next_packet_id();
+            tracing::info!("📤 [handle_text_message] Sending IPMSG_RECVMSG acknowledgment to {}: original_msg_id={}, ack_msg_id={}",
+                sender_ip, proto_msg.packet_id, ack_packet_id);
+
+            // Create acknowledgment message
+            let ack_msg = Message {
+                id: uuid::Uuid::new_v4(),
+                packet_id: ack_packet_id.to_string(),
+                sender: PeerInfo::new(
+                    self.config.bind_ip.parse().map_err(|_| {
+                        NeoLanError::Config(format!("Invalid bind IP: {}", self.config.bind_ip))
+                    })?,
+                    self.config.udp_port,
+                    Some(self.config.username.clone()),
+                ),
+                receiver: PeerInfo::new(sender_ip, self.peer_addr(sender_ip).port(), None),
+                msg_type: MessageType::RecvAck,  // This will map to IPMSG_RECVMSG
+                content: Payload::RecvMsg { original_packet_id: proto_msg.packet_id }.encode().0,  // Send back the original packet ID
+                timestamp: chrono::Utc::now(),
+            };
+
+            // Convert to protocol message, answering in the sender's encoding
+            let mut proto_ack = ack_msg.to_protocol(&self.config.username, &self.config.hostname);
+            Self::apply_encoding(
+                &mut proto_ack,
+                proto_msg.encoding.unwrap_or_else(|| self.peer_encoding(sender_ip)),
+            )?;
+
+            tracing::debug!("📤 [handle_text_message] ACK protocol msg_type=0x{:08x}, packet_id={}",
+                proto_ack.msg_type, proto_ack.packet_id);
+
+            // Serialize and send
+            let bytes = serialize_message(&proto_ack)?;
+            let target_addr = self.peer_addr(sender_ip);
+
+            tracing::debug!("📤 [handle_text_message] Sending ACK to {}, bytes_len={}",
+                target_addr, bytes.len());
+
+            self.udp.send_to(&bytes, target_addr)?;
+
+            tracing::debug!("✅ [handle_text_message] IPMSG_RECVMSG acknowledgment sent successfully");
+        } else {
+            tracing::debug!("ℹ️ [handle_text_message] No SENDCHECKOPT flag (msg_type=0x{:08x}) - skipping acknowledgment",
+                proto_msg.msg_type);
+        }
+
+        Ok(())
+    }
+
+    /// Store a received text message and notify the frontend
+    ///
+    /// # Arguments
+    /// * `proto_msg` - Protocol message
+    /// * `sender_ip` - Sender's IP address
+    /// * `local_ip` - Local IP address (receiver)
+    fn store_and_emit_text(
+        &self,
+        proto_msg: &ProtocolMessage,
+        sender_ip: IpAddr,
+        local_ip: IpAddr,
+    ) -> Result<()> {
         // Only store if message repository is available
         if let Some(ref repo) = self.message_repo {
             let message_model = MessageModel {
@@ -485,11 +943,12 @@ impl MessageHandler {
                 receiver_ip: local_ip.to_string(),
                 msg_type: proto_msg.msg_type as i32,
                 content: proto_msg.content.clone(),
-                is_encrypted: msg_type::has_opt(proto_msg.msg_type, msg_type::IPMSG_ENCRYPTOPT),
+                is_encrypted: proto_msg.command().has(Opt::Encrypt),
                 is_offline: false,
                 sent_at: Utc::now().naive_utc(),
                 received_at: Some(Utc::now().naive_utc()),
                 created_at: Utc::now().naive_utc(),
+                delivery_status: None,
             };
 
             // Store to database (blocking - should be async in production)
@@ -516,7 +975,7 @@ impl MessageHandler {
                 receiver_ip: local_ip.to_string(),
                 content: proto_msg.content.clone(),
                 msg_type: proto_msg.msg_type as i32,
-                is_encrypted: msg_type::has_opt(proto_msg.msg_type, msg_type::IPMSG_ENCRYPTOPT),
+                is_encrypted: proto_msg.command().has(Opt::Encrypt),
                 is_offline: false,
                 sent_at: now.timestamp_millis(),
                 received_at: Some(now.timestamp_millis()),
@@ -532,51 +991,44 @@ impl MessageHandler {
             tracing::warn!("⚠️ App state not available - cannot emit message-received event");
         }
 
-        // Send IPMSG_RECVMSG acknowledgment if message has SENDCHECKOPT flag
-        // This tells the sender that we received their message
-        if msg_type::has_opt(proto_msg.msg_type, msg_type::IPMSG_SENDCHECKOPT) {
-            tracing::info!("📤 [handle_text_message] Sending IPMSG_RECVMSG acknowledgment to {}: original_msg_id={}, ack_msg_id={}",
-                sender_ip, proto_msg.packet_id, self.next_packet_id());
-
-            // Create acknowledgment message
-            let ack_msg = Message {
-                id: uuid::Uuid::new_v4(),
-                packet_id: self.next_packet_id().to_string(),
-                sender: PeerInfo::new(
-                    self.config.bind_ip.parse().map_err(|_| {
-                        NeoLanError::Config(format!("Invalid bind IP: {}", self.config.bind_ip))
-                    })?,
-                    self.config.udp_port,
-                    Some(self.config.username.clone()),
-                ),
-                receiver: PeerInfo::new(sender_ip, self.config.udp_port, None),
-                msg_type: MessageType::RecvAck,  // This will map to IPMSG_RECVMSG
-                content: proto_msg.packet_id.to_string(),  // Send back the original packet ID
-                timestamp: chrono::Utc::now(),
-            };
-
-            // Convert to protocol message
-            let proto_ack = ack_msg.to_protocol(&self.config.username, &self.config.hostname);
-
-            tracing::debug!("📤 [handle_text_message] ACK protocol msg_type=0x{:08x}, packet_id={}",
-                proto_ack.msg_type, proto_ack.packet_id);
-
-            // Serialize and send
-            let bytes = serialize_message(&proto_ack)?;
-            let target_addr = SocketAddr::new(sender_ip, self.config.udp_port);
+        Ok(())
+    }
 
-            tracing::debug!("📤 [handle_text_message] Sending ACK to {}, bytes_len={}",
-                target_addr, bytes.len());
+    /// Handle file attachments (IPMSG_SENDMSG | IPMSG_FILEATTACHOPT)
+    ///
+    /// Parses the standard IPMsg attachment section and emits one
+    /// file-transfer-request event per file for user confirmation.
+    ///
+    /// # Arguments
+    /// * `proto_msg` - Protocol message carrying the attachment section
+    /// * `attachments` - Decoded attachment entries
+    /// * `sender_ip` - Sender's IP address
+    fn handle_file_attachments(
+        &self,
+        proto_msg: &ProtocolMessage,
+        attachments: Vec<FileAttachment>,
+        sender_ip: IpAddr,
+    ) {
+        let Some(ref handler) = self.file_transfer else {
+            tracing::warn!("File transfer handler not available - cannot handle file attachments");
+            return;
+        };
 
-            self.udp.send_to(&bytes, target_addr)?;
+        let pending_requests = handler.handle_incoming_attachments(proto_msg, attachments, sender_ip);
 
-            tracing::debug!("✅ [handle_text_message] IPMSG_RECVMSG acknowledgment sent successfully");
+        if let Some(ref app_state) = self.app_state {
+            for pending in &pending_requests {
+                app_state.emit_tauri_event(handler.to_event(pending));
+                tracing::info!(
+                    "📎 Emitted file-transfer-request event: requestId={}, file={}, file_id={:?}",
+                    pending.id,
+                    pending.file_name,
+                    pending.file_id
+                );
+            }
         } else {
-            tracing::debug!("ℹ️ [handle_text_message] No SENDCHECKOPT flag (msg_type=0x{:08x}) - skipping acknowledgment",
-                proto_msg.msg_type);
+            tracing::warn!("App state not available - cannot notify user of file attachments");
         }
-
-        Ok(())
     }
 
     /// Handle a file transfer request (IPMSG_GETFILEDATA)
@@ -585,23 +1037,25 @@ impl MessageHandler {
     ///
     /// # Arguments
     /// * `proto_msg` - Protocol message containing the file request
+    /// * `file_request` - Decoded file offer
     /// * `sender_ip` - Sender's IP address
     fn handle_file_transfer_request(
         &self,
         proto_msg: &ProtocolMessage,
+        file_request: &FileSendRequest,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "File transfer request from {} ({}): {}",
             proto_msg.sender_name,
             sender_ip,
-            proto_msg.content.chars().take(50).collect::<String>()
+            file_request.name
         );
 
         // Only handle if file transfer response handler is available
         if let Some(ref handler) = self.file_transfer {
             // Parse the request
-            let pending = handler.handle_incoming_request(proto_msg, sender_ip)?;
+            let pending = handler.handle_incoming_request(proto_msg, file_request, sender_ip);
 
             // Emit Tauri event for user confirmation
             if let Some(ref app_state) = self.app_state {
@@ -622,45 +1076,77 @@ impl MessageHandler {
         Ok(())
     }
 
+    /// Handle a NeoLan reply to one of our file offers (IPMSG_RELEASEFILES + JSON)
+    ///
+    /// An accepted offer starts pushing the file, a rejected one is cancelled.
+    ///
+    /// # Arguments
+    /// * `response` - Decoded reply
+    /// * `sender_ip` - Sender's IP address
+    fn handle_file_reply(&self, response: &FileSendResponse, sender_ip: IpAddr) {
+        tracing::debug!("📨 File transfer reply from {}: accept={}", sender_ip, response.accept);
+
+        let Some(ref handler) = self.file_transfer else {
+            tracing::warn!("File transfer handler not available - cannot handle file transfer reply");
+            return;
+        };
+
+        if let Err(e) = handler.handle_reply(response, self.peer_addr(sender_ip)) {
+            tracing::warn!("Ignoring file transfer reply from {}: {}", sender_ip, e);
+        }
+    }
+
     // ==================== Additional Message Handlers ====================
 
     /// Handle receive message acknowledgment (IPMSG_RECVMSG)
     ///
-    /// The peer has confirmed receipt of a message we sent.
+    /// The peer has confirmed receipt of a message we sent, which stops its
+    /// retransmission and marks it delivered. Receipts for the chunks of a
+    /// chunked message are reported once, with the last one.
     ///
     /// # Arguments
     /// * `proto_msg` - Protocol message
+    /// * `original_packet_id` - Packet ID of our acknowledged message
     /// * `sender_ip` - Sender's IP address
     #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip, packet_id = %proto_msg.packet_id))]
     fn handle_recv_msg(
         &self,
         proto_msg: &ProtocolMessage,
+        original_packet_id: u64,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
-            "✅ [handle_recv_msg] Message receipt acknowledged from {}: msg_id={}, content={}",
+            "✅ [handle_recv_msg] Message receipt acknowledged from {}: msg_id={}, original_msg_id={}",
             sender_ip,
             proto_msg.packet_id,
-            proto_msg.content
+            original_packet_id
         );
 
+        // Stop retransmitting; the outbox thread marks it delivered in the database
+        let message_id = self.outbox.message_id(original_packet_id).unwrap_or(original_packet_id);
+        if !self.outbox.acknowledge(original_packet_id, sender_ip) {
+            tracing::debug!("Receipt for a message that isn't pending: msg_id={}", original_packet_id);
+        } else if self.outbox.is_pending(message_id) {
+            tracing::debug!("Chunk {} of message {} acknowledged", original_packet_id, message_id);
+            return Ok(());
+        }
+
         // Emit Tauri event for message receipt acknowledgment
         if let Some(ref app_state) = self.app_state {
             let now = Utc::now();
             let event = TauriEvent::MessageReceiptAck {
-                msg_id: proto_msg.content.clone(),  // Content contains the original message ID
+                msg_id: message_id.to_string(),
                 sender_ip: sender_ip.to_string(),
                 sender_name: proto_msg.sender_name.clone(),
                 acknowledged_at: now.timestamp_millis(),
             };
             app_state.emit_tauri_event(event);
             tracing::info!("✅ [handle_recv_msg] Emitted message-receipt-ack event to frontend: msg_id={}, from={}",
-                proto_msg.content, sender_ip);
+                message_id, sender_ip);
         } else {
             tracing::warn!("⚠️ [handle_recv_msg] App state not available - cannot emit message-receipt-ack event");
         }
 
-        // TODO: Update message status in database to "delivered"
         Ok(())
     }
 
@@ -669,18 +1155,18 @@ impl MessageHandler {
     /// The peer has read a message we sent.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message
+    /// * `original_packet_id` - Packet ID of the message that was read
     /// * `sender_ip` - Sender's IP address
-    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip))]
+    #[instrument(skip(self), fields(sender_ip = %sender_ip))]
     fn handle_read_msg(
         &self,
-        proto_msg: &ProtocolMessage,
+        original_packet_id: u64,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "📖 Message read by {}: msg_id={}",
             sender_ip,
-            proto_msg.packet_id
+            original_packet_id
         );
         // TODO: Update message status in database to "read"
         // TODO: Emit Tauri event for frontend notification
@@ -692,18 +1178,18 @@ impl MessageHandler {
     /// The peer wants to delete a message.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message
+    /// * `original_packet_id` - Packet ID of the message to delete
     /// * `sender_ip` - Sender's IP address
-    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip))]
+    #[instrument(skip(self), fields(sender_ip = %sender_ip))]
     fn handle_del_msg(
         &self,
-        proto_msg: &ProtocolMessage,
+        original_packet_id: u64,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "🗑️ Delete message request from {}: msg_id={}",
             sender_ip,
-            proto_msg.packet_id
+            original_packet_id
         );
         // TODO: Mark message as deleted in database
         // TODO: Emit Tauri event for frontend update
@@ -715,18 +1201,18 @@ impl MessageHandler {
     /// Response to a read message confirmation.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message
+    /// * `original_packet_id` - Packet ID of our read notification
     /// * `sender_ip` - Sender's IP address
-    #[instrument(skip(self, proto_msg), fields(sender_ip = %sender_ip))]
+    #[instrument(skip(self), fields(sender_ip = %sender_ip))]
     fn handle_answer_read_msg(
         &self,
-        proto_msg: &ProtocolMessage,
+        original_packet_id: u64,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "📨 Read answer from {}: msg_id={}",
             sender_ip,
-            proto_msg.packet_id
+            original_packet_id
         );
         // TODO: Handle read answer confirmation
         Ok(())
@@ -737,21 +1223,33 @@ impl MessageHandler {
     /// Response containing the list of peers.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message containing peer list
+    /// * `start` - List position of this chunk
+    /// * `entries` - Decoded host entries
     /// * `sender_ip` - Sender's IP address
     fn handle_peer_list_response(
         &self,
-        proto_msg: &ProtocolMessage,
+        start: usize,
+        entries: &[HostListEntry],
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
-            "📋 [PEER LIST] Received peer list from {}: count={}",
+            "📋 [PEER LIST] Received peer list from {}: start={}, count={}",
             sender_ip,
-            proto_msg.content.lines().count()
+            start,
+            entries.len()
         );
-        // TODO: Parse peer list content and update peer database
-        // Format: Each line contains peer information
-        tracing::debug!("Peer list content:\n{}", proto_msg.content);
+        for entry in entries {
+            tracing::debug!(
+                "📋 [PEER LIST] {}@{} ({}:{}) nickname={}, group={}",
+                entry.user_name,
+                entry.host_name,
+                entry.address,
+                entry.port,
+                entry.nickname,
+                entry.group
+            );
+        }
+        // TODO: Update peer database from the list entries
         Ok(())
     }
 
@@ -760,19 +1258,19 @@ impl MessageHandler {
     /// Response containing user information.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message containing user info
+    /// * `version` - Peer's client version string
     /// * `sender_ip` - Sender's IP address
     fn handle_user_info(
         &self,
-        proto_msg: &ProtocolMessage,
+        version: &str,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "ℹ️ [USER INFO] User info from {}: {}",
             sender_ip,
-            proto_msg.content.chars().take(100).collect::<String>()
+            version.chars().take(100).collect::<String>()
         );
-        // TODO: Parse user info and update peer information
+        // TODO: Update peer information
         Ok(())
     }
 
@@ -781,19 +1279,19 @@ impl MessageHandler {
     /// Response containing absence reason.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message containing absence info
+    /// * `text` - Absence message
     /// * `sender_ip` - Sender's IP address
     fn handle_absence_info(
         &self,
-        proto_msg: &ProtocolMessage,
+        text: &str,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "🏖️ [ABSENCE] Absence info from {}: {}",
             sender_ip,
-            proto_msg.content.chars().take(100).collect::<String>()
+            text.chars().take(100).collect::<String>()
         );
-        // TODO: Parse absence info and update peer status
+        // TODO: Update peer status
         Ok(())
     }
 
@@ -802,19 +1300,22 @@ impl MessageHandler {
     /// The peer has released file transfer resources.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message
+    /// * `packet_id` - Packet ID of the released attachment message
     /// * `sender_ip` - Sender's IP address
     fn handle_release_files(
         &self,
-        proto_msg: &ProtocolMessage,
+        packet_id: u64,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
             "🔄 [RELEASE] File release notification from {}: msg_id={}",
             sender_ip,
-            proto_msg.packet_id
+            packet_id
         );
-        // TODO: Clean up file transfer resources
+
+        if let Some(ref handler) = self.file_transfer {
+            handler.handle_release(packet_id, sender_ip);
+        }
         // TODO: Emit Tauri event for frontend update
         Ok(())
     }
@@ -824,19 +1325,25 @@ impl MessageHandler {
     /// Response containing the peer's public key for encryption.
     ///
     /// # Arguments
-    /// * `proto_msg` - Protocol message containing public key
+    /// * `caps` - Peer's encryption capabilities
+    /// * `exponent` - RSA public exponent
+    /// * `modulus` - RSA modulus (hex)
     /// * `sender_ip` - Sender's IP address
     fn handle_public_key_response(
         &self,
-        proto_msg: &ProtocolMessage,
+        caps: u32,
+        exponent: u32,
+        modulus: &str,
         sender_ip: IpAddr,
     ) -> Result<()> {
         tracing::info!(
-            "🔑 [PUBLIC KEY] Public key received from {}: length={}",
+            "🔑 [PUBLIC KEY] Public key received from {}: caps=0x{:x}, exponent=0x{:x}, bits={}",
             sender_ip,
-            proto_msg.content.len()
+            caps,
+            exponent,
+            modulus.len() * 4
         );
-        // TODO: Parse and store public key for encrypted messaging
+        // TODO: Store public key for encrypted messaging
         // TODO: Update peer encryption capability
         Ok(())
     }
@@ -863,6 +1370,9 @@ impl MessageHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::modules::peer::discovery::PeerDiscovery;
+    use crate::modules::peer::{PeerManager, PeerNode};
+    use crate::network::{Command, UdpTransport};
 
     fn create_test_config() -> AppConfig {
         AppConfig {
@@ -874,65 +1384,195 @@ mod tests {
         }
     }
 
+    /// A handler whose PeerManager knows 127.0.0.1 at `peer_port`
+    fn peer_handler(peer_port: u16) -> MessageHandler {
+        let state = Arc::new(AppState::new(create_test_config()));
+        state.init_peer_manager(PeerManager::new(PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        )));
+        let peer = PeerNode::new("127.0.0.1".parse().unwrap(), peer_port);
+        state.get_peer_manager().unwrap().add_peer(peer).unwrap();
+        MessageHandler::new(UdpTransport::bind(0).unwrap().sender(), create_test_config()).with_app_state(state)
+    }
+
     #[test]
     fn test_message_handler_new() {
         let udp = UdpTransport::bind(0).unwrap();
         let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+        let handler = MessageHandler::new(udp.sender(), config);
 
-        assert_eq!(handler.packet_id_counter(), 1);
+        assert!(handler.recent_packets.is_empty());
     }
 
     #[test]
     fn test_next_packet_id() {
         let udp = UdpTransport::bind(0).unwrap();
         let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+        let handler = MessageHandler::new(udp.sender(), config);
 
         let id1 = handler.next_packet_id();
         let id2 = handler.next_packet_id();
         let id3 = handler.next_packet_id();
 
-        assert_eq!(id1, 1);
-        assert_eq!(id2, 2);
-        assert_eq!(id3, 3);
+        // Shared with every other sender in the process: unique and increasing
+        assert!(id2 > id1);
+        assert!(id3 > id2);
     }
 
     #[test]
-    fn test_packet_id_counter() {
-        let udp = UdpTransport::bind(0).unwrap();
-        let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+    fn test_peer_addr_uses_known_port() {
+        let handler = peer_handler(40125);
+
+        // Known peers at the port they were heard from, others at the IPMsg default
+        assert_eq!(handler.peer_addr("127.0.0.1".parse().unwrap()), "127.0.0.1:40125".parse().unwrap());
+        assert_eq!(handler.peer_addr("10.0.0.9".parse().unwrap()), "10.0.0.9:2425".parse().unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_text_message_is_acked_again() {
+        let receiver_udp = UdpTransport::bind(0).unwrap();
+        let handler = peer_handler(receiver_udp.port());
+
+        let proto_msg = ProtocolMessage {
+            version: 1,
+            packet_id: 4242,
+            sender_name: "alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: Command::new(Mode::SendMsg).with(Opt::SendCheck).unwrap().into(),
+            content: "hello".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+        let sender_ip: IpAddr = "127.0.0.1".parse().unwrap();
 
-        assert_eq!(handler.packet_id_counter(), 1);
+        // Original and retransmission
+        handler.handle_incoming_message(&proto_msg, sender_ip, sender_ip).unwrap();
+        handler.handle_incoming_message(&proto_msg, sender_ip, sender_ip).unwrap();
+        assert_eq!(handler.recent_packets.len(), 1);
 
-        handler.next_packet_id();
-        handler.next_packet_id();
+        // Both copies are acknowledged
+        receiver_udp.set_read_timeout(Some(1000)).unwrap();
+        let mut buffer = [0u8; 65535];
+        for _ in 0..2 {
+            let (len, _) = receiver_udp.recv_from(&mut buffer).unwrap();
+            let ack = crate::network::parse_message(&buffer[..len]).unwrap();
+            assert_eq!(ack.command().mode(), Mode::RecvMsg);
+            assert_eq!(ack.content, "4242");
+        }
+    }
 
-        assert_eq!(handler.packet_id_counter(), 3);
+    #[test]
+    fn test_bad_attachment_still_acked() {
+        let receiver_udp = UdpTransport::bind(0).unwrap();
+        let handler = peer_handler(receiver_udp.port());
+
+        // SENDMSG | SENDCHECKOPT | FILEATTACHOPT with an attachment entry that doesn't parse
+        let proto_msg = crate::network::parse_message(
+            b"1:4343:alice:alice-pc:2097440:see attached\x001:broken\x07\0",
+        )
+        .unwrap();
+        let sender_ip: IpAddr = "127.0.0.1".parse().unwrap();
+        handler.handle_incoming_message(&proto_msg, sender_ip, sender_ip).unwrap();
+
+        // The text is acknowledged, so the sender doesn't retransmit it
+        receiver_udp.set_read_timeout(Some(1000)).unwrap();
+        let mut buffer = [0u8; 65535];
+        let (len, _) = receiver_udp.recv_from(&mut buffer).unwrap();
+        let ack = crate::network::parse_message(&buffer[..len]).unwrap();
+        assert_eq!(ack.command().mode(), Mode::RecvMsg);
+        assert_eq!(ack.content, "4343");
     }
 
     #[test]
-    fn test_reset_packet_id_counter() {
-        let udp = UdpTransport::bind(0).unwrap();
-        let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+    fn test_receipt_stops_retransmission() {
+        let receiver_udp = UdpTransport::bind(0).unwrap();
+        let handler = peer_handler(receiver_udp.port());
+        let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
 
-        handler.next_packet_id();
-        handler.next_packet_id();
+        let packet_id = handler.send_text_message(target_ip, "Are you there?").unwrap();
+        assert!(handler.outbox().is_pending(packet_id));
+
+        let receipt = ProtocolMessage {
+            version: 1,
+            packet_id: 9001,
+            sender_name: "bob".to_string(),
+            sender_host: "bob-pc".to_string(),
+            msg_type: Command::new(Mode::RecvMsg).into(),
+            content: packet_id.to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+        handler.handle_incoming_message(&receipt, target_ip, target_ip).unwrap();
+        assert!(!handler.outbox().is_pending(packet_id));
+    }
+
+    #[test]
+    fn test_long_message_truncated_for_ipmsg_peers() {
+        let receiver_udp = UdpTransport::bind(0).unwrap();
+        let handler = peer_handler(receiver_udp.port());
+        let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
 
-        handler.reset_packet_id_counter(100);
+        // Unknown peers aren't known to take chunks: one datagram, cut to size
+        let text = "长".repeat(MAX_DATAGRAM_TEXT);
+        handler.send_text_message(target_ip, &text).unwrap();
 
-        assert_eq!(handler.packet_id_counter(), 100);
-        assert_eq!(handler.next_packet_id(), 100);
-        assert_eq!(handler.next_packet_id(), 101);
+        receiver_udp.set_read_timeout(Some(1000)).unwrap();
+        let mut buffer = [0u8; 65535];
+        let (len, _) = receiver_udp.recv_from(&mut buffer).unwrap();
+        let received = crate::network::parse_message(&buffer[..len]).unwrap();
+        assert!(!received.command().has(Opt::Chunk));
+        assert_eq!(received.content, "长".repeat(MAX_DATAGRAM_TEXT / 3));
+    }
+
+    #[test]
+    fn test_chunked_message_round_trip() {
+        let chunk_udp = UdpTransport::bind(0).unwrap();
+        let ack_udp = UdpTransport::bind(0).unwrap();
+        chunk_udp.set_read_timeout(Some(1000)).unwrap();
+        ack_udp.set_read_timeout(Some(1000)).unwrap();
+        let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        let sender = peer_handler(chunk_udp.port());
+        let receiver = peer_handler(ack_udp.port());
+
+        let text = "x".repeat(2 * MAX_DATAGRAM_TEXT + 10);
+        let message_id = sender.send_chunked_text(local_ip, &text, TextEncoding::Utf8).unwrap();
+        assert!(sender.outbox().is_pending(message_id));
+
+        let mut buffer = [0u8; 65535];
+        let mut receive = |udp: &UdpTransport| {
+            let (len, _) = udp.recv_from(&mut buffer).unwrap();
+            crate::network::parse_message(&buffer[..len]).unwrap()
+        };
+
+        // Three chunks; the receiver holds the text until the last one
+        for index in 0..3 {
+            let chunk = receive(&chunk_udp);
+            assert!(chunk.command().has(Opt::Chunk));
+            receiver.handle_incoming_message(&chunk, local_ip, local_ip).unwrap();
+            assert_eq!(receiver.reassembler.pending_count(), usize::from(index < 2));
+        }
+
+        // Each chunk is acknowledged; the message is delivered with the last receipt
+        for index in 0..3 {
+            let receipt = receive(&ack_udp);
+            assert_eq!(receipt.command().mode(), Mode::RecvMsg);
+            sender.handle_incoming_message(&receipt, local_ip, local_ip).unwrap();
+            assert_eq!(sender.outbox().is_pending(message_id), index < 2);
+        }
     }
 
     #[test]
     fn test_udp_reference() {
         let udp = UdpTransport::bind(0).unwrap();
         let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+        let handler = MessageHandler::new(udp.sender(), config);
 
         // Should be able to access UDP transport
         let port = handler.udp().port();
@@ -943,7 +1583,7 @@ mod tests {
     fn test_send_empty_message_error() {
         let udp = UdpTransport::bind(0).unwrap();
         let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+        let handler = MessageHandler::new(udp.sender(), config);
 
         let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
         let result = handler.send_text_message(target_ip, "   ");
@@ -971,7 +1611,7 @@ mod tests {
             ..Default::default()
         };
 
-        let handler = MessageHandler::new(sender_udp, config);
+        let handler = MessageHandler::new(sender_udp.sender(), config);
         let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
 
         // Override default port for testing
@@ -1011,11 +1651,42 @@ mod tests {
         assert_eq!(received.content, "Hello, Test!");
     }
 
+    #[test]
+    fn test_apply_encoding() {
+        let message = Message {
+            id: uuid::Uuid::new_v4(),
+            packet_id: "100".to_string(),
+            sender: PeerInfo::new("127.0.0.1".parse().unwrap(), 2425, Some("张三".to_string())),
+            receiver: PeerInfo::new("127.0.0.1".parse().unwrap(), 2425, None),
+            msg_type: MessageType::Text,
+            content: "你好".to_string(),
+            timestamp: chrono::Utc::now(),
+        };
+
+        // FeiQ peer: GBK bytes, no UTF8OPT
+        let mut gbk = message.to_protocol("张三", "zhangsan-pc");
+        MessageHandler::apply_encoding(&mut gbk, TextEncoding::Gbk).unwrap();
+        assert!(!gbk.command().has(Opt::Utf8));
+        let bytes = serialize_message(&gbk).unwrap();
+        assert!(std::str::from_utf8(&bytes).is_err());
+        let parsed = crate::network::parse_message(&bytes).unwrap();
+        assert_eq!(parsed.sender_name, "张三");
+        assert_eq!(parsed.content, "你好");
+
+        // UTF-8 peer: advertised via UTF8OPT
+        let mut utf8 = message.to_protocol("张三", "zhangsan-pc");
+        MessageHandler::apply_encoding(&mut utf8, TextEncoding::Utf8).unwrap();
+        assert!(utf8.command().has(Opt::Utf8));
+        let bytes = serialize_message(&utf8).unwrap();
+        assert!(std::str::from_utf8(&bytes).is_ok());
+        assert_eq!(crate::network::parse_message(&bytes).unwrap().encoding, Some(TextEncoding::Utf8));
+    }
+
     #[test]
     fn test_send_generic_message() {
         let udp = UdpTransport::bind(0).unwrap();
         let config = create_test_config();
-        let handler = MessageHandler::new(udp, config);
+        let handler = MessageHandler::new(udp.sender(), config);
 
         let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
         let result = handler.send_message(
@@ -3,10 +3,15 @@
 // This module handles all message-related functionality:
 // - Protocol parsing and serialization
 // - Message encryption/decryption
-// - Message routing and delivery
+// - Message routing and delivery (retransmission until acknowledged)
+// - Long messages split into chunks for NeoLan peers
 
+pub mod chunk;
 pub mod handler;
+pub mod outbox;
 pub mod types;
 
+pub use chunk::{Reassembler, MAX_DATAGRAM_TEXT};
 pub use handler::MessageHandler;
+pub use outbox::{Outbox, OutboxEvent, RetryPolicy};
 
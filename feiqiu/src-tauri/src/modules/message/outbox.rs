@@ -0,0 +1,658 @@
+// Outbox - retransmission of messages awaiting a receipt
+//
+// Text messages go out with IPMSG_SENDCHECKOPT, asking the receiver for an
+// IPMSG_RECVMSG receipt. UDP doesn't guarantee delivery, so until that receipt
+// arrives the message is resent on a backoff schedule, flagged
+// IPMSG_RETRYOPT and under the same packet ID (the receiver acknowledges the
+// copy again but shows it only once). After the last attempt the message is
+// given up on and reported as failed. Receipts for messages that needed no
+// retransmission give the round-trip time samples of the network statistics.
+//
+// A long message sent in chunks (IPMSG_CHUNKOPT) is tracked as one message:
+// each chunk is acknowledged and resent on its own, the message is delivered
+// once every chunk is, and it fails as a whole when any chunk does.
+//
+// Delivery state changes are queued as `OutboxEvent`s and handed to the
+// thread running `Outbox::run`, which records them (database writes can't
+// block the async command that sends the message).
+
+use crate::network::{canonical_ip, serialize_message, NetworkStats, Opt, ProtocolMessage, UdpSender};
+use crate::storage::message_repo::MessageModel;
+use crate::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often due retransmissions are looked for
+const TICK: Duration = Duration::from_millis(200);
+
+/// Retransmission schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Sends before giving up, the first one included
+    pub max_attempts: u32,
+
+    /// Wait for the receipt after the first send (doubled after each retry)
+    pub initial_delay: Duration,
+
+    /// Upper bound of the wait
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Wait for the receipt after the `attempt`-th send (1-based)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Six sends over about 30 seconds (waits of 1, 2, 4, 8, 8 and 8 s),
+    /// enough to ride out a Wi-Fi roam or a brief access point outage
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+        }
+    }
+}
+
+/// Delivery state change of an outgoing message
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutboxEvent {
+    /// Message sent for the first time (record to store)
+    Sent(MessageModel),
+
+    /// Receiver confirmed with IPMSG_RECVMSG
+    Delivered { packet_id: u64, peer_ip: IpAddr },
+
+    /// No receipt after the last attempt
+    Failed {
+        packet_id: u64,
+        peer_ip: IpAddr,
+        attempts: u32,
+    },
+}
+
+/// An unacknowledged packet (a whole message, or one chunk of it)
+struct PendingMessage {
+    /// Packet ID of the message (of its first chunk when chunked)
+    message_id: u64,
+
+    /// Receiver address
+    target: SocketAddr,
+
+    /// The message flagged IPMSG_RETRYOPT, ready to resend
+    retry_bytes: Vec<u8>,
+
+    /// Sends so far
+    attempts: u32,
+
+    /// When the packet was first sent
+    sent_at: Instant,
+
+    /// When to resend (or give up) without a receipt
+    deadline: Instant,
+}
+
+/// Outgoing messages awaiting their IPMSG_RECVMSG receipt
+///
+/// Clones share the pending messages and the event queue.
+#[derive(Clone)]
+pub struct Outbox {
+    /// Send handle retransmissions go out through
+    udp: UdpSender,
+
+    /// Retransmission schedule
+    policy: RetryPolicy,
+
+    /// Unacknowledged packets by packet ID
+    pending: Arc<Mutex<HashMap<u64, PendingMessage>>>,
+
+    /// Delivery state changes for `run`
+    events_tx: Sender<OutboxEvent>,
+
+    /// Receiving end of the event queue (taken by `run`)
+    events_rx: Arc<Mutex<Option<Receiver<OutboxEvent>>>>,
+
+    /// Set by `stop`
+    stopped: Arc<AtomicBool>,
+}
+
+impl Outbox {
+    /// Create an empty outbox
+    ///
+    /// # Arguments
+    /// * `udp` - Send handle of the shared UDP transport
+    /// * `policy` - Retransmission schedule
+    pub fn new(udp: UdpSender, policy: RetryPolicy) -> Self {
+        let (events_tx, events_rx) = mpsc::channel();
+
+        Self {
+            udp,
+            policy,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            events_tx,
+            events_rx: Arc::new(Mutex::new(Some(events_rx))),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Get the retransmission schedule
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Track a message about to be sent for the first time
+    ///
+    /// Call before sending it, so its receipt can't arrive untracked; if the
+    /// send fails, [`untrack`](Self::untrack) it.
+    ///
+    /// # Arguments
+    /// * `proto_msg` - The message (with IPMSG_SENDCHECKOPT)
+    /// * `target` - Receiver address
+    /// * `record` - Database record of the message (stored by `run`)
+    ///
+    /// # Returns
+    /// * `Ok(())` - The message is resent until acknowledged
+    /// * `Err(NeoLanError)` - The retransmission couldn't be serialized
+    pub fn track(&self, proto_msg: &ProtocolMessage, target: SocketAddr, record: MessageModel) -> Result<()> {
+        self.track_chunks(std::slice::from_ref(proto_msg), target, record)
+    }
+
+    /// Track a message about to be sent in chunks (see [`track`](Self::track))
+    ///
+    /// # Arguments
+    /// * `chunks` - The chunks, in order (the first one's packet ID identifies the message)
+    /// * `target` - Receiver address
+    /// * `record` - Database record of the whole message (stored by `run`)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Each chunk is resent until acknowledged
+    /// * `Err(NeoLanError)` - A retransmission couldn't be serialized
+    pub fn track_chunks(&self, chunks: &[ProtocolMessage], target: SocketAddr, record: MessageModel) -> Result<()> {
+        let Some(message_id) = chunks.first().map(|chunk| chunk.packet_id) else {
+            return Ok(());
+        };
+
+        let sent_at = Instant::now();
+        let deadline = sent_at + self.policy.delay(1);
+        let mut tracked = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let mut retry = chunk.clone();
+            retry.msg_type = retry.command().with(Opt::Retry)?.into();
+            let pending = PendingMessage {
+                message_id,
+                target,
+                retry_bytes: serialize_message(&retry)?,
+                attempts: 1,
+                sent_at,
+                deadline,
+            };
+            tracked.push((chunk.packet_id, pending));
+        }
+
+        self.lock_pending().extend(tracked);
+        let _ = self.events_tx.send(OutboxEvent::Sent(record));
+
+        Ok(())
+    }
+
+    /// Give up on a tracked message whose first send failed
+    ///
+    /// Its packets are no longer resent, and it is reported as failed (its
+    /// record was queued for storing by `track`).
+    ///
+    /// # Arguments
+    /// * `message_id` - Packet ID of the message (of its first chunk when chunked)
+    pub fn untrack(&self, message_id: u64) {
+        let mut pending = self.lock_pending();
+        let target = match pending.values().find(|message| message.message_id == message_id) {
+            Some(message) => message.target,
+            None => return,
+        };
+
+        pending.retain(|_, message| message.message_id != message_id);
+        let _ = self.events_tx.send(OutboxEvent::Failed {
+            packet_id: message_id,
+            peer_ip: canonical_ip(target.ip()),
+            attempts: 1,
+        });
+    }
+
+    /// Handle an IPMSG_RECVMSG receipt
+    ///
+    /// # Arguments
+    /// * `packet_id` - Packet ID the receipt confirms
+    /// * `from` - Sender of the receipt
+    ///
+    /// # Returns
+    /// `true` if it confirmed a pending message (or chunk) sent to `from`;
+    /// repeated receipts (one per retransmission) and receipts from other
+    /// hosts return `false`
+    pub fn acknowledge(&self, packet_id: u64, from: IpAddr) -> bool {
+        let mut pending = self.lock_pending();
+        let Some(message) = pending.get(&packet_id) else {
+            return false;
+        };
+
+        let peer_ip = canonical_ip(message.target.ip());
+        if peer_ip != canonical_ip(from) {
+            tracing::warn!(
+                "Ignoring receipt for packet {} from {} (sent to {})",
+                packet_id,
+                from,
+                peer_ip
+            );
+            return false;
+        }
+
+        // After a retransmission the receipt may answer any of the copies
+        if message.attempts == 1 {
+            NetworkStats::global().record_ack_rtt(message.sent_at.elapsed());
+        }
+
+        let message_id = message.message_id;
+        pending.remove(&packet_id);
+
+        // A chunked message is delivered with its last outstanding chunk
+        if !pending.values().any(|other| other.message_id == message_id) {
+            let _ = self.events_tx.send(OutboxEvent::Delivered {
+                packet_id: message_id,
+                peer_ip,
+            });
+        }
+        true
+    }
+
+    /// Get the message a pending packet belongs to (itself, unless it's a chunk)
+    pub fn message_id(&self, packet_id: u64) -> Option<u64> {
+        self.lock_pending().get(&packet_id).map(|message| message.message_id)
+    }
+
+    /// Check if a message (by the packet ID of its first chunk) still awaits receipts
+    pub fn is_pending(&self, message_id: u64) -> bool {
+        self.lock_pending().values().any(|message| message.message_id == message_id)
+    }
+
+    /// Number of messages awaiting their receipt
+    pub fn pending_count(&self) -> usize {
+        let pending = self.lock_pending();
+        let mut message_ids: Vec<u64> = pending.values().map(|message| message.message_id).collect();
+        message_ids.sort_unstable();
+        message_ids.dedup();
+        message_ids.len()
+    }
+
+    /// Resend the messages whose receipt is overdue
+    ///
+    /// Messages that already had their last attempt are dropped and
+    /// reported as failed, along with the other chunks of a chunked message.
+    ///
+    /// # Arguments
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    /// Number of packets resent
+    pub fn retransmit_due(&self, now: Instant) -> usize {
+        let mut pending = self.lock_pending();
+        let due: Vec<u64> = pending
+            .iter()
+            .filter(|(_, message)| message.deadline <= now)
+            .map(|(packet_id, _)| *packet_id)
+            .collect();
+
+        let mut resent = 0;
+        for packet_id in due {
+            let Some(message) = pending.get_mut(&packet_id) else {
+                continue;
+            };
+
+            if message.attempts >= self.policy.max_attempts {
+                let message_id = message.message_id;
+                let peer_ip = canonical_ip(message.target.ip());
+                let attempts = message.attempts;
+                pending.retain(|_, other| other.message_id != message_id);
+                tracing::warn!(
+                    "No receipt for packet {} from {} after {} attempts, giving up",
+                    packet_id,
+                    peer_ip,
+                    attempts
+                );
+                let _ = self.events_tx.send(OutboxEvent::Failed {
+                    packet_id: message_id,
+                    peer_ip,
+                    attempts,
+                });
+                continue;
+            }
+
+            message.attempts += 1;
+            message.deadline = now + self.policy.delay(message.attempts);
+            match self.udp.send_to(&message.retry_bytes, message.target) {
+                Ok(()) => tracing::debug!(
+                    "Retransmitted packet {} to {} (attempt {})",
+                    packet_id,
+                    message.target,
+                    message.attempts
+                ),
+                // Counts as an attempt: the network is down, which is what the retries wait out
+                Err(e) => tracing::warn!(
+                    "Failed to retransmit packet {} to {}: {:?}",
+                    packet_id,
+                    message.target,
+                    e
+                ),
+            }
+            resent += 1;
+        }
+
+        resent
+    }
+
+    /// Retransmit overdue messages and hand out delivery state changes (blocking)
+    ///
+    /// Runs until [`stop`](Self::stop) is called. Only one thread can run the
+    /// outbox; further calls return right away.
+    ///
+    /// # Arguments
+    /// * `on_event` - Called for each delivery state change, in order
+    pub fn run<F>(&self, mut on_event: F)
+    where
+        F: FnMut(OutboxEvent),
+    {
+        let Some(events_rx) = self.events_rx.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            tracing::warn!("Outbox is already running");
+            return;
+        };
+
+        tracing::info!("Outbox started");
+
+        while !self.stopped.load(Ordering::SeqCst) {
+            match events_rx.recv_timeout(TICK) {
+                Ok(event) => on_event(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.retransmit_due(Instant::now());
+        }
+
+        // Hand out what was queued before the stop (sent messages not stored
+        // yet), then give up on the messages no receipt came for: nothing
+        // resends them after the restart
+        for event in events_rx.try_iter() {
+            on_event(event);
+        }
+        let unacknowledged = self.fail_pending();
+        tracing::info!("Outbox stopped ({} message(s) never acknowledged)", unacknowledged.len());
+        for event in unacknowledged {
+            on_event(event);
+        }
+    }
+
+    /// Make `run` return
+    ///
+    /// `run` hands out the delivery state changes already queued, reports the
+    /// messages still awaiting their receipt as failed, then returns within
+    /// one tick.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Drop every pending message, one `Failed` event per message
+    fn fail_pending(&self) -> Vec<OutboxEvent> {
+        let mut failed: HashMap<u64, (IpAddr, u32)> = HashMap::new();
+        for (_, message) in self.lock_pending().drain() {
+            let entry = failed
+                .entry(message.message_id)
+                .or_insert((canonical_ip(message.target.ip()), 0));
+            entry.1 = entry.1.max(message.attempts);
+        }
+
+        failed
+            .into_iter()
+            .map(|(packet_id, (peer_ip, attempts))| OutboxEvent::Failed {
+                packet_id,
+                peer_ip,
+                attempts,
+            })
+            .collect()
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, HashMap<u64, PendingMessage>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Command, Mode, UdpTransport};
+
+    fn chunk(packet_id: u64) -> ProtocolMessage {
+        let mut msg = text_message(packet_id);
+        msg.msg_type = msg.command().with(Opt::Chunk).unwrap().into();
+        msg
+    }
+
+    fn text_message(packet_id: u64) -> ProtocolMessage {
+        ProtocolMessage {
+            version: 1,
+            packet_id,
+            sender_name: "alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: Command::new(Mode::SendMsg).with(Opt::SendCheck).unwrap().into(),
+            content: "hello".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        }
+    }
+
+    fn record(packet_id: u64) -> MessageModel {
+        let now = chrono::Utc::now().naive_utc();
+        MessageModel {
+            id: 0,
+            msg_id: packet_id.to_string(),
+            sender_ip: "127.0.0.1".to_string(),
+            sender_name: "alice".to_string(),
+            receiver_ip: "127.0.0.1".to_string(),
+            msg_type: 0,
+            content: "hello".to_string(),
+            is_encrypted: false,
+            is_offline: true,
+            sent_at: now,
+            received_at: None,
+            created_at: now,
+            delivery_status: Some("pending".to_string()),
+        }
+    }
+
+    fn take_events(outbox: &Outbox) -> Receiver<OutboxEvent> {
+        outbox.events_rx.lock().unwrap().take().unwrap()
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy::default();
+        let delays: Vec<u64> = (1..=6).map(|attempt| policy.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 8, 8]);
+        assert_eq!(policy.delay(40), policy.max_delay);
+    }
+
+    #[test]
+    fn test_retransmit_until_acknowledged() {
+        let receiver = UdpTransport::bind(0).unwrap();
+        receiver.set_read_timeout(Some(1000)).unwrap();
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), receiver.port());
+
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().sender(), RetryPolicy::default());
+        let events = take_events(&outbox);
+        outbox.track(&text_message(777), target, record(777)).unwrap();
+        assert!(matches!(events.try_recv().unwrap(), OutboxEvent::Sent(_)));
+
+        // Nothing is due before the first deadline
+        assert_eq!(outbox.retransmit_due(Instant::now()), 0);
+
+        // Overdue: resent with RETRYOPT under the same packet ID
+        assert_eq!(outbox.retransmit_due(Instant::now() + Duration::from_secs(2)), 1);
+        let mut buffer = [0u8; 65535];
+        let (len, _) = receiver.recv_from(&mut buffer).unwrap();
+        let resent = crate::network::parse_message(&buffer[..len]).unwrap();
+        assert_eq!(resent.packet_id, 777);
+        assert!(resent.command().has(Opt::Retry));
+        assert!(resent.command().has(Opt::SendCheck));
+
+        // Receipts from other hosts don't count; the first one from the receiver does
+        assert!(!outbox.acknowledge(777, "10.0.0.9".parse().unwrap()));
+        assert!(outbox.acknowledge(777, "::ffff:127.0.0.1".parse().unwrap()));
+        assert!(!outbox.acknowledge(777, "127.0.0.1".parse().unwrap()));
+        assert!(!outbox.is_pending(777));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            OutboxEvent::Delivered {
+                packet_id: 777,
+                peer_ip: "127.0.0.1".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(20),
+        };
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().sender(), policy);
+        let events = take_events(&outbox);
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), 9);
+        outbox.track(&text_message(888), target, record(888)).unwrap();
+
+        let later = Instant::now() + Duration::from_secs(1);
+        assert_eq!(outbox.retransmit_due(later), 1);
+        assert_eq!(outbox.retransmit_due(later + Duration::from_secs(1)), 1);
+        assert_eq!(outbox.retransmit_due(later + Duration::from_secs(2)), 0);
+        assert_eq!(outbox.pending_count(), 0);
+
+        let events: Vec<OutboxEvent> = events.try_iter().collect();
+        assert_eq!(
+            events.last(),
+            Some(&OutboxEvent::Failed {
+                packet_id: 888,
+                peer_ip: "127.0.0.1".parse().unwrap(),
+                attempts: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_untrack_after_failed_send() {
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().sender(), RetryPolicy::default());
+        let events = take_events(&outbox);
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), 9);
+        outbox
+            .track_chunks(&[chunk(950), chunk(951)], target, record(950))
+            .unwrap();
+        assert!(matches!(events.try_recv().unwrap(), OutboxEvent::Sent(_)));
+
+        outbox.untrack(950);
+        assert_eq!(outbox.pending_count(), 0);
+        assert!(!outbox.acknowledge(951, "127.0.0.1".parse().unwrap()));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            OutboxEvent::Failed {
+                packet_id: 950,
+                peer_ip: "127.0.0.1".parse().unwrap(),
+                attempts: 1
+            }
+        );
+
+        // Unknown messages are ignored
+        outbox.untrack(950);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_chunked_message_is_one_unit() {
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().sender(), RetryPolicy::default());
+        let events = take_events(&outbox);
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), 9);
+        let peer_ip: IpAddr = "127.0.0.1".parse().unwrap();
+
+        outbox
+            .track_chunks(&[chunk(900), chunk(901), chunk(902)], target, record(900))
+            .unwrap();
+        assert!(matches!(events.try_recv().unwrap(), OutboxEvent::Sent(_)));
+        assert_eq!(outbox.pending_count(), 1);
+
+        // Delivered once every chunk is acknowledged, in whatever order
+        assert!(outbox.acknowledge(902, peer_ip));
+        assert!(outbox.acknowledge(900, peer_ip));
+        assert!(outbox.is_pending(900));
+        assert!(events.try_recv().is_err());
+        assert!(outbox.acknowledge(901, peer_ip));
+        assert!(!outbox.is_pending(900));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            OutboxEvent::Delivered {
+                packet_id: 900,
+                peer_ip
+            }
+        );
+
+        // One chunk running out of attempts fails the whole message
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        };
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().sender(), policy);
+        let events = take_events(&outbox);
+        outbox.track_chunks(&[chunk(910), chunk(911)], target, record(910)).unwrap();
+        assert!(outbox.acknowledge(910, peer_ip));
+        assert_eq!(outbox.retransmit_due(Instant::now() + Duration::from_secs(2)), 0);
+        assert_eq!(outbox.pending_count(), 0);
+        assert_eq!(
+            events.try_iter().last(),
+            Some(OutboxEvent::Failed {
+                packet_id: 910,
+                peer_ip,
+                attempts: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_stop_flushes_queued_events() {
+        let target: SocketAddr = "127.0.0.1:2425".parse().unwrap();
+        let outbox = Outbox::new(UdpTransport::bind(0).unwrap().dry_run().sender(), RetryPolicy::default());
+        outbox.track(&text_message(950), target, record(950)).unwrap();
+        outbox.track_chunks(&[chunk(960), chunk(961)], target, record(960)).unwrap();
+        assert!(outbox.acknowledge(960, target.ip()));
+
+        // A stopped outbox still records the queued sends, then gives up on
+        // the messages without a receipt (a chunked one once)
+        outbox.stop();
+        let mut handed_out = Vec::new();
+        outbox.run(|event| handed_out.push(event));
+        assert_eq!(handed_out.len(), 4);
+        assert!(matches!(&handed_out[0], OutboxEvent::Sent(record) if record.msg_id == "950"));
+        assert!(matches!(&handed_out[1], OutboxEvent::Sent(record) if record.msg_id == "960"));
+        let mut failed: Vec<u64> = handed_out[2..]
+            .iter()
+            .map(|event| match event {
+                OutboxEvent::Failed { packet_id, attempts: 1, .. } => *packet_id,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        failed.sort();
+        assert_eq!(failed, vec![950, 960]);
+        assert_eq!(outbox.pending_count(), 0);
+    }
+}
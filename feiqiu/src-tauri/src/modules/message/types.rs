@@ -6,8 +6,8 @@
 // - Conversion functions between network and storage layers
 
 use crate::modules::peer::types::PeerInfo;
-use crate::network::msg_type;
-use crate::network::ProtocolMessage;
+use crate::network::{Command, FeiqHeader, Mode, Opt, ProtocolMessage};
+use crate::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -41,34 +41,32 @@ pub enum MessageType {
 }
 
 impl MessageType {
-    /// Convert from IPMsg protocol message type (u32)
-    pub fn from_protocol(msg_type: u32) -> Self {
-        let mode = msg_type::get_mode(msg_type) as u32;
-
-        match mode {
-            msg_type::IPMSG_SENDMSG => Self::Text,
-            msg_type::IPMSG_GETFILEDATA => Self::FileRequest,
-            msg_type::IPMSG_RELEASEFILES => Self::FileResponse,
-            msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_BR_EXIT | msg_type::IPMSG_ANSENTRY => {
-                Self::Presence
-            }
-            msg_type::IPMSG_READMSG => Self::ReadReceipt,
-            msg_type::IPMSG_RECVMSG => Self::RecvAck,
+    /// Convert from an IPMsg protocol command
+    pub fn from_protocol(command: Command) -> Self {
+        match command.mode() {
+            Mode::SendMsg => Self::Text,
+            Mode::GetFileData => Self::FileRequest,
+            Mode::ReleaseFiles => Self::FileResponse,
+            Mode::BrEntry | Mode::BrExit | Mode::AnsEntry => Self::Presence,
+            Mode::ReadMsg => Self::ReadReceipt,
+            Mode::RecvMsg => Self::RecvAck,
             _ => Self::Unknown,
         }
     }
 
-    /// Convert to IPMsg protocol message type (u32)
-    pub fn to_protocol(&self) -> u32 {
-        match self {
-            Self::Text => msg_type::IPMSG_SENDMSG,
-            Self::FileRequest => msg_type::IPMSG_GETFILEDATA,
-            Self::FileResponse => msg_type::IPMSG_RELEASEFILES,
-            Self::Presence => msg_type::IPMSG_BR_ENTRY, // Default to BR_ENTRY
-            Self::ReadReceipt => msg_type::IPMSG_READMSG,
-            Self::RecvAck => msg_type::IPMSG_RECVMSG,
-            Self::Unknown => msg_type::IPMSG_NOOPERATION,
-        }
+    /// Convert to an IPMsg protocol command (no options set)
+    pub fn to_protocol(&self) -> Command {
+        let mode = match self {
+            Self::Text => Mode::SendMsg,
+            Self::FileRequest => Mode::GetFileData,
+            Self::FileResponse => Mode::ReleaseFiles,
+            Self::Presence => Mode::BrEntry, // Default to BR_ENTRY
+            Self::ReadReceipt => Mode::ReadMsg,
+            Self::RecvAck => Mode::RecvMsg,
+            Self::Unknown => Mode::NoOperation,
+        };
+
+        Command::new(mode)
     }
 }
 
@@ -174,7 +172,7 @@ impl Message {
             packet_id: proto_msg.packet_id.to_string(),
             sender,
             receiver,
-            msg_type: MessageType::from_protocol(proto_msg.msg_type),
+            msg_type: MessageType::from_protocol(proto_msg.command()),
             content: proto_msg.content.clone(),
             timestamp: Utc::now(),
         }
@@ -189,7 +187,7 @@ impl Message {
     /// This method requires the sender's username and hostname
     /// to be set before calling. Those should come from the app config.
     pub fn to_protocol(&self, sender_name: &str, sender_host: &str) -> ProtocolMessage {
-        self.to_protocol_with_options(sender_name, sender_host, 0)
+        self.build_protocol(sender_name, sender_host, self.msg_type.to_protocol())
     }
 
     /// A ProtocolMessage with options ready for UDP transmission
@@ -197,12 +195,22 @@ impl Message {
     /// # Arguments
     /// * `sender_name` - Sender's username
     /// * `sender_host` - Sender's hostname
-    /// * `options` - Protocol options (e.g., IPMSG_SENDCHECKOPT)
-    pub fn to_protocol_with_options(&self, sender_name: &str, sender_host: &str, options: u32) -> ProtocolMessage {
-        // Combine mode and options using make_command
-        let mode = self.msg_type.to_protocol();
-        let msg_type = crate::network::msg_type::make_command(mode, options);
+    /// * `options` - Protocol options (e.g., `Opt::SendCheck`)
+    ///
+    /// # Returns
+    /// * `Ok(ProtocolMessage)` - Message with the options set
+    /// * `Err(NeoLanError::Protocol)` - An option doesn't apply to this message type
+    pub fn to_protocol_with_options(
+        &self,
+        sender_name: &str,
+        sender_host: &str,
+        options: &[Opt],
+    ) -> Result<ProtocolMessage> {
+        let command = self.msg_type.to_protocol().with_all(options)?;
+        Ok(self.build_protocol(sender_name, sender_host, command))
+    }
 
+    fn build_protocol(&self, sender_name: &str, sender_host: &str, command: Command) -> ProtocolMessage {
         ProtocolMessage {
             version: 1,
             packet_id: self
@@ -211,8 +219,12 @@ impl Message {
                 .unwrap_or_else(|_| Uuid::new_v4().as_u128() as u64),
             sender_name: sender_name.to_string(),
             sender_host: sender_host.to_string(),
-            msg_type,
+            msg_type: command.into(),
             content: self.content.clone(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: None,
         }
     }
 
@@ -240,6 +252,7 @@ impl Message {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::network::msg_type;
     use std::net::{IpAddr, Ipv4Addr};
 
     fn create_test_sender() -> PeerInfo {
@@ -261,43 +274,54 @@ mod tests {
     #[test]
     fn test_message_type_from_protocol() {
         assert_eq!(
-            MessageType::from_protocol(msg_type::IPMSG_SENDMSG),
+            MessageType::from_protocol(Command::from(msg_type::IPMSG_SENDMSG)),
             MessageType::Text
         );
         assert_eq!(
-            MessageType::from_protocol(msg_type::IPMSG_GETFILEDATA),
+            MessageType::from_protocol(Command::from(msg_type::IPMSG_GETFILEDATA)),
             MessageType::FileRequest
         );
         assert_eq!(
-            MessageType::from_protocol(msg_type::IPMSG_RELEASEFILES),
+            MessageType::from_protocol(Command::from(msg_type::IPMSG_RELEASEFILES)),
             MessageType::FileResponse
         );
         assert_eq!(
-            MessageType::from_protocol(msg_type::IPMSG_BR_ENTRY),
+            MessageType::from_protocol(Command::from(msg_type::IPMSG_BR_ENTRY)),
             MessageType::Presence
         );
         assert_eq!(
-            MessageType::from_protocol(msg_type::IPMSG_READMSG),
+            MessageType::from_protocol(Command::from(msg_type::IPMSG_READMSG)),
             MessageType::ReadReceipt
         );
+        assert_eq!(
+            MessageType::from_protocol(Command::from(
+                msg_type::IPMSG_SENDMSG | msg_type::IPMSG_SENDCHECKOPT
+            )),
+            MessageType::Text
+        );
     }
 
     #[test]
     fn test_message_type_to_protocol() {
-        assert_eq!(MessageType::Text.to_protocol(), msg_type::IPMSG_SENDMSG);
-        assert_eq!(
-            MessageType::FileRequest.to_protocol(),
-            msg_type::IPMSG_GETFILEDATA
-        );
-        assert_eq!(
-            MessageType::FileResponse.to_protocol(),
-            msg_type::IPMSG_RELEASEFILES
-        );
-        assert_eq!(MessageType::Presence.to_protocol(), msg_type::IPMSG_BR_ENTRY);
-        assert_eq!(
-            MessageType::ReadReceipt.to_protocol(),
-            msg_type::IPMSG_READMSG
-        );
+        assert_eq!(MessageType::Text.to_protocol().mode(), Mode::SendMsg);
+        assert_eq!(MessageType::FileRequest.to_protocol().mode(), Mode::GetFileData);
+        assert_eq!(MessageType::FileResponse.to_protocol().mode(), Mode::ReleaseFiles);
+        assert_eq!(MessageType::Presence.to_protocol().mode(), Mode::BrEntry);
+        assert_eq!(MessageType::ReadReceipt.to_protocol().mode(), Mode::ReadMsg);
+        assert_eq!(u32::from(MessageType::Text.to_protocol()), msg_type::IPMSG_SENDMSG);
+    }
+
+    #[test]
+    fn test_to_protocol_with_options_rejects_invalid_options() {
+        let msg = Message::new_text(create_test_sender(), create_test_receiver(), "Hi".to_string());
+
+        let proto_msg = msg
+            .to_protocol_with_options("Alice", "alice-pc", &[Opt::SendCheck])
+            .unwrap();
+        assert!(proto_msg.command().has(Opt::SendCheck));
+
+        // ABSENCE only means something on entry packets
+        assert!(msg.to_protocol_with_options("Alice", "alice-pc", &[Opt::Absence]).is_err());
     }
 
     #[test]
@@ -339,6 +363,10 @@ mod tests {
             sender_host: "alice-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: "Test message".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let sender = create_test_sender();
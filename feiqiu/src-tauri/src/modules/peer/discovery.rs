@@ -1,19 +1,31 @@
 // Peer discovery module - broadcast and LAN peer detection
 //
 // This module handles peer discovery through UDP broadcasts:
-// - Announcing online status to LAN
-// - Listening for peer announcements
+// - Announcing online/offline status to LAN (IPv4 directed broadcast on each
+//   local subnet and/or a configurable multicast group, plus IPv6 link-local
+//   multicast on dual-stack sockets), and by unicast to the configured extra
+//   broadcast hosts on other subnets
+// - Listening for peer announcements (rate-limited per source, see
+//   `network::flood`)
 // - Processing incoming discovery messages
 
-use crate::Result;
-use crate::network::{UdpTransport, serialize_message, ProtocolMessage, msg_type};
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use crate::config::{AppConfig, DiscoveryMode};
+use crate::modules::peer::types::PeerNode;
+use crate::{NeoLanError, Result};
+use crate::network::{UdpSender, UdpTransport, serialize_message, FeiqHeader, ProtocolMessage, TextEncoding, msg_type};
+use crate::network::{expand_hosts, list_interfaces, InterfaceFilter, LocalInterface, DEFAULT_UDP_PORT};
+use crate::network::{FloodGuard, FloodLimits, FloodStats, NetworkStats, Verdict};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// Default receive buffer size for UDP
 const RECV_BUFFER_SIZE: usize = 65535;
 
+/// How long the listener blocks in `recv_from` before checking for a stop request (ms)
+const LISTEN_POLL_INTERVAL_MS: u64 = 500;
+
 /// Peer discovery service
 ///
 /// Handles UDP broadcast-based peer discovery for LAN communication.
@@ -28,8 +40,26 @@ pub struct PeerDiscovery {
     /// Local hostname (sent in announcements)
     hostname: String,
 
-    /// Packet ID counter (for unique message IDs)
-    packet_id: Arc<AtomicU64>,
+    /// How announcements reach the LAN
+    mode: DiscoveryMode,
+
+    /// Multicast group announcements go to (multicast modes only)
+    multicast_addr: Option<SocketAddr>,
+
+    /// Which interfaces announcements are broadcast on
+    interface_filter: InterfaceFilter,
+
+    /// Local IPv4 interfaces as of the last announcement
+    interfaces: Arc<Mutex<Arc<Vec<LocalInterface>>>>,
+
+    /// Expanded extra broadcast hosts (shared by clones, so updates reach the heartbeat)
+    extra_hosts: Arc<Mutex<Vec<IpAddr>>>,
+
+    /// Per-source rate limiting of received packets (shared by clones)
+    flood_guard: Arc<FloodGuard>,
+
+    /// Set by `stop_listening` (shared by clones, so any of them can stop the listener)
+    stopped: Arc<AtomicBool>,
 }
 
 impl PeerDiscovery {
@@ -45,12 +75,77 @@ impl PeerDiscovery {
     pub fn new(udp: UdpTransport, username: String, hostname: String) -> Self {
         tracing::info!("Creating PeerDiscovery: {}@{}", username, hostname);
 
+        // IPv6 peers announce themselves to the multicast group, not by broadcast
+        if let Err(e) = udp.join_ipv6_discovery_group() {
+            tracing::warn!("Failed to join IPv6 discovery group: {:?}, IPv6 peers may be missed", e);
+        }
+
         Self {
             udp: Arc::new(udp),
             username,
             hostname,
-            packet_id: Arc::new(AtomicU64::new(1)),
+            mode: DiscoveryMode::Broadcast,
+            multicast_addr: None,
+            interface_filter: InterfaceFilter::default(),
+            interfaces: Arc::new(Mutex::new(Arc::new(Vec::new()))),
+            extra_hosts: Arc::new(Mutex::new(Vec::new())),
+            flood_guard: Arc::new(FloodGuard::default()),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Apply the discovery mode from the configuration
+    ///
+    /// Multicast modes join `config.multicast_group` (so announcements from
+    /// other subnets are received) and set the multicast TTL. Failures are
+    /// logged and discovery falls back to broadcast: a misconfigured group
+    /// must not leave us invisible on our own subnet. The extra broadcast
+    /// hosts and flood protection limits are taken over as well (an invalid
+    /// host list is logged and ignored).
+    ///
+    /// # Arguments
+    /// * `config` - Application configuration
+    ///
+    /// # Returns
+    /// * `PeerDiscovery` - Discovery service using the configured mode
+    pub fn with_config(mut self, config: &AppConfig) -> Self {
+        self.interface_filter = InterfaceFilter::from_config(config);
+        self.flood_guard.set_limits(FloodLimits::from_config(config));
+        if let Err(e) = self.set_extra_hosts(&config.extra_broadcast_hosts) {
+            tracing::warn!("Ignoring extra broadcast hosts: {:?}", e);
+        }
+        self.mode = config.discovery_mode;
+        if !self.mode.uses_multicast() {
+            return self;
+        }
+
+        let joined = config.multicast_addr().and_then(|addr| {
+            self.udp.join_multicast(&addr.ip().to_string())?;
+            self.udp.set_multicast_ttl(config.multicast_ttl)?;
+            Ok(addr)
+        });
+
+        match joined {
+            Ok(addr) => {
+                tracing::info!(
+                    "Multicast discovery on {} (TTL {}, mode {:?})",
+                    addr,
+                    config.multicast_ttl,
+                    self.mode
+                );
+                self.multicast_addr = Some(addr);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to set up multicast discovery on {}: {:?}, falling back to broadcast",
+                    config.multicast_group,
+                    e
+                );
+                self.mode = DiscoveryMode::Broadcast;
+            }
         }
+
+        self
     }
 
     /// Create from UdpTransport with default system username/hostname
@@ -72,6 +167,10 @@ impl PeerDiscovery {
     ///
     /// Sends a STATUS_ONLINE broadcast message to all peers on the LAN.
     /// Other peers running NeoLan will receive this and add this peer to their list.
+    /// Depending on the discovery mode the message is broadcast on each local
+    /// subnet, sent to the configured multicast group, or both. On a dual-stack
+    /// socket it also goes to the IPv6 discovery multicast group, for peers on
+    /// IPv6-only segments. Each extra broadcast host gets it by unicast.
     ///
     /// # Returns
     /// * `Ok(())` - Announcement sent successfully (or gracefully skipped on macOS)
@@ -79,29 +178,166 @@ impl PeerDiscovery {
     pub fn announce_online(&self) -> Result<()> {
         tracing::info!("Announcing online status to LAN");
 
-        // Enable broadcast if not already enabled
-        if let Err(e) = self.udp.set_broadcast_enabled(true) {
-            tracing::warn!("Failed to enable broadcast: {:?}, continuing anyway", e);
-            // On macOS, this can fail due to interface issues - continue anyway
+        // BR_ENTRY (broadcast online)
+        self.announce(msg_type::IPMSG_BR_ENTRY)
+    }
+
+    /// Announce offline status to LAN
+    ///
+    /// Sends BR_EXIT the same way [`announce_online`](Self::announce_online)
+    /// sends BR_ENTRY, so peers drop us right away instead of timing out.
+    ///
+    /// # Returns
+    /// * `Ok(())` - Announcement sent (individual send failures are logged)
+    /// * `Err(NeoLanError)` - Message couldn't be serialized
+    pub fn announce_offline(&self) -> Result<()> {
+        tracing::info!("Announcing offline status to LAN");
+
+        self.announce(msg_type::IPMSG_BR_EXIT)
+    }
+
+    /// Announce online status to the extra broadcast hosts only
+    ///
+    /// Called on every heartbeat: hosts beyond a router never hear our
+    /// broadcasts, so without it they'd see us only at startup.
+    ///
+    /// # Returns
+    /// * `Ok(())` - Announcement sent (individual send failures are logged)
+    /// * `Err(NeoLanError)` - Message couldn't be serialized
+    pub fn announce_to_extra_hosts(&self) -> Result<()> {
+        if self.extra_hosts().is_empty() {
+            return Ok(());
         }
 
-        // Create BR_ENTRY message (broadcast online)
+        let bytes = self.presence_packet(msg_type::IPMSG_BR_ENTRY, TextEncoding::Utf8)?;
+        self.unicast_to_extra_hosts(&bytes);
+        Ok(())
+    }
+
+    /// Answer a peer's BR_ENTRY with a unicast ANSENTRY
+    ///
+    /// Unlike our broadcasts, the answer is encoded the way the peer's client
+    /// speaks, so GBK-only FeiQ clients show our name correctly.
+    ///
+    /// # Arguments
+    /// * `peer` - The peer that announced itself (encoding already learned)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Answer sent
+    /// * `Err(NeoLanError)` - Serializing or sending failed
+    pub fn answer_entry(&self, peer: &PeerNode) -> Result<()> {
+        let bytes = self.presence_packet(msg_type::IPMSG_ANSENTRY, peer.encoding)?;
+        self.udp.send_to(&bytes, peer.socket_addr(peer.port))
+    }
+
+    /// Send a presence command to every discovery target
+    ///
+    /// Broadcasts can't be tailored per peer, so they speak UTF-8 and say so.
+    fn announce(&self, mode: u32) -> Result<()> {
+        let bytes = self.presence_packet(mode, TextEncoding::Utf8)?;
+
+        if self.mode.uses_broadcast() {
+            self.broadcast_announcement(&bytes);
+        }
+
+        if let Some(addr) = self.multicast_addr {
+            match self.udp.send_to(&bytes, addr) {
+                Ok(()) => tracing::debug!("Announcement sent to multicast group {}", addr),
+                Err(e) => tracing::warn!("Failed to send multicast announcement to {}: {:?}", addr, e),
+            }
+        }
+
+        if self.udp.is_dual_stack() {
+            if let Err(e) = self.udp.multicast(&bytes) {
+                // No IPv6 route on the default interface; IPv4 discovery is unaffected
+                tracing::debug!("Failed to send IPv6 multicast announcement: {:?}", e);
+            }
+        }
+
+        self.unicast_to_extra_hosts(&bytes);
+
+        Ok(())
+    }
+
+    /// Serialize a presence command (BR_ENTRY, BR_EXIT, ANSENTRY) with our identity
+    fn presence_packet(&self, mode: u32, encoding: TextEncoding) -> Result<Vec<u8>> {
+        // Say we read UTF-8 (and whether this packet is), and tell NeoLan
+        // peers they may send us chunked messages and JSON file offers
+        let mut options =
+            msg_type::IPMSG_CAPUTF8OPT | msg_type::IPMSG_CAPCHUNKOPT | msg_type::IPMSG_CAPFILEREQOPT;
+        if encoding == TextEncoding::Utf8 {
+            options |= msg_type::IPMSG_UTF8OPT;
+        }
         let msg = ProtocolMessage {
             version: 1,
             packet_id: self.next_packet_id(),
             sender_name: self.username.clone(),
             sender_host: self.hostname.clone(),
-            msg_type: msg_type::IPMSG_BR_ENTRY,
+            msg_type: msg_type::make_command(mode, options),
             content: String::new(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: Some(encoding),
         };
 
-        // Serialize and send
-        let bytes = serialize_message(&msg)?;
+        serialize_message(&msg)
+    }
+
+    /// Send an announcement to each extra broadcast host
+    fn unicast_to_extra_hosts(&self, bytes: &[u8]) {
+        let hosts = self.extra_hosts();
+        for ip in &hosts {
+            let addr = SocketAddr::new(*ip, DEFAULT_UDP_PORT);
+            if let Err(e) = self.udp.send_to(bytes, addr) {
+                tracing::warn!("Failed to send announcement to extra host {}: {:?}", addr, e);
+            }
+        }
+        if !hosts.is_empty() {
+            tracing::debug!("Announcement sent to {} extra broadcast hosts", hosts.len());
+        }
+    }
+
+    /// Broadcast an announcement on every local subnet
+    ///
+    /// The interface list is refreshed first, so NICs and VPNs that came up
+    /// since the last announcement are covered. Each allowed interface gets
+    /// the packet on its directed broadcast address; without any (enumeration
+    /// failed, or every interface filtered out) the limited broadcast address
+    /// is used instead. Failures are logged, not returned (see below).
+    fn broadcast_announcement(&self, bytes: &[u8]) {
+        // Enable broadcast if not already enabled
+        if let Err(e) = self.udp.set_broadcast_enabled(true) {
+            tracing::warn!("Failed to enable broadcast: {:?}, continuing anyway", e);
+            // On macOS, this can fail due to interface issues - continue anyway
+        }
+
+        self.refresh_interfaces();
+        let interfaces = self.interfaces();
+        let targets: Vec<&LocalInterface> = interfaces
+            .iter()
+            .filter(|interface| self.interface_filter.allows(interface))
+            .collect();
+        if !targets.is_empty() {
+            for interface in targets {
+                let addr = SocketAddr::new(interface.broadcast.into(), DEFAULT_UDP_PORT);
+                match self.udp.send_to(bytes, addr) {
+                    Ok(()) => tracing::debug!("Announcement broadcast on {} ({})", interface.name, addr),
+                    Err(e) => tracing::warn!(
+                        "Failed to broadcast announcement on {} ({}): {:?}",
+                        interface.name,
+                        addr,
+                        e
+                    ),
+                }
+            }
+            return;
+        }
 
         // Try to broadcast, but handle macOS broadcast issues gracefully
-        match self.udp.broadcast(&bytes) {
+        match self.udp.broadcast(bytes) {
             Ok(()) => {
-                tracing::debug!("Online announcement sent: {}@{}", self.username, self.hostname);
+                tracing::debug!("Announcement sent: {}@{}", self.username, self.hostname);
             }
             Err(e) => {
                 // On macOS, broadcast can fail with EADDRNOTAVAIL (error 49) due to
@@ -115,15 +351,16 @@ impl PeerDiscovery {
                 );
             }
         }
-
-        Ok(())
     }
 
     /// Listen for incoming peer messages (blocking)
     ///
-    /// This method blocks and continuously listens for incoming UDP messages.
+    /// This method blocks and continuously listens for incoming UDP messages
+    /// until [`stop_listening`](Self::stop_listening) is called.
     /// For each received message, the provided callback is invoked with the
-    /// parsed protocol message and sender address.
+    /// parsed protocol message and sender address. Packets over their
+    /// source's rate limit, and all packets of quarantined sources, are
+    /// dropped before parsing.
     ///
     /// # Arguments
     /// * `callback` - Function to call for each received message
@@ -138,12 +375,19 @@ impl PeerDiscovery {
     {
         tracing::info!("Starting peer discovery listener");
 
+        // Wake up regularly so a stop request is noticed without traffic
+        self.udp.set_read_timeout(Some(LISTEN_POLL_INTERVAL_MS))?;
         let mut buffer = [0u8; RECV_BUFFER_SIZE];
 
-        loop {
+        while !self.stopped.load(Ordering::SeqCst) {
             // Receive UDP packet
             let (len, sender) = match self.udp.recv_from(&mut buffer) {
                 Ok(result) => result,
+                Err(NeoLanError::Network(e))
+                    if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) =>
+                {
+                    continue;
+                }
                 Err(e) => {
                     tracing::warn!("Receive error: {:?}", e);
                     // Brief sleep before retry
@@ -152,24 +396,58 @@ impl PeerDiscovery {
                 }
             };
 
-            let data = &buffer[..len];
+            if let Some(msg) = self.screen_datagram(&buffer[..len], sender, Instant::now()) {
+                callback(msg, sender);
+            }
+        }
+
+        tracing::info!("Peer discovery listener stopped");
+        Ok(())
+    }
 
-            // Parse protocol message
-            match crate::network::parse_message(data) {
-                Ok(msg) => {
-                    tracing::trace!(
-                        "Received {} from {}: {}",
-                        crate::network::get_message_type_name(msg.msg_type),
-                        sender,
-                        msg.sender_name
-                    );
+    /// Make `listen_incoming` return
+    ///
+    /// The listener notices within [`LISTEN_POLL_INTERVAL_MS`]; a packet
+    /// already being handled is finished first. Final: a stopped discovery
+    /// service doesn't listen again.
+    pub fn stop_listening(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
 
-                    // Invoke callback
-                    callback(msg, sender);
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to parse message from {}: {:?}", sender, e);
-                }
+    /// Rate-limit and parse one received datagram
+    ///
+    /// # Returns
+    /// * `Some(ProtocolMessage)` - The packet is accepted and parses
+    /// * `None` - The packet was dropped (throttled, quarantined source or
+    ///   unparseable)
+    fn screen_datagram(&self, data: &[u8], sender: SocketAddr, now: Instant) -> Option<ProtocolMessage> {
+        match self.flood_guard.check(sender.ip(), now) {
+            Verdict::Accept => {}
+            Verdict::Throttle => {
+                tracing::debug!("Throttled packet from {}", sender);
+                return None;
+            }
+            Verdict::Quarantined => {
+                tracing::trace!("Dropped packet from quarantined {}", sender);
+                return None;
+            }
+        }
+
+        match crate::network::parse_message(data) {
+            Ok(msg) => {
+                tracing::trace!(
+                    "Received {} from {}: {}",
+                    crate::network::get_message_type_name(msg.msg_type),
+                    sender,
+                    msg.sender_name
+                );
+                Some(msg)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to parse message from {}: {:?}", sender, e);
+                self.flood_guard.record_parse_error(sender.ip(), now);
+                NetworkStats::global().record_parse_failure(sender.ip());
+                None
             }
         }
     }
@@ -205,6 +483,61 @@ impl PeerDiscovery {
         self.udp.local_addr()
     }
 
+    /// Re-read the local IPv4 interfaces
+    ///
+    /// Keeps the previous list if enumeration fails.
+    pub fn refresh_interfaces(&self) {
+        match list_interfaces() {
+            Ok(interfaces) => *self.interfaces.lock().unwrap_or_else(|e| e.into_inner()) = Arc::new(interfaces),
+            Err(e) => tracing::warn!("Failed to enumerate network interfaces: {:?}", e),
+        }
+    }
+
+    /// Get the local IPv4 interfaces (as of the last announcement or refresh)
+    ///
+    /// Includes interfaces the filter skips for broadcasting: peers can still
+    /// reach us through them.
+    pub fn interfaces(&self) -> Arc<Vec<LocalInterface>> {
+        Arc::clone(&self.interfaces.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Replace the extra broadcast hosts
+    ///
+    /// # Arguments
+    /// * `entries` - Hosts and CIDR ranges (`10.8.0.7`, `10.8.1.0/24`)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Hosts replaced; the next announcement uses them
+    /// * `Err(NeoLanError::Validation)` - An entry is invalid or the list is
+    ///   too large (the previous hosts are kept)
+    pub fn set_extra_hosts(&self, entries: &[String]) -> Result<()> {
+        let hosts = expand_hosts(entries)?;
+        *self.extra_hosts.lock().unwrap_or_else(|e| e.into_inner()) = hosts;
+        Ok(())
+    }
+
+    /// Get the expanded extra broadcast hosts
+    pub fn extra_hosts(&self) -> Vec<IpAddr> {
+        self.extra_hosts.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the flood protection limits (takes effect for the next packet)
+    pub fn set_flood_limits(&self, limits: FloodLimits) {
+        self.flood_guard.set_limits(limits);
+    }
+
+    /// Get the receive path counters (accepted, throttled, dropped packets)
+    pub fn flood_stats(&self) -> FloodStats {
+        self.flood_guard.stats(Instant::now())
+    }
+
+    /// Get the discovery mode in effect
+    ///
+    /// This is the configured mode unless multicast setup failed.
+    pub fn mode(&self) -> DiscoveryMode {
+        self.mode
+    }
+
     /// Get local username
     ///
     /// # Returns
@@ -221,12 +554,12 @@ impl PeerDiscovery {
         &self.hostname
     }
 
-    /// Generate next packet ID
+    /// Generate next packet ID (from the process-wide allocator)
     ///
     /// # Returns
     /// * `u64` - Next unique packet ID
     fn next_packet_id(&self) -> u64 {
-        self.packet_id.fetch_add(1, Ordering::SeqCst)
+        crate::network::next_packet_id()
     }
 
     /// Create a protocol message with local identity
@@ -245,6 +578,10 @@ impl PeerDiscovery {
             sender_host: self.hostname.clone(),
             msg_type,
             content,
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::local()),
+            encoding: None,
         }
     }
 
@@ -255,6 +592,17 @@ impl PeerDiscovery {
     pub fn clone_transport(&self) -> Arc<UdpTransport> {
         Arc::clone(&self.udp)
     }
+
+    /// Get a send handle for the discovery socket
+    ///
+    /// Other components send through it so that everything we send comes
+    /// from the protocol port peers know us by.
+    ///
+    /// # Returns
+    /// * `UdpSender` - Send handle sharing this service's socket
+    pub fn sender(&self) -> UdpSender {
+        self.udp.sender()
+    }
 }
 
 #[cfg(test)]
@@ -299,6 +647,153 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_discovery_mode_from_config() {
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+        assert_eq!(discovery.mode(), DiscoveryMode::Broadcast);
+
+        // A group that isn't multicast falls back to broadcast
+        let config = AppConfig {
+            discovery_mode: DiscoveryMode::Multicast,
+            multicast_group: "192.168.1.1".to_string(),
+            ..AppConfig::default()
+        };
+        let discovery = discovery.with_config(&config);
+        assert_eq!(discovery.mode(), DiscoveryMode::Broadcast);
+        assert!(discovery.multicast_addr.is_none());
+    }
+
+    #[test]
+    fn test_multicast_announcement() {
+        let receiver = UdpTransport::bind(0).unwrap();
+        receiver.set_read_timeout(Some(1000)).unwrap();
+        let config = AppConfig {
+            udp_port: receiver.port(),
+            discovery_mode: DiscoveryMode::Multicast,
+            multicast_ttl: 1,
+            ..AppConfig::default()
+        };
+        if receiver.join_multicast(&config.multicast_group).is_err() {
+            return; // Host without a multicast-capable interface
+        }
+
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "Alice".to_string(),
+            "alice-pc".to_string(),
+        )
+        .with_config(&config);
+        assert_eq!(discovery.mode(), DiscoveryMode::Multicast);
+        discovery.announce_online().unwrap();
+
+        let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+        let (len, _) = receiver.recv_from(&mut buffer).unwrap();
+        let msg = crate::network::parse_message(&buffer[..len]).unwrap();
+        assert_eq!(msg.sender_name, "Alice");
+        assert_eq!(msg.command().mode(), crate::network::Mode::BrEntry);
+        assert!(msg.command().has(crate::network::Opt::CapChunk));
+    }
+
+    #[test]
+    fn test_extra_host_announcement() {
+        // Extra hosts get the announcement on the protocol port
+        let receiver = match UdpTransport::bind(DEFAULT_UDP_PORT) {
+            Ok(receiver) => receiver,
+            Err(_) => return, // Port taken by another test or a running instance
+        };
+        receiver.set_read_timeout(Some(1000)).unwrap();
+
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "ExtraHostUser".to_string(),
+            "extra-pc".to_string(),
+        );
+        assert!(discovery.set_extra_hosts(&["not-a-host".to_string()]).is_err());
+        discovery.set_extra_hosts(&["127.0.0.1".to_string()]).unwrap();
+        assert_eq!(discovery.extra_hosts(), vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+
+        discovery.clone().announce_to_extra_hosts().unwrap();
+
+        // Skip broadcasts other tests send to the same port
+        let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+        let msg = loop {
+            let (len, _) = receiver.recv_from(&mut buffer).unwrap();
+            let msg = crate::network::parse_message(&buffer[..len]).unwrap();
+            if msg.sender_name == "ExtraHostUser" {
+                break msg;
+            }
+        };
+        assert_eq!(msg.command().mode(), crate::network::Mode::BrEntry);
+    }
+
+    #[test]
+    fn test_screen_datagram_limits_sources() {
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        )
+        .with_config(&AppConfig {
+            rate_limit_packets_per_second: 1,
+            rate_limit_burst: 2,
+            max_parse_errors_per_minute: 1,
+            quarantine_seconds: 60,
+            ..AppConfig::default()
+        });
+        let packet = serialize_message(&discovery.create_message(msg_type::IPMSG_BR_ENTRY, String::new())).unwrap();
+        let now = Instant::now();
+
+        // A burst of two, then throttled until the source is quarantined
+        let flooder: SocketAddr = "192.168.1.50:2425".parse().unwrap();
+        assert!(discovery.screen_datagram(&packet, flooder, now).is_some());
+        assert!(discovery.screen_datagram(&packet, flooder, now).is_some());
+        for _ in 0..20 {
+            assert!(discovery.screen_datagram(&packet, flooder, now).is_none());
+        }
+
+        // Garbage beyond the parse error cap quarantines too
+        let garbler: SocketAddr = "192.168.1.51:2425".parse().unwrap();
+        assert!(discovery.screen_datagram(b"garbage", garbler, now).is_none());
+        assert!(discovery.screen_datagram(b"garbage", garbler, now).is_none());
+        assert!(discovery.screen_datagram(&packet, garbler, now).is_none());
+
+        // Other sources are unaffected
+        let peer: SocketAddr = "192.168.1.52:2425".parse().unwrap();
+        assert!(discovery.screen_datagram(&packet, peer, now).is_some());
+
+        let stats = discovery.flood_stats();
+        assert_eq!(stats.accepted, 5);
+        assert_eq!(stats.throttled, 20);
+        assert_eq!(stats.dropped_quarantined, 1);
+        assert_eq!(stats.parse_errors, 2);
+        assert_eq!(stats.quarantined_sources, vec!["192.168.1.50", "192.168.1.51"]);
+    }
+
+    #[test]
+    fn test_stop_listening() {
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+
+        let listener = {
+            let discovery = discovery.clone();
+            std::thread::spawn(move || discovery.listen_incoming(|_, _| {}))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        // A clone stops it, without any traffic to wake it up
+        let started = Instant::now();
+        discovery.stop_listening();
+        listener.join().unwrap().unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(LISTEN_POLL_INTERVAL_MS * 3));
+    }
+
     #[test]
     fn test_create_message() {
         let udp = UdpTransport::bind(0).unwrap();
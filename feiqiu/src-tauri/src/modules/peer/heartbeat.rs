@@ -5,12 +5,45 @@
 // - Detecting offline peers (no heartbeat within timeout)
 // - Maintaining peer online status
 
+use crate::modules::peer::discovery::PeerDiscovery;
 use crate::modules::peer::types::PeerNode;
+use crate::state::AppState;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the heartbeat thread checks for shutdown between announcements
+const HEARTBEAT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Spawn the heartbeat thread for the extra broadcast hosts
+///
+/// Every `interval_seconds` the discovery service re-announces BR_ENTRY to
+/// its extra broadcast hosts (a no-op while the list is empty). Peers on the
+/// local subnets need no heartbeat: they hear every broadcast. The thread
+/// ends once `app_state` starts shutting down.
+pub fn spawn_extra_host_heartbeat(
+    discovery: PeerDiscovery,
+    interval_seconds: u64,
+    app_state: AppState,
+) -> thread::JoinHandle<()> {
+    let interval = Duration::from_secs(interval_seconds.max(1));
+
+    thread::spawn(move || {
+        let mut next = Instant::now() + interval;
+        while !app_state.is_shutting_down() {
+            if Instant::now() >= next {
+                if let Err(e) = discovery.announce_to_extra_hosts() {
+                    tracing::warn!("Extra host heartbeat failed: {:?}", e);
+                }
+                next += interval;
+            }
+            thread::sleep(HEARTBEAT_POLL_INTERVAL);
+        }
+    })
+}
 
 #[allow(dead_code)]
 pub fn check_offline_peers(
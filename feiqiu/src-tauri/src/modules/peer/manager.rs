@@ -5,8 +5,9 @@
 // - Processing discovery messages
 // - Managing peer state transitions
 // - Routing text messages to MessageHandler
+// - Replaying packet captures through the same path
 
-use crate::{network::ProtocolMessage, Result};
+use crate::{network::{parse_message, CaptureRecord, Direction, Mode, ProtocolMessage}, Result};
 use crate::modules::peer::{types::*, discovery::PeerDiscovery};
 use std::collections::HashMap;
 use std::io::{self, Error as IoError};
@@ -98,8 +99,9 @@ impl PeerManager {
     /// Start the peer manager
     ///
     /// This announces online presence and starts listening for peer messages.
-    /// Note: This is a blocking call that runs the message listener loop.
-    /// In production, run this in a separate thread.
+    /// Note: This is a blocking call that runs the message listener loop
+    /// until [`stop`](Self::stop) is called. In production, run this in a
+    /// separate thread.
     ///
     /// # Returns
     /// * `Ok(())` - Manager started successfully
@@ -128,6 +130,7 @@ impl PeerManager {
         let peers = Arc::clone(&self.peers);
         let running = Arc::clone(&self.running);
         let message_tx = Arc::clone(&self.message_tx);
+        let discovery = self.discovery.clone();
 
         self.discovery.listen_incoming(move |msg, sender| {
             // Check if still running
@@ -141,8 +144,11 @@ impl PeerManager {
             }
 
             // Handle the message
+            let is_entry = msg.command().mode() == Mode::BrEntry;
             if let Err(e) = Self::handle_message(&peers, msg, sender, &message_tx) {
                 warn!("Failed to handle message: {:?}", e);
+            } else if is_entry {
+                Self::answer_entry(&peers, &discovery, sender.ip());
             }
         })?;
 
@@ -150,11 +156,58 @@ impl PeerManager {
     }
 
     /// Stop the peer manager
+    ///
+    /// Announces BR_EXIT so peers mark us offline right away, makes the
+    /// listener loop in [`start`](Self::start) return, and closes the
+    /// message handler channel: the thread routing messages finishes the
+    /// ones already queued and ends.
     pub fn stop(&self) {
-        if let Ok(mut running) = self.running.lock() {
-            *running = false;
-            info!("Stopping PeerManager");
+        // Stop listening even if `start` hasn't got going yet, so it can't outlive shutdown
+        self.discovery.stop_listening();
+        safe_lock!(self.message_tx).take();
+
+        let was_running = std::mem::replace(&mut *safe_lock!(self.running), false);
+        if !was_running {
+            return;
+        }
+        info!("Stopping PeerManager");
+
+        if let Err(e) = self.discovery.announce_offline() {
+            warn!("Failed to announce offline status: {:?}", e);
+        }
+    }
+
+    /// Replay captured datagrams as if they had just been received
+    ///
+    /// Inbound records go through `parse_message` and the same handling as
+    /// live traffic: peers are added and updated, and text messages and
+    /// acknowledgments are routed to the MessageHandler channel. Outbound
+    /// records are skipped. Doesn't require the manager to be started.
+    ///
+    /// # Arguments
+    /// * `records` - Capture records, in capture order
+    ///
+    /// # Returns
+    /// * `usize` - Number of inbound records that parsed and were handled
+    pub fn replay(&self, records: &[CaptureRecord]) -> usize {
+        let mut handled = 0;
+        for record in records.iter().filter(|r| r.direction == Direction::In) {
+            let msg = match parse_message(&record.data) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    warn!("Replay: failed to parse packet from {}: {:?}", record.peer, e);
+                    continue;
+                }
+            };
+
+            match Self::handle_message(&self.peers, msg, record.peer, &self.message_tx) {
+                Ok(()) => handled += 1,
+                Err(e) => warn!("Replay: failed to handle message from {}: {:?}", record.peer, e),
+            }
         }
+
+        info!("Replayed {} of {} captured packets", handled, records.len());
+        handled
     }
 
     /// Handle a protocol message
@@ -170,27 +223,36 @@ impl PeerManager {
             ip, msg.msg_type, msg.sender_name,
             msg.content.chars().take(50).collect::<String>());
 
-        // Extract base mode (low 8 bits) to handle messages with options
-        let mode = crate::network::msg_type::get_mode(msg.msg_type);
+        // Learn the peer's wire encoding and FeiQ identity from anything it sends us
+        if let Some(peer) = safe_lock!(peers).get_mut(&ip) {
+            if peer.observe_encoding(&msg) {
+                debug!("🔤 Peer {} encoding: {}", ip, peer.encoding.as_str());
+            }
+            peer.observe_feiq_header(&msg);
+            peer.observe_capabilities(&msg);
+        }
+
+        // Match on the mode only, so messages with options are handled too
+        let command = msg.command();
 
-        match mode as u32 {
+        match command.mode() {
             // IPMSG_BR_ENTRY: Peer is online / broadcasting presence
-            crate::network::msg_type::IPMSG_BR_ENTRY => {
+            Mode::BrEntry => {
                 debug!("📢 Handling BR_ENTRY (peer online)");
                 Self::handle_online_msg(peers, msg, sender)?;
             }
             // IPMSG_BR_EXIT: Peer is going offline
-            crate::network::msg_type::IPMSG_BR_EXIT => {
+            Mode::BrExit => {
                 debug!("📴 Handling BR_EXIT (peer offline)");
                 Self::handle_offline_msg(peers, ip)?;
             }
             // IPMSG_ANSENTRY: Response to BR_ENTRY (also indicates online presence)
-            crate::network::msg_type::IPMSG_ANSENTRY => {
+            Mode::AnsEntry => {
                 debug!("📢 Handling ANSENTRY (peer online response)");
                 Self::handle_online_msg(peers, msg, sender)?;
             }
             // IPMSG_SENDMSG: Text message - route to MessageHandler
-            crate::network::msg_type::IPMSG_SENDMSG => {
+            Mode::SendMsg => {
                 info!("💌 [TEXT MESSAGE] Routing text message to MessageHandler: from={}, content={}",
                     msg.sender_name, msg.content.chars().take(100).collect::<String>());
                 if let Some(ref tx) = *safe_lock!(message_tx) {
@@ -208,7 +270,7 @@ impl PeerManager {
                 }
             }
             // IPMSG_RECVMSG: Message acknowledgment - route to MessageHandler
-            crate::network::msg_type::IPMSG_RECVMSG => {
+            Mode::RecvMsg => {
                 info!("✅ [RECEIPT ACK] Routing message acknowledgment to MessageHandler: from={}, packet_id={}",
                     msg.sender_name, msg.packet_id);
                 if let Some(ref tx) = *safe_lock!(message_tx) {
@@ -227,14 +289,27 @@ impl PeerManager {
             }
             _ => {
                 // Other message types (FILE_SEND_REQ, etc.)
-                debug!("ℹ️ Ignoring message type: {} (mode: {}, options: 0x{:06x})",
-                    msg.msg_type, mode, crate::network::msg_type::get_opt(msg.msg_type));
+                debug!("ℹ️ Ignoring message type: {}", command);
             }
         }
 
         Ok(())
     }
 
+    /// Answer a peer's BR_ENTRY with an ANSENTRY in the peer's encoding
+    ///
+    /// Not part of [`handle_message`](Self::handle_message): replayed
+    /// captures mustn't answer anyone.
+    fn answer_entry(peers: &Arc<Mutex<HashMap<IpAddr, PeerNode>>>, discovery: &PeerDiscovery, ip: IpAddr) {
+        let Some(peer) = safe_lock!(peers).get(&ip).cloned() else {
+            return;
+        };
+        match discovery.answer_entry(&peer) {
+            Ok(()) => debug!("📢 Answered BR_ENTRY from {} ({})", ip, peer.encoding.as_str()),
+            Err(e) => warn!("Failed to answer BR_ENTRY from {}: {:?}", ip, e),
+        }
+    }
+
     /// Handle online message
     fn handle_online_msg(
         peers: &Arc<Mutex<HashMap<IpAddr, PeerNode>>>,
@@ -255,12 +330,28 @@ impl PeerManager {
 
         // Update peer information
         peer.port = sender.port();
+        if let SocketAddr::V6(v6) = sender {
+            peer.scope_id = v6.scope_id();
+        }
         peer.username = Some(msg.sender_name.clone());
         peer.hostname = Some(msg.sender_host.clone());
+
+        // IPMsg entry packets carry nickname\0group\0 (FeiQ's nickname is
+        // already in sender_name, leaving the content empty)
+        if !msg.content.is_empty() {
+            peer.nickname = Some(msg.content.clone());
+        }
+        if let Some(group) = msg.group_name() {
+            peer.groups = vec![group.to_string()];
+        }
+
         peer.status = PeerStatus::Online;
         peer.last_seen = std::time::SystemTime::now();
+        peer.observe_encoding(&msg);
+        peer.observe_feiq_header(&msg);
+        peer.observe_capabilities(&msg);
 
-        debug!("Peer added/updated: {}", ip);
+        debug!("Peer added/updated: {} (encoding: {})", ip, peer.encoding.as_str());
         Ok(())
     }
 
@@ -450,7 +541,7 @@ impl PeerManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::network::UdpTransport;
+    use crate::network::{Opt, UdpTransport};
 
     #[test]
     fn test_peer_manager_creation() {
@@ -466,6 +557,75 @@ mod tests {
         assert_eq!(manager.online_peer_count(), 0);
     }
 
+    #[test]
+    fn test_stop_ends_listener_and_router() {
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        );
+        let manager = PeerManager::new(discovery);
+        let (route_tx, route_rx) = std::sync::mpsc::channel::<MessageRouteRequest>();
+        manager.set_message_handler_channel(route_tx);
+
+        let listener = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.start())
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        manager.stop();
+
+        listener.join().unwrap().unwrap();
+        // With the channel closed, the routing loop (`for request in route_rx`) ends
+        assert!(route_rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_entry_answered_in_peer_encoding() {
+        let discovery = PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "张三".to_string(),
+            "zhangsan-pc".to_string(),
+        );
+        let manager = PeerManager::new(discovery);
+        let manager_addr = SocketAddr::new("127.0.0.1".parse().unwrap(), manager.discovery.port());
+        let listener = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.start())
+        };
+        let mut buffer = [0u8; 65535];
+
+        // FeiQ BR_ENTRY | CAPUTF8OPT | ENCRYPTOPT | FILEATTACHOPT with its GBK name (陈俞辛)
+        let feiq = UdpTransport::bind(0).unwrap();
+        feiq.set_read_timeout(Some(1000)).unwrap();
+        feiq.send_to(
+            b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153481:t0250254:DESKTOP-IOHG15K:23068673:\xb3\xc2\xd3\xe1\xd0\xc1\0",
+            manager_addr,
+        )
+        .unwrap();
+        let (len, _) = feiq.recv_from(&mut buffer).unwrap();
+        let answer = &buffer[..len];
+        // Our name in GBK, with no claim the packet is UTF-8
+        assert!(answer.windows(4).any(|bytes| bytes == b"\xd5\xc5\xc8\xfd"));
+        let answer = parse_message(answer).unwrap();
+        assert_eq!(answer.command().mode(), Mode::AnsEntry);
+        assert!(!answer.command().has(Opt::Utf8));
+        assert!(answer.command().has(Opt::CapUtf8));
+        assert_eq!(answer.sender_name, "张三");
+        assert_eq!(manager.get_peer("127.0.0.1".parse().unwrap()).unwrap().username.as_deref(), Some("陈俞辛"));
+
+        // IPMsg BR_ENTRY | UTF8OPT | CAPUTF8OPT: answered in UTF-8
+        let ipmsg = UdpTransport::bind(0).unwrap();
+        ipmsg.set_read_timeout(Some(1000)).unwrap();
+        ipmsg.send_to("1:100:李四:lisi-pc:25165825:李四\0".as_bytes(), manager_addr).unwrap();
+        let (len, _) = ipmsg.recv_from(&mut buffer).unwrap();
+        assert!(buffer[..len].windows(6).any(|bytes| bytes == "张三".as_bytes()));
+        assert!(parse_message(&buffer[..len]).unwrap().command().has(Opt::Utf8));
+
+        manager.stop();
+        listener.join().unwrap().unwrap();
+    }
+
     #[test]
     fn test_add_peer() {
         let udp = UdpTransport::bind(0).unwrap();
@@ -605,4 +765,104 @@ mod tests {
         assert_eq!(online_peers.len(), 1);
         assert_eq!(online_peers[0].ip, ip2);
     }
+
+    #[test]
+    fn test_replay_capture_through_message_handler() {
+        use crate::config::AppConfig;
+        use crate::modules::message::MessageHandler;
+        use crate::network::PacketCapture;
+        use crate::state::{app_state::TauriEvent, AppState};
+
+        let peer: SocketAddr = "192.168.1.100:2425".parse().unwrap();
+        let local: SocketAddr = "0.0.0.0:2425".parse().unwrap();
+        let capture_path = std::env::temp_dir().join("test_replay_capture.jsonl");
+        let replies_path = std::env::temp_dir().join("test_replay_replies.jsonl");
+
+        // A FeiQ peer announces itself (GBK nickname 张三) and sends 你好 with SENDCHECK
+        let capture = PacketCapture::create(&capture_path).unwrap();
+        capture.record(
+            Direction::In,
+            Some(local),
+            peer,
+            b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-1:6291459:\xd5\xc5\xc8\xfd\0\0",
+        );
+        capture.record(Direction::Out, Some(local), peer, b"1:1:me:my-pc:3:");
+        capture.record(
+            Direction::In,
+            Some(local),
+            peer,
+            b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386708:cgc:DESKTOP-1:288:\xc4\xe3\xba\xc3\0",
+        );
+        drop(capture);
+        let records = crate::network::read_capture(&capture_path).unwrap();
+
+        let manager = PeerManager::new(PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap().dry_run(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let (route_tx, route_rx) = std::sync::mpsc::channel();
+        manager.set_message_handler_channel(route_tx);
+
+        assert_eq!(manager.replay(&records), 2);
+
+        let replayed_peer = manager.get_peer(peer.ip()).unwrap();
+        assert_eq!(replayed_peer.username, Some("张三".to_string()));
+        assert!(replayed_peer.is_online());
+
+        // The routed text message reaches the MessageHandler; its ACK is captured, not sent
+        let app_state = AppState::new(AppConfig::default());
+        let (event_tx, event_rx) = std::sync::mpsc::channel();
+        app_state.set_event_sender(event_tx);
+        let replies = Arc::new(PacketCapture::create(&replies_path).unwrap());
+        let handler = MessageHandler::new(
+            UdpTransport::bind(0).unwrap().dry_run().with_capture(replies).sender(),
+            AppConfig {
+                bind_ip: "127.0.0.1".to_string(),
+                ..AppConfig::default()
+            },
+        )
+        .with_app_state(Arc::new(app_state));
+
+        let routed: Vec<MessageRouteRequest> = route_rx.try_iter().collect();
+        assert_eq!(routed.len(), 1);
+        for request in &routed {
+            handler
+                .handle_incoming_message(&request.message, request.sender.ip(), local.ip())
+                .unwrap();
+        }
+
+        match event_rx.try_recv().unwrap() {
+            TauriEvent::MessageReceived { content, sender_ip, .. } => {
+                assert_eq!(content, "你好");
+                assert_eq!(sender_ip, "192.168.1.100");
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        let replies = crate::network::read_capture(&replies_path).unwrap();
+        assert_eq!(replies.len(), 1);
+        let ack = parse_message(&replies[0].data).unwrap();
+        assert_eq!(ack.command().mode(), Mode::RecvMsg);
+        assert_eq!(ack.content, "1761386708");
+
+        std::fs::remove_file(&capture_path).unwrap();
+        std::fs::remove_file(&replies_path).unwrap();
+    }
+
+    #[test]
+    fn test_handle_online_msg_reads_group() {
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let sender: SocketAddr = "192.168.1.100:2425".parse().unwrap();
+        let msg = crate::network::parse_message(b"1:300:alice:alice-pc:1:Alice Liu\0Engineering\0").unwrap();
+
+        PeerManager::handle_online_msg(&peers, msg, sender).unwrap();
+
+        let peers = peers.lock().unwrap();
+        let peer = peers.get(&sender.ip()).unwrap();
+        assert_eq!(peer.username, Some("alice".to_string()));
+        assert_eq!(peer.nickname, Some("Alice Liu".to_string()));
+        assert_eq!(peer.groups, vec!["Engineering".to_string()]);
+        assert_eq!(peer.display_name(), "Alice Liu");
+    }
 }
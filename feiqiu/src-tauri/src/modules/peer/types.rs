@@ -5,8 +5,9 @@
 // - PeerStatus: Online/Offline/Away status
 // - PeerInfo: Lightweight peer info for messages
 
+use crate::network::{FeiqHeader, Mode, Opt, ProtocolMessage, TextEncoding};
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
 use std::time::SystemTime;
 
 /// Peer node runtime state (in-memory representation)
@@ -21,6 +22,13 @@ pub struct PeerNode {
     /// UDP port
     pub port: u16,
 
+    /// IPv6 scope (interface index) the peer was seen on, 0 for IPv4
+    ///
+    /// Link-local addresses (`fe80::/10`) are only reachable through the
+    /// interface they were learned on.
+    #[serde(default)]
+    pub scope_id: u32,
+
     /// Username (display name)
     pub username: Option<String>,
 
@@ -41,6 +49,35 @@ pub struct PeerNode {
 
     /// Last seen timestamp
     pub last_seen: SystemTime,
+
+    /// Text encoding the peer's client speaks (learned from inbound packets)
+    #[serde(default)]
+    pub encoding: TextEncoding,
+
+    /// FeiQ header from the peer's latest packet (`None` for non-FeiQ clients)
+    #[serde(default)]
+    pub feiq_header: Option<FeiqHeader>,
+
+    /// Whether the peer reassembles chunked messages (NeoLan clients)
+    ///
+    /// Learned from IPMSG_CAPCHUNKOPT on its presence packets. Long messages
+    /// to other peers are truncated to what one IPMsg datagram can carry.
+    #[serde(default)]
+    pub supports_chunks: bool,
+
+    /// Whether the peer takes NeoLan JSON file offers (NeoLan clients)
+    ///
+    /// Learned from IPMSG_CAPFILEREQOPT on its presence packets. Files go to
+    /// other peers as IPMsg attachments.
+    #[serde(default)]
+    pub supports_file_requests: bool,
+
+    /// Whether the peer's client can read UTF-8
+    ///
+    /// Learned from IPMSG_CAPUTF8OPT on its presence packets. FeiQ sets it on
+    /// packets it sends in GBK, so it doesn't change `encoding`.
+    #[serde(default)]
+    pub supports_utf8: bool,
 }
 
 impl PeerNode {
@@ -49,6 +86,7 @@ impl PeerNode {
         Self {
             ip,
             port,
+            scope_id: 0,
             username: None,
             hostname: None,
             nickname: None,
@@ -56,6 +94,11 @@ impl PeerNode {
             groups: Vec::new(),
             status: PeerStatus::Online,
             last_seen: SystemTime::now(),
+            encoding: TextEncoding::default(),
+            feiq_header: None,
+            supports_chunks: false,
+            supports_utf8: false,
+            supports_file_requests: false,
         }
     }
 
@@ -69,6 +112,7 @@ impl PeerNode {
         Self {
             ip,
             port,
+            scope_id: 0,
             username,
             hostname,
             nickname: None,
@@ -76,6 +120,22 @@ impl PeerNode {
             groups: Vec::new(),
             status: PeerStatus::Online,
             last_seen: SystemTime::now(),
+            encoding: TextEncoding::default(),
+            feiq_header: None,
+            supports_chunks: false,
+            supports_utf8: false,
+            supports_file_requests: false,
+        }
+    }
+
+    /// Get the address to reach the peer at on `port`
+    ///
+    /// IPv6 addresses carry the peer's scope, so replies to link-local peers
+    /// leave through the right interface.
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, self.scope_id)),
+            IpAddr::V4(_) => SocketAddr::new(self.ip, port),
         }
     }
 
@@ -109,6 +169,59 @@ impl PeerNode {
         self.status = PeerStatus::Online;
         self.last_seen = SystemTime::now();
     }
+
+    /// Get the peer's MAC address (`AA:BB:CC:DD:EE:FF`, FeiQ clients only)
+    ///
+    /// Unlike the IP address this survives DHCP changes, so it can be used
+    /// as a stable identity for the peer.
+    pub fn mac_address(&self) -> Option<String> {
+        self.feiq_header.as_ref().map(FeiqHeader::mac_address)
+    }
+
+    /// Check if the peer is a FeiQ client
+    pub fn is_feiq(&self) -> bool {
+        self.feiq_header.is_some()
+    }
+
+    /// Record the FeiQ header carried by an inbound message
+    pub fn observe_feiq_header(&mut self, msg: &ProtocolMessage) {
+        if let Some(ref header) = msg.feiq_header {
+            self.feiq_header = Some(header.clone());
+        }
+    }
+
+    /// Learn whether the peer reassembles chunked messages, reads UTF-8 and
+    /// takes JSON file offers
+    ///
+    /// Presence packets state the capabilities (a client can be replaced by
+    /// another on the same address); a chunk proves chunk support.
+    pub fn observe_capabilities(&mut self, msg: &ProtocolMessage) {
+        let command = msg.command();
+        if command.mode().is_entry() && command.mode() != Mode::BrExit {
+            self.supports_chunks = command.has(Opt::CapChunk);
+            self.supports_utf8 = command.has(Opt::CapUtf8);
+            self.supports_file_requests = command.has(Opt::CapFileRequest);
+        } else if command.has(Opt::Chunk) {
+            self.supports_chunks = true;
+        }
+    }
+
+    /// Learn the peer's text encoding from an inbound message
+    ///
+    /// Messages whose encoding couldn't be determined (pure ASCII from
+    /// a client that doesn't advertise UTF-8) leave the current value alone.
+    ///
+    /// # Returns
+    /// * `true` - The encoding changed
+    pub fn observe_encoding(&mut self, msg: &ProtocolMessage) -> bool {
+        match msg.encoding {
+            Some(encoding) if encoding != self.encoding => {
+                self.encoding = encoding;
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 /// Peer status
@@ -237,6 +350,20 @@ mod tests {
         assert_eq!(node.hostname, Some("alice-pc".to_string()));
     }
 
+    #[test]
+    fn test_socket_addr_keeps_ipv6_scope() {
+        let node = PeerNode::new("192.168.1.100".parse().unwrap(), 2425);
+        assert_eq!(node.socket_addr(2425), "192.168.1.100:2425".parse::<SocketAddr>().unwrap());
+
+        let mut node = PeerNode::new("fe80::1".parse().unwrap(), 2425);
+        node.scope_id = 3;
+        let SocketAddr::V6(addr) = node.socket_addr(2426) else {
+            panic!("expected an IPv6 address");
+        };
+        assert_eq!(addr.port(), 2426);
+        assert_eq!(addr.scope_id(), 3);
+    }
+
     #[test]
     fn test_display_name() {
         let ip = "192.168.1.100".parse().unwrap();
@@ -300,6 +427,82 @@ mod tests {
         assert_eq!(info.username, Some("Alice".to_string()));
     }
 
+    #[test]
+    fn test_observe_encoding() {
+        let ip = "192.168.1.100".parse().unwrap();
+        let mut node = PeerNode::new(ip, 2425);
+        assert_eq!(node.encoding, TextEncoding::Utf8);
+
+        // FeiQ packet (GBK bytes)
+        let feiq = crate::network::parse_message(
+            b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:6291459:\xb3\xc2\xd3\xe1\xd0\xc1",
+        )
+        .unwrap();
+        assert!(node.observe_encoding(&feiq));
+        assert_eq!(node.encoding, TextEncoding::Gbk);
+
+        // Plain ASCII from a legacy client teaches nothing
+        let ascii = crate::network::parse_message(b"1:100:bob:bob-pc:32:hello").unwrap();
+        assert!(!node.observe_encoding(&ascii));
+        assert_eq!(node.encoding, TextEncoding::Gbk);
+    }
+
+    #[test]
+    fn test_observe_feiq_header() {
+        let ip = "192.168.1.100".parse().unwrap();
+        let mut node = PeerNode::new(ip, 2425);
+        assert!(!node.is_feiq());
+        assert_eq!(node.mac_address(), None);
+
+        let msg = crate::network::parse_message(
+            b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:32:hi",
+        )
+        .unwrap();
+        node.observe_feiq_header(&msg);
+
+        assert!(node.is_feiq());
+        assert_eq!(node.mac_address(), Some("24:F5:AA:D7:C9:6A".to_string()));
+        assert_eq!(node.feiq_header.as_ref().unwrap().face_id, 41);
+    }
+
+    #[test]
+    fn test_observe_capabilities() {
+        let ip = "192.168.1.100".parse().unwrap();
+        let mut node = PeerNode::new(ip, 2425);
+        assert!(!node.supports_chunks);
+
+        // BR_ENTRY | CAPCHUNKOPT from a NeoLan client
+        let neolan = crate::network::parse_message(b"1:100:bob:bob-pc:536870913:Bob").unwrap();
+        node.observe_capabilities(&neolan);
+        assert!(node.supports_chunks);
+        assert!(!node.supports_file_requests);
+
+        // Each capability is learned on its own (BR_ENTRY | CAPFILEREQOPT)
+        let files = crate::network::parse_message(b"1:103:bob:bob-pc:1073741825:Bob").unwrap();
+        node.observe_capabilities(&files);
+        assert!(node.supports_file_requests);
+        assert!(!node.supports_chunks);
+        node.observe_capabilities(&neolan);
+
+        // Ordinary text messages leave it alone, a plain ANSENTRY clears it
+        let text = crate::network::parse_message(b"1:101:bob:bob-pc:32:hello").unwrap();
+        node.observe_capabilities(&text);
+        assert!(node.supports_chunks);
+        let ipmsg = crate::network::parse_message(b"1:102:bob:bob-pc:3:Bob").unwrap();
+        node.observe_capabilities(&ipmsg);
+        assert!(!node.supports_chunks);
+
+        // FeiQ BR_ENTRY | CAPUTF8OPT with a GBK name: a capability, not the encoding
+        let feiq = crate::network::parse_message(
+            b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153481:t0250254:DESKTOP-IOHG15K:23068673:\xb3\xc2\xd3\xe1\xd0\xc1\0",
+        )
+        .unwrap();
+        node.observe_capabilities(&feiq);
+        node.observe_encoding(&feiq);
+        assert!(node.supports_utf8);
+        assert_eq!(node.encoding, TextEncoding::Gbk);
+    }
+
     #[test]
     fn test_mark_offline_online() {
         let ip = "192.168.1.100".parse().unwrap();
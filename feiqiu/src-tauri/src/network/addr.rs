@@ -0,0 +1,181 @@
+// Address helpers shared by the UDP and TCP transports
+//
+// Sockets are bound dual-stack (`[::]` with IPV6_V6ONLY off) so one socket
+// serves both IPv4 and IPv6 peers. On such a socket IPv4 peers show up as
+// IPv4-mapped addresses (`::ffff:192.168.1.5`); everything above the
+// transports sees them as plain IPv4, so addresses are converted on the way
+// in (`canonical_addr`) and on the way out (`to_socket_family`). Hosts without
+// IPv6 fall back to an IPv4-only socket.
+
+use crate::config::AppConfig;
+use crate::{NeoLanError, Result};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, UdpSocket};
+
+/// IPv6 link-local multicast group for discovery (re-exported from AppConfig)
+pub const IPV6_MULTICAST_ADDR: Ipv6Addr = AppConfig::IPV6_MULTICAST_ADDR;
+
+/// Listen backlog for TCP listeners (same as `std::net::TcpListener::bind`)
+const TCP_BACKLOG: i32 = 128;
+
+/// Map an IPv4-mapped IPv6 address back to IPv4
+///
+/// Other addresses are returned unchanged.
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+/// Map an IPv4-mapped IPv6 socket address back to IPv4
+///
+/// Native IPv6 addresses keep their scope ID, which link-local peers need
+/// for replies.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => addr,
+        },
+        v4 => v4,
+    }
+}
+
+/// Convert a target address to the family of the local socket
+///
+/// A dual-stack (IPv6) socket reaches IPv4 peers through their IPv4-mapped
+/// address; an IPv4 socket can't reach IPv6 peers at all.
+///
+/// # Returns
+/// * `Ok(SocketAddr)` - Address usable with a socket bound to `local`
+/// * `Err(NeoLanError::Network)` - IPv6 target on an IPv4-only socket
+pub fn to_socket_family(addr: SocketAddr, local: SocketAddr) -> Result<SocketAddr> {
+    match (addr, local) {
+        (SocketAddr::V4(v4), SocketAddr::V6(_)) => Ok(SocketAddr::V6(SocketAddrV6::new(
+            v4.ip().to_ipv6_mapped(),
+            v4.port(),
+            0,
+            0,
+        ))),
+        (SocketAddr::V6(v6), SocketAddr::V4(_)) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => Ok(SocketAddr::new(IpAddr::V4(v4), v6.port())),
+            None => Err(NeoLanError::Network(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("Can't reach IPv6 peer {} from IPv4-only socket", addr),
+            ))),
+        },
+        _ => Ok(addr),
+    }
+}
+
+/// Create a dual-stack socket bound to `[::]:port`
+fn bind_dual_stack(port: u16, ty: Type, protocol: Protocol) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    socket.set_only_v6(false)?;
+    if ty == Type::STREAM {
+        // std's TcpListener does the same on Unix, so restarts don't hit TIME_WAIT
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+    }
+
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+    socket.bind(&SockAddr::from(addr))?;
+    Ok(socket)
+}
+
+/// Bind a UDP socket for both IPv4 and IPv6
+///
+/// Falls back to `0.0.0.0:port` when IPv6 is unavailable.
+pub fn bind_udp(port: u16) -> Result<UdpSocket> {
+    match bind_dual_stack(port, Type::DGRAM, Protocol::UDP) {
+        Ok(socket) => Ok(socket.into()),
+        Err(e) => {
+            tracing::debug!("Dual-stack UDP bind on port {} failed ({}), using IPv4 only", port, e);
+            let addr = format!("{}:{}", AppConfig::DEFAULT_BIND_IP, port);
+            UdpSocket::bind(&addr).map_err(NeoLanError::Network)
+        }
+    }
+}
+
+/// Bind a TCP listener for both IPv4 and IPv6
+///
+/// Falls back to `0.0.0.0:port` when IPv6 is unavailable.
+pub fn bind_tcp(port: u16) -> Result<TcpListener> {
+    let dual_stack = bind_dual_stack(port, Type::STREAM, Protocol::TCP).and_then(|socket| {
+        socket.listen(TCP_BACKLOG)?;
+        Ok(socket)
+    });
+
+    match dual_stack {
+        Ok(socket) => Ok(socket.into()),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Err(NeoLanError::Network(e)),
+        Err(e) => {
+            tracing::debug!("Dual-stack TCP bind on port {} failed ({}), using IPv4 only", port, e);
+            let addr = format!("{}:{}", AppConfig::DEFAULT_BIND_IP, port);
+            TcpListener::bind(&addr).map_err(NeoLanError::Network)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_addr() {
+        let mapped: SocketAddr = "[::ffff:192.168.1.5]:2425".parse().unwrap();
+        assert_eq!(canonical_addr(mapped), "192.168.1.5:2425".parse::<SocketAddr>().unwrap());
+
+        let v4: SocketAddr = "192.168.1.5:2425".parse().unwrap();
+        assert_eq!(canonical_addr(v4), v4);
+
+        // Link-local peers keep their scope
+        let link_local = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 2425, 0, 3));
+        assert_eq!(canonical_addr(link_local), link_local);
+    }
+
+    #[test]
+    fn test_to_socket_family() {
+        let v4: SocketAddr = "192.168.1.5:2425".parse().unwrap();
+        let v6: SocketAddr = "[fe80::1]:2425".parse().unwrap();
+        let local_v6: SocketAddr = "[::]:2425".parse().unwrap();
+        let local_v4: SocketAddr = "0.0.0.0:2425".parse().unwrap();
+
+        assert_eq!(
+            to_socket_family(v4, local_v6).unwrap(),
+            "[::ffff:192.168.1.5]:2425".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(to_socket_family(v6, local_v6).unwrap(), v6);
+        assert_eq!(to_socket_family(v4, local_v4).unwrap(), v4);
+        assert!(to_socket_family(v6, local_v4).is_err());
+    }
+
+    #[test]
+    fn test_bind_udp_reaches_both_families() {
+        let socket = bind_udp(0).unwrap();
+        let local = socket.local_addr().unwrap();
+        let target = SocketAddr::new("127.0.0.1".parse().unwrap(), local.port());
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"v4", target).unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (len, from) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"v4");
+        assert_eq!(canonical_addr(from), client.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_bind_tcp_accepts_ipv4() {
+        let listener = bind_tcp(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (_, from) = listener.accept().unwrap();
+        assert_eq!(canonical_addr(from), client.local_addr().unwrap());
+    }
+}
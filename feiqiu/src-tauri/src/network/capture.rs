@@ -0,0 +1,334 @@
+// Packet capture - raw datagram recording, reading and dissection
+//
+// Interop problems with FeiQ/IPMsg clients are easiest to reproduce from the
+// exact bytes on the wire. A capture is a JSON Lines file with one record per
+// datagram (time, direction, addresses, raw bytes as hex), so it can be
+// attached to a bug report, read back with `read_capture`, printed with
+// `CaptureRecord::dissect` and replayed with `PeerManager::replay`.
+
+use super::protocol::{explain_message_type, parse_message};
+use crate::{NeoLanError, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Environment variable naming the file to record a capture to
+pub const CAPTURE_ENV: &str = "NEOLAN_CAPTURE";
+
+/// Environment variable naming a capture to replay at startup
+pub const REPLAY_ENV: &str = "NEOLAN_REPLAY";
+
+/// Direction of a captured datagram
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Received from `peer`
+    In,
+    /// Sent to `peer`
+    Out,
+}
+
+impl Direction {
+    /// Get the short label used in dissector output
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::In => "IN",
+            Self::Out => "OUT",
+        }
+    }
+}
+
+/// One captured datagram
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// When the datagram was sent or received
+    pub timestamp: DateTime<Utc>,
+
+    /// Whether we received or sent it
+    pub direction: Direction,
+
+    /// Local socket address it went through (if known)
+    pub local: Option<SocketAddr>,
+
+    /// Remote address (sender for inbound, target for outbound)
+    pub peer: SocketAddr,
+
+    /// Raw datagram bytes, hex encoded in the file
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// Create a record stamped with the current time
+    pub fn new(direction: Direction, local: Option<SocketAddr>, peer: SocketAddr, data: &[u8]) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            direction,
+            local,
+            peer,
+            data: data.to_vec(),
+        }
+    }
+
+    /// Describe the datagram for a human reader
+    ///
+    /// The first line summarizes the packet (time, direction, peer, command
+    /// via [`explain_message_type`], sender); the following indented lines
+    /// show the decoded content and extension sections. Packets that don't
+    /// parse are shown with the parse error and their escaped bytes.
+    pub fn dissect(&self) -> String {
+        let mut out = format!(
+            "{} {:<3} {} {} bytes",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.direction.as_str(),
+            self.peer,
+            self.data.len()
+        );
+
+        match parse_message(&self.data) {
+            Ok(msg) => {
+                let _ = write!(
+                    out,
+                    " {} packet_id={} from={}@{}",
+                    explain_message_type(msg.msg_type),
+                    msg.packet_id,
+                    msg.sender_name,
+                    msg.sender_host
+                );
+                if let Some(encoding) = msg.encoding {
+                    let _ = write!(out, " encoding={}", encoding.as_str());
+                }
+                if let Some(header) = &msg.feiq_header {
+                    let _ = write!(out, "\n    feiq: {:?}", header);
+                }
+                if !msg.content.is_empty() {
+                    let _ = write!(out, "\n    content: {:?}", msg.content);
+                }
+                for (i, section) in msg.extensions.iter().enumerate() {
+                    let _ = write!(out, "\n    ext[{}]: {:?}", i, section);
+                }
+            }
+            Err(e) => {
+                let _ = write!(out, " <unparsed: {}>\n    raw: {}", e, self.data.escape_ascii());
+            }
+        }
+
+        out
+    }
+}
+
+/// Capture writer shared by every transport that records
+///
+/// Each record is written as one line and flushed immediately, so a capture
+/// taken up to a crash is still readable.
+pub struct PacketCapture {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl PacketCapture {
+    /// Record into any writer
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Create (or truncate) a capture file
+    ///
+    /// # Returns
+    /// * `Ok(PacketCapture)` - Capture writing to `path`
+    /// * `Err(NeoLanError::Network)` - File couldn't be created
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::create(path.as_ref())?;
+        tracing::info!("Capturing UDP datagrams to {}", path.as_ref().display());
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    /// Open the capture named by [`CAPTURE_ENV`], if set
+    ///
+    /// A capture that can't be created is logged and skipped: debugging aids
+    /// must not stop the application from starting.
+    pub fn from_env() -> Option<Arc<Self>> {
+        let path = std::env::var_os(CAPTURE_ENV)?;
+        match Self::create(&path) {
+            Ok(capture) => Some(Arc::new(capture)),
+            Err(e) => {
+                tracing::error!("Failed to create capture file {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Append a datagram to the capture
+    ///
+    /// Write errors are logged, never returned: a failing capture must not
+    /// break sending or receiving.
+    pub fn record(&self, direction: Direction, local: Option<SocketAddr>, peer: SocketAddr, data: &[u8]) {
+        let record = CaptureRecord::new(direction, local, peer, data);
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize capture record: {}", e);
+                return;
+            }
+        };
+
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(e) => e.into_inner(),
+        };
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::warn!("Failed to write capture record: {}", e);
+        }
+    }
+}
+
+/// Read every record of a capture file
+///
+/// Blank lines are skipped.
+///
+/// # Returns
+/// * `Ok(Vec<CaptureRecord>)` - Records in file order
+/// * `Err(NeoLanError::Protocol)` - A line isn't a valid record (with its line number)
+pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<CaptureRecord>> {
+    let reader = BufReader::new(File::open(path.as_ref())?);
+    let mut records = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = serde_json::from_str(&line).map_err(|e| {
+            NeoLanError::Protocol(format!(
+                "Invalid capture record at {}:{}: {}",
+                path.as_ref().display(),
+                index + 1,
+                e
+            ))
+        })?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Hex (de)serialization of the raw datagram bytes
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| D::Error::custom(format!("invalid hex at offset {}", i)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writer whose bytes the test can read back
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_capture_file_round_trip() {
+        let path = std::env::temp_dir().join("test_capture_round_trip.jsonl");
+        let peer: SocketAddr = "192.168.1.100:2425".parse().unwrap();
+        let local: SocketAddr = "0.0.0.0:2425".parse().unwrap();
+
+        let capture = PacketCapture::create(&path).unwrap();
+        capture.record(Direction::In, Some(local), peer, b"1:1:alice:alice-pc:32:hi\0\xff\0");
+        capture.record(Direction::Out, None, peer, b"1:2:bob:bob-pc:33:1");
+        drop(capture);
+
+        let records = read_capture(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::In);
+        assert_eq!(records[0].local, Some(local));
+        assert_eq!(records[0].peer, peer);
+        assert_eq!(records[0].data, b"1:1:alice:alice-pc:32:hi\0\xff\0");
+        assert_eq!(records[1].direction, Direction::Out);
+        assert_eq!(records[1].data, b"1:2:bob:bob-pc:33:1");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_is_one_json_line() {
+        let buffer = SharedBuffer::default();
+        let capture = PacketCapture::new(buffer.clone());
+        capture.record(Direction::Out, None, "10.0.0.1:2425".parse().unwrap(), b"\x00\x1f\xab");
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.contains(r#""direction":"out""#));
+        assert!(text.contains(r#""data":"001fab""#));
+    }
+
+    #[test]
+    fn test_read_capture_reports_bad_line() {
+        let path = std::env::temp_dir().join("test_capture_bad_line.jsonl");
+        std::fs::write(&path, "\n{\"not\": \"a record\"}\n").unwrap();
+
+        let err = read_capture(&path).unwrap_err().to_string();
+        assert!(err.contains(":2:"), "{}", err);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dissect() {
+        let peer: SocketAddr = "192.168.1.100:2425".parse().unwrap();
+        let record = CaptureRecord::new(
+            Direction::In,
+            None,
+            peer,
+            b"1:42:alice:alice-pc:288:Hello\0Engineering\0",
+        );
+
+        let text = record.dissect();
+        assert!(text.contains("IN  192.168.1.100:2425"), "{}", text);
+        assert!(text.contains(&explain_message_type(288)), "{}", text);
+        assert!(text.contains("packet_id=42 from=alice@alice-pc"), "{}", text);
+        assert!(text.contains("content: \"Hello\""), "{}", text);
+        assert!(text.contains("ext[0]: \"Engineering\""), "{}", text);
+
+        let garbage = CaptureRecord::new(Direction::In, None, peer, b"not ipmsg\xff");
+        let text = garbage.dissect();
+        assert!(text.contains("<unparsed:"), "{}", text);
+        assert!(text.contains("raw: not ipmsg\\xff"), "{}", text);
+    }
+}
@@ -0,0 +1,816 @@
+// Payload codec - typed content for every IPMsg mode
+//
+// `ProtocolMessage` carries the content as text; what that text means depends
+// on the command mode (a packet ID for RECVMSG, hex fields for GETFILEDATA, a
+// `\a`-separated host list for ANSLIST, ...). `Payload` decodes it once into a
+// typed value and encodes it back into content + extension sections.
+
+use super::command::{Mode, Opt};
+use super::protocol::{
+    encode_attachments, FileAttachment, FileDataRequest, FileSendRequest, FileSendResponse,
+    ProtocolMessage, EXTENSION_SEPARATOR, FILELIST_SEPARATOR,
+};
+use crate::{NeoLanError, Result};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+/// Number of `\a`-separated fields per IPMSG_ANSLIST host entry
+const HOST_ENTRY_FIELDS: usize = 7;
+
+/// Placeholder IPMsg puts in empty host list fields (nickname / group)
+const EMPTY_FIELD: &str = "\u{08}";
+
+/// Decoded message content
+///
+/// One variant per content format. Modes that share a format share a variant
+/// (e.g. every presence packet decodes to [`Payload::Entry`]).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Payload {
+    /// IPMSG_NOOPERATION
+    NoOperation,
+
+    /// IPMSG_BR_ENTRY / IPMSG_BR_EXIT / IPMSG_ANSENTRY / IPMSG_BR_ABSENCE: `nickname\0group\0`
+    Entry {
+        /// Display name (the sender name when the content is empty, as with FeiQ)
+        nickname: String,
+        group: Option<String>,
+    },
+
+    /// IPMSG_SENDMSG: `text\0attachments\0` (attachments only with IPMSG_FILEATTACHOPT)
+    ///
+    /// Attachment entries that don't parse are left out (see [`decode_attachments`]).
+    Text {
+        text: String,
+        attachments: Vec<FileAttachment>,
+    },
+
+    /// IPMSG_SENDMSG | IPMSG_CHUNKOPT (NeoLan): `messageID:index:count:text` (decimal)
+    ///
+    /// One piece of a message too long for a single datagram. `message_id` is
+    /// the packet ID of the first chunk; every chunk has its own packet ID and
+    /// is acknowledged on its own.
+    Chunk {
+        message_id: u64,
+        index: usize,
+        count: usize,
+        text: String,
+    },
+
+    /// IPMSG_RECVMSG: packet ID of the acknowledged message (decimal)
+    RecvMsg { original_packet_id: u64 },
+
+    /// IPMSG_READMSG: packet ID of the message that was opened (decimal)
+    ReadMsg { original_packet_id: u64 },
+
+    /// IPMSG_DELMSG: packet ID of the message that was deleted (decimal)
+    DelMsg { original_packet_id: u64 },
+
+    /// IPMSG_ANSREADMSG: packet ID of the READMSG being answered (decimal)
+    AnsReadMsg { original_packet_id: u64 },
+
+    /// IPMSG_BR_ISGETLIST / IPMSG_BR_ISGETLIST2 (no content)
+    IsGetList,
+
+    /// IPMSG_OKGETLIST (no content)
+    OkGetList,
+
+    /// IPMSG_GETLIST: index of the first host wanted (decimal, empty = 0)
+    GetList { start: usize },
+
+    /// IPMSG_ANSLIST: `start\acount\a` followed by the host entries
+    AnsList {
+        start: usize,
+        entries: Vec<HostListEntry>,
+    },
+
+    /// IPMSG_GETINFO (no content)
+    GetInfo,
+
+    /// IPMSG_SENDINFO: client version string
+    SendInfo { version: String },
+
+    /// IPMSG_GETABSENCEINFO (no content)
+    GetAbsenceInfo,
+
+    /// IPMSG_SENDABSENCEINFO: absence message text
+    AbsenceInfo { text: String },
+
+    /// IPMSG_GETFILEDATA (standard IPMsg): `packetID:fileID:offset:` (hex)
+    GetFileData(FileDataRequest),
+
+    /// IPMSG_GETFILEDATA (NeoLan): JSON file offer
+    FileOffer(FileSendRequest),
+
+    /// IPMSG_RELEASEFILES (standard IPMsg): packet ID of the attachment message (decimal)
+    ReleaseFiles { packet_id: u64 },
+
+    /// IPMSG_RELEASEFILES (NeoLan): JSON accept / reject
+    FileReply(FileSendResponse),
+
+    /// IPMSG_GETDIRFILES: `packetID:fileID:` (hex)
+    GetDirFiles { packet_id: u64, file_id: u64 },
+
+    /// IPMSG_GETPUBKEY: sender's encryption capabilities (hex)
+    GetPubKey { caps: u32 },
+
+    /// IPMSG_ANSPUBKEY: `caps:exponent-modulus` (hex)
+    PubKey {
+        caps: u32,
+        exponent: u32,
+        modulus: String,
+    },
+
+    /// Mode without a known content format (content kept verbatim)
+    Unknown { content: String },
+}
+
+/// One host in an IPMSG_ANSLIST reply
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostListEntry {
+    /// Login name
+    pub user_name: String,
+
+    /// Hostname
+    pub host_name: String,
+
+    /// Last command word the host broadcast (presence options)
+    pub command: u32,
+
+    /// Host address
+    pub address: Ipv4Addr,
+
+    /// Host UDP port
+    pub port: u16,
+
+    /// Display name (empty if not set)
+    pub nickname: String,
+
+    /// Group name (empty if not set)
+    pub group: String,
+}
+
+impl HostListEntry {
+    /// Encode as 7 `\a`-terminated fields
+    fn encode(&self) -> String {
+        let or_placeholder = |value: &str| {
+            if value.is_empty() {
+                EMPTY_FIELD.to_string()
+            } else {
+                value.to_string()
+            }
+        };
+
+        [
+            self.user_name.clone(),
+            self.host_name.clone(),
+            self.command.to_string(),
+            self.address.to_string(),
+            self.port.to_string(),
+            or_placeholder(&self.nickname),
+            or_placeholder(&self.group),
+        ]
+        .iter()
+        .map(|field| format!("{}{}", field, FILELIST_SEPARATOR))
+        .collect()
+    }
+
+    /// Parse from 7 host list fields
+    fn parse(fields: &[&str]) -> Result<Self> {
+        let [user_name, host_name, command, address, port, nickname, group] = fields else {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid host list entry: expected {} fields, got {}",
+                HOST_ENTRY_FIELDS,
+                fields.len()
+            )));
+        };
+
+        let field_value = |value: &str| {
+            if value == EMPTY_FIELD {
+                String::new()
+            } else {
+                value.to_string()
+            }
+        };
+
+        Ok(Self {
+            user_name: user_name.to_string(),
+            host_name: host_name.to_string(),
+            command: parse_decimal("host command", command)?,
+            address: address.trim().parse().map_err(|_| {
+                NeoLanError::Protocol(format!("Invalid host address in host list: {}", address))
+            })?,
+            port: parse_decimal("host port", port)?,
+            nickname: field_value(nickname),
+            group: field_value(group),
+        })
+    }
+}
+
+impl Payload {
+    /// Decode the content of a protocol message according to its mode
+    ///
+    /// # Arguments
+    /// * `msg` - Parsed protocol message
+    ///
+    /// # Returns
+    /// * `Ok(Payload)` - Typed content
+    /// * `Err(NeoLanError::Protocol)` - Content doesn't match the mode's format
+    pub fn decode(msg: &ProtocolMessage) -> Result<Self> {
+        let content = msg.content.as_str();
+
+        Ok(match msg.command().mode() {
+            Mode::NoOperation => Self::NoOperation,
+            Mode::BrEntry | Mode::BrExit | Mode::AnsEntry | Mode::BrAbsence => Self::Entry {
+                nickname: if content.is_empty() {
+                    msg.sender_name.clone()
+                } else {
+                    content.to_string()
+                },
+                group: msg.group_name().map(str::to_string),
+            },
+            Mode::SendMsg if msg.command().has(Opt::Chunk) => decode_chunk(content)?,
+            Mode::SendMsg => Self::Text {
+                text: content.to_string(),
+                attachments: decode_attachments(msg),
+            },
+            Mode::RecvMsg => Self::RecvMsg {
+                original_packet_id: parse_decimal("packet ID", content)?,
+            },
+            Mode::ReadMsg => Self::ReadMsg {
+                original_packet_id: parse_decimal("packet ID", content)?,
+            },
+            Mode::DelMsg => Self::DelMsg {
+                original_packet_id: parse_decimal("packet ID", content)?,
+            },
+            Mode::AnsReadMsg => Self::AnsReadMsg {
+                original_packet_id: parse_decimal("packet ID", content)?,
+            },
+            Mode::BrIsGetList | Mode::BrIsGetList2 => Self::IsGetList,
+            Mode::OkGetList => Self::OkGetList,
+            Mode::GetList => Self::GetList {
+                start: if content.trim().is_empty() {
+                    0
+                } else {
+                    parse_decimal("list start", content)?
+                },
+            },
+            Mode::AnsList => decode_host_list(content)?,
+            Mode::GetInfo => Self::GetInfo,
+            Mode::SendInfo => Self::SendInfo {
+                version: content.to_string(),
+            },
+            Mode::GetAbsenceInfo => Self::GetAbsenceInfo,
+            Mode::SendAbsenceInfo => Self::AbsenceInfo {
+                text: content.to_string(),
+            },
+            Mode::GetFileData if is_json(content) => Self::FileOffer(parse_json(content)?),
+            Mode::GetFileData => Self::GetFileData(FileDataRequest::parse(content)?),
+            Mode::ReleaseFiles if is_json(content) => Self::FileReply(parse_json(content)?),
+            Mode::ReleaseFiles => Self::ReleaseFiles {
+                packet_id: parse_decimal("packet ID", content)?,
+            },
+            Mode::GetDirFiles => {
+                let mut fields = content.split(':');
+                Self::GetDirFiles {
+                    packet_id: parse_hex("packet ID", fields.next().unwrap_or_default())?,
+                    file_id: parse_hex("file ID", fields.next().unwrap_or_default())?,
+                }
+            }
+            Mode::GetPubKey => Self::GetPubKey {
+                caps: parse_hex("key capabilities", content)?,
+            },
+            Mode::AnsPubKey => decode_public_key(content)?,
+            Mode::Unknown(_) => Self::Unknown {
+                content: content.to_string(),
+            },
+        })
+    }
+
+    /// Encode into message content and extension sections
+    ///
+    /// # Returns
+    /// `(content, extensions)` ready for [`ProtocolMessage`]. The caller sets the
+    /// command word (e.g. `IPMSG_FILEATTACHOPT` when attachments are present).
+    pub fn encode(&self) -> (String, Vec<String>) {
+        match self {
+            Self::NoOperation
+            | Self::IsGetList
+            | Self::OkGetList
+            | Self::GetInfo
+            | Self::GetAbsenceInfo => (String::new(), Vec::new()),
+            Self::Entry { nickname, group } => (
+                nickname.clone(),
+                group.iter().cloned().collect(),
+            ),
+            Self::Text { text, attachments } => {
+                let extensions = if attachments.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![encode_attachments(attachments)]
+                };
+                (text.clone(), extensions)
+            }
+            Self::Chunk {
+                message_id,
+                index,
+                count,
+                text,
+            } => (format!("{}:{}:{}:{}", message_id, index, count, text), Vec::new()),
+            Self::RecvMsg { original_packet_id }
+            | Self::ReadMsg { original_packet_id }
+            | Self::DelMsg { original_packet_id }
+            | Self::AnsReadMsg { original_packet_id } => {
+                (original_packet_id.to_string(), Vec::new())
+            }
+            Self::GetList { start } => (start.to_string(), Vec::new()),
+            Self::AnsList { start, entries } => {
+                let mut content = format!(
+                    "{:5}{}{:5}{}",
+                    start,
+                    FILELIST_SEPARATOR,
+                    entries.len(),
+                    FILELIST_SEPARATOR
+                );
+                content.extend(entries.iter().map(HostListEntry::encode));
+                (content, Vec::new())
+            }
+            Self::SendInfo { version } => (version.clone(), Vec::new()),
+            Self::AbsenceInfo { text } => (text.clone(), Vec::new()),
+            Self::GetFileData(request) => (request.encode(), Vec::new()),
+            Self::FileOffer(request) => (to_json(request), Vec::new()),
+            Self::ReleaseFiles { packet_id } => (packet_id.to_string(), Vec::new()),
+            Self::FileReply(response) => (to_json(response), Vec::new()),
+            Self::GetDirFiles { packet_id, file_id } => {
+                (format!("{:x}:{:x}:", packet_id, file_id), Vec::new())
+            }
+            Self::GetPubKey { caps } => (format!("{:X}", caps), Vec::new()),
+            Self::PubKey {
+                caps,
+                exponent,
+                modulus,
+            } => (format!("{:X}:{:X}-{}", caps, exponent, modulus), Vec::new()),
+            Self::Unknown { content } => (content.clone(), Vec::new()),
+        }
+    }
+}
+
+/// Attachments of an IPMSG_SENDMSG, leaving out the entries that don't parse
+///
+/// A bad attachment section mustn't cost the message: its text is still
+/// stored and acknowledged (otherwise the sender retransmits it in vain).
+fn decode_attachments(msg: &ProtocolMessage) -> Vec<FileAttachment> {
+    let error = match msg.attachments() {
+        Ok(attachments) => return attachments,
+        Err(e) => e,
+    };
+
+    let attachments: Vec<FileAttachment> = msg
+        .extension(0)
+        .unwrap_or_default()
+        .trim_end_matches(EXTENSION_SEPARATOR)
+        .split(FILELIST_SEPARATOR)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| FileAttachment::parse(entry).ok())
+        .collect();
+    tracing::warn!(
+        "Ignoring unreadable attachments of message {} from {} (kept {}): {}",
+        msg.packet_id,
+        msg.sender_host,
+        attachments.len(),
+        error
+    );
+    attachments
+}
+
+/// Decode the content of a NeoLan message chunk
+fn decode_chunk(content: &str) -> Result<Payload> {
+    let mut fields = content.splitn(4, ':');
+    let mut next = |name: &str| {
+        fields
+            .next()
+            .ok_or_else(|| NeoLanError::Protocol(format!("Message chunk without {}", name)))
+    };
+
+    let message_id = parse_decimal("message ID", next("message ID")?)?;
+    let index = parse_decimal("chunk index", next("chunk index")?)?;
+    let count = parse_decimal("chunk count", next("chunk count")?)?;
+    let text = next("text")?.to_string();
+
+    Ok(Payload::Chunk {
+        message_id,
+        index,
+        count,
+        text,
+    })
+}
+
+/// Decode IPMSG_ANSLIST content
+fn decode_host_list(content: &str) -> Result<Payload> {
+    let mut fields: Vec<&str> = content.split(FILELIST_SEPARATOR).collect();
+    if fields.last().is_some_and(|last| last.is_empty()) {
+        fields.pop();
+    }
+
+    if fields.len() < 2 {
+        return Err(NeoLanError::Protocol(format!(
+            "Invalid host list: missing start/count header: {}",
+            content
+        )));
+    }
+
+    let start = parse_decimal("list start", fields[0])?;
+    let count: usize = parse_decimal("host count", fields[1])?;
+
+    let hosts = &fields[2..];
+    if hosts.len() != count * HOST_ENTRY_FIELDS {
+        return Err(NeoLanError::Protocol(format!(
+            "Invalid host list: {} hosts announced, {} fields present",
+            count,
+            hosts.len()
+        )));
+    }
+
+    let entries = hosts
+        .chunks(HOST_ENTRY_FIELDS)
+        .map(HostListEntry::parse)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Payload::AnsList { start, entries })
+}
+
+/// Decode IPMSG_ANSPUBKEY content (`caps:exponent-modulus`)
+fn decode_public_key(content: &str) -> Result<Payload> {
+    let invalid = || NeoLanError::Protocol(format!("Invalid public key: {}", content));
+
+    let (caps, key) = content.split_once(':').ok_or_else(invalid)?;
+    let (exponent, modulus) = key.split_once('-').ok_or_else(invalid)?;
+    let modulus = modulus.trim();
+
+    if modulus.is_empty() || !modulus.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    Ok(Payload::PubKey {
+        caps: parse_hex("key capabilities", caps)?,
+        exponent: parse_hex("key exponent", exponent)?,
+        modulus: modulus.to_string(),
+    })
+}
+
+fn parse_decimal<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| NeoLanError::Protocol(format!("Invalid {}: {:?}", name, value)))
+}
+
+fn parse_hex<T: TryFrom<u64>>(name: &str, value: &str) -> Result<T> {
+    u64::from_str_radix(value.trim(), 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| NeoLanError::Protocol(format!("Invalid {} (hex): {:?}", name, value)))
+}
+
+fn is_json(content: &str) -> bool {
+    content.trim_start().starts_with('{')
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(content: &str) -> Result<T> {
+    serde_json::from_str(content)
+        .map_err(|e| NeoLanError::Protocol(format!("Invalid JSON payload: {}", e)))
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    // Plain data structs: serialization can't fail
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::parse_message;
+
+    /// Decode a packet, check the payload, and check it encodes back to the
+    /// same content and extension sections
+    fn assert_round_trip(packet: &[u8], expected: Payload) {
+        let msg = parse_message(packet).unwrap();
+        let payload = Payload::decode(&msg).unwrap();
+        assert_eq!(payload, expected);
+
+        let (content, extensions) = payload.encode();
+        assert_eq!(content, msg.content);
+        assert_eq!(extensions, msg.extensions);
+    }
+
+    #[test]
+    fn test_round_trip_ipmsg_entry() {
+        // IPMsg 3.x BR_ENTRY | UTF8OPT | CAPUTF8OPT
+        assert_round_trip(
+            b"1:1700000001:alice:alice-pc:25165825:Alice\0Dev\0",
+            Payload::Entry {
+                nickname: "Alice".to_string(),
+                group: Some("Dev".to_string()),
+            },
+        );
+    }
+
+    #[test]
+    fn test_decode_feiq_entry() {
+        // FeiQ ANSENTRY: the nickname ends up in sender_name
+        let packet = "1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:6291459:陈俞辛\0研发部\0";
+        let msg = parse_message(packet.as_bytes()).unwrap();
+
+        assert_eq!(
+            Payload::decode(&msg).unwrap(),
+            Payload::Entry {
+                nickname: "陈俞辛".to_string(),
+                group: Some("研发部".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_round_trip_text_with_attachment() {
+        // SENDMSG | SENDCHECKOPT | FILEATTACHOPT
+        assert_round_trip(
+            b"1:1700000002:alice:alice-pc:2097440:see attached\x000:report.pdf:1a2b:65000000:1:\x07\0",
+            Payload::Text {
+                text: "see attached".to_string(),
+                attachments: vec![FileAttachment::regular(
+                    0,
+                    "report.pdf".to_string(),
+                    0x1a2b,
+                    0x65000000,
+                )],
+            },
+        );
+    }
+
+    #[test]
+    fn test_bad_attachment_keeps_text() {
+        // The second entry lacks its size and time: only it is left out
+        let msg = parse_message(
+            b"1:1700000019:alice:alice-pc:2097440:see attached\x000:report.pdf:1a2b:65000000:1:\x071:broken\x07\0",
+        )
+        .unwrap();
+        assert!(msg.attachments().is_err());
+
+        assert_eq!(
+            Payload::decode(&msg).unwrap(),
+            Payload::Text {
+                text: "see attached".to_string(),
+                attachments: vec![FileAttachment::regular(
+                    0,
+                    "report.pdf".to_string(),
+                    0x1a2b,
+                    0x65000000,
+                )],
+            }
+        );
+    }
+
+    #[test]
+    fn test_round_trip_chunk() {
+        // SENDMSG | SENDCHECKOPT | CHUNKOPT; the text may contain colons
+        assert_round_trip(
+            b"1:1700000021:alice:alice-pc:536871200:1700000020:1:3:see: part two",
+            Payload::Chunk {
+                message_id: 1700000020,
+                index: 1,
+                count: 3,
+                text: "see: part two".to_string(),
+            },
+        );
+
+        let decode = |packet: &[u8]| Payload::decode(&parse_message(packet).unwrap());
+        assert!(decode(b"1:1:alice:alice-pc:536871200:1700000020:1").is_err());
+        assert!(decode(b"1:1:alice:alice-pc:536871200:x:1:3:text").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_recv_msg() {
+        assert_round_trip(
+            b"1:1700000003:bob:bob-pc:33:1700000002",
+            Payload::RecvMsg {
+                original_packet_id: 1700000002,
+            },
+        );
+
+        // FeiQ acknowledgment
+        assert_round_trip(
+            b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386710:cgc:DESKTOP-IOHG15K:33:1761386707",
+            Payload::RecvMsg {
+                original_packet_id: 1761386707,
+            },
+        );
+    }
+
+    #[test]
+    fn test_round_trip_read_and_delete() {
+        assert_round_trip(
+            b"1:1700000004:bob:bob-pc:48:1700000002",
+            Payload::ReadMsg {
+                original_packet_id: 1700000002,
+            },
+        );
+        assert_round_trip(
+            b"1:1700000005:bob:bob-pc:49:1700000002",
+            Payload::DelMsg {
+                original_packet_id: 1700000002,
+            },
+        );
+        assert_round_trip(
+            b"1:1700000006:alice:alice-pc:50:1700000004",
+            Payload::AnsReadMsg {
+                original_packet_id: 1700000004,
+            },
+        );
+    }
+
+    #[test]
+    fn test_round_trip_absence_info() {
+        // FeiQ, GBK: "外出"
+        assert_round_trip(
+            b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386711:cgc:DESKTOP-IOHG15K:81:\xcd\xe2\xb3\xf6",
+            Payload::AbsenceInfo {
+                text: "外出".to_string(),
+            },
+        );
+        assert_round_trip(
+            b"1:1700000007:alice:alice-pc:80:",
+            Payload::GetAbsenceInfo,
+        );
+    }
+
+    #[test]
+    fn test_round_trip_user_info() {
+        assert_round_trip(b"1:1700000008:bob:bob-pc:64:", Payload::GetInfo);
+        assert_round_trip(
+            b"1:1700000009:alice:alice-pc:65:Win32 version 3.42",
+            Payload::SendInfo {
+                version: "Win32 version 3.42".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn test_round_trip_host_list() {
+        assert_round_trip(b"1:1700000010:bob:bob-pc:18:0", Payload::GetList { start: 0 });
+        assert_round_trip(
+            b"1:1700000011:alice:alice-pc:19:    0\x07    2\x07bob\x07bob-pc\x071\x07192.168.1.20\x072425\x07Bob\x07Dev\x07carol\x07carol-pc\x07257\x07192.168.1.30\x072425\x07\x08\x07\x08\x07",
+            Payload::AnsList {
+                start: 0,
+                entries: vec![
+                    HostListEntry {
+                        user_name: "bob".to_string(),
+                        host_name: "bob-pc".to_string(),
+                        command: 1,
+                        address: Ipv4Addr::new(192, 168, 1, 20),
+                        port: 2425,
+                        nickname: "Bob".to_string(),
+                        group: "Dev".to_string(),
+                    },
+                    HostListEntry {
+                        user_name: "carol".to_string(),
+                        host_name: "carol-pc".to_string(),
+                        command: 257,
+                        address: Ipv4Addr::new(192, 168, 1, 30),
+                        port: 2425,
+                        nickname: String::new(),
+                        group: String::new(),
+                    },
+                ],
+            },
+        );
+    }
+
+    #[test]
+    fn test_round_trip_file_transfer() {
+        assert_round_trip(
+            b"1:1700000012:bob:bob-pc:96:6553f100:0:0:",
+            Payload::GetFileData(FileDataRequest {
+                packet_id: 0x6553f100,
+                file_id: 0,
+                offset: 0,
+            }),
+        );
+        assert_round_trip(
+            b"1:1700000013:bob:bob-pc:97:1700000002",
+            Payload::ReleaseFiles {
+                packet_id: 1700000002,
+            },
+        );
+        assert_round_trip(
+            b"1:1700000014:bob:bob-pc:98:6553f100:1:",
+            Payload::GetDirFiles {
+                packet_id: 0x6553f100,
+                file_id: 1,
+            },
+        );
+
+        // NeoLan JSON offer over GETFILEDATA
+        assert_round_trip(
+            br#"1:1700000015:alice:alice-pc:96:{"name":"a.txt","size":12,"md5":"abc"}"#,
+            Payload::FileOffer(FileSendRequest {
+                name: "a.txt".to_string(),
+                size: 12,
+                md5: "abc".to_string(),
+            }),
+        );
+    }
+
+    #[test]
+    fn test_round_trip_public_key() {
+        assert_round_trip(
+            b"1:1700000016:alice:alice-pc:114:21003",
+            Payload::GetPubKey { caps: 0x21003 },
+        );
+        assert_round_trip(
+            b"1:1700000017:bob:bob-pc:115:21003:10001-C3A5F1E2D4B6",
+            Payload::PubKey {
+                caps: 0x21003,
+                exponent: 0x10001,
+                modulus: "C3A5F1E2D4B6".to_string(),
+            },
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_content() {
+        let decode = |packet: &[u8]| Payload::decode(&parse_message(packet).unwrap());
+
+        assert!(decode(b"1:1:bob:bob-pc:33:not-a-number").is_err());
+        assert!(decode(b"1:1:bob:bob-pc:115:21003").is_err());
+        assert!(decode(b"1:1:bob:bob-pc:19:    0\x07    2\x07bob\x07").is_err());
+        assert!(decode(b"1:1:bob:bob-pc:96:zz:0:0:").is_err());
+    }
+
+    /// FeiQ SENDMSG | SENDCHECKOPT | FILEATTACHOPT, all text in GBK:
+    /// "请查收" with the attachment "报告.doc" (1000 bytes)
+    const FEIQ_GBK_ATTACHMENT: &[u8] = b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153483:t0250254:DESKTOP-IOHG15K:2097440:\xc7\xeb\xb2\xe9\xca\xd5\x000:\xb1\xa8\xb8\xe6.doc:3e8:65a1b2c3:1:\x07\0";
+
+    /// IPMsg BR_ENTRY in Shift_JIS: nickname "たなか", group "営業部"
+    const IPMSG_SJIS_ENTRY: &[u8] = b"1:1700000101:tanaka:tanaka-pc:1:\x82\xbd\x82\xc8\x82\xa9\0\x89c\x8b\xc6\x95\x94\0";
+
+    #[test]
+    fn test_decode_gbk_feiq_attachment() {
+        let msg = parse_message(FEIQ_GBK_ATTACHMENT).unwrap();
+        assert_eq!(msg.encoding, Some(crate::network::TextEncoding::Gbk));
+        assert_eq!(
+            Payload::decode(&msg).unwrap(),
+            Payload::Text {
+                text: "请查收".to_string(),
+                attachments: vec![FileAttachment::regular(0, "报告.doc".to_string(), 1000, 0x65a1b2c3)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_shift_jis_entry() {
+        let msg = parse_message(IPMSG_SJIS_ENTRY).unwrap();
+        assert_eq!(
+            Payload::decode(&msg).unwrap(),
+            Payload::Entry {
+                nickname: "たなか".to_string(),
+                group: Some("営業部".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_gbk_receipt_bytes() {
+        // Our receipt for the FeiQ message above, in the encoding it came in
+        let original = parse_message(FEIQ_GBK_ATTACHMENT).unwrap();
+        let (content, _) = Payload::RecvMsg {
+            original_packet_id: original.packet_id,
+        }
+        .encode();
+        let receipt = ProtocolMessage {
+            version: 1,
+            packet_id: 1767153490,
+            sender_name: "张三".to_string(),
+            sender_host: "zhangsan-pc".to_string(),
+            msg_type: 33,
+            content,
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: original.encoding,
+        };
+
+        assert_eq!(
+            crate::network::serialize_message(&receipt).unwrap(),
+            b"1:1767153490:\xd5\xc5\xc8\xfd:zhangsan-pc:33:1767153483"
+        );
+    }
+
+    #[test]
+    fn test_unknown_mode_keeps_content() {
+        assert_round_trip(
+            b"1:1700000018:bob:bob-pc:127:whatever",
+            Payload::Unknown {
+                content: "whatever".to_string(),
+            },
+        );
+    }
+}
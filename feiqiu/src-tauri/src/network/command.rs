@@ -0,0 +1,564 @@
+// Typed IPMsg command model - mode plus context-aware option flags
+//
+// An IPMsg command word packs the mode into the low 8 bits and option flags
+// into the high 24 bits. Several option bits are reused by different modes
+// (IPMSG_ABSENCEOPT == IPMSG_SENDCHECKOPT, IPMSG_SERVEROPT == IPMSG_SECRETOPT),
+// so a bit can only be named once the mode is known. `Command` keeps the two
+// together and only accepts options that make sense for its mode.
+
+use super::protocol::msg_type;
+use crate::{NeoLanError, Result};
+use std::fmt;
+
+/// Command mode (low 8 bits of the command word)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    NoOperation,
+    BrEntry,
+    BrExit,
+    AnsEntry,
+    BrAbsence,
+    BrIsGetList,
+    OkGetList,
+    GetList,
+    AnsList,
+    BrIsGetList2,
+    SendMsg,
+    RecvMsg,
+    ReadMsg,
+    DelMsg,
+    AnsReadMsg,
+    GetInfo,
+    SendInfo,
+    GetAbsenceInfo,
+    SendAbsenceInfo,
+    GetFileData,
+    ReleaseFiles,
+    GetDirFiles,
+    GetPubKey,
+    AnsPubKey,
+    /// Mode this implementation doesn't know (kept for round-tripping)
+    Unknown(u8),
+}
+
+impl Mode {
+    /// All known modes with their wire values
+    const KNOWN: [(Mode, u32); 24] = [
+        (Mode::NoOperation, msg_type::IPMSG_NOOPERATION),
+        (Mode::BrEntry, msg_type::IPMSG_BR_ENTRY),
+        (Mode::BrExit, msg_type::IPMSG_BR_EXIT),
+        (Mode::AnsEntry, msg_type::IPMSG_ANSENTRY),
+        (Mode::BrAbsence, msg_type::IPMSG_BR_ABSENCE),
+        (Mode::BrIsGetList, msg_type::IPMSG_BR_ISGETLIST),
+        (Mode::OkGetList, msg_type::IPMSG_OKGETLIST),
+        (Mode::GetList, msg_type::IPMSG_GETLIST),
+        (Mode::AnsList, msg_type::IPMSG_ANSLIST),
+        (Mode::BrIsGetList2, msg_type::IPMSG_BR_ISGETLIST2),
+        (Mode::SendMsg, msg_type::IPMSG_SENDMSG),
+        (Mode::RecvMsg, msg_type::IPMSG_RECVMSG),
+        (Mode::ReadMsg, msg_type::IPMSG_READMSG),
+        (Mode::DelMsg, msg_type::IPMSG_DELMSG),
+        (Mode::AnsReadMsg, msg_type::IPMSG_ANSREADMSG),
+        (Mode::GetInfo, msg_type::IPMSG_GETINFO),
+        (Mode::SendInfo, msg_type::IPMSG_SENDINFO),
+        (Mode::GetAbsenceInfo, msg_type::IPMSG_GETABSENCEINFO),
+        (Mode::SendAbsenceInfo, msg_type::IPMSG_SENDABSENCEINFO),
+        (Mode::GetFileData, msg_type::IPMSG_GETFILEDATA),
+        (Mode::ReleaseFiles, msg_type::IPMSG_RELEASEFILES),
+        (Mode::GetDirFiles, msg_type::IPMSG_GETDIRFILES),
+        (Mode::GetPubKey, msg_type::IPMSG_GETPUBKEY),
+        (Mode::AnsPubKey, msg_type::IPMSG_ANSPUBKEY),
+    ];
+
+    /// Convert from the wire mode byte
+    pub fn from_u8(mode: u8) -> Self {
+        Self::KNOWN
+            .iter()
+            .find(|(_, value)| *value == mode as u32)
+            .map(|(known, _)| *known)
+            .unwrap_or(Self::Unknown(mode))
+    }
+
+    /// Convert to the wire mode byte
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Self::Unknown(mode) => mode,
+            known => Self::KNOWN
+                .iter()
+                .find(|(mode, _)| *mode == known)
+                .map(|(_, value)| *value as u8)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Get the IPMsg name of this mode (e.g. `IPMSG_SENDMSG`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoOperation => "IPMSG_NOOPERATION",
+            Self::BrEntry => "IPMSG_BR_ENTRY",
+            Self::BrExit => "IPMSG_BR_EXIT",
+            Self::AnsEntry => "IPMSG_ANSENTRY",
+            Self::BrAbsence => "IPMSG_BR_ABSENCE",
+            Self::BrIsGetList => "IPMSG_BR_ISGETLIST",
+            Self::OkGetList => "IPMSG_OKGETLIST",
+            Self::GetList => "IPMSG_GETLIST",
+            Self::AnsList => "IPMSG_ANSLIST",
+            Self::BrIsGetList2 => "IPMSG_BR_ISGETLIST2",
+            Self::SendMsg => "IPMSG_SENDMSG",
+            Self::RecvMsg => "IPMSG_RECVMSG",
+            Self::ReadMsg => "IPMSG_READMSG",
+            Self::DelMsg => "IPMSG_DELMSG",
+            Self::AnsReadMsg => "IPMSG_ANSREADMSG",
+            Self::GetInfo => "IPMSG_GETINFO",
+            Self::SendInfo => "IPMSG_SENDINFO",
+            Self::GetAbsenceInfo => "IPMSG_GETABSENCEINFO",
+            Self::SendAbsenceInfo => "IPMSG_SENDABSENCEINFO",
+            Self::GetFileData => "IPMSG_GETFILEDATA",
+            Self::ReleaseFiles => "IPMSG_RELEASEFILES",
+            Self::GetDirFiles => "IPMSG_GETDIRFILES",
+            Self::GetPubKey => "IPMSG_GETPUBKEY",
+            Self::AnsPubKey => "IPMSG_ANSPUBKEY",
+            Self::Unknown(_) => "UNKNOWN",
+        }
+    }
+
+    /// Check if this is a presence mode (entry/exit/absence broadcasts)
+    pub fn is_entry(self) -> bool {
+        self.option_context() == OptionContext::Entry
+    }
+
+    /// Get the family of option flags this mode understands
+    pub fn option_context(self) -> OptionContext {
+        match self {
+            Self::BrEntry | Self::BrExit | Self::AnsEntry | Self::BrAbsence => OptionContext::Entry,
+            Self::SendMsg => OptionContext::Send,
+            _ => OptionContext::Other,
+        }
+    }
+}
+
+/// Family of option flags a mode understands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OptionContext {
+    /// Presence packets (BR_ENTRY, BR_EXIT, ANSENTRY, BR_ABSENCE)
+    Entry,
+
+    /// Message packets (SENDMSG)
+    Send,
+
+    /// Everything else (only the common flags apply)
+    Other,
+}
+
+/// Option flag (high 24 bits of the command word)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opt {
+    // Entry context
+    /// Sender is absent (IPMSG_ABSENCEOPT)
+    Absence,
+    /// Sender is a server (IPMSG_SERVEROPT)
+    Server,
+    /// Sender is on a dial-up link (IPMSG_DIALUPOPT)
+    DialUp,
+    /// Sender understands UTF-8 (IPMSG_CAPUTF8OPT)
+    CapUtf8,
+    /// Sender reassembles chunked messages (IPMSG_CAPCHUNKOPT, NeoLan extension)
+    CapChunk,
+    /// Sender takes JSON file offers (IPMSG_CAPFILEREQOPT, NeoLan extension)
+    CapFileRequest,
+
+    // Send context
+    /// Receiver should acknowledge with RECVMSG (IPMSG_SENDCHECKOPT)
+    SendCheck,
+    /// Sealed message (IPMSG_SECRETOPT)
+    Secret,
+    /// Sent by broadcast (IPMSG_BROADCASTOPT)
+    Broadcast,
+    /// Sent by multicast (IPMSG_MULTICASTOPT)
+    Multicast,
+    /// Don't pop up on the receiver (IPMSG_NOPOPUPOPT)
+    NoPopup,
+    /// Automatic reply (IPMSG_AUTORETOPT)
+    AutoRet,
+    /// Retransmission (IPMSG_RETRYOPT)
+    Retry,
+    /// Password protected (IPMSG_PASSWORDOPT)
+    Password,
+    /// Don't log (IPMSG_NOLOGOPT)
+    NoLog,
+    /// One chunk of a message too long for a datagram (IPMSG_CHUNKOPT, NeoLan extension)
+    Chunk,
+
+    // Common
+    /// Files are attached (IPMSG_FILEATTACHOPT)
+    FileAttach,
+    /// Encrypted (IPMSG_ENCRYPTOPT)
+    Encrypt,
+    /// Text is UTF-8 (IPMSG_UTF8OPT)
+    Utf8,
+}
+
+impl Opt {
+    /// All option flags
+    pub const ALL: [Opt; 19] = [
+        Opt::Absence,
+        Opt::Server,
+        Opt::DialUp,
+        Opt::CapUtf8,
+        Opt::CapChunk,
+        Opt::CapFileRequest,
+        Opt::SendCheck,
+        Opt::Secret,
+        Opt::Broadcast,
+        Opt::Multicast,
+        Opt::NoPopup,
+        Opt::AutoRet,
+        Opt::Retry,
+        Opt::Password,
+        Opt::NoLog,
+        Opt::Chunk,
+        Opt::FileAttach,
+        Opt::Encrypt,
+        Opt::Utf8,
+    ];
+
+    /// Get the wire bit of this flag
+    pub const fn bit(self) -> u32 {
+        match self {
+            Self::Absence => msg_type::IPMSG_ABSENCEOPT,
+            Self::Server => msg_type::IPMSG_SERVEROPT,
+            Self::DialUp => msg_type::IPMSG_DIALUPOPT,
+            Self::CapUtf8 => msg_type::IPMSG_CAPUTF8OPT,
+            Self::CapChunk => msg_type::IPMSG_CAPCHUNKOPT,
+            Self::CapFileRequest => msg_type::IPMSG_CAPFILEREQOPT,
+            Self::SendCheck => msg_type::IPMSG_SENDCHECKOPT,
+            Self::Secret => msg_type::IPMSG_SECRETOPT,
+            Self::Broadcast => msg_type::IPMSG_BROADCASTOPT,
+            Self::Multicast => msg_type::IPMSG_MULTICASTOPT,
+            Self::NoPopup => msg_type::IPMSG_NOPOPUPOPT,
+            Self::AutoRet => msg_type::IPMSG_AUTORETOPT,
+            Self::Retry => msg_type::IPMSG_RETRYOPT,
+            Self::Password => msg_type::IPMSG_PASSWORDOPT,
+            Self::NoLog => msg_type::IPMSG_NOLOGOPT,
+            Self::Chunk => msg_type::IPMSG_CHUNKOPT,
+            Self::FileAttach => msg_type::IPMSG_FILEATTACHOPT,
+            Self::Encrypt => msg_type::IPMSG_ENCRYPTOPT,
+            Self::Utf8 => msg_type::IPMSG_UTF8OPT,
+        }
+    }
+
+    /// Get the short flag name (e.g. `SENDCHECK`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Absence => "ABSENCE",
+            Self::Server => "SERVER",
+            Self::DialUp => "DIALUP",
+            Self::CapUtf8 => "CAPUTF8",
+            Self::CapChunk => "CAPCHUNK",
+            Self::CapFileRequest => "CAPFILEREQ",
+            Self::SendCheck => "SENDCHECK",
+            Self::Secret => "SECRET",
+            Self::Broadcast => "BROADCAST",
+            Self::Multicast => "MULTICAST",
+            Self::NoPopup => "NOPOPUP",
+            Self::AutoRet => "AUTORET",
+            Self::Retry => "RETRY",
+            Self::Password => "PASSWORD",
+            Self::NoLog => "NOLOG",
+            Self::Chunk => "CHUNK",
+            Self::FileAttach => "FILEATTACH",
+            Self::Encrypt => "ENCRYPT",
+            Self::Utf8 => "UTF8",
+        }
+    }
+
+    /// Get the context this flag belongs to (`None` = valid for every mode)
+    pub fn context(self) -> Option<OptionContext> {
+        match self {
+            Self::Absence
+            | Self::Server
+            | Self::DialUp
+            | Self::CapUtf8
+            | Self::CapChunk
+            | Self::CapFileRequest => Some(OptionContext::Entry),
+            Self::SendCheck
+            | Self::Secret
+            | Self::Broadcast
+            | Self::Multicast
+            | Self::NoPopup
+            | Self::AutoRet
+            | Self::Retry
+            | Self::Password
+            | Self::NoLog
+            | Self::Chunk => Some(OptionContext::Send),
+            Self::FileAttach | Self::Encrypt | Self::Utf8 => None,
+        }
+    }
+
+    /// Check if this flag means anything for the given mode
+    pub fn is_valid_for(self, mode: Mode) -> bool {
+        match self.context() {
+            None => true,
+            Some(context) => context == mode.option_context(),
+        }
+    }
+}
+
+/// Typed IPMsg command (mode + options valid for that mode)
+///
+/// Options can only be added through [`Command::with`], which rejects flags
+/// that don't belong to the mode. Commands decoded from the wire keep any
+/// bits they don't understand, so re-encoding is lossless.
+///
+/// # Examples
+/// ```
+/// # use neolan_lib::network::{Command, Mode, Opt};
+/// let cmd = Command::new(Mode::SendMsg).with(Opt::SendCheck)?;
+/// assert_eq!(u32::from(cmd), 0x0000_0120);
+///
+/// // 0x100 is SENDCHECK for SENDMSG but ABSENCE for BR_ENTRY
+/// let entry = Command::from(0x0000_0101);
+/// assert!(entry.has(Opt::Absence));
+/// assert!(!entry.has(Opt::SendCheck));
+///
+/// assert!(Command::new(Mode::BrEntry).with(Opt::SendCheck).is_err());
+/// # Ok::<(), neolan_lib::NeoLanError>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Command {
+    /// Command mode
+    mode: Mode,
+
+    /// Option bits valid for the mode
+    opts: u32,
+
+    /// Option bits received from the wire that mean nothing for the mode
+    unknown_opts: u32,
+}
+
+impl Command {
+    /// Create a command with no options
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            opts: 0,
+            unknown_opts: 0,
+        }
+    }
+
+    /// Add an option flag
+    ///
+    /// # Returns
+    /// * `Ok(Command)` - Command with the flag set
+    /// * `Err(NeoLanError::Protocol)` - The flag doesn't apply to this mode
+    pub fn with(mut self, opt: Opt) -> Result<Self> {
+        if !opt.is_valid_for(self.mode) {
+            return Err(NeoLanError::Protocol(format!(
+                "Option {} is not valid for {}",
+                opt.name(),
+                self.mode.name()
+            )));
+        }
+
+        self.opts |= opt.bit();
+        Ok(self)
+    }
+
+    /// Add several option flags
+    pub fn with_all(self, opts: &[Opt]) -> Result<Self> {
+        opts.iter().try_fold(self, |command, opt| command.with(*opt))
+    }
+
+    /// Remove an option flag
+    pub fn without(mut self, opt: Opt) -> Self {
+        if opt.is_valid_for(self.mode) {
+            self.opts &= !opt.bit();
+        }
+        self
+    }
+
+    /// Get the command mode
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Check if an option flag is set (interpreted for this mode)
+    pub fn has(&self, opt: Opt) -> bool {
+        opt.is_valid_for(self.mode) && self.opts & opt.bit() != 0
+    }
+
+    /// Get the option flags that are set
+    pub fn opts(&self) -> Vec<Opt> {
+        Opt::ALL.into_iter().filter(|opt| self.has(*opt)).collect()
+    }
+
+    /// Get the option bits that mean nothing for this mode
+    pub fn unknown_opts(&self) -> u32 {
+        self.unknown_opts
+    }
+
+    /// Decode a command word
+    pub fn from_u32(command: u32) -> Self {
+        let mode = Mode::from_u8(msg_type::get_mode(command));
+        let raw_opts = msg_type::get_opt(command);
+
+        let known = Opt::ALL
+            .into_iter()
+            .filter(|opt| opt.is_valid_for(mode))
+            .fold(0, |bits, opt| bits | opt.bit());
+
+        Self {
+            mode,
+            opts: raw_opts & known,
+            unknown_opts: raw_opts & !known,
+        }
+    }
+
+    /// Encode as a command word
+    pub fn to_u32(&self) -> u32 {
+        msg_type::make_command(self.mode.as_u8() as u32, self.opts | self.unknown_opts)
+    }
+}
+
+impl From<u32> for Command {
+    fn from(command: u32) -> Self {
+        Self::from_u32(command)
+    }
+}
+
+impl From<Command> for u32 {
+    fn from(command: Command) -> Self {
+        command.to_u32()
+    }
+}
+
+impl From<Mode> for Command {
+    fn from(mode: Mode) -> Self {
+        Self::new(mode)
+    }
+}
+
+impl fmt::Display for Command {
+    /// `IPMSG_SENDMSG (0x00000120 = mode: 0x20 | [SENDCHECK])`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut flags: Vec<String> = self.opts().iter().map(|opt| opt.name().to_string()).collect();
+        if self.unknown_opts != 0 {
+            flags.push(format!("0x{:06X}", self.unknown_opts));
+        }
+
+        write!(
+            f,
+            "{} (0x{:08X} = mode: 0x{:02X}",
+            self.mode.name(),
+            self.to_u32(),
+            self.mode.as_u8()
+        )?;
+        if !flags.is_empty() {
+            write!(f, " | [{}]", flags.join(" | "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_round_trip() {
+        for (mode, value) in Mode::KNOWN {
+            assert_eq!(Mode::from_u8(value as u8), mode);
+            assert_eq!(mode.as_u8(), value as u8);
+        }
+        assert_eq!(Mode::from_u8(0x7f), Mode::Unknown(0x7f));
+        assert_eq!(Mode::Unknown(0x7f).as_u8(), 0x7f);
+        assert_eq!(Mode::Unknown(0x7f).name(), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_overlapping_bits_depend_on_mode() {
+        // 0x100 / 0x200: ABSENCE / SERVER on entries, SENDCHECK / SECRET on messages
+        let entry = Command::from(msg_type::IPMSG_BR_ENTRY | 0x300);
+        assert!(entry.has(Opt::Absence));
+        assert!(entry.has(Opt::Server));
+        assert!(!entry.has(Opt::SendCheck));
+        assert!(!entry.has(Opt::Secret));
+
+        let send = Command::from(msg_type::IPMSG_SENDMSG | 0x300);
+        assert!(send.has(Opt::SendCheck));
+        assert!(send.has(Opt::Secret));
+        assert!(!send.has(Opt::Absence));
+        assert!(!send.has(Opt::Server));
+    }
+
+    #[test]
+    fn test_invalid_combinations_rejected() {
+        assert!(Command::new(Mode::BrEntry).with(Opt::SendCheck).is_err());
+        assert!(Command::new(Mode::SendMsg).with(Opt::Absence).is_err());
+        assert!(Command::new(Mode::RecvMsg).with(Opt::Retry).is_err());
+
+        // Common flags apply everywhere
+        assert!(Command::new(Mode::RecvMsg).with(Opt::Utf8).is_ok());
+        assert!(Command::new(Mode::AnsEntry).with_all(&[Opt::Encrypt, Opt::FileAttach]).is_ok());
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        let cmd = Command::new(Mode::SendMsg)
+            .with_all(&[Opt::SendCheck, Opt::FileAttach, Opt::Utf8])
+            .unwrap();
+        let word = cmd.to_u32();
+
+        assert_eq!(
+            word,
+            msg_type::IPMSG_SENDMSG
+                | msg_type::IPMSG_SENDCHECKOPT
+                | msg_type::IPMSG_FILEATTACHOPT
+                | msg_type::IPMSG_UTF8OPT
+        );
+        assert_eq!(Command::from(word), cmd);
+        assert_eq!(cmd.opts(), vec![Opt::SendCheck, Opt::FileAttach, Opt::Utf8]);
+        assert!(!cmd.without(Opt::SendCheck).has(Opt::SendCheck));
+    }
+
+    #[test]
+    fn test_chunk_bits() {
+        // NeoLan's extension bit: a capability on entries, a chunk on messages
+        let entry = Command::from(msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_CAPCHUNKOPT);
+        assert!(entry.has(Opt::CapChunk));
+        assert!(!entry.has(Opt::Chunk));
+
+        let send = Command::new(Mode::SendMsg).with(Opt::Chunk).unwrap();
+        assert_eq!(send.to_string(), "IPMSG_SENDMSG (0x20000020 = mode: 0x20 | [CHUNK])");
+        assert!(Command::new(Mode::RecvMsg).with(Opt::Chunk).is_err());
+
+        // The JSON file offer capability is a bit of its own
+        let entry = Command::from(msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_CAPFILEREQOPT);
+        assert_eq!(entry.opts(), vec![Opt::CapFileRequest]);
+    }
+
+    #[test]
+    fn test_unknown_bits_preserved() {
+        // Entry packet with a send-only bit (RETRY) and an undefined high bit
+        let word = msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_RETRYOPT | 0x8000_0000;
+        let cmd = Command::from(word);
+
+        assert!(cmd.opts().is_empty());
+        assert_eq!(cmd.unknown_opts(), msg_type::IPMSG_RETRYOPT | 0x8000_0000);
+        assert_eq!(cmd.to_u32(), word);
+    }
+
+    #[test]
+    fn test_display() {
+        let entry = Command::from(msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_ABSENCEOPT);
+        assert_eq!(entry.to_string(), "IPMSG_BR_ENTRY (0x00000101 = mode: 0x01 | [ABSENCE])");
+
+        let send = Command::from(msg_type::IPMSG_SENDMSG | msg_type::IPMSG_SENDCHECKOPT);
+        assert_eq!(send.to_string(), "IPMSG_SENDMSG (0x00000120 = mode: 0x20 | [SENDCHECK])");
+
+        assert_eq!(
+            Command::new(Mode::RecvMsg).to_string(),
+            "IPMSG_RECVMSG (0x00000021 = mode: 0x21)"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// Extra broadcast hosts - unicast presence targets outside the local subnets
+//
+// Broadcast and link-local multicast never cross a router or VPN, so (like
+// IPMsg's and FeiQ's "additional broadcast addresses") users can list single
+// hosts or CIDR ranges that get BR_ENTRY by unicast. Entries are kept as the
+// user typed them (`10.8.0.7`, `10.8.1.0/24`, `fd00::5`) and expanded to
+// addresses when sending.
+
+use crate::{NeoLanError, Result};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Upper bound on the addresses one host list may expand to (a /20)
+///
+/// Every address gets a packet on each heartbeat; larger ranges are almost
+/// certainly a typo (`/8` for `/24`).
+pub const MAX_EXTRA_HOST_ADDRS: usize = 4096;
+
+/// One entry of the extra broadcast host list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostEntry {
+    /// A single host
+    Host(IpAddr),
+
+    /// Every host of a network (`network/prefix`)
+    Range { network: IpAddr, prefix: u8 },
+}
+
+impl HostEntry {
+    /// Number of addresses the entry expands to
+    pub fn address_count(&self) -> u128 {
+        match *self {
+            Self::Host(_) => 1,
+            Self::Range { network, prefix } => {
+                let host_bits = u32::from(max_prefix(network) - prefix);
+                let size = 1u128.checked_shl(host_bits).unwrap_or(u128::MAX);
+                // IPv4 networks larger than /31 lose their network and broadcast addresses
+                if network.is_ipv4() && prefix < 31 {
+                    size - 2
+                } else {
+                    size
+                }
+            }
+        }
+    }
+
+    /// Expand the entry to the addresses that get a unicast announcement
+    ///
+    /// IPv4 ranges skip the network and broadcast addresses. Check
+    /// [`address_count`](Self::address_count) first: a short IPv6 prefix
+    /// covers more addresses than fit in memory.
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        match *self {
+            Self::Host(ip) => vec![ip],
+            Self::Range { network: IpAddr::V4(network), prefix } => {
+                let first = u32::from(network);
+                let last = first | u32::MAX.checked_shr(u32::from(prefix)).unwrap_or(0);
+                let (first, last) = if prefix < 31 { (first + 1, last - 1) } else { (first, last) };
+                (first..=last).map(|ip| IpAddr::V4(Ipv4Addr::from(ip))).collect()
+            }
+            Self::Range { network: IpAddr::V6(network), prefix } => {
+                let first = u128::from(network);
+                let last = first | u128::MAX.checked_shr(u32::from(prefix)).unwrap_or(0);
+                (first..=last).map(|ip| IpAddr::V6(Ipv6Addr::from(ip))).collect()
+            }
+        }
+    }
+}
+
+impl FromStr for HostEntry {
+    type Err = NeoLanError;
+
+    /// Parse `ip` or `ip/prefix` (the host bits of a range are cleared)
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || NeoLanError::Validation(format!("Invalid host or CIDR range: {}", s));
+
+        let Some((ip, prefix)) = s.trim().split_once('/') else {
+            return s.trim().parse().map(Self::Host).map_err(|_| invalid());
+        };
+
+        let ip: IpAddr = ip.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        if prefix > max_prefix(ip) {
+            return Err(invalid());
+        }
+        if prefix == max_prefix(ip) {
+            return Ok(Self::Host(ip));
+        }
+
+        let network = match ip {
+            IpAddr::V4(v4) => {
+                let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask))
+            }
+            IpAddr::V6(v6) => {
+                let mask = u128::MAX.checked_shl(u32::from(128 - prefix)).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask))
+            }
+        };
+
+        Ok(Self::Range { network, prefix })
+    }
+}
+
+impl fmt::Display for HostEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Host(ip) => write!(f, "{}", ip),
+            Self::Range { network, prefix } => write!(f, "{}/{}", network, prefix),
+        }
+    }
+}
+
+/// Prefix length of a single host of the address family
+fn max_prefix(ip: IpAddr) -> u8 {
+    if ip.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+/// Parse and expand a host list
+///
+/// Duplicates (a host also covered by a range) are sent to once.
+///
+/// # Returns
+/// * `Ok(Vec<IpAddr>)` - Addresses in list order
+/// * `Err(NeoLanError::Validation)` - An entry doesn't parse, or the list
+///   expands to more than [`MAX_EXTRA_HOST_ADDRS`] addresses
+pub fn expand_hosts(entries: &[String]) -> Result<Vec<IpAddr>> {
+    let entries = entries
+        .iter()
+        .map(|entry| entry.parse())
+        .collect::<Result<Vec<HostEntry>>>()?;
+
+    let total: u128 = entries.iter().map(HostEntry::address_count).fold(0, u128::saturating_add);
+    if total > MAX_EXTRA_HOST_ADDRS as u128 {
+        return Err(NeoLanError::Validation(format!(
+            "Extra broadcast hosts cover {} addresses (at most {} allowed)",
+            total, MAX_EXTRA_HOST_ADDRS
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    Ok(entries
+        .iter()
+        .flat_map(HostEntry::addresses)
+        .filter(|ip| seen.insert(*ip))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entries() {
+        let host: HostEntry = "10.8.0.7".parse().unwrap();
+        assert_eq!(host, HostEntry::Host("10.8.0.7".parse().unwrap()));
+
+        // Host bits are cleared, and /32 is a host
+        let range: HostEntry = " 10.8.1.77/24 ".parse().unwrap();
+        assert_eq!(range.to_string(), "10.8.1.0/24");
+        assert_eq!(range.address_count(), 254);
+        assert_eq!("10.8.0.7/32".parse::<HostEntry>().unwrap(), host);
+
+        let v6: HostEntry = "fd00::1/126".parse().unwrap();
+        assert_eq!(v6.addresses().len(), 4);
+
+        for bad in ["", "10.8.0", "10.8.0.0/33", "10.8.0.0/x", "host.example"] {
+            assert!(bad.parse::<HostEntry>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_range_addresses() {
+        let addresses = "192.168.5.0/30".parse::<HostEntry>().unwrap().addresses();
+        assert_eq!(
+            addresses,
+            vec!["192.168.5.1".parse::<IpAddr>().unwrap(), "192.168.5.2".parse().unwrap()]
+        );
+
+        // /31 point-to-point links have no network or broadcast address
+        assert_eq!("10.0.0.0/31".parse::<HostEntry>().unwrap().addresses().len(), 2);
+    }
+
+    #[test]
+    fn test_expand_hosts() {
+        let entries = vec![
+            "192.168.5.2".to_string(),
+            "192.168.5.0/30".to_string(),
+            "10.8.0.7".to_string(),
+        ];
+        let addresses = expand_hosts(&entries).unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0], "192.168.5.2".parse::<IpAddr>().unwrap());
+
+        assert!(expand_hosts(&["10.0.0.0/8".to_string()]).is_err());
+        assert!(expand_hosts(&["fd00::/64".to_string()]).is_err());
+        assert!(expand_hosts(&["10.0.0.0/20".to_string()]).is_ok());
+        assert!(expand_hosts(&["nonsense".to_string()]).is_err());
+    }
+}
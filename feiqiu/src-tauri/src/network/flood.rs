@@ -0,0 +1,468 @@
+// Inbound flood protection - per-source rate limiting of the receive path
+//
+// Every datagram on the protocol port is parsed and dispatched: peer table
+// updates, database writes, frontend events. A misbehaving client or a
+// broadcast storm can therefore swamp the whole application. Each source IP
+// gets a token bucket; packets beyond it are dropped unparsed. Sources that
+// keep exceeding their bucket, or keep sending packets that don't parse, are
+// quarantined (all their packets dropped) for a while.
+
+use crate::config::AppConfig;
+use super::addr::canonical_ip;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window the throttled and malformed packets of a source are counted in
+const ABUSE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Sources tracked before idle ones are forgotten
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// Bursts' worth of throttled packets within a window that quarantine a source
+///
+/// A peer pasting a few long messages in a row legitimately overruns its
+/// bucket: each is up to 256 chunks, and the throttled ones are resent until
+/// they get through. Only a source throttled far beyond that is flooding.
+const QUARANTINE_BURSTS: u32 = 10;
+
+/// Flood protection thresholds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloodLimits {
+    /// Sustained packets per second allowed from one source (0 = no rate limit)
+    pub packets_per_second: u32,
+
+    /// Packets one source may send at once (bucket size)
+    pub burst: u32,
+
+    /// Unparseable packets per minute tolerated from one source (0 = no limit)
+    pub max_parse_errors_per_minute: u32,
+
+    /// How long abusive sources are ignored (zero = never quarantine)
+    pub quarantine: Duration,
+}
+
+impl FloodLimits {
+    /// Build the thresholds from the configuration
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            packets_per_second: config.rate_limit_packets_per_second,
+            burst: config.rate_limit_burst,
+            max_parse_errors_per_minute: config.max_parse_errors_per_minute,
+            quarantine: Duration::from_secs(config.quarantine_seconds),
+        }
+    }
+}
+
+impl Default for FloodLimits {
+    fn default() -> Self {
+        Self::from_config(&AppConfig::default())
+    }
+}
+
+/// What to do with a received packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Within the source's rate: parse and dispatch it
+    Accept,
+
+    /// Over the source's rate: drop it
+    Throttle,
+
+    /// The source is quarantined: drop it
+    Quarantined,
+}
+
+/// Receive path counters (since startup)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FloodStats {
+    /// Packets passed on for parsing
+    pub accepted: u64,
+
+    /// Packets dropped for exceeding their source's rate
+    pub throttled: u64,
+
+    /// Packets dropped because their source was quarantined
+    pub dropped_quarantined: u64,
+
+    /// Packets that failed to parse
+    pub parse_errors: u64,
+
+    /// Times a source was put in quarantine
+    pub quarantines: u64,
+
+    /// Sources in quarantine right now
+    pub quarantined_sources: Vec<String>,
+}
+
+/// Rate state of one source
+struct Source {
+    /// Packets the source may still send right away
+    tokens: f64,
+
+    /// When tokens were last added
+    refilled: Instant,
+
+    /// When the source last sent a packet
+    last_seen: Instant,
+
+    /// Start of the current abuse window
+    window_start: Instant,
+
+    /// Packets throttled in the current window
+    throttled: u32,
+
+    /// Unparseable packets in the current window
+    parse_errors: u32,
+
+    /// End of the source's quarantine
+    quarantined_until: Option<Instant>,
+}
+
+impl Source {
+    fn new(limits: &FloodLimits, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limits.burst),
+            refilled: now,
+            last_seen: now,
+            window_start: now,
+            throttled: 0,
+            parse_errors: 0,
+            quarantined_until: None,
+        }
+    }
+
+    fn is_quarantined(&self, now: Instant) -> bool {
+        self.quarantined_until.is_some_and(|until| now < until)
+    }
+
+    /// Start a new abuse window when the current one is over
+    fn roll_window(&mut self, now: Instant) {
+        if now.duration_since(self.window_start) >= ABUSE_WINDOW {
+            self.window_start = now;
+            self.throttled = 0;
+            self.parse_errors = 0;
+        }
+    }
+
+    /// Quiet long enough that forgetting it loses nothing (its bucket is full again)
+    fn is_idle(&self, now: Instant) -> bool {
+        !self.is_quarantined(now) && now.duration_since(self.last_seen) >= ABUSE_WINDOW
+    }
+}
+
+/// Per-source rate limiter and quarantine of the receive path
+///
+/// Shared by the receive loop (which asks [`check`](Self::check) about every
+/// packet) and whoever reads the counters.
+pub struct FloodGuard {
+    limits: Mutex<FloodLimits>,
+    sources: Mutex<HashMap<IpAddr, Source>>,
+    accepted: AtomicU64,
+    throttled: AtomicU64,
+    dropped_quarantined: AtomicU64,
+    parse_errors: AtomicU64,
+    quarantines: AtomicU64,
+}
+
+impl FloodGuard {
+    /// Create a guard with the given thresholds
+    pub fn new(limits: FloodLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            sources: Mutex::new(HashMap::new()),
+            accepted: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            dropped_quarantined: AtomicU64::new(0),
+            parse_errors: AtomicU64::new(0),
+            quarantines: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the thresholds
+    pub fn limits(&self) -> FloodLimits {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replace the thresholds (sources in quarantine stay there)
+    pub fn set_limits(&self, limits: FloodLimits) {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+    }
+
+    /// Decide what to do with a packet from `ip`
+    ///
+    /// Takes a token from the source's bucket. A source whose throttled
+    /// packets reach [`QUARANTINE_BURSTS`] bursts within a minute is quarantined.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Verdict {
+        let limits = self.limits();
+        let mut sources = self.lock_sources();
+        let source = Self::source(&mut sources, &limits, canonical_ip(ip), now);
+
+        let verdict = if source.is_quarantined(now) {
+            Verdict::Quarantined
+        } else if limits.packets_per_second == 0 {
+            Verdict::Accept
+        } else {
+            let elapsed = now.duration_since(source.refilled).as_secs_f64();
+            source.tokens = (source.tokens + elapsed * f64::from(limits.packets_per_second))
+                .min(f64::from(limits.burst));
+            source.refilled = now;
+
+            if source.tokens >= 1.0 {
+                source.tokens -= 1.0;
+                Verdict::Accept
+            } else {
+                source.roll_window(now);
+                source.throttled += 1;
+                if source.throttled >= limits.burst.max(1).saturating_mul(QUARANTINE_BURSTS) {
+                    self.quarantine(source, ip, &limits, now, "flooding");
+                }
+                Verdict::Throttle
+            }
+        };
+
+        let counter = match verdict {
+            Verdict::Accept => &self.accepted,
+            Verdict::Throttle => &self.throttled,
+            Verdict::Quarantined => &self.dropped_quarantined,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        verdict
+    }
+
+    /// Record a packet from `ip` that failed to parse
+    ///
+    /// # Returns
+    /// `true` if it put the source in quarantine
+    pub fn record_parse_error(&self, ip: IpAddr, now: Instant) -> bool {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+
+        let limits = self.limits();
+        if limits.max_parse_errors_per_minute == 0 {
+            return false;
+        }
+
+        let mut sources = self.lock_sources();
+        let source = Self::source(&mut sources, &limits, canonical_ip(ip), now);
+        if source.is_quarantined(now) {
+            return false;
+        }
+
+        source.roll_window(now);
+        source.parse_errors += 1;
+        if source.parse_errors > limits.max_parse_errors_per_minute {
+            return self.quarantine(source, ip, &limits, now, "malformed packets");
+        }
+        false
+    }
+
+    /// Check if a source is in quarantine
+    pub fn is_quarantined(&self, ip: IpAddr, now: Instant) -> bool {
+        self.lock_sources()
+            .get(&canonical_ip(ip))
+            .is_some_and(|source| source.is_quarantined(now))
+    }
+
+    /// Get the counters
+    pub fn stats(&self, now: Instant) -> FloodStats {
+        let mut quarantined_sources: Vec<String> = self
+            .lock_sources()
+            .iter()
+            .filter(|(_, source)| source.is_quarantined(now))
+            .map(|(ip, _)| ip.to_string())
+            .collect();
+        quarantined_sources.sort();
+
+        FloodStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            dropped_quarantined: self.dropped_quarantined.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            quarantines: self.quarantines.load(Ordering::Relaxed),
+            quarantined_sources,
+        }
+    }
+
+    /// Get (or start tracking) a source
+    fn source<'a>(
+        sources: &'a mut HashMap<IpAddr, Source>,
+        limits: &FloodLimits,
+        ip: IpAddr,
+        now: Instant,
+    ) -> &'a mut Source {
+        if sources.len() >= MAX_TRACKED_SOURCES && !sources.contains_key(&ip) {
+            sources.retain(|_, source| !source.is_idle(now));
+        }
+        let source = sources.entry(ip).or_insert_with(|| Source::new(limits, now));
+        source.last_seen = now;
+        source
+    }
+
+    /// Put a source in quarantine (no-op when quarantine is disabled)
+    fn quarantine(&self, source: &mut Source, ip: IpAddr, limits: &FloodLimits, now: Instant, reason: &str) -> bool {
+        if limits.quarantine.is_zero() {
+            return false;
+        }
+
+        source.quarantined_until = Some(now + limits.quarantine);
+        source.throttled = 0;
+        source.parse_errors = 0;
+        self.quarantines.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("Quarantining {} for {:?} ({})", ip, limits.quarantine, reason);
+        true
+    }
+
+    fn lock_sources(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, Source>> {
+        self.sources.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for FloodGuard {
+    fn default() -> Self {
+        Self::new(FloodLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> FloodLimits {
+        FloodLimits {
+            packets_per_second: 10,
+            burst: 5,
+            max_parse_errors_per_minute: 3,
+            quarantine: Duration::from_secs(300),
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let guard = FloodGuard::new(limits());
+        let now = Instant::now();
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+
+        // A burst goes through, the rest is throttled
+        for _ in 0..5 {
+            assert_eq!(guard.check(ip, now), Verdict::Accept);
+        }
+        assert_eq!(guard.check(ip, now), Verdict::Throttle);
+
+        // Other sources have their own bucket (IPv4-mapped addresses are the same source)
+        assert_eq!(guard.check("192.168.1.21".parse().unwrap(), now), Verdict::Accept);
+        assert_eq!(guard.check("::ffff:192.168.1.20".parse().unwrap(), now), Verdict::Throttle);
+
+        // Tokens come back at the configured rate
+        let later = now + Duration::from_millis(200);
+        assert_eq!(guard.check(ip, later), Verdict::Accept);
+        assert_eq!(guard.check(ip, later), Verdict::Accept);
+        assert_eq!(guard.check(ip, later), Verdict::Throttle);
+
+        let stats = guard.stats(later);
+        assert_eq!(stats.accepted, 8);
+        assert_eq!(stats.throttled, 3);
+        assert!(stats.quarantined_sources.is_empty());
+    }
+
+    #[test]
+    fn test_flooding_source_is_quarantined() {
+        let guard = FloodGuard::new(limits());
+        let now = Instant::now();
+        let ip: IpAddr = "10.0.0.66".parse().unwrap();
+
+        // Ten bursts' worth of throttled packets within a minute
+        let verdicts: Vec<Verdict> = (0..54).map(|_| guard.check(ip, now)).collect();
+        assert_eq!(verdicts.iter().filter(|v| **v == Verdict::Throttle).count(), 49);
+        assert!(!guard.is_quarantined(ip, now));
+        assert_eq!(guard.check(ip, now), Verdict::Throttle);
+        assert!(guard.is_quarantined(ip, now));
+
+        // Everything is dropped until the quarantine ends, tokens or not
+        let soon = now + Duration::from_secs(10);
+        assert_eq!(guard.check(ip, soon), Verdict::Quarantined);
+        let after = now + Duration::from_secs(301);
+        assert_eq!(guard.check(ip, after), Verdict::Accept);
+
+        let stats = guard.stats(soon);
+        assert_eq!(stats.quarantines, 1);
+        assert_eq!(stats.dropped_quarantined, 1);
+        assert_eq!(stats.quarantined_sources, vec!["10.0.0.66".to_string()]);
+    }
+
+    #[test]
+    fn test_chunked_messages_not_quarantined() {
+        use crate::modules::message::chunk::MAX_CHUNKS;
+
+        let guard = FloodGuard::new(FloodLimits::default());
+        let start = Instant::now();
+        let ip: IpAddr = "192.168.1.30".parse().unwrap();
+
+        // Three maximal chunked messages at once, the dropped chunks resent on
+        // the outbox schedule (1, 2, 4, 8, 8 s apart) until they get through
+        let mut unsent = 3 * MAX_CHUNKS;
+        let mut at = start;
+        for delay in [0, 1, 2, 4, 8, 8] {
+            at += Duration::from_secs(delay);
+            unsent = (0..unsent).filter(|_| guard.check(ip, at) != Verdict::Accept).count();
+        }
+
+        assert_eq!(unsent, 0);
+        assert!(!guard.is_quarantined(ip, at));
+        assert_eq!(guard.stats(at).quarantines, 0);
+    }
+
+    #[test]
+    fn test_parse_errors_quarantine() {
+        let guard = FloodGuard::new(limits());
+        let now = Instant::now();
+        let ip: IpAddr = "10.0.0.77".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(!guard.record_parse_error(ip, now));
+        }
+        // The window restarts after a minute
+        let next_minute = now + Duration::from_secs(61);
+        assert!(!guard.record_parse_error(ip, next_minute));
+        assert!(!guard.is_quarantined(ip, next_minute));
+
+        for _ in 0..3 {
+            guard.record_parse_error(ip, next_minute);
+        }
+        assert!(guard.is_quarantined(ip, next_minute));
+        assert_eq!(guard.stats(next_minute).parse_errors, 7);
+    }
+
+    #[test]
+    fn test_disabled_limits() {
+        let guard = FloodGuard::new(FloodLimits {
+            packets_per_second: 0,
+            burst: 0,
+            max_parse_errors_per_minute: 0,
+            quarantine: Duration::ZERO,
+        });
+        let now = Instant::now();
+        let ip: IpAddr = "10.0.0.88".parse().unwrap();
+
+        for _ in 0..1000 {
+            assert_eq!(guard.check(ip, now), Verdict::Accept);
+            assert!(!guard.record_parse_error(ip, now));
+        }
+        assert!(!guard.is_quarantined(ip, now));
+
+        // Rate limited but never quarantined
+        guard.set_limits(FloodLimits {
+            quarantine: Duration::ZERO,
+            ..limits()
+        });
+        for _ in 0..100 {
+            guard.check(ip, now);
+        }
+        assert!(!guard.is_quarantined(ip, now));
+        assert_eq!(guard.check(ip, now), Verdict::Throttle);
+    }
+}
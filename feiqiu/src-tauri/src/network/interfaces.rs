@@ -0,0 +1,191 @@
+// Local network interfaces - enumeration for per-subnet directed broadcast
+//
+// The limited broadcast address (255.255.255.255) leaves through a single,
+// OS-chosen interface, so on machines with several NICs, VPNs or Docker
+// bridges most subnets never see our announcements. Presence packets are
+// instead sent to each IPv4 subnet's directed broadcast address (e.g.
+// 192.168.1.255). Virtual interfaces can be skipped, and interfaces excluded
+// by name, through the configuration.
+
+use crate::config::AppConfig;
+use crate::{NeoLanError, Result};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+/// Name prefixes of common virtual interfaces (container bridges, VMs, VPNs)
+const VIRTUAL_PREFIXES: &[&str] = &[
+    "docker", "br-", "veth", "virbr", "vmnet", "vboxnet", "vethernet", "tun", "tap", "utun",
+    "wg", "zt", "tailscale", "ppp", "ipsec", "llw", "awdl", "bridge", "cni", "flannel", "podman",
+];
+
+/// Name fragments of virtual interfaces that don't use a fixed prefix (Windows adapter names)
+const VIRTUAL_FRAGMENTS: &[&str] = &["virtualbox", "vmware", "hyper-v", "loopback", "pseudo"];
+
+/// An IPv4 address on a local network interface
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocalInterface {
+    /// Interface name (`eth0`, `en0`, `Ethernet 2`, ...)
+    pub name: String,
+
+    /// Interface address
+    pub ip: Ipv4Addr,
+
+    /// Subnet mask
+    pub netmask: Ipv4Addr,
+
+    /// Directed broadcast address of the subnet
+    pub broadcast: Ipv4Addr,
+
+    /// Whether the interface is operationally up
+    pub up: bool,
+}
+
+impl LocalInterface {
+    /// Check if `ip` is on this interface's subnet
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(ip) & mask == u32::from(self.ip) & mask
+    }
+
+    /// Check if the interface looks virtual (container bridge, VM adapter, VPN tunnel)
+    ///
+    /// Decided from the interface name; there's no portable flag for it.
+    pub fn is_virtual(&self) -> bool {
+        let name = self.name.to_lowercase();
+        VIRTUAL_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+            || VIRTUAL_FRAGMENTS.iter().any(|fragment| name.contains(fragment))
+    }
+
+    /// Convert an enumerated interface address
+    ///
+    /// Returns `None` for IPv6, loopback, and point-to-point (/31, /32)
+    /// addresses, which have no broadcast address.
+    fn from_if_addrs(interface: &if_addrs::Interface) -> Option<Self> {
+        let if_addrs::IfAddr::V4(ref v4) = interface.addr else {
+            return None;
+        };
+        if v4.ip.is_loopback() || v4.prefixlen >= 31 {
+            return None;
+        }
+
+        let broadcast = v4
+            .broadcast
+            .unwrap_or_else(|| Ipv4Addr::from(u32::from(v4.ip) | !u32::from(v4.netmask)));
+
+        Some(Self {
+            name: interface.name.clone(),
+            ip: v4.ip,
+            netmask: v4.netmask,
+            broadcast,
+            up: interface.is_oper_up(),
+        })
+    }
+}
+
+/// Which interfaces presence packets are sent on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceFilter {
+    /// Skip interfaces that look virtual (see [`LocalInterface::is_virtual`])
+    pub skip_virtual: bool,
+
+    /// Interface names to skip (a trailing `*` matches a prefix)
+    pub excluded: Vec<String>,
+}
+
+impl InterfaceFilter {
+    /// Build the filter from the configuration
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            skip_virtual: config.skip_virtual_interfaces,
+            excluded: config.excluded_interfaces.clone(),
+        }
+    }
+
+    /// Check if presence packets should be sent on `interface`
+    ///
+    /// Interfaces that are down are always skipped.
+    pub fn allows(&self, interface: &LocalInterface) -> bool {
+        if !interface.up || (self.skip_virtual && interface.is_virtual()) {
+            return false;
+        }
+
+        !self.excluded.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => interface.name.starts_with(prefix),
+            None => interface.name == *pattern,
+        })
+    }
+}
+
+impl Default for InterfaceFilter {
+    fn default() -> Self {
+        Self::from_config(&AppConfig::default())
+    }
+}
+
+/// List the local IPv4 interfaces that have a broadcast address
+///
+/// # Returns
+/// * `Ok(Vec<LocalInterface>)` - Interfaces in OS order, including ones that are down
+/// * `Err(NeoLanError::Network)` - Enumeration failed
+pub fn list_interfaces() -> Result<Vec<LocalInterface>> {
+    let interfaces = if_addrs::get_if_addrs().map_err(NeoLanError::Network)?;
+    Ok(interfaces.iter().filter_map(LocalInterface::from_if_addrs).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, ip: &str, netmask: &str) -> LocalInterface {
+        let ip: Ipv4Addr = ip.parse().unwrap();
+        let netmask: Ipv4Addr = netmask.parse().unwrap();
+        LocalInterface {
+            name: name.to_string(),
+            ip,
+            netmask,
+            broadcast: Ipv4Addr::from(u32::from(ip) | !u32::from(netmask)),
+            up: true,
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let lan = interface("eth0", "192.168.1.5", "255.255.255.0");
+        assert_eq!(lan.broadcast, Ipv4Addr::new(192, 168, 1, 255));
+        assert!(lan.contains(Ipv4Addr::new(192, 168, 1, 200)));
+        assert!(!lan.contains(Ipv4Addr::new(192, 168, 2, 1)));
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = InterfaceFilter {
+            skip_virtual: true,
+            excluded: vec!["eth1".to_string(), "wlan*".to_string()],
+        };
+
+        assert!(filter.allows(&interface("eth0", "192.168.1.5", "255.255.255.0")));
+        assert!(!filter.allows(&interface("docker0", "172.17.0.1", "255.255.0.0")));
+        assert!(!filter.allows(&interface("VMware Network Adapter VMnet8", "192.168.8.1", "255.255.255.0")));
+        assert!(!filter.allows(&interface("eth1", "10.0.0.5", "255.255.255.0")));
+        assert!(!filter.allows(&interface("wlan0", "10.1.0.5", "255.255.255.0")));
+
+        let mut down = interface("eth0", "192.168.1.5", "255.255.255.0");
+        down.up = false;
+        assert!(!filter.allows(&down));
+
+        let keep_virtual = InterfaceFilter {
+            skip_virtual: false,
+            excluded: Vec::new(),
+        };
+        assert!(keep_virtual.allows(&interface("docker0", "172.17.0.1", "255.255.0.0")));
+    }
+
+    #[test]
+    fn test_list_interfaces() {
+        // Whatever the host has, loopback and point-to-point addresses are left out
+        for interface in list_interfaces().unwrap() {
+            assert!(!interface.ip.is_loopback());
+            assert!(interface.contains(interface.broadcast));
+        }
+    }
+}
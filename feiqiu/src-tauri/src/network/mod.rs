@@ -1,6 +1,15 @@
 // Network communication layer - UDP/TCP sockets, packet parsing/sending
 
+pub mod addr;
+pub mod capture;
+pub mod codec;
+pub mod command;
+pub mod extra_hosts;
+pub mod flood;
+pub mod interfaces;
+pub mod packet_id;
 pub mod protocol;
+pub mod stats;
 pub mod udp;
 pub mod tcp;
 
@@ -10,12 +19,31 @@ pub use protocol::{
     serialize_message,
     FileSendRequest,
     FileSendResponse,
+    FileAttachment,
+    FileDataRequest,
+    FeiqHeader,
+    encode_attachments,
     ProtocolMessage,
+    RawPacket,
+    TextEncoding,
     PROTOCOL_VERSION,
+    MAX_CONTENT_SIZE,
     msg_type,
     get_message_type_name,
 };
 
-pub use udp::{UdpTransport, DEFAULT_UDP_PORT};
+pub use addr::{canonical_addr, canonical_ip, IPV6_MULTICAST_ADDR};
+pub use capture::{read_capture, CaptureRecord, Direction, PacketCapture};
+pub use codec::{HostListEntry, Payload};
+pub use command::{Command, Mode, Opt, OptionContext};
+pub use extra_hosts::{expand_hosts, HostEntry, MAX_EXTRA_HOST_ADDRS};
+pub use flood::{FloodGuard, FloodLimits, FloodStats, Verdict};
+pub use interfaces::{list_interfaces, InterfaceFilter, LocalInterface};
+pub use packet_id::{next_packet_id, PacketIdAllocator, RecentPackets};
+pub use stats::{NetworkStats, NetworkStatsSnapshot, TcpConnection};
 
-pub use tcp::{TcpTransport, DEFAULT_BUFFER_SIZE, PORT_RANGE_START, PORT_RANGE_END};
+pub use udp::{UdpSender, UdpTransport, DEFAULT_UDP_PORT};
+
+pub use tcp::{
+    TcpTransport, TransferControl, TransferSignal, DEFAULT_BUFFER_SIZE, PORT_RANGE_START, PORT_RANGE_END,
+};
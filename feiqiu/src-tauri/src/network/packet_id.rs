@@ -0,0 +1,246 @@
+// Packet ID allocation and inbound duplicate suppression
+//
+// IPMsg identifies a packet by (sender, packet ID): acknowledgments, file
+// requests and releases all refer back to it. Every outbound packet in the
+// process takes its ID from one allocator, so two subsystems (or two sends in
+// the same second) never reuse an ID. Inbound, `RecentPackets` remembers what
+// was already processed so a retransmission isn't handled twice.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Largest packet ID the protocol parser accepts (IPMsg uses 32-bit IDs)
+pub const MAX_PACKET_ID: u64 = u32::MAX as u64;
+
+/// How long a received packet ID is remembered by default
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many received packet IDs are remembered at most by default
+pub const DEFAULT_DEDUP_CAPACITY: usize = 1024;
+
+/// Monotonic packet ID allocator
+///
+/// IDs increase by one per call and wrap back to 1 after [`MAX_PACKET_ID`].
+#[derive(Debug)]
+pub struct PacketIdAllocator {
+    next: AtomicU64,
+}
+
+impl PacketIdAllocator {
+    /// Create an allocator starting at `start`
+    pub fn new(start: u64) -> Self {
+        Self {
+            next: AtomicU64::new(start.clamp(1, MAX_PACKET_ID)),
+        }
+    }
+
+    /// Get the process-wide allocator
+    ///
+    /// Seeded with the current Unix time (as IPMsg does), so IDs from a
+    /// restarted process don't collide with ones peers have just seen.
+    pub fn global() -> &'static PacketIdAllocator {
+        static GLOBAL: OnceLock<PacketIdAllocator> = OnceLock::new();
+
+        GLOBAL.get_or_init(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(1);
+            PacketIdAllocator::new(now)
+        })
+    }
+
+    /// Allocate the next packet ID
+    pub fn next(&self) -> u64 {
+        self.next
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| {
+                Some(if id >= MAX_PACKET_ID { 1 } else { id + 1 })
+            })
+            .unwrap_or(1)
+    }
+
+    /// Peek at the ID the next call to [`next`](Self::next) will return
+    pub fn peek(&self) -> u64 {
+        self.next.load(Ordering::SeqCst)
+    }
+}
+
+/// Allocate a packet ID from the process-wide allocator
+pub fn next_packet_id() -> u64 {
+    PacketIdAllocator::global().next()
+}
+
+/// Recently received packets, keyed by (sender, packet ID)
+///
+/// Bounded both in time (`window`) and size (`capacity`): the oldest entries
+/// are forgotten first.
+#[derive(Debug)]
+pub struct RecentPackets {
+    inner: Mutex<RecentPacketsInner>,
+    window: Duration,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct RecentPacketsInner {
+    seen: HashMap<(IpAddr, u64), Instant>,
+    order: VecDeque<((IpAddr, u64), Instant)>,
+}
+
+impl RecentPackets {
+    /// Create a cache
+    ///
+    /// # Arguments
+    /// * `capacity` - Maximum number of remembered packets
+    /// * `window` - How long a packet is remembered
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        Self {
+            inner: Mutex::new(RecentPacketsInner::default()),
+            window,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Record a received packet
+    ///
+    /// # Returns
+    /// * `true` - The packet was already seen within the window (duplicate)
+    /// * `false` - First time seen (now remembered)
+    pub fn check_and_insert(&self, sender: IpAddr, packet_id: u64) -> bool {
+        self.check_and_insert_at(sender, packet_id, Instant::now())
+    }
+
+    fn check_and_insert_at(&self, sender: IpAddr, packet_id: u64, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (sender, packet_id);
+
+        // Forget expired entries (oldest first)
+        while let Some(&(old_key, received_at)) = inner.order.front() {
+            if now.duration_since(received_at) < self.window {
+                break;
+            }
+            inner.order.pop_front();
+            if inner.seen.get(&old_key) == Some(&received_at) {
+                inner.seen.remove(&old_key);
+            }
+        }
+
+        if inner.seen.contains_key(&key) {
+            return true;
+        }
+
+        // Make room
+        while inner.seen.len() >= self.capacity {
+            match inner.order.pop_front() {
+                Some((old_key, received_at)) => {
+                    if inner.seen.get(&old_key) == Some(&received_at) {
+                        inner.seen.remove(&old_key);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        inner.seen.insert(key, now);
+        inner.order.push_back((key, now));
+        false
+    }
+
+    /// Forget a packet so a retransmission of it is processed again
+    ///
+    /// Used when handling the first copy failed.
+    pub fn forget(&self, sender: IpAddr, packet_id: u64) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.seen.remove(&(sender, packet_id));
+    }
+
+    /// Number of packets currently remembered
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).seen.len()
+    }
+
+    /// Check if no packets are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for RecentPackets {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_CAPACITY, DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_allocator_increments_and_wraps() {
+        let allocator = PacketIdAllocator::new(MAX_PACKET_ID - 1);
+
+        assert_eq!(allocator.next(), MAX_PACKET_ID - 1);
+        assert_eq!(allocator.next(), MAX_PACKET_ID);
+        assert_eq!(allocator.next(), 1);
+        assert_eq!(allocator.peek(), 2);
+    }
+
+    #[test]
+    fn test_global_allocator_is_unique_across_threads() {
+        let ids: Vec<u64> = (0..8)
+            .map(|_| std::thread::spawn(|| (0..500).map(|_| next_packet_id()).collect::<Vec<_>>()))
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+
+        let unique: HashSet<_> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len());
+        assert!(ids.iter().all(|id| (1..=MAX_PACKET_ID).contains(id)));
+    }
+
+    #[test]
+    fn test_recent_packets_detects_duplicates() {
+        let cache = RecentPackets::default();
+        let alice: IpAddr = "192.168.1.10".parse().unwrap();
+        let bob: IpAddr = "192.168.1.11".parse().unwrap();
+
+        assert!(!cache.check_and_insert(alice, 100));
+        assert!(cache.check_and_insert(alice, 100));
+
+        // Same ID from another sender is a different packet
+        assert!(!cache.check_and_insert(bob, 100));
+        assert_eq!(cache.len(), 2);
+
+        cache.forget(alice, 100);
+        assert!(!cache.check_and_insert(alice, 100));
+    }
+
+    #[test]
+    fn test_recent_packets_expire() {
+        let cache = RecentPackets::new(16, Duration::from_secs(10));
+        let alice: IpAddr = "192.168.1.10".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(!cache.check_and_insert_at(alice, 1, start));
+        assert!(cache.check_and_insert_at(alice, 1, start + Duration::from_secs(5)));
+        assert!(!cache.check_and_insert_at(alice, 1, start + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_recent_packets_capacity() {
+        let cache = RecentPackets::new(2, DEFAULT_DEDUP_WINDOW);
+        let alice: IpAddr = "192.168.1.10".parse().unwrap();
+
+        cache.check_and_insert(alice, 1);
+        cache.check_and_insert(alice, 2);
+        cache.check_and_insert(alice, 3);
+
+        assert_eq!(cache.len(), 2);
+        // Oldest entry was evicted
+        assert!(!cache.check_and_insert(alice, 1));
+        assert!(cache.check_and_insert(alice, 3));
+    }
+}
@@ -6,9 +6,11 @@
 // Encoding: UTF-8 (standard), GBK (FeiQ compatibility)
 // Default UDP port: 2425
 
+use super::command::{Command, Mode, Opt};
+use super::packet_id::MAX_PACKET_ID;
 use crate::{NeoLanError, Result};
 use serde::{Deserialize, Serialize};
-use encoding_rs::GBK;
+use encoding_rs::{GBK, SHIFT_JIS};
 
 /// Message type constants (compatible with IPMsg protocol)
 pub mod msg_type {
@@ -74,6 +76,7 @@ pub mod msg_type {
     pub const IPMSG_FILEATTACHOPT: u32 = 0x00200000; // 2097152 文件附加标志
     pub const IPMSG_ENCRYPTOPT: u32 = 0x00400000; // 4194304 加密标志
     pub const IPMSG_UTF8OPT: u32 = 0x00800000; // 8388608 UTF-8 编码标志
+    pub const IPMSG_CAPUTF8OPT: u32 = 0x01000000; // 16777216 支持 UTF-8（能力标志）
 
     /// option for send command（发送上下文特有）
     // 注意：有些值与上面的“通用”标志数值相同 —— 解释时应基于 mode（即先 GET_MODE）
@@ -87,6 +90,18 @@ pub mod msg_type {
     pub const IPMSG_PASSWORDOPT: u32 = 0x00008000; // 32768 带密码发送
     pub const IPMSG_NOLOGOPT: u32 = 0x00020000; // 131072 不记录日志
 
+    /// NeoLan 扩展（IPMsg / 飞秋未使用的位）
+    // 上线包：发送方支持分片消息；SENDMSG：本包是长消息的一个分片
+    pub const IPMSG_CAPCHUNKOPT: u32 = 0x20000000; // 536870912 支持分片消息（能力标志）
+    pub const IPMSG_CHUNKOPT: u32 = 0x20000000; // 536870912 分片消息（send 上下文）
+    // 上线包：发送方接受 JSON 文件请求（FileSendRequest）
+    pub const IPMSG_CAPFILEREQOPT: u32 = 0x40000000; // 1073741824 支持 JSON 文件请求（能力标志）
+
+    /// file attributes（FILEATTACHOPT 附件列表中的 attr 字段）
+    pub const IPMSG_FILE_REGULAR: u32 = 0x00000001; // 普通文件
+    pub const IPMSG_FILE_DIR: u32 = 0x00000002; // 目录
+    pub const IPMSG_FILE_RETPARENT: u32 = 0x00000003; // 返回上级目录（目录传输用）
+
     // 下面给出一些常用组合构造函数作为参考：
     #[inline]
     pub const fn make_command(mode: u32, opts: u32) -> u32 {
@@ -116,70 +131,122 @@ const PROTOCOL_DELIMITER: &str = ":";
 /// Minimum number of protocol fields
 const MIN_FIELD_COUNT: usize = 6;
 
-/// Maximum packet ID value
-const MAX_PACKET_ID: u64 = u32::MAX as u64;
 
 /// Maximum message content size (1MB)
-const MAX_CONTENT_SIZE: usize = 1024 * 1024;
+pub const MAX_CONTENT_SIZE: usize = 1024 * 1024;
 
-/// Decode bytes to string with encoding auto-detection
-/// Tries UTF-8 first (standard IPMsg), then GBK (FeiQ compatibility)
-fn decode_message_bytes(data: &[u8]) -> String {
-    // Try UTF-8 first (standard IPMsg)
-    if let Ok(utf8_str) = std::str::from_utf8(data) {
-        return utf8_str.to_string();
-    }
+/// Separator between the fields of a FeiQ header
+const FEIQ_HEADER_SEPARATOR: char = '#';
 
-    // Try GBK encoding (FeiQ uses GBK for Chinese)
-    let (cow, _, _) = GBK.decode(data);
-    let gbk_str = cow.to_string();
+/// Separator between the version, client tag and face ID in a FeiQ header
+const FEIQ_TAG_SEPARATOR: char = '_';
 
-    // Log encoding detection for debugging
-    tracing::debug!("GBK encoding detected for message (non-UTF8 bytes)");
+/// Separator between the content field and its extension sections (NUL)
+///
+/// IPMsg appends extra data after a NUL byte: the group name in entry
+/// packets, the attachment list with FILEATTACHOPT, and so on.
+pub const EXTENSION_SEPARATOR: char = '\0';
 
-    gbk_str
+/// Separator between attachment entries (IPMsg FILELIST_SEPARATOR, '\a')
+pub const FILELIST_SEPARATOR: char = '\u{07}';
+
+/// Wire bytes of the delimiters the parser splits on
+///
+/// All three are ASCII and can never be the trail byte of a GBK or
+/// Shift_JIS double-byte character (those start at 0x40), so a packet can be
+/// split before its text is decoded, whatever the encoding.
+const DELIMITER_BYTE: u8 = b':';
+const FEIQ_HEADER_BYTE: u8 = b'#';
+const EXTENSION_BYTE: u8 = 0;
+
+/// Text encoding used on the wire
+///
+/// - `Utf8`: NeoLan and IPMsg clients advertising `IPMSG_UTF8OPT`
+/// - `Gbk`: FeiQ and Chinese IPMsg builds
+/// - `ShiftJis`: original (Japanese) IPMsg
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    /// UTF-8 (default)
+    #[default]
+    Utf8,
+
+    /// GBK (FeiQ)
+    Gbk,
+
+    /// Shift_JIS (original IPMsg)
+    ShiftJis,
 }
 
-/// Explain message type with its flags for debugging
-/// Returns a human-readable description of the message type
-pub fn explain_message_type(msg_type: u32) -> String {
-    let mode = msg_type::get_mode(msg_type) as u32;
-    let opts = msg_type::get_opt(msg_type);
+impl TextEncoding {
+    /// Encode text with this encoding
+    ///
+    /// Characters the codec can't represent are written as numeric
+    /// character references (`&#NNNN;`) rather than dropped.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Gbk => GBK.encode(text).0.into_owned(),
+            Self::ShiftJis => SHIFT_JIS.encode(text).0.into_owned(),
+        }
+    }
 
-    let mode_name = get_message_type_name(msg_type);
+    /// Decode bytes with this encoding (malformed sequences are replaced)
+    pub fn decode(&self, data: &[u8]) -> String {
+        match self {
+            Self::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Self::Gbk => GBK.decode(data).0.into_owned(),
+            Self::ShiftJis => SHIFT_JIS.decode(data).0.into_owned(),
+        }
+    }
 
-    let mut flags = Vec::new();
+    /// Detect the encoding of inbound bytes
+    ///
+    /// # Returns
+    /// * `None` - Pure ASCII (compatible with every encoding, nothing learned)
+    /// * `Some(Utf8)` - Valid UTF-8 with non-ASCII characters
+    /// * `Some(ShiftJis)` - Decodes cleanly as Shift_JIS and contains kana
+    /// * `Some(Gbk)` - Anything else (FeiQ is by far the most common sender)
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.is_ascii() {
+            return None;
+        }
 
-    // Check common option flags
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_FILEATTACHOPT) {
-        flags.push("FILEATTACH".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_UTF8OPT) {
-        flags.push("UTF8".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_ENCRYPTOPT) {
-        flags.push("ENCRYPT".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_ABSENCEOPT) {
-        flags.push("ABSENCE".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_SENDCHECKOPT) {
-        flags.push("SENDCHECK".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_SECRETOPT) {
-        flags.push("SECRET".to_string());
-    }
-    if msg_type::has_opt(msg_type, msg_type::IPMSG_BROADCASTOPT) {
-        flags.push("BROADCAST".to_string());
+        if std::str::from_utf8(data).is_ok() {
+            return Some(Self::Utf8);
+        }
+
+        // GBK and Shift_JIS overlap heavily, so a clean decode proves little.
+        // Japanese text is almost always full of (full-width) kana, while GBK
+        // Chinese decoded as Shift_JIS turns into half-width katakana.
+        if let Some(text) = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(data) {
+            let has_kana = text.chars().any(|c| ('\u{3040}'..='\u{30FF}').contains(&c));
+            let has_halfwidth_kana = text.chars().any(|c| ('\u{FF61}'..='\u{FF9F}').contains(&c));
+            if has_kana && !has_halfwidth_kana {
+                return Some(Self::ShiftJis);
+            }
+        }
+
+        Some(Self::Gbk)
     }
 
-    let flags_str = if flags.is_empty() {
-        String::new()
-    } else {
-        format!(" | [{}]", flags.join(" | "))
-    };
+    /// Get encoding name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf8",
+            Self::Gbk => "gbk",
+            Self::ShiftJis => "shiftjis",
+        }
+    }
+}
 
-    format!("{} (0x{:08X} = mode: 0x{:02X}{})", mode_name, msg_type, mode, flags_str)
+/// Explain message type with its flags for debugging
+/// Returns a human-readable description of the message type
+///
+/// Option bits are named for the message's mode, so `0x100` reads as
+/// ABSENCE on entry packets and SENDCHECK on SENDMSG.
+pub fn explain_message_type(msg_type: u32) -> String {
+    Command::from(msg_type).to_string()
 }
 
 /// IPMsg-compatible protocol message
@@ -205,10 +272,42 @@ pub struct ProtocolMessage {
 
     /// Message content (format depends on msg_type)
     pub content: String,
+
+    /// Extension sections following the content, one per NUL-separated part
+    ///
+    /// Meaning depends on msg_type: the group name for entry packets
+    /// (`IPMSG_BR_ENTRY`/`IPMSG_ANSENTRY`/`IPMSG_BR_ABSENCE`), the attachment
+    /// list for `IPMSG_FILEATTACHOPT`, etc. Trailing empty sections are dropped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+
+    /// Bytes following the content field, exactly as received
+    ///
+    /// Everything after the first NUL (extension sections and their
+    /// terminators), never run through a text decoder. Binary payloads such as
+    /// encrypted data must be read from here: `extensions` is only their
+    /// decoded text. Filled in by the parser; ignored on serialize.
+    #[serde(skip)]
+    pub raw_extensions: Vec<u8>,
+
+    /// FeiQ packet header (`1_lbt4_41#128#MAC#...`), `None` for plain IPMsg
+    ///
+    /// On serialize it replaces the version field, so FeiQ recognises the
+    /// sender as a FeiQ client (IPMsg reads the leading `1` as the version).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feiq_header: Option<FeiqHeader>,
+
+    /// Wire text encoding
+    ///
+    /// On parse: detected from the bytes, advertised via `IPMSG_UTF8OPT`,
+    /// or implied by the sender's format (FeiQ => GBK); `None` if unknown.
+    /// On serialize: the codec to encode with (`None` => UTF-8).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<TextEncoding>,
 }
 
 /// File transfer request (JSON content for FILE_SEND_REQ)
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileSendRequest {
     /// File name
     pub name: String,
@@ -221,7 +320,7 @@ pub struct FileSendRequest {
 }
 
 /// File transfer response (JSON content for FILE_SEND_RSP)
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileSendResponse {
     /// true = accept, false = reject
     pub accept: bool,
@@ -229,10 +328,550 @@ pub struct FileSendResponse {
     /// TCP port for data transfer (only if accept = true)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
+
+    /// Packet ID of the offer being answered (older NeoLan builds leave it out)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packet_id: Option<u64>,
+
+    /// Resume: bytes of the file the receiver already has
+    ///
+    /// When set, the sender starts the data connection with the offset it
+    /// continues from as 8 bytes big-endian: `offset` when the receiver's
+    /// prefix matches `prefix_md5`, 0 when the file is sent over again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+
+    /// Resume: MD5 of the first `offset` bytes the receiver has (hex string)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_md5: Option<String>,
+}
+
+impl ProtocolMessage {
+    /// Get the typed command (mode + options) of this message
+    pub fn command(&self) -> Command {
+        Command::from(self.msg_type)
+    }
+
+    /// Get an extension section by index
+    ///
+    /// # Returns
+    /// * `Some(&str)` - Section text (may be empty)
+    /// * `None` - The message has fewer sections
+    pub fn extension(&self, index: usize) -> Option<&str> {
+        self.extensions.get(index).map(String::as_str)
+    }
+
+    /// Get the sender's group name (entry packets only)
+    ///
+    /// IPMsg entry packets carry `nickname\0group\0`: the nickname is the
+    /// content and the group is the first extension section.
+    pub fn group_name(&self) -> Option<&str> {
+        if !self.command().mode().is_entry() {
+            return None;
+        }
+
+        self.extension(0)
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+    }
+
+    /// Get the file attachments carried by this message
+    ///
+    /// # Returns
+    /// * `Ok(Vec<FileAttachment>)` - Attachments (empty if IPMSG_FILEATTACHOPT is not set)
+    /// * `Err(NeoLanError)` - Attachment section is malformed
+    pub fn attachments(&self) -> Result<Vec<FileAttachment>> {
+        if !self.command().has(Opt::FileAttach) {
+            return Ok(Vec::new());
+        }
+
+        match self.extension(0) {
+            Some(section) => parse_attachments(section),
+            None => Err(NeoLanError::Protocol(
+                "IPMSG_FILEATTACHOPT set but message has no attachment section".to_string(),
+            )),
+        }
+    }
+}
+
+/// FeiQ packet header
+///
+/// FeiQ replaces the IPMsg version field with `#`-separated client data:
+/// `1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9`
+/// - `1_lbt4_41`: IPMsg version, client tag and avatar/face ID
+/// - `128`: feature flags
+/// - `24F5AAD7C96A`: sender's MAC address
+/// - the remaining fields are kept verbatim
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeiqHeader {
+    /// Client tag (e.g. `lbt4`)
+    pub tag: String,
+
+    /// Avatar / face ID
+    pub face_id: u32,
+
+    /// Feature flags (which FeiQ extensions the client supports)
+    pub flags: u32,
+
+    /// MAC address (12 uppercase hex digits, no separators)
+    pub mac: String,
+
+    /// Remaining header fields (undocumented), re-emitted as-is
+    pub reserved: Vec<String>,
+}
+
+impl FeiqHeader {
+    /// Client tag sent by current FeiQ releases
+    pub const DEFAULT_TAG: &'static str = "lbt4";
+
+    /// Feature flags sent by current FeiQ releases
+    pub const DEFAULT_FLAGS: u32 = 128;
+
+    /// Trailing fields sent by current FeiQ releases
+    const DEFAULT_RESERVED: [&'static str; 5] = ["0", "0", "0", "311c", "9"];
+
+    /// Create a header with FeiQ's default tag, flags and trailing fields
+    ///
+    /// # Arguments
+    /// * `mac` - MAC address (12 hex digits)
+    /// * `face_id` - Avatar / face ID
+    pub fn new(mac: String, face_id: u32) -> Self {
+        Self {
+            tag: Self::DEFAULT_TAG.to_string(),
+            face_id,
+            flags: Self::DEFAULT_FLAGS,
+            mac,
+            reserved: Self::DEFAULT_RESERVED.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Create the header for this machine (identified by its MAC address)
+    pub fn local() -> Self {
+        Self::new(crate::utils::mac::local_mac_address().to_string(), 0)
+    }
+
+    /// Check if a feature flag is set
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Get the MAC address in `AA:BB:CC:DD:EE:FF` form
+    pub fn mac_address(&self) -> String {
+        self.mac
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| String::from_utf8_lossy(pair).into_owned())
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    /// Encode as the leading field of a FeiQ packet
+    pub fn encode(&self) -> String {
+        let mut header = format!(
+            "{}{}{}{}{}{}{}{}{}",
+            PROTOCOL_VERSION,
+            FEIQ_TAG_SEPARATOR,
+            self.tag,
+            FEIQ_TAG_SEPARATOR,
+            self.face_id,
+            FEIQ_HEADER_SEPARATOR,
+            self.flags,
+            FEIQ_HEADER_SEPARATOR,
+            self.mac
+        );
+        for field in &self.reserved {
+            header.push(FEIQ_HEADER_SEPARATOR);
+            header.push_str(field);
+        }
+        header
+    }
+
+    /// Parse the leading field of a FeiQ packet
+    ///
+    /// # Arguments
+    /// * `header` - Everything before the first `:` of the packet
+    pub fn parse(header: &str) -> Result<Self> {
+        let mut fields = header.split(FEIQ_HEADER_SEPARATOR);
+
+        let client = fields.next().unwrap_or_default();
+        let flags = fields.next();
+        let mac = fields.next();
+
+        let (Some(flags), Some(mac)) = (flags, mac) else {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid FeiQ header: expected at least 3 '#' fields, got: {}",
+                header
+            )));
+        };
+
+        let client_parts: Vec<&str> = client.split(FEIQ_TAG_SEPARATOR).collect();
+        if client_parts.len() < 3 {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid FeiQ client field: {}",
+                client
+            )));
+        }
+
+        let face_id: u32 = client_parts[2].parse().map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid FeiQ face ID: {}", client_parts[2]))
+        })?;
+
+        let flags: u32 = flags.parse().map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid FeiQ flags: {}", flags))
+        })?;
+
+        let mac = crate::utils::mac::normalize_mac(mac).ok_or_else(|| {
+            NeoLanError::Protocol(format!("Invalid FeiQ MAC address: {}", mac))
+        })?;
+
+        Ok(Self {
+            tag: client_parts[1].to_string(),
+            face_id,
+            flags,
+            mac,
+            reserved: fields.map(str::to_string).collect(),
+        })
+    }
+}
+
+/// File attachment entry (standard IPMsg FILEATTACHOPT format)
+///
+/// Wire format: `fileID:filename:size:mtime:attr:`
+/// - `fileID` is decimal, `size`, `mtime` and `attr` are hexadecimal
+/// - A `:` inside the file name is escaped as `::`
+/// - Entries are separated by `\a` and follow the message text after a NUL byte
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileAttachment {
+    /// File ID (unique within the announcing packet)
+    pub file_id: u64,
+
+    /// File name (without directory)
+    pub name: String,
+
+    /// File size in bytes
+    pub size: u64,
+
+    /// Last modification time (Unix seconds)
+    pub mtime: u64,
+
+    /// File attribute (see msg_type::IPMSG_FILE_* constants)
+    pub attr: u32,
+}
+
+impl FileAttachment {
+    /// Create an attachment entry for a regular file
+    pub fn regular(file_id: u64, name: String, size: u64, mtime: u64) -> Self {
+        Self {
+            file_id,
+            name,
+            size,
+            mtime,
+            attr: msg_type::IPMSG_FILE_REGULAR,
+        }
+    }
+
+    /// Check if this entry is a regular file
+    pub fn is_regular(&self) -> bool {
+        self.attr & 0xff == msg_type::IPMSG_FILE_REGULAR
+    }
+
+    /// Check if this entry is a directory
+    pub fn is_directory(&self) -> bool {
+        self.attr & 0xff == msg_type::IPMSG_FILE_DIR
+    }
+
+    /// Encode this entry as `fileID:filename:size:mtime:attr:`
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{:x}:{:x}:{:x}:",
+            self.file_id,
+            self.name.replace(':', "::"),
+            self.size,
+            self.mtime,
+            self.attr
+        )
+    }
+
+    /// Parse a single attachment entry
+    ///
+    /// Extended attributes after the `attr` field (IPMsg `key=value` pairs) are ignored.
+    ///
+    /// # Arguments
+    /// * `entry` - Entry text without the trailing `\a` separator
+    pub fn parse(entry: &str) -> Result<Self> {
+        let fields = split_escaped_fields(entry);
+
+        if fields.len() < 5 {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid attachment entry: expected at least 5 fields, got {}. Entry: {}",
+                fields.len(),
+                entry
+            )));
+        }
+
+        let file_id: u64 = fields[0].trim().parse().map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid attachment file ID: {}", fields[0]))
+        })?;
+
+        let name = fields[1].clone();
+        if name.is_empty() {
+            return Err(NeoLanError::Protocol(
+                "Attachment file name cannot be empty".to_string(),
+            ));
+        }
+
+        let size = u64::from_str_radix(&fields[2], 16).map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid attachment size: {}", fields[2]))
+        })?;
+
+        let mtime = u64::from_str_radix(&fields[3], 16).map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid attachment mtime: {}", fields[3]))
+        })?;
+
+        let attr = u32::from_str_radix(&fields[4], 16).map_err(|_| {
+            NeoLanError::Protocol(format!("Invalid attachment attr: {}", fields[4]))
+        })?;
+
+        Ok(Self {
+            file_id,
+            name,
+            size,
+            mtime,
+            attr,
+        })
+    }
+}
+
+/// Split an attachment entry on `:`, treating `::` as an escaped colon
+fn split_escaped_fields(entry: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut chars = entry.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == ':' {
+            if chars.peek() == Some(&':') {
+                chars.next();
+                current.push(':');
+            } else {
+                fields.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(c);
+        }
+    }
+
+    if !current.is_empty() {
+        fields.push(current);
+    }
+
+    fields
+}
+
+/// Encode a list of attachments into an attachment section
+///
+/// Each entry is terminated by the `\a` separator, as IPMsg does.
+pub fn encode_attachments(files: &[FileAttachment]) -> String {
+    files
+        .iter()
+        .map(|f| format!("{}{}", f.encode(), FILELIST_SEPARATOR))
+        .collect()
+}
+
+/// Parse an attachment section into attachment entries
+///
+/// Trailing NUL bytes and empty entries are ignored.
+pub fn parse_attachments(section: &str) -> Result<Vec<FileAttachment>> {
+    section
+        .trim_end_matches(EXTENSION_SEPARATOR)
+        .split(FILELIST_SEPARATOR)
+        .filter(|entry| !entry.is_empty())
+        .map(FileAttachment::parse)
+        .collect()
+}
+
+/// IPMsg file data request (IPMSG_GETFILEDATA content sent over TCP)
+///
+/// Wire format: `packetID:fileID:offset:` (all hexadecimal). The receiver
+/// sends it over a TCP connection to the sender's IPMsg port, and the sender
+/// answers with the raw file bytes starting at `offset`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDataRequest {
+    /// Packet ID of the FILEATTACHOPT message that announced the file
+    pub packet_id: u64,
+
+    /// File ID within that message
+    pub file_id: u64,
+
+    /// Byte offset to start from
+    pub offset: u64,
+}
+
+impl FileDataRequest {
+    /// Encode as `packetID:fileID:offset:` (hexadecimal)
+    pub fn encode(&self) -> String {
+        format!("{:x}:{:x}:{:x}:", self.packet_id, self.file_id, self.offset)
+    }
+
+    /// Parse from IPMSG_GETFILEDATA content
+    ///
+    /// # Arguments
+    /// * `content` - Message content (`packetID:fileID:offset[:...]`)
+    pub fn parse(content: &str) -> Result<Self> {
+        let fields: Vec<&str> = content
+            .trim_end_matches(EXTENSION_SEPARATOR)
+            .split(PROTOCOL_DELIMITER)
+            .collect();
+
+        if fields.len() < 3 {
+            return Err(NeoLanError::Protocol(format!(
+                "Invalid file data request: expected packetID:fileID:offset, got: {}",
+                content
+            )));
+        }
+
+        let parse_hex = |name: &str, value: &str| {
+            u64::from_str_radix(value.trim(), 16).map_err(|_| {
+                NeoLanError::Protocol(format!("Invalid {} in file data request: {}", name, value))
+            })
+        };
+
+        Ok(Self {
+            packet_id: parse_hex("packetID", fields[0])?,
+            file_id: parse_hex("fileID", fields[1])?,
+            offset: parse_hex("offset", fields[2])?,
+        })
+    }
+}
+
+/// A packet split into its fields, borrowing from the received datagram
+///
+/// Splitting works on bytes and decodes nothing, so it doesn't allocate and
+/// never touches the body: binary data after the content (encrypted payloads,
+/// attachment lists) stays exactly as received. [`parse_message`] builds a
+/// [`ProtocolMessage`] on top of it, decoding only the textual fields.
+///
+/// # Examples
+/// ```
+/// # use neolan_lib::network::RawPacket;
+/// let packet = RawPacket::split(b"1:123:Alice:alice-pc:32:Hi:there\0\x01\x02\0")?;
+/// assert_eq!(packet.sender_name, b"Alice");
+/// assert_eq!(packet.content(), b"Hi:there");
+/// assert_eq!(packet.extensions(), b"\x01\x02\0");
+/// # Ok::<(), neolan_lib::NeoLanError>(())
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawPacket<'a> {
+    /// Version field, or the whole FeiQ header (`1_lbt4_41#128#MAC#...`)
+    pub header: &'a [u8],
+
+    /// Packet ID field (decimal)
+    pub packet_id: &'a [u8],
+
+    /// Sender's username field (encoded text)
+    pub sender_name: &'a [u8],
+
+    /// Sender's hostname field (encoded text)
+    pub sender_host: &'a [u8],
+
+    /// Command field (decimal)
+    pub command: &'a [u8],
+
+    /// Everything after the command field: the content, then the
+    /// NUL-separated extension sections
+    pub body: &'a [u8],
+}
+
+impl<'a> RawPacket<'a> {
+    /// Split a datagram into its six fields
+    ///
+    /// Only the first five `:` are delimiters; any further `:` belong to the
+    /// body.
+    ///
+    /// # Returns
+    /// * `Ok(RawPacket)` - Fields borrowed from `data`
+    /// * `Err(NeoLanError::Protocol)` - Fewer than six fields
+    pub fn split(data: &'a [u8]) -> Result<Self> {
+        let mut fields = data.splitn(MIN_FIELD_COUNT, |&b| b == DELIMITER_BYTE);
+        let fields: [Option<&'a [u8]>; MIN_FIELD_COUNT] = std::array::from_fn(|_| fields.next());
+
+        match fields {
+            [Some(header), Some(packet_id), Some(sender_name), Some(sender_host), Some(command), Some(body)] => {
+                Ok(Self {
+                    header,
+                    packet_id,
+                    sender_name,
+                    sender_host,
+                    command,
+                    body,
+                })
+            }
+            _ => Err(NeoLanError::Protocol(format!(
+                "Invalid message format: expected at least {} fields, got {}. Data: {}",
+                MIN_FIELD_COUNT,
+                fields.iter().flatten().count(),
+                data.escape_ascii()
+            ))),
+        }
+    }
+
+    /// Check if the packet carries a FeiQ header (`#` in the first field)
+    pub fn is_feiq(&self) -> bool {
+        self.header.contains(&FEIQ_HEADER_BYTE)
+    }
+
+    /// Get the mode of the command field (`None` if it isn't a number)
+    pub fn mode(&self) -> Option<Mode> {
+        let command: u32 = std::str::from_utf8(self.command).ok()?.parse().ok()?;
+        Some(Command::from(command).mode())
+    }
+
+    /// Get the content field (the body up to the first NUL)
+    pub fn content(&self) -> &'a [u8] {
+        match self.body.iter().position(|&b| b == EXTENSION_BYTE) {
+            Some(end) => &self.body[..end],
+            None => self.body,
+        }
+    }
+
+    /// Get the bytes after the content field (empty if there's no NUL)
+    pub fn extensions(&self) -> &'a [u8] {
+        self.body.get(self.content().len() + 1..).unwrap_or_default()
+    }
+
+    /// Iterate over the extension sections
+    ///
+    /// Trailing empty sections (the NUL terminators IPMsg appends) are skipped.
+    pub fn sections(&self) -> impl Iterator<Item = &'a [u8]> {
+        let extensions = self.extensions();
+        let end = extensions
+            .iter()
+            .rposition(|&b| b != EXTENSION_BYTE)
+            .map_or(0, |last| last + 1);
+        let trimmed = &extensions[..end];
+
+        (!trimmed.is_empty())
+            .then(|| trimmed.split(|&b| b == EXTENSION_BYTE))
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// Parse a numeric packet field (ASCII decimal)
+fn parse_number_field<T: std::str::FromStr>(field: &[u8], name: &str) -> Result<T> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| NeoLanError::Protocol(format!("Invalid {}: {}", name, field.escape_ascii())))
 }
 
 /// Parse a byte stream into a ProtocolMessage
 ///
+/// The packet is split on bytes first ([`RawPacket`]), then only the sender
+/// fields, the content and the extension sections are decoded with the
+/// detected encoding. The bytes after the content are also kept untouched
+/// in `raw_extensions`.
+///
 /// # Arguments
 /// * `data` - Raw bytes received from UDP socket
 ///
@@ -242,7 +881,7 @@ pub struct FileSendResponse {
 ///
 /// # Protocol Format
 /// Standard: `version:packet_id:sender_name:sender_host:msg_type:content[:ext_fields]`
-/// FeiQ: `1_lbt4_6#128#...#packet_id:timestamp:sender_name:sender_host:msg_type:content`
+/// FeiQ: `1_lbt4_6#128#MAC#0#0#0#311c#9:packet_id:sender_name:sender_host:msg_type:content` (header decoded into `feiq_header`)
 ///
 /// # Examples
 /// ```no_run
@@ -253,72 +892,27 @@ pub struct FileSendResponse {
 /// # Ok::<(), NeoLanError>(())
 /// ```
 pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
-    // Decode bytes with auto-detection (UTF-8 or GBK for FeiQ)
-    let message_str = decode_message_bytes(data);
-    tracing::debug!("Received message: {}", message_str);
+    tracing::debug!("Received message: {}", data.escape_ascii());
 
-    // Handle FeiQ hybrid format: contains '#' followed by IPMsg-compatible section
     // FeiQ format: 1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-IOHG15K:6291459:...
-    // The actual IPMsg section starts after the last '#'
-    let parse_section = if message_str.contains('#') {
-        // Find the last '#' and extract the IPMsg section after it
-        if let Some(last_hash_pos) = message_str.rfind('#') {
-            &message_str[last_hash_pos + 1..]
-        } else {
-            &message_str
-        }
+    // The whole FeiQ header sits where IPMsg has its version, so both split the same way
+    let raw = RawPacket::split(data)?;
+    let is_feiq = raw.is_feiq();
+
+    // Decode the FeiQ header (MAC, flags, face ID); a malformed one isn't fatal
+    let feiq_header = if is_feiq {
+        FeiqHeader::parse(&String::from_utf8_lossy(raw.header))
+            .map_err(|e| tracing::warn!("Ignoring FeiQ header: {}", e))
+            .ok()
     } else {
-        &message_str
+        None
     };
 
-    // Split by delimiter
-    let fields: Vec<&str> = parse_section.split(PROTOCOL_DELIMITER).collect();
-
-    // Validate minimum field count
-    if fields.len() < MIN_FIELD_COUNT {
-        return Err(NeoLanError::Protocol(format!(
-            "Invalid message format: expected at least {} fields, got {}. Data: {}",
-            MIN_FIELD_COUNT,
-            fields.len(),
-            parse_section
-        )));
-    }
-
-    // Detect FeiQ format: version:timestamp:packet_id:hostname:msg_type:content
-    // vs standard: version:packet_id:sender_name:sender_host:msg_type:content
-    // FeiQ timestamp is typically a 10-digit Unix timestamp (e.g., 1761386707) at fields[1]
-    // NOTE: In FeiQ BR_ENTRY messages, the username is in the CONTENT field, not sender_name!
-    let (version, packet_id, sender_name, sender_host, is_feiq) = {
-        // Try to detect if fields[1] is a timestamp (10-digit number)
-        // FeiQ format after stripping last '#': version:timestamp:packet_id:hostname:msg_type:content
-        if fields.len() >= 6
-            && fields[1].len() == 10
-            && fields[1].chars().all(|c| c.is_ascii_digit())
-        {
-            // FeiQ format detected
-            // fields[0]=version, fields[1]=timestamp, fields[2]=packet_id, fields[3]=hostname, fields[4]=msg_type, fields[5]=content
-            // For FeiQ BR_ENTRY, the username is in the content field, not sender_name
-            eprintln!("Detected FeiQ format message (with timestamp field)");
-            // Extract username from content field (fields[5+])
-            let content = if fields.len() > 6 {
-                fields[5..].join(PROTOCOL_DELIMITER)
-            } else {
-                fields[5].to_string()
-            };
-            (
-                PROTOCOL_VERSION,
-                fields[2],           // Packet ID field
-                content,              // Content field (contains username for BR_ENTRY)
-                fields[3].to_string(),  // Hostname field
-                true, // Mark as FeiQ format
-            )
-        } else {
-            // Standard IPMsg format
-            let v: u8 = fields[0]
-                .parse()
-                .map_err(|_| NeoLanError::Protocol(format!("Invalid version: {}", fields[0])))?;
-            (v, fields[1], fields[2].to_string(), fields[3].to_string(), false)
-        }
+    // FeiQ keeps the version inside its header
+    let version: u8 = if is_feiq {
+        PROTOCOL_VERSION
+    } else {
+        parse_number_field(raw.header, "version")?
     };
 
     // Validate version (FeiQ compatibility: only warn, don't error)
@@ -330,23 +924,8 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
         );
     }
 
-    // Parse packet ID - for standard IPMsg it's numeric, for FeiQ it might not be
-    let packet_id: u64 = if !is_feiq {
-        // Standard IPMsg: packet_id is numeric
-        packet_id
-            .parse()
-            .map_err(|_| NeoLanError::Protocol(format!("Invalid packet_id: {}", packet_id)))?
-    } else {
-        // FeiQ: packet_id might not be numeric, use timestamp-based fallback
-        use std::time::SystemTime;
-        packet_id.parse::<u64>().unwrap_or_else(|_| {
-            tracing::warn!("FeiQ packet_id '{}' is not numeric, using timestamp-based fallback", packet_id);
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map(|d| d.as_secs() as u64)
-                .unwrap_or(1)
-        })
-    };
+    // Parse packet ID (numeric for both standard IPMsg and FeiQ)
+    let packet_id: u64 = parse_number_field(raw.packet_id, "packet_id")?;
 
     // Validate packet ID range
     if packet_id > MAX_PACKET_ID {
@@ -356,6 +935,66 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
         )));
     }
 
+    // Parse message type
+    let msg_type: u32 = parse_number_field(raw.command, "msg_type")?;
+    let command = Command::from(msg_type);
+
+    // Log message type with explanation
+    tracing::debug!("Message type: {}", command);
+
+    // Validate content size (including extension sections)
+    if raw.body.len() > MAX_CONTENT_SIZE {
+        return Err(NeoLanError::Protocol(format!(
+            "Content too large: {} bytes (max {})",
+            raw.body.len(),
+            MAX_CONTENT_SIZE
+        )));
+    }
+
+    // Detect the encoding from the text up to the end of the content. The
+    // extension sections only count if that's pure ASCII (e.g. an attachment
+    // offer with an empty message), and never when they're encrypted.
+    let text_len = data.len() - raw.body.len() + raw.content().len();
+    let detected_encoding = TextEncoding::detect(&data[..text_len]).or_else(|| {
+        if command.has(Opt::Encrypt) {
+            None
+        } else {
+            TextEncoding::detect(raw.extensions())
+        }
+    });
+    if let Some(enc) = detected_encoding.filter(|enc| *enc != TextEncoding::Utf8) {
+        tracing::debug!("{} encoding detected for message (non-UTF8 bytes)", enc.as_str());
+    }
+
+    // Wire encoding: advertised UTF-8 wins, then what the bytes tell us,
+    // then the sender's format (FeiQ clients always use GBK). IPMSG_CAPUTF8OPT
+    // only says the sender could read UTF-8; FeiQ sets it on GBK packets.
+    let encoding = if msg_type::has_opt(msg_type, msg_type::IPMSG_UTF8OPT) {
+        Some(TextEncoding::Utf8)
+    } else if detected_encoding.is_some() {
+        detected_encoding
+    } else if is_feiq {
+        Some(TextEncoding::Gbk)
+    } else {
+        None
+    };
+
+    // Decode only the textual fields
+    let text = encoding.unwrap_or_default();
+    let mut sender_name = text.decode(raw.sender_name);
+    let sender_host = text.decode(raw.sender_host);
+    let mut content = text.decode(raw.content());
+    let extensions: Vec<String> = raw.sections().map(|section| text.decode(section)).collect();
+
+    // FeiQ entry messages carry the display name in the content field:
+    // use it as sender_name and clear the content (it's been consumed as the username)
+    if is_feiq
+        && matches!(command.mode(), Mode::BrEntry | Mode::AnsEntry | Mode::BrAbsence)
+        && !content.is_empty()
+    {
+        sender_name = std::mem::take(&mut content);
+    }
+
     // Validate sender name is not empty
     if sender_name.is_empty() {
         return Err(NeoLanError::Protocol(
@@ -370,58 +1009,15 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
         ));
     }
 
-    // Debug log for FeiQ format detection
-    if is_feiq {
-        eprintln!("DEBUG: FeiQ format detected");
-        eprintln!("DEBUG: fields[0]={}", fields[0]);
-        eprintln!("DEBUG: fields[1]={}", fields[1]);
-        eprintln!("DEBUG: fields[2]={}", fields[2]);
-        eprintln!("DEBUG: fields[3]={}", fields[3]);
-        eprintln!("DEBUG: fields[4]={}", fields[4]);
-        eprintln!("DEBUG: sender_name={}, sender_host={}", sender_name, sender_host);
-    } else {
-        eprintln!("DEBUG: Standard IPMsg format");
-        eprintln!("DEBUG: fields[0]={}", fields[0]);
-        eprintln!("DEBUG: fields[1]={}", fields[1]);
-        eprintln!("DEBUG: fields[2]={}", fields[2]);
-        eprintln!("DEBUG: fields[3]={}", fields[3]);
-        eprintln!("DEBUG: sender_name={}, sender_host={}", sender_name, sender_host);
-    }
-
-    // Parse message type
-    // For FeiQ format: msg_type is at fields[4]
-    // For standard IPMsg format: msg_type is at fields[4]
-    let msg_type: u32 = fields[4]
-        .parse()
-        .map_err(|_| NeoLanError::Protocol(format!("Invalid msg_type: {}", fields[4])))?;
-
-    // Log message type with explanation
-    tracing::debug!("Message type: {}", explain_message_type(msg_type));
-
-    // Extract content
-    // For FeiQ format: content was already extracted above as sender_name
-    // For standard IPMsg format: content is at fields[5+]
-    let content = if is_feiq {
-        // For FeiQ, the content field was already used as sender_name
-        // Set content empty since it's been consumed as the username
-        String::new()
-    } else {
-        // Standard IPMsg format: content is at fields[5+]
-        if fields.len() > 6 {
-            fields[5..].join(PROTOCOL_DELIMITER)
-        } else {
-            fields[5].to_string()
-        }
-    };
-
-    // Validate content size
-    if content.len() > MAX_CONTENT_SIZE {
-        return Err(NeoLanError::Protocol(format!(
-            "Content too large: {} bytes (max {})",
-            content.len(),
-            MAX_CONTENT_SIZE
-        )));
-    }
+    tracing::trace!(
+        "Parsed {} packet: packet_id={}, sender_name={}, sender_host={}, content={} bytes, extensions={} bytes",
+        if is_feiq { "FeiQ" } else { "IPMsg" },
+        packet_id,
+        sender_name,
+        sender_host,
+        raw.content().len(),
+        raw.extensions().len()
+    );
 
     Ok(ProtocolMessage {
         version,
@@ -430,6 +1026,10 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
         sender_host,
         msg_type,
         content,
+        extensions,
+        raw_extensions: raw.extensions().to_vec(),
+        feiq_header,
+        encoding,
     })
 }
 
@@ -443,7 +1043,13 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
 /// * `Err(NeoLanError)` - Serialization error
 ///
 /// # Protocol Format
-/// `version:packet_id:sender_name:sender_host:msg_type:content`
+/// `version:packet_id:sender_name:sender_host:msg_type:content`, followed
+/// by `\0extension` for each extension section and a terminating NUL
+///
+/// # Encoding
+/// The text is encoded with `msg.encoding` (UTF-8 if `None`), so packets
+/// for FeiQ/IPMsg peers can be emitted in GBK or Shift_JIS. UTF-8 packets
+/// with a FeiQ header also get `IPMSG_UTF8OPT`, since FeiQ assumes GBK.
 ///
 /// # Examples
 /// ```no_run
@@ -456,6 +1062,10 @@ pub fn parse_message(data: &[u8]) -> Result<ProtocolMessage> {
 ///     sender_host: "alice-pc".to_string(),
 ///     msg_type: msg_type::MSG_SEND,
 ///     content: "Hello World".to_string(),
+///     extensions: Vec::new(),
+///     raw_extensions: Vec::new(),
+///     feiq_header: None,
+///     encoding: None,
 /// };
 /// let bytes = serialize_message(&msg)?;
 /// # Ok::<(), NeoLanError>(())
@@ -505,58 +1115,52 @@ pub fn serialize_message(msg: &ProtocolMessage) -> Result<Vec<u8>> {
         ));
     }
 
-    // Validate content size
-    if msg.content.len() > MAX_CONTENT_SIZE {
+    // Validate content size (including extension sections)
+    let content_len = msg.content.len()
+        + msg.extensions.iter().map(|section| section.len() + 1).sum::<usize>();
+    if content_len > MAX_CONTENT_SIZE {
         return Err(NeoLanError::Protocol(format!(
             "Content too large: {} bytes (max {})",
-            msg.content.len(),
+            content_len,
             MAX_CONTENT_SIZE
         )));
     }
 
-    // Build protocol string
-    let protocol_string = format!(
+    // FeiQ clients assume GBK under a FeiQ header, so UTF-8 must be flagged
+    let encoding = msg.encoding.unwrap_or_default();
+    let msg_type = if msg.feiq_header.is_some() && encoding == TextEncoding::Utf8 {
+        msg.msg_type | msg_type::IPMSG_UTF8OPT
+    } else {
+        msg.msg_type
+    };
+
+    // Build protocol string (a FeiQ header takes the place of the version)
+    let version = match &msg.feiq_header {
+        Some(header) => header.encode(),
+        None => msg.version.to_string(),
+    };
+    let mut protocol_string = format!(
         "{}:{}:{}:{}:{}:{}",
-        msg.version, msg.packet_id, msg.sender_name, msg.sender_host, msg.msg_type, msg.content
+        version, msg.packet_id, msg.sender_name, msg.sender_host, msg_type, msg.content
     );
 
-    // Convert to bytes (UTF-8)
-    Ok(protocol_string.into_bytes())
+    // Append extension sections, NUL-terminated like IPMsg does
+    if !msg.extensions.is_empty() {
+        for section in &msg.extensions {
+            protocol_string.push(EXTENSION_SEPARATOR);
+            protocol_string.push_str(section);
+        }
+        protocol_string.push(EXTENSION_SEPARATOR);
+    }
+
+    // Convert to bytes with the peer's encoding (UTF-8 by default)
+    Ok(encoding.encode(&protocol_string))
 }
 
 /// Get message type name for debugging
 /// Uses IPMsg protocol standard message type names
 pub fn get_message_type_name(msg_type: u32) -> &'static str {
-    // Extract mode (low 8 bits) for comparison
-    let mode = msg_type::get_mode(msg_type) as u32;
-
-    match mode {
-        msg_type::IPMSG_NOOPERATION => "IPMSG_NOOPERATION",
-        msg_type::IPMSG_BR_ENTRY => "IPMSG_BR_ENTRY",
-        msg_type::IPMSG_BR_EXIT => "IPMSG_BR_EXIT",
-        msg_type::IPMSG_ANSENTRY => "IPMSG_ANSENTRY",
-        msg_type::IPMSG_BR_ABSENCE => "IPMSG_BR_ABSENCE",
-        msg_type::IPMSG_BR_ISGETLIST => "IPMSG_BR_ISGETLIST",
-        msg_type::IPMSG_OKGETLIST => "IPMSG_OKGETLIST",
-        msg_type::IPMSG_GETLIST => "IPMSG_GETLIST",
-        msg_type::IPMSG_ANSLIST => "IPMSG_ANSLIST",
-        msg_type::IPMSG_BR_ISGETLIST2 => "IPMSG_BR_ISGETLIST2",
-        msg_type::IPMSG_SENDMSG => "IPMSG_SENDMSG",
-        msg_type::IPMSG_RECVMSG => "IPMSG_RECVMSG",
-        msg_type::IPMSG_READMSG => "IPMSG_READMSG",
-        msg_type::IPMSG_DELMSG => "IPMSG_DELMSG",
-        msg_type::IPMSG_ANSREADMSG => "IPMSG_ANSREADMSG",
-        msg_type::IPMSG_GETINFO => "IPMSG_GETINFO",
-        msg_type::IPMSG_SENDINFO => "IPMSG_SENDINFO",
-        msg_type::IPMSG_GETABSENCEINFO => "IPMSG_GETABSENCEINFO",
-        msg_type::IPMSG_SENDABSENCEINFO => "IPMSG_SENDABSENCEINFO",
-        msg_type::IPMSG_GETFILEDATA => "IPMSG_GETFILEDATA",
-        msg_type::IPMSG_RELEASEFILES => "IPMSG_RELEASEFILES",
-        msg_type::IPMSG_GETDIRFILES => "IPMSG_GETDIRFILES",
-        msg_type::IPMSG_GETPUBKEY => "IPMSG_GETPUBKEY",
-        msg_type::IPMSG_ANSPUBKEY => "IPMSG_ANSPUBKEY",
-        _ => "UNKNOWN",
-    }
+    Command::from(msg_type).mode().name()
 }
 
 #[cfg(test)]
@@ -585,6 +1189,10 @@ mod tests {
             sender_host: "bob-pc".to_string(),
             msg_type: msg_type::IPMSG_BR_ENTRY,
             content: "".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let bytes = serialize_message(&original).unwrap();
@@ -612,6 +1220,10 @@ mod tests {
             sender_host: "test-pc".to_string(),
             msg_type: msg_type::IPMSG_BR_ENTRY,
             content: "".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let bytes = serialize_message(&msg).unwrap();
@@ -622,11 +1234,10 @@ mod tests {
 
     #[test]
     fn test_content_with_colon() {
-        // Content containing ":" should be preserved by joining fields 5+
+        // Content containing ":" is preserved: only the first five ":" delimit fields
         let data = b"1:1:Alice:alice-pc:32:Time: 12:30:45"; // 32 = IPMSG_SENDMSG
         let msg = parse_message(data).unwrap();
 
-        // Fields 5+ are joined with ":"
         assert_eq!(msg.content, "Time: 12:30:45");
 
         // JSON encoding also works with colons
@@ -638,6 +1249,10 @@ mod tests {
             sender_host: "alice-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: json_content.to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let bytes = serialize_message(&msg).unwrap();
@@ -661,6 +1276,10 @@ mod tests {
             sender_host: "alice-pc".to_string(),
             msg_type: msg_type::IPMSG_GETFILEDATA,
             content: serde_json::to_string(&request).unwrap(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let bytes = serialize_message(&msg).unwrap();
@@ -678,12 +1297,39 @@ mod tests {
         let response = FileSendResponse {
             accept: true,
             port: Some(8001),
+            packet_id: Some(42),
+            offset: None,
+            prefix_md5: None,
         };
 
         let content = serde_json::to_string(&response).unwrap();
 
         assert!(content.contains(r#""accept":true"#));
         assert!(content.contains(r#""port":8001"#));
+        assert!(content.contains(r#""packet_id":42"#));
+        assert!(!content.contains("offset"));
+
+        // Replies without the offer's packet ID still parse
+        let old: FileSendResponse = serde_json::from_str(r#"{"accept":true,"port":8001}"#).unwrap();
+        assert_eq!(old.packet_id, None);
+        assert_eq!(old.offset, None);
+    }
+
+    #[test]
+    fn test_serialize_file_response_resume() {
+        let response = FileSendResponse {
+            accept: true,
+            port: Some(8001),
+            packet_id: Some(42),
+            offset: Some(4096),
+            prefix_md5: Some("0123456789abcdef0123456789abcdef".to_string()),
+        };
+
+        let content = serde_json::to_string(&response).unwrap();
+        assert!(content.contains(r#""offset":4096"#));
+
+        let parsed: FileSendResponse = serde_json::from_str(&content).unwrap();
+        assert_eq!(parsed, response);
     }
 
     #[test]
@@ -691,6 +1337,9 @@ mod tests {
         let response = FileSendResponse {
             accept: false,
             port: None,
+            packet_id: None,
+            offset: None,
+            prefix_md5: None,
         };
 
         let content = serde_json::to_string(&response).unwrap();
@@ -736,6 +1385,10 @@ mod tests {
             sender_host: "test-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: "test".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let result = serialize_message(&msg);
@@ -759,6 +1412,10 @@ mod tests {
             sender_host: "test-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: "test".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let result = serialize_message(&msg);
@@ -778,6 +1435,17 @@ mod tests {
         assert_eq!(get_message_type_name(0xFFFFFFFF), "UNKNOWN");
     }
 
+    #[test]
+    fn test_explain_message_type_names_bits_per_mode() {
+        let entry = explain_message_type(msg_type::IPMSG_BR_ENTRY | msg_type::IPMSG_ABSENCEOPT);
+        assert!(entry.contains("ABSENCE"));
+        assert!(!entry.contains("SENDCHECK"));
+
+        let send = explain_message_type(msg_type::IPMSG_SENDMSG | msg_type::IPMSG_SENDCHECKOPT);
+        assert!(send.contains("SENDCHECK"));
+        assert!(!send.contains("ABSENCE"));
+    }
+
     #[test]
     fn test_packet_id_overflow() {
         let msg = ProtocolMessage {
@@ -787,6 +1455,10 @@ mod tests {
             sender_host: "test-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: "test".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let result = serialize_message(&msg);
@@ -804,6 +1476,10 @@ mod tests {
             sender_host: "test-pc".to_string(),
             msg_type: msg_type::IPMSG_SENDMSG,
             content: large_content,
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
         };
 
         let result = serialize_message(&msg);
@@ -840,4 +1516,521 @@ mod tests {
         assert_eq!(msg.sender_name, "Alice");
         assert_eq!(msg.sender_host, "DESKTOP-IOHG15K");
     }
+
+    #[test]
+    fn test_parse_feiq_send_message_keeps_content() {
+        // FeiQ text messages keep their content; the packet ID is the timestamp-based field
+        let data = "1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-IOHG15K:288:你好 #1".as_bytes();
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.packet_id, 1761386707);
+        assert_eq!(msg.sender_name, "cgc");
+        assert_eq!(msg.sender_host, "DESKTOP-IOHG15K");
+        assert_eq!(msg_type::get_mode(msg.msg_type) as u32, msg_type::IPMSG_SENDMSG);
+        assert_eq!(msg.content, "你好 #1");
+    }
+
+    #[test]
+    fn test_feiq_header_parse() {
+        let header = FeiqHeader::parse("1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9").unwrap();
+
+        assert_eq!(header.tag, "lbt4");
+        assert_eq!(header.face_id, 41);
+        assert_eq!(header.flags, 128);
+        assert!(header.has_flag(FeiqHeader::DEFAULT_FLAGS));
+        assert_eq!(header.mac, "24F5AAD7C96A");
+        assert_eq!(header.mac_address(), "24:F5:AA:D7:C9:6A");
+        assert_eq!(header.encode(), "1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9");
+
+        assert!(FeiqHeader::parse("1_lbt4_41#128").is_err());
+        assert!(FeiqHeader::parse("1_lbt4#128#24F5AAD7C96A").is_err());
+        assert!(FeiqHeader::parse("1_lbt4_41#128#not-a-mac").is_err());
+    }
+
+    #[test]
+    fn test_parse_feiq_header_from_packet() {
+        let data = "1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-IOHG15K:288:hi".as_bytes();
+        let msg = parse_message(data).unwrap();
+
+        let header = msg.feiq_header.as_ref().unwrap();
+        assert_eq!(header.face_id, 6);
+        assert_eq!(header.mac, "C81F663237C8");
+
+        // Plain IPMsg packets (even with timestamp packet IDs) carry no header
+        let ipmsg = parse_message(b"1:1761386707:bob:bob-pc:32:hi").unwrap();
+        assert!(ipmsg.feiq_header.is_none());
+    }
+
+    #[test]
+    fn test_feiq_packet_round_trip() {
+        // FeiQ text message in GBK (你好 #1), header re-emitted unchanged
+        let data: &[u8] = b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:1761386707:cgc:DESKTOP-IOHG15K:288:\xc4\xe3\xba\xc3 #1";
+        let msg = parse_message(data).unwrap();
+        assert_eq!(msg.content, "你好 #1");
+
+        assert_eq!(serialize_message(&msg).unwrap(), data);
+    }
+
+    #[test]
+    fn test_serialize_local_feiq_header() {
+        let msg = ProtocolMessage {
+            version: 1,
+            packet_id: 42,
+            sender_name: "张三".to_string(),
+            sender_host: "zhangsan-pc".to_string(),
+            msg_type: msg_type::IPMSG_SENDMSG,
+            content: "你好".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: Some(FeiqHeader::new("0A1B2C3D4E5F".to_string(), 0)),
+            encoding: None,
+        };
+
+        // UTF-8 under a FeiQ header is flagged so FeiQ doesn't read it as GBK
+        let bytes = serialize_message(&msg).unwrap();
+        assert!(bytes.starts_with(b"1_lbt4_0#128#0A1B2C3D4E5F#0#0#0#311c#9:42:"));
+
+        let parsed = parse_message(&bytes).unwrap();
+        assert_eq!(parsed.feiq_header, msg.feiq_header);
+        assert!(msg_type::has_opt(parsed.msg_type, msg_type::IPMSG_UTF8OPT));
+        assert_eq!(parsed.encoding, Some(TextEncoding::Utf8));
+        assert_eq!(parsed.content, "你好");
+    }
+
+    #[test]
+    fn test_attachment_encode_parse_round_trip() {
+        let files = vec![
+            FileAttachment::regular(0, "report.pdf".to_string(), 0x1f400, 0x6789abcd),
+            FileAttachment::regular(1, "a:b.txt".to_string(), 10, 0x65000000),
+        ];
+
+        let section = encode_attachments(&files);
+        assert_eq!(
+            section,
+            "0:report.pdf:1f400:6789abcd:1:\u{07}1:a::b.txt:a:65000000:1:\u{07}"
+        );
+
+        let parsed = parse_attachments(&section).unwrap();
+        assert_eq!(parsed, files);
+    }
+
+    #[test]
+    fn test_parse_attachment_with_extended_attrs() {
+        // IPMsg may append extended attributes (key=value) after attr
+        let entry = "3:photo.jpg:400:5f000000:1:14=5f000001:16=5f000002:";
+        let attachment = FileAttachment::parse(entry).unwrap();
+
+        assert_eq!(attachment.file_id, 3);
+        assert_eq!(attachment.name, "photo.jpg");
+        assert_eq!(attachment.size, 0x400);
+        assert_eq!(attachment.mtime, 0x5f000000);
+        assert!(attachment.is_regular());
+        assert!(!attachment.is_directory());
+    }
+
+    #[test]
+    fn test_parse_invalid_attachment() {
+        assert!(FileAttachment::parse("0:file.txt:zz:0:1:").is_err());
+        assert!(FileAttachment::parse("0:file.txt").is_err());
+        assert!(FileAttachment::parse("x:file.txt:1:0:1:").is_err());
+    }
+
+    #[test]
+    fn test_file_attach_message_round_trip() {
+        let files = vec![FileAttachment::regular(0, "图片 #1.png".to_string(), 2048, 0x6500_0000)];
+        let msg = ProtocolMessage {
+            version: 1,
+            packet_id: 1234,
+            sender_name: "Alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: msg_type::make_command(
+                msg_type::IPMSG_SENDMSG,
+                msg_type::IPMSG_SENDCHECKOPT | msg_type::IPMSG_FILEATTACHOPT,
+            ),
+            content: "see attached".to_string(),
+            extensions: vec![encode_attachments(&files)],
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+
+        let bytes = serialize_message(&msg).unwrap();
+        let parsed = parse_message(&bytes).unwrap();
+
+        assert_eq!(parsed.content, "see attached");
+        assert_eq!(parsed.attachments().unwrap(), files);
+        assert_eq!(parsed.extensions, msg.extensions);
+    }
+
+    #[test]
+    fn test_parse_ipmsg_attachment_packet() {
+        // Attachment-only message as sent by IPMsg/FeiQ: empty text, trailing NUL
+        let data = b"1:100:bob:bob-pc:2097440:\x000:notes.txt:1a:65000000:1:\x07\x00";
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.content, "");
+        assert_eq!(msg.extensions.len(), 1);
+        let files = msg.attachments().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "notes.txt");
+        assert_eq!(files[0].size, 26);
+    }
+
+    #[test]
+    fn test_attachments_without_option() {
+        let msg = ProtocolMessage {
+            version: 1,
+            packet_id: 1,
+            sender_name: "Alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: msg_type::IPMSG_SENDMSG,
+            content: "plain text".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+
+        assert_eq!(msg.content, "plain text");
+        assert!(msg.attachments().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_entry_group_extension() {
+        // IPMsg BR_ENTRY: nickname\0group\0
+        let data = b"1:300:alice:alice-pc:1:Alice Liu\0Engineering\0";
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.sender_name, "alice");
+        assert_eq!(msg.content, "Alice Liu");
+        assert_eq!(msg.extensions, vec!["Engineering".to_string()]);
+        assert_eq!(msg.group_name(), Some("Engineering"));
+
+        // Re-emitted with the same NUL-terminated layout
+        assert_eq!(serialize_message(&msg).unwrap(), data);
+    }
+
+    #[test]
+    fn test_parse_entry_without_group() {
+        let msg = parse_message(b"1:301:bob:bob-pc:1:Bob\0\0").unwrap();
+        assert_eq!(msg.content, "Bob");
+        assert!(msg.extensions.is_empty());
+        assert_eq!(msg.group_name(), None);
+
+        // Group sections only mean something on entry packets
+        let text = parse_message(b"1:302:bob:bob-pc:32:hi\0Engineering\0").unwrap();
+        assert_eq!(text.extension(0), Some("Engineering"));
+        assert_eq!(text.group_name(), None);
+    }
+
+    #[test]
+    fn test_parse_feiq_entry_group_extension() {
+        let data = "1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:6291459:陈俞辛\0研发部\0".as_bytes();
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.sender_name, "陈俞辛");
+        assert!(msg.content.is_empty());
+        assert_eq!(msg.group_name(), Some("研发部"));
+    }
+
+    #[test]
+    fn test_serialize_multiple_extensions() {
+        let msg = ProtocolMessage {
+            version: 1,
+            packet_id: 5,
+            sender_name: "alice".to_string(),
+            sender_host: "alice-pc".to_string(),
+            msg_type: msg_type::IPMSG_SENDMSG,
+            content: "body".to_string(),
+            extensions: vec!["first".to_string(), String::new(), "third".to_string()],
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: None,
+        };
+
+        let bytes = serialize_message(&msg).unwrap();
+        assert_eq!(bytes, b"1:5:alice:alice-pc:32:body\0first\0\0third\0");
+
+        let parsed = parse_message(&bytes).unwrap();
+        assert_eq!(parsed.raw_extensions, b"first\0\0third\0");
+        assert_eq!(ProtocolMessage { raw_extensions: Vec::new(), ..parsed }, msg);
+    }
+
+    #[test]
+    fn test_file_data_request_round_trip() {
+        let request = FileDataRequest {
+            packet_id: 0x6500_1234,
+            file_id: 2,
+            offset: 0x10000,
+        };
+
+        let content = request.encode();
+        assert_eq!(content, "65001234:2:10000:");
+        assert_eq!(FileDataRequest::parse(&content).unwrap(), request);
+    }
+
+    #[test]
+    fn test_parse_ipmsg_getfiledata_packet() {
+        // As sent by IPMsg/FeiQ over TCP to the sender's port 2425
+        let data = b"1:200:bob:bob-pc:96:3e8:0:0:";
+        let msg = parse_message(data).unwrap();
+        assert_eq!(msg_type::get_mode(msg.msg_type) as u32, msg_type::IPMSG_GETFILEDATA);
+
+        let request = FileDataRequest::parse(&msg.content).unwrap();
+        assert_eq!(request.packet_id, 1000);
+        assert_eq!(request.file_id, 0);
+        assert_eq!(request.offset, 0);
+
+        assert!(FileDataRequest::parse("3e8:0").is_err());
+        assert!(FileDataRequest::parse("xyz:0:0:").is_err());
+    }
+
+    #[test]
+    fn test_gbk_packet_round_trip() {
+        // Chinese IPMsg build: 张三 / 你好，世界 in GBK
+        let data: &[u8] = b"1:1767153479:\xd5\xc5\xc8\xfd:zhangsan-pc:288:\xc4\xe3\xba\xc3\xa3\xac\xca\xc0\xbd\xe7";
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.sender_name, "张三");
+        assert_eq!(msg.content, "你好，世界");
+        assert_eq!(msg.encoding, Some(TextEncoding::Gbk));
+
+        // Replying with the learned encoding reproduces the original bytes
+        assert_eq!(serialize_message(&msg).unwrap(), data);
+    }
+
+    #[test]
+    fn test_shift_jis_packet_round_trip() {
+        // Original IPMsg: 山田 / こんにちは in Shift_JIS
+        let data: &[u8] = b"1:100:\x8eR\x93c:yamada-pc:288:\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd";
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.sender_name, "山田");
+        assert_eq!(msg.content, "こんにちは");
+        assert_eq!(msg.encoding, Some(TextEncoding::ShiftJis));
+
+        assert_eq!(serialize_message(&msg).unwrap(), data);
+    }
+
+    #[test]
+    fn test_feiq_packet_is_gbk() {
+        let data: &[u8] = b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:6291459:\xb3\xc2\xd3\xe1\xd0\xc1";
+        let msg = parse_message(data).unwrap();
+        assert_eq!(msg.sender_name, "陈俞辛");
+        assert_eq!(msg.encoding, Some(TextEncoding::Gbk));
+
+        // FeiQ always speaks GBK, even when this packet happens to be ASCII
+        let ascii = parse_message(b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153479:t0250254:DESKTOP-IOHG15K:32:hello").unwrap();
+        assert_eq!(ascii.encoding, Some(TextEncoding::Gbk));
+
+        // Outbound: a Chinese reply is encoded as GBK
+        let reply = ProtocolMessage {
+            version: 1,
+            packet_id: 1767153480,
+            sender_name: "张三".to_string(),
+            sender_host: "zhangsan-pc".to_string(),
+            msg_type: msg_type::IPMSG_SENDMSG,
+            content: "你好，世界".to_string(),
+            extensions: Vec::new(),
+            raw_extensions: Vec::new(),
+            feiq_header: None,
+            encoding: msg.encoding,
+        };
+        assert_eq!(
+            serialize_message(&reply).unwrap(),
+            b"1:1767153480:\xd5\xc5\xc8\xfd:zhangsan-pc:32:\xc4\xe3\xba\xc3\xa3\xac\xca\xc0\xbd\xe7"
+        );
+    }
+
+    #[test]
+    fn test_feiq_caputf8_packet_is_gbk() {
+        // FeiQ BR_ENTRY | CAPUTF8OPT | ENCRYPTOPT | FILEATTACHOPT with its GBK name (陈俞辛)
+        let data: &[u8] = b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153481:t0250254:DESKTOP-IOHG15K:23068673:\xb3\xc2\xd3\xe1\xd0\xc1\0";
+        let msg = parse_message(data).unwrap();
+        assert!(msg.command().has(Opt::CapUtf8));
+        assert_eq!(msg.sender_name, "陈俞辛");
+        assert_eq!(msg.encoding, Some(TextEncoding::Gbk));
+
+        // Its ASCII packets are still GBK
+        let ascii = parse_message(b"1_lbt4_41#128#24F5AAD7C96A#0#0#0#311c#9:1767153482:t0250254:DESKTOP-IOHG15K:16777248:hello").unwrap();
+        assert_eq!(ascii.encoding, Some(TextEncoding::Gbk));
+    }
+
+    #[test]
+    fn test_utf8_option_encoding() {
+        // IPMSG_UTF8OPT | IPMSG_SENDCHECKOPT | IPMSG_SENDMSG
+        let msg = parse_message("1:100:张三:zhangsan-pc:8388896:你好".as_bytes()).unwrap();
+        assert_eq!(msg.encoding, Some(TextEncoding::Utf8));
+        assert_eq!(msg.content, "你好");
+
+        // The capability flag alone says nothing about this packet's bytes
+        let entry = parse_message(b"1:101:alice:alice-pc:16777217:").unwrap();
+        assert_eq!(entry.encoding, None);
+
+        // Plain ASCII IPMsg packet: nothing to learn
+        let ascii = parse_message(b"1:102:bob:bob-pc:32:hello").unwrap();
+        assert_eq!(ascii.encoding, None);
+    }
+
+    #[test]
+    fn test_text_encoding_detect() {
+        assert_eq!(TextEncoding::detect(b"hello"), None);
+        assert_eq!(TextEncoding::detect("你好".as_bytes()), Some(TextEncoding::Utf8));
+        assert_eq!(TextEncoding::detect(b"\xc4\xe3\xba\xc3"), Some(TextEncoding::Gbk));
+        assert_eq!(
+            TextEncoding::detect(b"\x82\xb1\x82\xf1\x82\xc9\x82\xbf\x82\xcd"),
+            Some(TextEncoding::ShiftJis)
+        );
+    }
+
+    #[test]
+    fn test_raw_packet_split() {
+        let data = b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9:42:cgc:host:32:a:b\0ext\0";
+        let raw = RawPacket::split(data).unwrap();
+
+        assert!(raw.is_feiq());
+        assert_eq!(raw.header, b"1_lbt4_6#128#C81F663237C8#0#0#0#311c#9");
+        assert_eq!(raw.packet_id, b"42");
+        assert_eq!(raw.sender_name, b"cgc");
+        assert_eq!(raw.sender_host, b"host");
+        assert_eq!(raw.command, b"32");
+        assert_eq!(raw.body, b"a:b\0ext\0");
+        assert_eq!(raw.content(), b"a:b");
+        assert_eq!(raw.extensions(), b"ext\0");
+        assert_eq!(raw.sections().collect::<Vec<_>>(), vec![&b"ext"[..]]);
+        assert_eq!(raw.mode(), Some(Mode::SendMsg));
+
+        // No NUL: no extensions at all
+        let raw = RawPacket::split(b"1:1:a:b:32:text").unwrap();
+        assert!(!raw.is_feiq());
+        assert_eq!(raw.extensions(), b"");
+        assert_eq!(raw.sections().count(), 0);
+        assert_eq!(RawPacket::split(b"1:1:a:b:x:text").unwrap().mode(), None);
+
+        assert!(RawPacket::split(b"1:1:a:b:32").is_err());
+    }
+
+    #[test]
+    fn test_binary_extension_bytes_preserved() {
+        // Encrypted GBK message (你好) followed by binary data that is neither
+        // valid text nor free of delimiter bytes
+        let mut data = b"1:7:alice:alice-pc:4194336:\xc4\xe3\xba\xc3\0".to_vec();
+        let payload = b"\xff\xfe:#\x80\0\x01\x00\x9f";
+        data.extend_from_slice(payload);
+
+        let msg = parse_message(&data).unwrap();
+        assert_eq!(msg.content, "你好");
+        assert_eq!(msg.encoding, Some(TextEncoding::Gbk));
+        assert_eq!(msg.raw_extensions, payload);
+    }
+
+    #[test]
+    fn test_encoding_detected_from_extensions() {
+        // Attachment-only offer: the only non-ASCII text is the GBK file name (文件.txt)
+        let data = b"1:100:bob:bob-pc:2097440:\x000:\xce\xc4\xbc\xfe.txt:1a:65000000:1:\x07\x00";
+        let msg = parse_message(data).unwrap();
+
+        assert_eq!(msg.encoding, Some(TextEncoding::Gbk));
+        assert_eq!(msg.attachments().unwrap()[0].name, "文件.txt");
+    }
+
+    mod prop {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Modes whose content the parser keeps as-is (FeiQ entry packets move it to sender_name)
+        const MODES: [u32; 6] = [
+            msg_type::IPMSG_NOOPERATION,
+            msg_type::IPMSG_BR_EXIT,
+            msg_type::IPMSG_SENDMSG,
+            msg_type::IPMSG_RECVMSG,
+            msg_type::IPMSG_GETINFO,
+            msg_type::IPMSG_RELEASEFILES,
+        ];
+
+        fn arb_feiq_header() -> impl Strategy<Value = Option<FeiqHeader>> {
+            proptest::option::of(
+                ("[0-9A-F]{12}", 0u32..100).prop_map(|(mac, face_id)| FeiqHeader::new(mac, face_id)),
+            )
+        }
+
+        fn arb_message() -> impl Strategy<Value = ProtocolMessage> {
+            (
+                0..=MAX_PACKET_ID,
+                "[^:\0]{1,16}",
+                "[^:\0]{1,16}",
+                proptest::sample::select(MODES.to_vec()),
+                any::<u32>(),
+                "[^\0]{0,64}",
+                proptest::collection::vec("[^\0]{0,16}", 0..4),
+                arb_feiq_header(),
+            )
+                .prop_map(
+                    |(packet_id, sender_name, sender_host, mode, opts, content, mut extensions, feiq_header)| {
+                        // Trailing empty sections are indistinguishable from NUL terminators
+                        while extensions.last().is_some_and(String::is_empty) {
+                            extensions.pop();
+                        }
+
+                        ProtocolMessage {
+                            version: PROTOCOL_VERSION,
+                            packet_id,
+                            sender_name,
+                            sender_host,
+                            msg_type: msg_type::make_command(mode, opts),
+                            content,
+                            extensions,
+                            raw_extensions: Vec::new(),
+                            feiq_header,
+                            encoding: None,
+                        }
+                    },
+                )
+        }
+
+        proptest! {
+            #[test]
+            fn prop_serialize_parse_round_trip(msg in arb_message()) {
+                let bytes = serialize_message(&msg).unwrap();
+                let parsed = parse_message(&bytes).unwrap();
+
+                prop_assert_eq!(parsed.version, msg.version);
+                prop_assert_eq!(parsed.packet_id, msg.packet_id);
+                prop_assert_eq!(&parsed.sender_name, &msg.sender_name);
+                prop_assert_eq!(&parsed.sender_host, &msg.sender_host);
+                // UTF-8 under a FeiQ header gets IPMSG_UTF8OPT on the wire
+                prop_assert_eq!(parsed.msg_type & !msg_type::IPMSG_UTF8OPT, msg.msg_type & !msg_type::IPMSG_UTF8OPT);
+                prop_assert_eq!(&parsed.content, &msg.content);
+                prop_assert_eq!(&parsed.extensions, &msg.extensions);
+                prop_assert_eq!(&parsed.feiq_header, &msg.feiq_header);
+
+                // The raw extension bytes are exactly what followed the content on the wire
+                let content_end = bytes.len() - parsed.raw_extensions.len();
+                prop_assert_eq!(&bytes[content_end..], &parsed.raw_extensions[..]);
+
+                // And the parsed message serializes back to the same bytes
+                prop_assert_eq!(serialize_message(&parsed).unwrap(), bytes);
+            }
+
+            #[test]
+            fn prop_binary_extensions_untouched(
+                content in "[ -9;-~]{0,32}",
+                payload in proptest::collection::vec(any::<u8>(), 0..256),
+            ) {
+                let mut data = format!(
+                    "1:1:alice:alice-pc:{}:{}",
+                    msg_type::IPMSG_SENDMSG | msg_type::IPMSG_ENCRYPTOPT,
+                    content
+                )
+                .into_bytes();
+                data.push(0);
+                data.extend_from_slice(&payload);
+
+                let msg = parse_message(&data).unwrap();
+                prop_assert_eq!(&msg.content, &content);
+                prop_assert_eq!(&msg.raw_extensions, &payload);
+                prop_assert_eq!(msg.encoding, None);
+            }
+        }
+    }
 }
@@ -0,0 +1,457 @@
+// Network statistics - counters of what the transports are doing
+//
+// The UDP transport counts every datagram (per IPMsg mode) it sends and
+// receives, the TCP file transfer paths register their connections while
+// they stream, and the dispatch paths report packets that don't parse and
+// how long receipts take. Counters are process-wide, like packet IDs: every
+// subsystem shares the one socket and the one listener, so there is nothing
+// finer to attribute them to. `snapshot` turns them into what the frontend's
+// network status card shows.
+
+use super::capture::Direction;
+use super::flood::FloodStats;
+use super::protocol::RawPacket;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Peers whose parse failures are counted individually (later ones only add to the total)
+const MAX_TRACKED_PEERS: usize = 1024;
+
+/// Label of datagrams whose command field isn't a number
+const UNPARSEABLE_MODE: &str = "UNPARSEABLE";
+
+/// Packets and bytes in each direction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Traffic {
+    pub packets_in: u64,
+    pub packets_out: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Traffic {
+    fn record(&mut self, direction: Direction, bytes: usize) {
+        match direction {
+            Direction::In => {
+                self.packets_in += 1;
+                self.bytes_in += bytes as u64;
+            }
+            Direction::Out => {
+                self.packets_out += 1;
+                self.bytes_out += bytes as u64;
+            }
+        }
+    }
+
+    fn add(&mut self, other: &Traffic) {
+        self.packets_in += other.packets_in;
+        self.packets_out += other.packets_out;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
+
+/// UDP traffic of one IPMsg mode
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModeTraffic {
+    /// Mode name (`IPMSG_SENDMSG`, ...)
+    pub mode: String,
+
+    #[serde(flatten)]
+    pub traffic: Traffic,
+}
+
+/// Packets from one peer that failed to parse
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerParseFailures {
+    pub ip: String,
+    pub count: u64,
+}
+
+/// Round-trip times of acknowledged messages (send to IPMSG_RECVMSG)
+///
+/// Only messages acknowledged on their first attempt are sampled: a receipt
+/// after a retransmission can't tell which send it answers.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RttStats {
+    pub samples: u64,
+    pub last_ms: Option<f64>,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+/// One TCP connection transferring file data
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TcpConnectionStats {
+    pub id: u64,
+
+    /// Remote address (if the socket could tell)
+    pub peer: Option<String>,
+
+    /// `out` when we send the file, `in` when we receive it
+    pub direction: Direction,
+
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+
+    /// Time since the connection was registered
+    pub duration_ms: u64,
+
+    /// Average bytes per second in both directions since then
+    pub throughput_bps: f64,
+}
+
+/// TCP file transfer activity
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TcpStats {
+    /// Connections transferring right now
+    pub active: Vec<TcpConnectionStats>,
+
+    /// Connections registered since startup (active ones included)
+    pub connections_total: u64,
+
+    /// Bytes of all connections since startup
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Point-in-time view of the network counters
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkStatsSnapshot {
+    /// Seconds since the counters started
+    pub uptime_secs: u64,
+
+    /// UDP datagrams of all modes
+    pub udp: Traffic,
+
+    /// UDP datagrams per IPMsg mode, busiest first
+    pub by_mode: Vec<ModeTraffic>,
+
+    /// Packets that failed to parse (all peers)
+    pub parse_failures_total: u64,
+
+    /// Packets that failed to parse, per peer, most first
+    pub parse_failures: Vec<PeerParseFailures>,
+
+    pub ack_rtt: RttStats,
+
+    pub tcp: TcpStats,
+
+    /// Receive path rate limiting (filled in by the discovery service's guard)
+    pub flood: FloodStats,
+}
+
+/// Byte counters of a registered TCP connection
+struct TcpCounters {
+    peer: Option<SocketAddr>,
+    direction: Direction,
+    opened: Instant,
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Counters of [`NetworkStats`] behind its lock
+#[derive(Default)]
+struct Counters {
+    by_mode: HashMap<&'static str, Traffic>,
+    parse_failures: HashMap<IpAddr, u64>,
+    parse_failures_total: u64,
+    rtt_samples: u64,
+    rtt_total: Duration,
+    rtt_last: Option<Duration>,
+    rtt_min: Option<Duration>,
+    rtt_max: Option<Duration>,
+    tcp_active: HashMap<u64, Arc<TcpCounters>>,
+    tcp_connections_total: u64,
+    tcp_closed_sent: u64,
+    tcp_closed_received: u64,
+}
+
+/// Network counters
+pub struct NetworkStats {
+    started: Instant,
+    next_connection_id: AtomicU64,
+    counters: Mutex<Counters>,
+}
+
+impl NetworkStats {
+    /// Create zeroed counters
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            next_connection_id: AtomicU64::new(1),
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    /// Get the process-wide counters
+    pub fn global() -> &'static NetworkStats {
+        static GLOBAL: OnceLock<NetworkStats> = OnceLock::new();
+        GLOBAL.get_or_init(NetworkStats::new)
+    }
+
+    /// Count a UDP datagram sent or received
+    ///
+    /// The mode is read from the command field without parsing the rest.
+    pub fn record_udp(&self, direction: Direction, data: &[u8]) {
+        let mode = match RawPacket::split(data).ok().and_then(|raw| raw.mode()) {
+            Some(mode) => mode.name(),
+            None => UNPARSEABLE_MODE,
+        };
+        self.lock()
+            .by_mode
+            .entry(mode)
+            .or_default()
+            .record(direction, data.len());
+    }
+
+    /// Count a packet from `ip` that failed to parse
+    pub fn record_parse_failure(&self, ip: IpAddr) {
+        let mut counters = self.lock();
+        counters.parse_failures_total += 1;
+        let ip = super::addr::canonical_ip(ip);
+        if counters.parse_failures.len() < MAX_TRACKED_PEERS || counters.parse_failures.contains_key(&ip) {
+            *counters.parse_failures.entry(ip).or_default() += 1;
+        }
+    }
+
+    /// Record the time from sending a message to receiving its IPMSG_RECVMSG
+    pub fn record_ack_rtt(&self, rtt: Duration) {
+        let mut counters = self.lock();
+        counters.rtt_samples += 1;
+        counters.rtt_total += rtt;
+        counters.rtt_last = Some(rtt);
+        counters.rtt_min = Some(counters.rtt_min.map_or(rtt, |min| min.min(rtt)));
+        counters.rtt_max = Some(counters.rtt_max.map_or(rtt, |max| max.max(rtt)));
+    }
+
+    /// Register a TCP connection that starts transferring
+    ///
+    /// The connection counts as active until the returned handle is dropped.
+    pub fn open_tcp(&self, peer: Option<SocketAddr>, direction: Direction) -> TcpConnection<'_> {
+        let id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let counters = Arc::new(TcpCounters {
+            peer: peer.map(super::addr::canonical_addr),
+            direction,
+            opened: Instant::now(),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+        });
+
+        let mut stats = self.lock();
+        stats.tcp_active.insert(id, Arc::clone(&counters));
+        stats.tcp_connections_total += 1;
+
+        TcpConnection {
+            stats: self,
+            id,
+            counters,
+        }
+    }
+
+    /// Get the current counters
+    pub fn snapshot(&self, now: Instant) -> NetworkStatsSnapshot {
+        let counters = self.lock();
+
+        let mut udp = Traffic::default();
+        let mut by_mode: Vec<ModeTraffic> = counters
+            .by_mode
+            .iter()
+            .map(|(mode, traffic)| {
+                udp.add(traffic);
+                ModeTraffic {
+                    mode: mode.to_string(),
+                    traffic: *traffic,
+                }
+            })
+            .collect();
+        by_mode.sort_by(|a, b| {
+            let total = |t: &Traffic| t.packets_in + t.packets_out;
+            total(&b.traffic).cmp(&total(&a.traffic)).then_with(|| a.mode.cmp(&b.mode))
+        });
+
+        let mut parse_failures: Vec<PeerParseFailures> = counters
+            .parse_failures
+            .iter()
+            .map(|(ip, count)| PeerParseFailures {
+                ip: ip.to_string(),
+                count: *count,
+            })
+            .collect();
+        parse_failures.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.ip.cmp(&b.ip)));
+
+        let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+        let ack_rtt = RttStats {
+            samples: counters.rtt_samples,
+            last_ms: counters.rtt_last.map(millis),
+            min_ms: counters.rtt_min.map(millis),
+            avg_ms: (counters.rtt_samples > 0)
+                .then(|| millis(counters.rtt_total) / counters.rtt_samples as f64),
+            max_ms: counters.rtt_max.map(millis),
+        };
+
+        let mut tcp = TcpStats {
+            active: Vec::with_capacity(counters.tcp_active.len()),
+            connections_total: counters.tcp_connections_total,
+            bytes_sent: counters.tcp_closed_sent,
+            bytes_received: counters.tcp_closed_received,
+        };
+        for (id, connection) in &counters.tcp_active {
+            let sent = connection.sent.load(Ordering::Relaxed);
+            let received = connection.received.load(Ordering::Relaxed);
+            let elapsed = now.saturating_duration_since(connection.opened);
+            tcp.bytes_sent += sent;
+            tcp.bytes_received += received;
+            tcp.active.push(TcpConnectionStats {
+                id: *id,
+                peer: connection.peer.map(|peer| peer.to_string()),
+                direction: connection.direction,
+                bytes_sent: sent,
+                bytes_received: received,
+                duration_ms: elapsed.as_millis() as u64,
+                throughput_bps: if elapsed.is_zero() {
+                    0.0
+                } else {
+                    (sent + received) as f64 / elapsed.as_secs_f64()
+                },
+            });
+        }
+        tcp.active.sort_by_key(|connection| connection.id);
+
+        NetworkStatsSnapshot {
+            uptime_secs: now.saturating_duration_since(self.started).as_secs(),
+            udp,
+            by_mode,
+            parse_failures_total: counters.parse_failures_total,
+            parse_failures,
+            ack_rtt,
+            tcp,
+            flood: FloodStats::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for NetworkStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle of a registered TCP connection (unregistered when dropped)
+pub struct TcpConnection<'a> {
+    stats: &'a NetworkStats,
+    id: u64,
+    counters: Arc<TcpCounters>,
+}
+
+impl TcpConnection<'_> {
+    /// Count bytes written to the connection
+    pub fn record_sent(&self, bytes: usize) {
+        self.counters.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count bytes read from the connection
+    pub fn record_received(&self, bytes: usize) {
+        self.counters.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl Drop for TcpConnection<'_> {
+    fn drop(&mut self) {
+        let mut counters = self.stats.lock();
+        counters.tcp_active.remove(&self.id);
+        counters.tcp_closed_sent += self.counters.sent.load(Ordering::Relaxed);
+        counters.tcp_closed_received += self.counters.received.load(Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udp_traffic_per_mode() {
+        let stats = NetworkStats::new();
+        stats.record_udp(Direction::Out, b"1:1:alice:pc:32:hello");
+        stats.record_udp(Direction::Out, b"1:2:alice:pc:288:hello");
+        stats.record_udp(Direction::In, b"1:3:bob:pc:33:1");
+        stats.record_udp(Direction::In, b"garbage");
+
+        let snapshot = stats.snapshot(Instant::now());
+        assert_eq!(snapshot.udp.packets_out, 2);
+        assert_eq!(snapshot.udp.packets_in, 2);
+        assert_eq!(snapshot.udp.bytes_in, 15 + 7);
+
+        // SENDMSG with options counts as SENDMSG
+        assert_eq!(snapshot.by_mode[0].mode, "IPMSG_SENDMSG");
+        assert_eq!(snapshot.by_mode[0].traffic.packets_out, 2);
+        let modes: Vec<&str> = snapshot.by_mode.iter().map(|m| m.mode.as_str()).collect();
+        assert!(modes.contains(&"IPMSG_RECVMSG"));
+        assert!(modes.contains(&UNPARSEABLE_MODE));
+    }
+
+    #[test]
+    fn test_parse_failures_and_rtt() {
+        let stats = NetworkStats::new();
+        let noisy: IpAddr = "192.168.1.9".parse().unwrap();
+        stats.record_parse_failure(noisy);
+        stats.record_parse_failure(noisy);
+        stats.record_parse_failure("192.168.1.10".parse().unwrap());
+
+        stats.record_ack_rtt(Duration::from_millis(10));
+        stats.record_ack_rtt(Duration::from_millis(30));
+
+        let snapshot = stats.snapshot(Instant::now());
+        assert_eq!(snapshot.parse_failures_total, 3);
+        assert_eq!(
+            snapshot.parse_failures[0],
+            PeerParseFailures { ip: "192.168.1.9".to_string(), count: 2 }
+        );
+        assert_eq!(snapshot.ack_rtt.samples, 2);
+        assert_eq!(snapshot.ack_rtt.min_ms, Some(10.0));
+        assert_eq!(snapshot.ack_rtt.avg_ms, Some(20.0));
+        assert_eq!(snapshot.ack_rtt.max_ms, Some(30.0));
+        assert_eq!(snapshot.ack_rtt.last_ms, Some(30.0));
+    }
+
+    #[test]
+    fn test_tcp_connections() {
+        let stats = NetworkStats::new();
+        let peer: SocketAddr = "192.168.1.9:2425".parse().unwrap();
+
+        let connection = stats.open_tcp(Some(peer), Direction::Out);
+        connection.record_sent(4096);
+        connection.record_received(100);
+
+        let snapshot = stats.snapshot(Instant::now() + Duration::from_secs(2));
+        assert_eq!(snapshot.tcp.active.len(), 1);
+        let active = &snapshot.tcp.active[0];
+        assert_eq!(active.peer.as_deref(), Some("192.168.1.9:2425"));
+        assert_eq!(active.bytes_sent, 4096);
+        assert!(active.throughput_bps > 0.0);
+
+        // Closed connections leave the active list but stay in the totals
+        drop(connection);
+        let snapshot = stats.snapshot(Instant::now());
+        assert!(snapshot.tcp.active.is_empty());
+        assert_eq!(snapshot.tcp.connections_total, 1);
+        assert_eq!(snapshot.tcp.bytes_sent, 4096);
+        assert_eq!(snapshot.tcp.bytes_received, 100);
+    }
+}
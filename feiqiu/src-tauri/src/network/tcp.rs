@@ -5,12 +5,21 @@
 // - Connecting to peers for data transfer
 // - Sending file data in chunks
 // - Receiving file data in chunks
+// - Pausing and cancelling running transfers (`TransferControl`)
+// - Registering transfers as active connections (see `stats`)
+//
+// Listeners are dual-stack (see `addr`), so IPv4 and IPv6 peers connect to
+// the same port.
 
+use super::addr;
+use super::capture::Direction;
+use super::stats::NetworkStats;
 use crate::{NeoLanError, Result};
 use crate::config::AppConfig;
-use std::io::{Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 /// Default buffer size for file transfer (re-exported from AppConfig)
 pub const DEFAULT_BUFFER_SIZE: usize = AppConfig::TCP_BUFFER_SIZE;
@@ -19,9 +28,118 @@ pub const DEFAULT_BUFFER_SIZE: usize = AppConfig::TCP_BUFFER_SIZE;
 pub const PORT_RANGE_START: u16 = AppConfig::DEFAULT_TCP_PORT_START;
 pub const PORT_RANGE_END: u16 = AppConfig::DEFAULT_TCP_PORT_END;
 
-/// Default bind IP address (re-exported from AppConfig)
+/// Default bind IP address for IPv4-only hosts (re-exported from AppConfig)
 pub const DEFAULT_BIND_IP: &str = AppConfig::DEFAULT_BIND_IP;
 
+/// What a running transfer is told to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferSignal {
+    /// Keep transferring
+    Run,
+
+    /// Stop, to continue later from the offset reached; the transfer fails
+    /// with a "paused" error
+    Pause,
+
+    /// Stop; the transfer fails with a "cancelled" error
+    Cancel,
+}
+
+/// Signal of a transfer and the connection it runs on
+#[derive(Debug)]
+struct ControlState {
+    signal: TransferSignal,
+    stream: Option<TcpStream>,
+}
+
+/// Control handle of a running transfer
+///
+/// Clones share the signal: the transfer loop checks it between chunks,
+/// whoever pauses or cancels the transfer sets it. Stopping also shuts the
+/// attached connection down, so a loop blocked on the socket ends at once
+/// (a pause doesn't keep the connection open: the peer would time it out).
+/// Cancelling is final.
+#[derive(Debug, Clone)]
+pub struct TransferControl {
+    state: Arc<Mutex<ControlState>>,
+}
+
+impl TransferControl {
+    /// Create a handle in the given state
+    pub fn new(signal: TransferSignal) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ControlState { signal, stream: None })),
+        }
+    }
+
+    /// Current signal
+    pub fn signal(&self) -> TransferSignal {
+        self.lock().signal
+    }
+
+    /// Stop the transfer to continue it later (unless it was cancelled)
+    pub fn pause(&self) {
+        self.stop(TransferSignal::Pause);
+    }
+
+    /// Stop the transfer for good
+    pub fn cancel(&self) {
+        self.stop(TransferSignal::Cancel);
+    }
+
+    /// Attach the connection the transfer runs on (replacing an earlier one)
+    ///
+    /// It is shut down when the transfer is stopped, at once if it was
+    /// stopped already.
+    pub fn attach(&self, stream: &TcpStream) -> Result<()> {
+        let stream = stream.try_clone().map_err(NeoLanError::Network)?;
+        let mut state = self.lock();
+        if state.signal != TransferSignal::Run {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        state.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Check whether the transfer may go on (called between chunks)
+    ///
+    /// # Returns
+    /// * `Ok(())` - Transfer may go on
+    /// * `Err(NeoLanError)` - Transfer was paused or cancelled
+    pub fn checkpoint(&self) -> Result<()> {
+        match self.signal() {
+            TransferSignal::Run => Ok(()),
+            TransferSignal::Pause => Err(NeoLanError::FileTransfer("Transfer paused".to_string())),
+            TransferSignal::Cancel => Err(NeoLanError::FileTransfer("Transfer cancelled".to_string())),
+        }
+    }
+
+    /// Whether `other` is a clone of this handle
+    pub fn is_same(&self, other: &TransferControl) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+
+    fn stop(&self, signal: TransferSignal) {
+        let mut state = self.lock();
+        if state.signal != TransferSignal::Cancel {
+            state.signal = signal;
+        }
+        if let Some(stream) = &state.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TransferControl {
+    fn default() -> Self {
+        Self::new(TransferSignal::Run)
+    }
+}
+
 /// TCP transport wrapper
 ///
 /// Provides a high-level interface for TCP socket operations.
@@ -31,6 +149,9 @@ pub struct TcpTransport;
 impl TcpTransport {
     /// Bind to an available port in the configured range
     ///
+    /// The listener accepts both IPv4 and IPv6 connections where the host
+    /// supports it.
+    ///
     /// # Returns
     /// * `Ok((TcpListener, u16))` - Successfully bound listener and port number
     /// * `Err(NeoLanError)` - Binding failed
@@ -45,8 +166,7 @@ impl TcpTransport {
     /// ```
     pub fn bind_available() -> Result<(TcpListener, u16)> {
         for port in PORT_RANGE_START..PORT_RANGE_END {
-            let addr = format!("{}:{}", DEFAULT_BIND_IP, port);
-            match TcpListener::bind(&addr) {
+            match addr::bind_tcp(port) {
                 Ok(listener) => {
                     tracing::info!("TCP listener bound to port {}", port);
                     return Ok((listener, port));
@@ -64,10 +184,29 @@ impl TcpTransport {
         )))
     }
 
+    /// Bind a TCP listener to a specific port
+    ///
+    /// Used for the IPMsg file data listener, which listens on the same port
+    /// number as the UDP socket (2425 by default).
+    ///
+    /// # Arguments
+    /// * `port` - Port to bind
+    ///
+    /// # Returns
+    /// * `Ok(TcpListener)` - Successfully bound listener
+    /// * `Err(NeoLanError)` - Binding failed
+    pub fn bind(port: u16) -> Result<TcpListener> {
+        let listener = addr::bind_tcp(port)?;
+
+        tracing::info!("TCP listener bound to port {}", port);
+
+        Ok(listener)
+    }
+
     /// Connect to a remote peer
     ///
     /// # Arguments
-    /// * `addr` - Target socket address
+    /// * `addr` - Target socket address (IPv6 link-local targets need their scope ID)
     ///
     /// # Returns
     /// * `Ok(TcpStream)` - Successfully connected stream
@@ -83,6 +222,7 @@ impl TcpTransport {
     /// # Ok::<(), NeoLanError>(())
     /// ```
     pub fn connect(addr: SocketAddr) -> Result<TcpStream> {
+        let addr = addr::canonical_addr(addr);
         tracing::debug!("Connecting to TCP peer: {}", addr);
 
         let stream = TcpStream::connect(addr).map_err(NeoLanError::Network)?;
@@ -109,12 +249,40 @@ impl TcpTransport {
     /// 3. Send each chunk over TCP
     /// 4. Update progress if callback provided
     pub fn send_file<F>(
+        stream: TcpStream,
+        path: &Path,
+        progress_callback: Option<F>,
+    ) -> Result<u64>
+    where
+        F: FnMut(u64, u64), // (sent_bytes, total_bytes)
+    {
+        Self::send_file_from_offset(stream, path, 0, None, progress_callback)
+    }
+
+    /// Send a file over TCP stream, starting at a byte offset
+    ///
+    /// # Arguments
+    /// * `stream` - TCP stream to send data over
+    /// * `path` - Path to the file to send
+    /// * `offset` - Byte offset to start from (IPMsg GETFILEDATA offset)
+    /// * `control` - Optional control handle, checked before each chunk
+    /// * `progress_callback` - Optional callback for progress updates
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - Number of bytes sent (excluding the skipped offset)
+    /// * `Err(NeoLanError)` - Send failed, or was cancelled
+    ///
+    /// # Note
+    /// The progress callback reports absolute positions: `(offset + sent, file_size)`.
+    pub fn send_file_from_offset<F>(
         mut stream: TcpStream,
         path: &Path,
+        offset: u64,
+        control: Option<&TransferControl>,
         mut progress_callback: Option<F>,
     ) -> Result<u64>
     where
-        F: FnMut(u64, u64), // (sent_bytes, total_bytes)
+        F: FnMut(u64, u64), // (position, total_bytes)
     {
         // Open file
         let mut file = std::fs::File::open(path).map_err(|e| {
@@ -129,17 +297,38 @@ impl TcpTransport {
             ))
         })?.len();
 
+        if offset > file_size {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Offset {} is beyond end of file {} ({} bytes)",
+                offset,
+                path.display(),
+                file_size
+            )));
+        }
+
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).map_err(|e| {
+                NeoLanError::FileTransfer(format!("Failed to seek to offset {}: {}", offset, e))
+            })?;
+        }
+
         tracing::info!(
-            "Sending file {} ({} bytes) via TCP",
+            "Sending file {} ({} bytes, from offset {}) via TCP",
             path.display(),
-            file_size
+            file_size,
+            offset
         );
 
+        let connection = NetworkStats::global().open_tcp(stream.peer_addr().ok(), Direction::Out);
         let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
         let mut total_sent = 0u64;
 
         // Read and send file in chunks
         loop {
+            if let Some(control) = control {
+                control.checkpoint()?;
+            }
+
             let n = file.read(&mut buffer).map_err(|e| {
                 NeoLanError::FileTransfer(format!("Failed to read file: {}", e))
             })?;
@@ -153,18 +342,19 @@ impl TcpTransport {
                 NeoLanError::FileTransfer(format!("Failed to send file data: {}", e))
             })?;
 
+            connection.record_sent(n);
             total_sent += n as u64;
 
             // Update progress
             if let Some(ref mut callback) = progress_callback {
-                callback(total_sent, file_size);
+                callback(offset + total_sent, file_size);
             }
 
             tracing::trace!(
                 "Sent {}/{} bytes ({}%)",
-                total_sent,
+                offset + total_sent,
                 file_size,
-                (total_sent as f64 / file_size as f64 * 100.0) as u32
+                ((offset + total_sent) as f64 / file_size as f64 * 100.0) as u32
             );
         }
 
@@ -200,30 +390,91 @@ impl TcpTransport {
     /// 4. Update progress if callback provided
     /// 5. Validate received size matches expected size
     pub fn receive_file<F>(
+        stream: TcpStream,
+        path: &Path,
+        expected_size: u64,
+        progress_callback: Option<F>,
+    ) -> Result<u64>
+    where
+        F: FnMut(u64, u64), // (received_bytes, total_bytes)
+    {
+        Self::receive_file_from_offset(stream, path, 0, expected_size, None, progress_callback)
+    }
+
+    /// Receive the rest of a partially received file over TCP stream
+    ///
+    /// # Arguments
+    /// * `stream` - TCP stream to receive data from (starting at `offset`)
+    /// * `path` - Path of the file (created if missing)
+    /// * `offset` - Bytes of the file kept; anything after them is discarded
+    /// * `expected_size` - Expected file size (for progress and validation)
+    /// * `control` - Optional control handle, checked before each chunk
+    /// * `progress_callback` - Optional callback for progress updates
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - Number of bytes received (excluding the kept offset)
+    /// * `Err(NeoLanError)` - Receive failed or was cancelled, or the file is
+    ///   shorter than `offset`
+    ///
+    /// # Note
+    /// The progress callback reports absolute positions: `(offset + received, expected_size)`.
+    pub fn receive_file_from_offset<F>(
         mut stream: TcpStream,
         path: &Path,
+        offset: u64,
         expected_size: u64,
+        control: Option<&TransferControl>,
         mut progress_callback: Option<F>,
     ) -> Result<u64>
     where
-        F: FnMut(u64, u64), // (received_bytes, total_bytes)
+        F: FnMut(u64, u64), // (position, total_bytes)
     {
-        // Create output file
-        let mut file = std::fs::File::create(path).map_err(|e| {
-            NeoLanError::FileTransfer(format!("Failed to create file {}: {}", path.display(), e))
-        })?;
+        // Open output file, keeping the first `offset` bytes
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| {
+                NeoLanError::FileTransfer(format!("Failed to create file {}: {}", path.display(), e))
+            })?;
+
+        let existing = file.metadata().map_err(|e| {
+            NeoLanError::FileTransfer(format!("Failed to get file metadata: {}", e))
+        })?.len();
+
+        if offset > existing {
+            return Err(NeoLanError::FileTransfer(format!(
+                "Offset {} is beyond end of file {} ({} bytes)",
+                offset,
+                path.display(),
+                existing
+            )));
+        }
+
+        file.set_len(offset)
+            .and_then(|_| file.seek(SeekFrom::Start(offset)))
+            .map_err(|e| {
+                NeoLanError::FileTransfer(format!("Failed to seek to offset {}: {}", offset, e))
+            })?;
 
         tracing::info!(
-            "Receiving file {} (expected {} bytes) via TCP",
+            "Receiving file {} (expected {} bytes, from offset {}) via TCP",
             path.display(),
-            expected_size
+            expected_size,
+            offset
         );
 
+        let connection = NetworkStats::global().open_tcp(stream.peer_addr().ok(), Direction::In);
         let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
         let mut total_received = 0u64;
 
-        // Read and write file in chunks
-        loop {
+        // Read and write file in chunks, until all data is there
+        while expected_size == 0 || offset + total_received < expected_size {
+            if let Some(control) = control {
+                control.checkpoint()?;
+            }
+
             let n = stream.read(&mut buffer).map_err(|e| {
                 NeoLanError::FileTransfer(format!("Failed to read from stream: {}", e))
             })?;
@@ -231,6 +482,7 @@ impl TcpTransport {
             if n == 0 {
                 break; // Connection closed
             }
+            connection.record_received(n);
 
             // Write chunk to file
             file.write_all(&buffer[..n]).map_err(|e| {
@@ -241,20 +493,15 @@ impl TcpTransport {
 
             // Update progress
             if let Some(ref mut callback) = progress_callback {
-                callback(total_received, expected_size);
+                callback(offset + total_received, expected_size);
             }
 
             tracing::trace!(
                 "Received {}/{} bytes ({}%)",
-                total_received,
+                offset + total_received,
                 expected_size,
-                (total_received as f64 / expected_size as f64 * 100.0) as u32
+                ((offset + total_received) as f64 / expected_size as f64 * 100.0) as u32
             );
-
-            // Check if we've received all data
-            if expected_size > 0 && total_received >= expected_size {
-                break;
-            }
         }
 
         // Flush file
@@ -359,6 +606,22 @@ mod tests {
         thread::sleep(Duration::from_millis(100));
     }
 
+    #[test]
+    fn test_connect_over_ipv6() {
+        let (listener, port) = TcpTransport::bind_available().unwrap();
+        if !listener.local_addr().unwrap().is_ipv6() {
+            return; // Host without IPv6
+        }
+
+        thread::spawn(move || {
+            let _stream = listener.incoming().next().unwrap().unwrap();
+        });
+
+        let addr = SocketAddr::new(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST), port);
+        let stream = TcpTransport::connect(addr).unwrap();
+        assert!(stream.peer_addr().unwrap().is_ipv6());
+    }
+
     #[test]
     fn test_send_and_receive_file() {
         // Create a test file
@@ -494,6 +757,132 @@ mod tests {
         std::fs::remove_file(&output_file).unwrap();
     }
 
+    #[test]
+    fn test_send_file_from_offset() {
+        let test_file = std::env::temp_dir().join("test_send_offset.txt");
+        let output_file = std::env::temp_dir().join("test_send_offset_received.txt");
+
+        let test_data = b"0123456789abcdef";
+        std::fs::write(&test_file, test_data).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let output_file_clone = output_file.clone();
+        let server = thread::spawn(move || {
+            let stream = listener.incoming().next().unwrap().unwrap();
+            TcpTransport::receive_file::<fn(u64, u64)>(stream, &output_file_clone, 6, None).unwrap()
+        });
+
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+        let stream = TcpTransport::connect(addr).unwrap();
+
+        let mut last_position = 0;
+        let sent = TcpTransport::send_file_from_offset(stream, &test_file, 10, None, Some(|pos, _| {
+            last_position = pos;
+        }))
+        .unwrap();
+
+        assert_eq!(sent, 6);
+        assert_eq!(last_position, 16);
+        assert_eq!(server.join().unwrap(), 6);
+        assert_eq!(std::fs::read(&output_file).unwrap(), b"abcdef");
+
+        // Offset beyond the end of the file is rejected
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = TcpTransport::connect(addr).unwrap();
+        assert!(TcpTransport::send_file_from_offset::<fn(u64, u64)>(stream, &test_file, 17, None, None).is_err());
+
+        std::fs::remove_file(&test_file).unwrap();
+        std::fs::remove_file(&output_file).unwrap();
+    }
+
+    #[test]
+    fn test_receive_file_from_offset() {
+        let test_file = std::env::temp_dir().join("test_resume_source.txt");
+        let output_file = std::env::temp_dir().join("test_resume_partial.txt");
+
+        let test_data = b"0123456789abcdef";
+        std::fs::write(&test_file, test_data).unwrap();
+        // Partial copy, with a torn write after the kept part
+        std::fs::write(&output_file, b"0123456789XX").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let test_file_clone = test_file.clone();
+        let sender = thread::spawn(move || {
+            let stream = listener.incoming().next().unwrap().unwrap();
+            TcpTransport::send_file_from_offset::<fn(u64, u64)>(stream, &test_file_clone, 10, None, None).unwrap()
+        });
+
+        let stream = TcpTransport::connect(addr).unwrap();
+        let mut last_position = 0;
+        let received = TcpTransport::receive_file_from_offset(stream, &output_file, 10, 16, None, Some(|pos, _| {
+            last_position = pos;
+        }))
+        .unwrap();
+
+        assert_eq!(received, 6);
+        assert_eq!(last_position, 16);
+        assert_eq!(sender.join().unwrap(), 6);
+        assert_eq!(std::fs::read(&output_file).unwrap(), test_data);
+
+        // The file must hold the kept part
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(TcpTransport::receive_file_from_offset::<fn(u64, u64)>(stream, &output_file, 17, 20, None, None).is_err());
+
+        std::fs::remove_file(&test_file).unwrap();
+        std::fs::remove_file(&output_file).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_control() {
+        let test_file = std::env::temp_dir().join("test_send_stopped.bin");
+        std::fs::write(&test_file, vec![0x5au8; 16 * 1024 * 1024]).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let control = TransferControl::default();
+
+        let sender_control = control.clone();
+        let test_file_clone = test_file.clone();
+        let sender = thread::spawn(move || {
+            let stream = TcpTransport::connect(addr).unwrap();
+            sender_control.attach(&stream).unwrap();
+            TcpTransport::send_file_from_offset::<fn(u64, u64)>(stream, &test_file_clone, 0, Some(&sender_control), None)
+        });
+
+        // The receiver doesn't read: the sender blocks writing until paused
+        let (_stream, _) = listener.accept().unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(!sender.is_finished());
+        let paused_at = std::time::Instant::now();
+        control.pause();
+        assert!(sender.join().unwrap().is_err());
+        assert!(paused_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(control.signal(), TransferSignal::Pause);
+
+        // Cancelling is final; connections attached afterwards are shut down at once
+        control.cancel();
+        control.pause();
+        assert_eq!(control.signal(), TransferSignal::Cancel);
+        assert!(control.checkpoint().is_err());
+        let stream = TcpTransport::connect(addr).unwrap();
+        control.attach(&stream).unwrap();
+        let (mut accepted, _) = listener.accept().unwrap();
+        let mut buffer = [0u8; 16];
+        assert_eq!(accepted.read(&mut buffer).unwrap(), 0);
+        assert!(TcpTransport::send_file_from_offset::<fn(u64, u64)>(stream, &test_file, 0, Some(&control), None).is_err());
+
+        assert!(control.is_same(&control.clone()));
+        assert!(!control.is_same(&TransferControl::default()));
+
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
     #[test]
     fn test_set_timeouts() {
         let stream = TcpStream::connect("127.0.0.1:80").unwrap(); // May fail, but that's ok for this test
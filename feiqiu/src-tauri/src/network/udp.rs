@@ -1,13 +1,26 @@
 // UDP transport module - handles UDP socket for control messages
 //
 // This module provides UDP socket functionality for:
-// - Broadcasting to LAN (peer discovery)
+// - Broadcasting to LAN (peer discovery), plus IPv6 link-local multicast
 // - Unicast messaging (direct peer communication)
 // - Receiving incoming messages
-
+// - Optionally recording every datagram to a packet capture
+// - Counting datagrams per IPMsg mode (see `stats`)
+//
+// One socket bound to the protocol port serves the whole application:
+// IPMsg/FeiQ clients reply to, and identify peers by, the source address of
+// what we send. `UdpTransport` owns the socket and receives; `UdpSender`
+// handles share it for sending from any thread. The socket is dual-stack
+// when the host has IPv6 (see `addr`); peer addresses are always reported in
+// canonical form (IPv4 peers as plain IPv4).
+
+use super::addr::{self, IPV6_MULTICAST_ADDR};
+use super::capture::{Direction, PacketCapture};
+use super::stats::NetworkStats;
 use crate::{NeoLanError, Result};
 use crate::config::AppConfig;
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{SocketAddr, SocketAddrV6, TcpListener, UdpSocket};
+use std::sync::Arc;
 
 /// Default UDP port for IPMsg protocol (re-exported from AppConfig)
 pub const DEFAULT_UDP_PORT: u16 = AppConfig::DEFAULT_UDP_PORT;
@@ -23,13 +36,30 @@ pub const DEFAULT_BUFFER_SIZE: usize = AppConfig::UDP_BUFFER_SIZE;
 /// Provides a high-level interface for UDP socket operations.
 /// Supports broadcasting and unicast messaging.
 pub struct UdpTransport {
-    /// Inner UdpSocket
-    socket: UdpSocket,
+    /// Send handle, holding the shared socket
+    sender: UdpSender,
 
     /// Bound port
     port: u16,
 }
 
+/// Cloneable send handle for a [`UdpTransport`]'s socket
+///
+/// Every clone sends from the same socket, so the datagrams of all
+/// subsystems carry the transport's source port. Cheap to clone and safe to
+/// move across threads.
+#[derive(Clone)]
+pub struct UdpSender {
+    /// Socket shared with the transport
+    socket: Arc<UdpSocket>,
+
+    /// Packet capture recording every datagram sent and received (optional)
+    capture: Option<Arc<PacketCapture>>,
+
+    /// Whether datagrams really go out (false when replaying a capture)
+    send_enabled: bool,
+}
+
 impl UdpTransport {
     /// Bind to a specific port
     ///
@@ -50,10 +80,8 @@ impl UdpTransport {
     pub fn bind(port: u16) -> Result<Self> {
         tracing::info!("Binding UDP socket to port {}", port);
 
-        // Bind to specified address (0.0.0.0 means all interfaces)
-        let addr = format!("0.0.0.0:{}", port);
-        let socket = UdpSocket::bind(&addr)
-            .map_err(|e| NeoLanError::Network(e))?;
+        // All interfaces, IPv4 and IPv6 (IPv4 only if the host has no IPv6)
+        let socket = addr::bind_udp(port)?;
 
         // Get the actual bound port (in case port was 0)
         let local_addr = socket
@@ -61,14 +89,56 @@ impl UdpTransport {
             .map_err(|e| NeoLanError::Network(e))?;
         let actual_port = local_addr.port();
 
-        tracing::info!("UDP socket bound to port {}", actual_port);
+        tracing::info!(
+            "UDP socket bound to port {} ({})",
+            actual_port,
+            if local_addr.is_ipv6() { "dual-stack" } else { "IPv4 only" }
+        );
 
         Ok(UdpTransport {
-            socket,
+            sender: UdpSender {
+                socket: Arc::new(socket),
+                capture: None,
+                send_enabled: true,
+            },
             port: actual_port,
         })
     }
 
+    /// Get a send handle sharing this transport's socket
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use neolan_lib::network::udp::UdpTransport;
+    /// # use neolan_lib::NeoLanError;
+    /// let udp = UdpTransport::bind(2425)?;
+    /// let sender = udp.sender();
+    /// std::thread::spawn(move || sender.send_to(b"Hello", "192.168.1.100:2425".parse().unwrap()));
+    /// # Ok::<(), NeoLanError>(())
+    /// ```
+    pub fn sender(&self) -> UdpSender {
+        self.sender.clone()
+    }
+
+    /// Record every datagram sent and received to a packet capture
+    ///
+    /// # Arguments
+    /// * `capture` - Capture to record to (may be shared by several transports)
+    pub fn with_capture(mut self, capture: Arc<PacketCapture>) -> Self {
+        self.sender.capture = Some(capture);
+        self
+    }
+
+    /// Stop sending datagrams on the wire
+    ///
+    /// `send_to` and `broadcast` still succeed and still record to the
+    /// capture, they just don't transmit. Used when replaying a capture, so
+    /// the replies it triggers don't reach the hosts it was recorded from.
+    pub fn dry_run(mut self) -> Self {
+        self.sender.send_enabled = false;
+        self
+    }
+
     /// Bind to a specific port with retry logic
     ///
     /// # Arguments
@@ -102,6 +172,44 @@ impl UdpTransport {
         )))
     }
 
+    /// Bind the UDP socket and the TCP file data listener to one port, with retry logic
+    ///
+    /// IPMsg peers fetch attachments from the TCP port numbered like the UDP
+    /// port they know us by, so a port only counts when both bind. Retries
+    /// the way [`bind_with_retry`](Self::bind_with_retry) does.
+    ///
+    /// # Arguments
+    /// * `port` - Port to bind to (use 0 for the IPMsg default)
+    /// * `max_retries` - Maximum number of retries with different ports
+    ///
+    /// # Returns
+    /// * `Ok((UdpTransport, TcpListener))` - Both bound to the same port
+    /// * `Err(NeoLanError)` - Binding failed after all retries
+    pub fn bind_with_tcp_listener(port: u16, max_retries: u16) -> Result<(Self, TcpListener)> {
+        let start_port = if port == 0 { DEFAULT_UDP_PORT } else { port };
+        let end_port = start_port.saturating_add(max_retries);
+
+        let mut last_error = None;
+        for attempt_port in start_port..end_port {
+            match Self::bind(attempt_port).and_then(|udp| Ok((udp, addr::bind_tcp(attempt_port)?))) {
+                Ok(bound) => {
+                    tracing::info!("UDP and TCP bound to port {} (attempt {})", attempt_port, attempt_port - start_port + 1);
+                    return Ok(bound);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to bind UDP and TCP to port {}: {}, retrying...", attempt_port, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            NeoLanError::Network(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("Failed to bind UDP and TCP after {} attempts", max_retries),
+            ))
+        }))
+    }
+
     /// Enable or disable broadcast mode
     ///
     /// # Arguments
@@ -120,75 +228,28 @@ impl UdpTransport {
     /// # Ok::<(), NeoLanError>(())
     /// ```
     pub fn set_broadcast_enabled(&self, enabled: bool) -> Result<()> {
-        tracing::debug!("Setting broadcast to {}", enabled);
-        self.socket
-            .set_broadcast(enabled)
-            .map_err(|e| NeoLanError::Network(e))?;
-        Ok(())
+        self.sender.set_broadcast_enabled(enabled)
     }
 
     /// Send data to broadcast address (255.255.255.255)
     ///
-    /// # Arguments
-    /// * `data` - Data to broadcast
-    ///
-    /// # Returns
-    /// * `Ok(())` - Data sent successfully
-    /// * `Err(NeoLanError)` - Send failed
-    ///
-    /// # Examples
-    /// ```no_run
-    /// # use neolan_lib::network::udp::UdpTransport;
-    /// # use neolan_lib::NeoLanError;
-    /// let udp = UdpTransport::bind(2425)?;
-    /// udp.set_broadcast_enabled(true)?;
-    /// udp.broadcast(b"Hello, LAN!")?;
-    /// # Ok::<(), NeoLanError>(())
-    /// ```
+    /// See [`UdpSender::broadcast`].
     pub fn broadcast(&self, data: &[u8]) -> Result<()> {
-        let addr: SocketAddr = format!("{}:{}", BROADCAST_ADDR, DEFAULT_UDP_PORT)
-            .parse()
-            .map_err(|_| NeoLanError::Network(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid broadcast address",
-            )))?;
+        self.sender.broadcast(data)
+    }
 
-        self.send_to(data, addr)
+    /// Send data to the IPv6 discovery multicast group
+    ///
+    /// See [`UdpSender::multicast`].
+    pub fn multicast(&self, data: &[u8]) -> Result<()> {
+        self.sender.multicast(data)
     }
 
     /// Send data to a specific address
     ///
-    /// # Arguments
-    /// * `data` - Data to send
-    /// * `addr` - Target socket address
-    ///
-    /// # Returns
-    /// * `Ok(())` - Data sent successfully
-    /// * `Err(NeoLanError)` - Send failed
-    ///
-    /// # Examples
-    /// ```no_run
-    /// # use neolan_lib::network::udp::UdpTransport;
-    /// # use neolan_lib::NeoLanError;
-    /// # use std::net::SocketAddr;
-    /// let udp = UdpTransport::bind(2425)?;
-    /// let addr = "192.168.1.100:2425".parse::<SocketAddr>().unwrap();
-    /// udp.send_to(b"Hello, Peer!", addr)?;
-    /// # Ok::<(), NeoLanError>(())
-    /// ```
+    /// See [`UdpSender::send_to`].
     pub fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<()> {
-        let bytes_sent = self
-            .socket
-            .send_to(data, addr)
-            .map_err(|e| NeoLanError::Network(e))?;
-
-        tracing::trace!(
-            "Sent {} bytes to {}",
-            bytes_sent,
-            addr.ip()
-        );
-
-        Ok(())
+        self.sender.send_to(data, addr)
     }
 
     /// Receive data from any peer
@@ -212,17 +273,13 @@ impl UdpTransport {
     /// ```
     pub fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr)> {
         let (bytes_received, addr) = self
+            .sender
             .socket
             .recv_from(buffer)
             .map_err(|e| NeoLanError::Network(e))?;
 
-        // Only accept IPv4 addresses
-        if addr.is_ipv6() {
-            return Err(NeoLanError::Network(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "IPv6 not supported",
-            )));
-        }
+        // IPv4 peers arrive IPv4-mapped on the dual-stack socket
+        let addr = addr::canonical_addr(addr);
 
         tracing::trace!(
             "Received {} bytes from {}",
@@ -230,6 +287,11 @@ impl UdpTransport {
             addr.ip()
         );
 
+        if let Some(capture) = &self.sender.capture {
+            capture.record(Direction::In, self.local_addr().ok(), addr, &buffer[..bytes_received]);
+        }
+        NetworkStats::global().record_udp(Direction::In, &buffer[..bytes_received]);
+
         Ok((bytes_received, addr))
     }
 
@@ -241,17 +303,39 @@ impl UdpTransport {
         self.port
     }
 
+    /// Check if the socket can reach IPv6 peers (dual-stack bind succeeded)
+    pub fn is_dual_stack(&self) -> bool {
+        self.sender.is_dual_stack()
+    }
+
+    /// Join the IPv6 link-local discovery group ([`IPV6_MULTICAST_ADDR`])
+    ///
+    /// Joined on the system's default multicast interface.
+    ///
+    /// # Returns
+    /// * `Ok(())` - Joined (or the socket is IPv4 only, so there's nothing to join)
+    /// * `Err(NeoLanError)` - Failed to join
+    pub fn join_ipv6_discovery_group(&self) -> Result<()> {
+        if !self.is_dual_stack() {
+            tracing::debug!("IPv4-only socket, skipping IPv6 discovery group");
+            return Ok(());
+        }
+
+        self.sender
+            .socket
+            .join_multicast_v6(&IPV6_MULTICAST_ADDR, 0)
+            .map_err(NeoLanError::Network)?;
+
+        tracing::info!("Joined IPv6 discovery group {}", IPV6_MULTICAST_ADDR);
+        Ok(())
+    }
+
     /// Get the local socket address
     ///
     /// # Returns
     /// * `Result<SocketAddr>` - Local socket address
     pub fn local_addr(&self) -> Result<SocketAddr> {
-        let addr = self
-            .socket
-            .local_addr()
-            .map_err(|e| NeoLanError::Network(e))?;
-
-        Ok(addr)
+        self.sender.local_addr()
     }
 
     /// Set receive timeout
@@ -264,7 +348,8 @@ impl UdpTransport {
     /// * `Err(NeoLanError)` - Failed to set timeout
     pub fn set_read_timeout(&self, duration_ms: Option<u64>) -> Result<()> {
         let timeout = duration_ms.map(|d| std::time::Duration::from_millis(d));
-        self.socket
+        self.sender
+            .socket
             .set_read_timeout(timeout)
             .map_err(|e| NeoLanError::Network(e))?;
         Ok(())
@@ -272,13 +357,15 @@ impl UdpTransport {
 
     /// Join a multicast group
     ///
+    /// Joined on the system's default multicast interface. IPv4 groups work
+    /// on the dual-stack socket too.
+    ///
     /// # Arguments
     /// * `multiaddr` - Multicast address to join
     ///
     /// # Returns
     /// * `Ok(())` - Successfully joined multicast group
     /// * `Err(NeoLanError)` - Failed to join
-    #[cfg(target_os = "linux")]
     pub fn join_multicast(&self, multiaddr: &str) -> Result<()> {
         use std::net::IpAddr;
 
@@ -289,13 +376,188 @@ impl UdpTransport {
                 "Invalid multicast address",
             )))?;
 
+        match addr {
+            IpAddr::V4(v4) => self
+                .sender
+                .socket
+                .join_multicast_v4(&v4, &std::net::Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(v6) => self.sender.socket.join_multicast_v6(&v6, 0),
+        }
+        .map_err(|e| NeoLanError::Network(e))?;
+
+        tracing::info!("Joined multicast group {}", multiaddr);
+        Ok(())
+    }
+
+    /// Set the TTL (hop limit) of outgoing multicast packets
+    ///
+    /// Sets both the IPv4 TTL and, on a dual-stack socket, the IPv6 hop limit.
+    /// The default of 1 keeps multicast on the local subnet; routed networks
+    /// need one more per router crossed.
+    ///
+    /// # Arguments
+    /// * `ttl` - Number of hops (1-255)
+    ///
+    /// # Returns
+    /// * `Ok(())` - TTL set successfully
+    /// * `Err(NeoLanError)` - Failed to set TTL
+    pub fn set_multicast_ttl(&self, ttl: u32) -> Result<()> {
+        let socket = &self.sender.socket;
+        socket
+            .set_multicast_ttl_v4(ttl)
+            .map_err(NeoLanError::Network)?;
+
+        if self.is_dual_stack() {
+            socket2::SockRef::from(socket.as_ref())
+                .set_multicast_hops_v6(ttl)
+                .map_err(NeoLanError::Network)?;
+        }
+
+        tracing::debug!("Multicast TTL set to {}", ttl);
+        Ok(())
+    }
+}
+
+impl UdpSender {
+    /// Enable or disable broadcast mode
+    ///
+    /// # Arguments
+    /// * `enabled` - true to enable broadcast, false to disable
+    ///
+    /// # Returns
+    /// * `Ok(())` - Broadcast setting updated
+    /// * `Err(NeoLanError)` - Failed to update setting
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use neolan_lib::network::udp::UdpTransport;
+    /// # use neolan_lib::NeoLanError;
+    /// let sender = UdpTransport::bind(2425)?.sender();
+    /// sender.set_broadcast_enabled(true)?;
+    /// # Ok::<(), NeoLanError>(())
+    /// ```
+    pub fn set_broadcast_enabled(&self, enabled: bool) -> Result<()> {
+        tracing::debug!("Setting broadcast to {}", enabled);
         self.socket
-            .join_multicast_v4(&addr, std::net::Ipv4Addr::UNSPECIFIED)
+            .set_broadcast(enabled)
             .map_err(|e| NeoLanError::Network(e))?;
+        Ok(())
+    }
+
+    /// Send data to broadcast address (255.255.255.255)
+    ///
+    /// # Arguments
+    /// * `data` - Data to broadcast
+    ///
+    /// # Returns
+    /// * `Ok(())` - Data sent successfully
+    /// * `Err(NeoLanError)` - Send failed
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use neolan_lib::network::udp::UdpTransport;
+    /// # use neolan_lib::NeoLanError;
+    /// let sender = UdpTransport::bind(2425)?.sender();
+    /// sender.set_broadcast_enabled(true)?;
+    /// sender.broadcast(b"Hello, LAN!")?;
+    /// # Ok::<(), NeoLanError>(())
+    /// ```
+    pub fn broadcast(&self, data: &[u8]) -> Result<()> {
+        let addr: SocketAddr = format!("{}:{}", BROADCAST_ADDR, DEFAULT_UDP_PORT)
+            .parse()
+            .map_err(|_| NeoLanError::Network(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid broadcast address",
+            )))?;
+
+        self.send_to(data, addr)
+    }
+
+    /// Send data to the IPv6 link-local discovery group
+    ///
+    /// IPv6-only segments have no broadcast: discovery packets go to
+    /// [`IPV6_MULTICAST_ADDR`] on the protocol port instead.
+    ///
+    /// # Returns
+    /// * `Ok(())` - Data sent successfully
+    /// * `Err(NeoLanError)` - Send failed, or the socket is IPv4 only
+    pub fn multicast(&self, data: &[u8]) -> Result<()> {
+        let addr = SocketAddr::V6(SocketAddrV6::new(IPV6_MULTICAST_ADDR, DEFAULT_UDP_PORT, 0, 0));
+        self.send_to(data, addr)
+    }
+
+    /// Check if the socket can reach IPv6 peers (dual-stack bind succeeded)
+    pub fn is_dual_stack(&self) -> bool {
+        self.local_addr().is_ok_and(|addr| addr.is_ipv6())
+    }
+
+    /// Send data to a specific address
+    ///
+    /// # Arguments
+    /// * `data` - Data to send
+    /// * `addr` - Target socket address
+    ///
+    /// # Returns
+    /// * `Ok(())` - Data sent successfully
+    /// * `Err(NeoLanError)` - Send failed
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use neolan_lib::network::udp::UdpTransport;
+    /// # use neolan_lib::NeoLanError;
+    /// # use std::net::SocketAddr;
+    /// let sender = UdpTransport::bind(2425)?.sender();
+    /// let addr = "192.168.1.100:2425".parse::<SocketAddr>().unwrap();
+    /// sender.send_to(b"Hello, Peer!", addr)?;
+    /// # Ok::<(), NeoLanError>(())
+    /// ```
+    pub fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<()> {
+        let local = self.local_addr()?;
+
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Out, Some(local), addr::canonical_addr(addr), data);
+        }
+
+        if !self.send_enabled {
+            tracing::trace!("Dry run: not sending {} bytes to {}", data.len(), addr.ip());
+            return Ok(());
+        }
+
+        let bytes_sent = self
+            .socket
+            .send_to(data, addr::to_socket_family(addr, local)?)
+            .map_err(|e| NeoLanError::Network(e))?;
+        NetworkStats::global().record_udp(Direction::Out, data);
+
+        tracing::trace!(
+            "Sent {} bytes to {}",
+            bytes_sent,
+            addr.ip()
+        );
 
-        tracing::info!("Joined multicast group {}", multiaddr);
         Ok(())
     }
+
+    /// Get the local socket address
+    ///
+    /// # Returns
+    /// * `Result<SocketAddr>` - Local socket address
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        let addr = self
+            .socket
+            .local_addr()
+            .map_err(|e| NeoLanError::Network(e))?;
+
+        Ok(addr)
+    }
+
+    /// Get the port datagrams are sent from
+    ///
+    /// # Returns
+    /// * `u16` - Local port (0 if it can't be read)
+    pub fn port(&self) -> u16 {
+        self.local_addr().map(|addr| addr.port()).unwrap_or(0)
+    }
 }
 
 #[cfg(test)]
@@ -324,6 +586,20 @@ mod tests {
         assert_eq!(udp.port(), port);
     }
 
+    #[test]
+    fn test_bind_with_tcp_listener_skips_taken_tcp_port() {
+        // A port whose TCP side is taken (UDP is free there)
+        let taken = addr::bind_tcp(0).unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+
+        let (udp, listener) = match UdpTransport::bind_with_tcp_listener(taken_port, 5) {
+            Ok(bound) => bound,
+            Err(_) => return, // Following ports taken by other processes
+        };
+        assert_ne!(udp.port(), taken_port);
+        assert_eq!(listener.local_addr().unwrap().port(), udp.port());
+    }
+
     #[test]
     fn test_set_broadcast_enabled() {
         let udp = UdpTransport::bind(0).unwrap();
@@ -372,6 +648,101 @@ mod tests {
         udp.set_read_timeout(None).unwrap();
     }
 
+    #[test]
+    fn test_capture_records_both_directions() {
+        let path = std::env::temp_dir().join("test_udp_capture.jsonl");
+        let capture = Arc::new(PacketCapture::create(&path).unwrap());
+
+        let sender = UdpTransport::bind(0).unwrap().with_capture(capture.clone());
+        let receiver = UdpTransport::bind(0).unwrap().with_capture(capture.clone());
+        let receiver_addr: SocketAddr = format!("127.0.0.1:{}", receiver.port()).parse().unwrap();
+
+        sender.send_to(b"1:1:a:b:32:hi", receiver_addr).unwrap();
+        let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
+        let (len, from) = receiver.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"1:1:a:b:32:hi");
+
+        let records = crate::network::capture::read_capture(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Out);
+        assert_eq!(records[0].peer, receiver_addr);
+        assert_eq!(records[1].direction, Direction::In);
+        assert_eq!(records[1].peer, from);
+        assert_eq!(records[1].data, b"1:1:a:b:32:hi");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sender_shares_socket() {
+        let udp = UdpTransport::bind(0).unwrap();
+        let receiver = UdpTransport::bind(0).unwrap();
+        receiver.set_read_timeout(Some(1000)).unwrap();
+        let receiver_addr: SocketAddr = format!("127.0.0.1:{}", receiver.port()).parse().unwrap();
+
+        let sender = udp.sender();
+        assert_eq!(sender.port(), udp.port());
+        std::thread::spawn(move || sender.send_to(b"from a clone", receiver_addr).unwrap())
+            .join()
+            .unwrap();
+
+        // The datagram comes from the transport's own port
+        let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
+        let (len, from) = receiver.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"from a clone");
+        assert_eq!(from.port(), udp.port());
+    }
+
+    #[test]
+    fn test_dry_run_does_not_send() {
+        let sender = UdpTransport::bind(0).unwrap().dry_run();
+        let receiver = UdpTransport::bind(0).unwrap();
+        receiver.set_read_timeout(Some(100)).unwrap();
+        let receiver_addr: SocketAddr = format!("127.0.0.1:{}", receiver.port()).parse().unwrap();
+
+        sender.send_to(b"not sent", receiver_addr).unwrap();
+
+        let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
+        assert!(receiver.recv_from(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_loopback() {
+        let udp = UdpTransport::bind(0).unwrap();
+        if !udp.is_dual_stack() {
+            return; // Host without IPv6
+        }
+        let receiver = UdpTransport::bind(0).unwrap();
+        receiver.set_read_timeout(Some(1000)).unwrap();
+
+        let target: SocketAddr = format!("[::1]:{}", receiver.port()).parse().unwrap();
+        udp.send_to(b"over ipv6", target).unwrap();
+
+        let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
+        let (len, from) = receiver.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"over ipv6");
+        assert!(from.is_ipv6());
+        assert_eq!(from.port(), udp.port());
+    }
+
+    #[test]
+    fn test_ipv4_peer_is_reported_as_ipv4() {
+        let udp = UdpTransport::bind(0).unwrap();
+        udp.set_read_timeout(Some(1000)).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        client.send_to(b"hello", ("127.0.0.1", udp.port())).unwrap();
+
+        let mut buffer = [0u8; DEFAULT_BUFFER_SIZE];
+        let (_, from) = udp.recv_from(&mut buffer).unwrap();
+        assert_eq!(from, client.local_addr().unwrap());
+
+        // And the reply reaches it through the same socket
+        udp.send_to(b"reply", from).unwrap();
+        let (len, _) = client.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"reply");
+    }
+
     #[test]
     fn test_constants() {
         assert_eq!(DEFAULT_UDP_PORT, 2425);
@@ -2,17 +2,36 @@
 //
 // Provides a centralized state management structure for the Tauri application.
 
-use crate::config::AppConfig;
+use crate::config::{AppConfig, ConfigRepository};
+use crate::modules::file_transfer::types::TransferTask;
+use crate::modules::file_transfer::{FileServer, FileTransferManager, FileTransferResponse, TransferStore};
 use crate::modules::message::MessageHandler;
 use crate::modules::peer::{PeerManager, PeerNode};
+use crate::network::{FloodLimits, NetworkStats, NetworkStatsSnapshot};
 use crate::storage::database::establish_connection;
 use crate::storage::message_repo::MessageRepository;
 use crate::storage::peer_repo::PeerRepository;
+use crate::storage::transfer_repo::TransferRepository;
 use crate::Result;
 use sea_orm::DatabaseConnection;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long shutdown waits for the background threads in total
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Background threads by name
+type NamedThreads = Vec<(&'static str, JoinHandle<()>)>;
+
+/// Transfer store and the thread writing it
+type RunningStore = (Arc<TransferStore>, JoinHandle<()>);
+
+/// File data server and the thread serving it
+type RunningServer = (Arc<FileServer>, JoinHandle<()>);
 
 /// Tauri event payload - serializable events that can be emitted to frontend
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -79,6 +98,29 @@ pub enum TauriEvent {
         created_at: i64,
     },
 
+    /// Transfer task created or changed (status or progress)
+    #[serde(rename = "FileTransferUpdated")]
+    FileTransferUpdated {
+        #[serde(rename = "taskId")]
+        task_id: String,
+        #[serde(rename = "direction")]
+        direction: String, // "upload" or "download"
+        #[serde(rename = "peerIp")]
+        peer_ip: String,
+        #[serde(rename = "fileName")]
+        file_name: String,
+        #[serde(rename = "fileSize")]
+        file_size: u64,
+        #[serde(rename = "status")]
+        status: String, // "pending", "active", "paused", "completed", "failed", "cancelled"
+        #[serde(rename = "transferredBytes")]
+        transferred_bytes: u64,
+        #[serde(rename = "error")]
+        error: Option<String>,
+        #[serde(rename = "updatedAt")]
+        updated_at: i64,
+    },
+
     /// Message receipt acknowledgment received
     #[serde(rename = "MessageReceiptAck")]
     MessageReceiptAck {
@@ -92,12 +134,45 @@ pub enum TauriEvent {
         acknowledged_at: i64,
     },
 
+    /// Message we sent was never acknowledged (retransmissions exhausted)
+    #[serde(rename = "MessageDeliveryFailed")]
+    MessageDeliveryFailed {
+        #[serde(rename = "msgId")]
+        msg_id: String,
+        #[serde(rename = "receiverIp")]
+        receiver_ip: String,
+        #[serde(rename = "attempts")]
+        attempts: u32,
+        #[serde(rename = "failedAt")]
+        failed_at: i64,
+    },
+
+    /// Message was cut to fit one datagram (the receiver can't take chunked messages)
+    #[serde(rename = "MessageTruncated")]
+    MessageTruncated {
+        #[serde(rename = "msgId")]
+        msg_id: String,
+        #[serde(rename = "receiverIp")]
+        receiver_ip: String,
+        #[serde(rename = "originalSize")]
+        original_size: usize,
+        #[serde(rename = "sentSize")]
+        sent_size: usize,
+    },
+
     /// Peers discovered after startup
     #[serde(rename = "PeersDiscovered")]
     PeersDiscovered {
         #[serde(rename = "peers")]
         peers: Vec<PeerDiscoveredDto>,
     },
+
+    /// Periodic snapshot of the network counters
+    #[serde(rename = "NetworkStats")]
+    NetworkStats {
+        #[serde(rename = "stats")]
+        stats: NetworkStatsSnapshot,
+    },
 }
 
 /// Peer discovered DTO for frontend
@@ -161,12 +236,27 @@ pub struct AppState {
     /// Peer repository
     peer_repo: Arc<Mutex<Option<PeerRepository>>>,
 
+    /// Settings repository
+    config_repo: Arc<Mutex<Option<ConfigRepository>>>,
+
+    /// Transfer repository
+    transfer_repo: Arc<Mutex<Option<TransferRepository>>>,
+
     /// Peer manager (when initialized)
     peer_manager: Arc<Mutex<Option<PeerManager>>>,
 
     /// Message handler (when initialized)
     message_handler: Arc<Mutex<Option<MessageHandler>>>,
 
+    /// File transfer tasks (when initialized)
+    file_transfer_manager: Arc<Mutex<Option<Arc<FileTransferManager>>>>,
+
+    /// File transfer requests awaiting the user's answer, and their downloads
+    file_transfer: Arc<Mutex<Option<Arc<FileTransferResponse>>>>,
+
+    /// Persistent record of the transfer tasks, and its store thread
+    transfer_store: Arc<Mutex<Option<RunningStore>>>,
+
     /// Current application configuration
     config: Arc<Mutex<AppConfig>>,
 
@@ -175,6 +265,21 @@ pub struct AppState {
 
     /// Tauri event sender for forwarding events to main thread
     tauri_event_sender: Arc<Mutex<Option<mpsc::Sender<TauriEvent>>>>,
+
+    /// Threads receiving and routing incoming packets, joined at shutdown
+    receive_threads: Arc<Mutex<NamedThreads>>,
+
+    /// Outbox thread, joined at shutdown after the receive threads
+    outbox_thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    /// IPMsg file data server, stopped and joined at shutdown
+    file_server: Arc<Mutex<Option<RunningServer>>>,
+
+    /// Extra broadcast host heartbeat thread, joined first at shutdown
+    heartbeat_thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    /// Set once shutdown has started
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl AppState {
@@ -184,11 +289,21 @@ impl AppState {
             db: Arc::new(Mutex::new(None)),
             message_repo: Arc::new(Mutex::new(None)),
             peer_repo: Arc::new(Mutex::new(None)),
+            config_repo: Arc::new(Mutex::new(None)),
+            transfer_repo: Arc::new(Mutex::new(None)),
             peer_manager: Arc::new(Mutex::new(None)),
             message_handler: Arc::new(Mutex::new(None)),
+            file_transfer_manager: Arc::new(Mutex::new(None)),
+            file_transfer: Arc::new(Mutex::new(None)),
+            transfer_store: Arc::new(Mutex::new(None)),
             config: Arc::new(Mutex::new(config)),
             event_emitter: Arc::new(Mutex::new(super::events::AppEventEmitter::new())),
             tauri_event_sender: Arc::new(Mutex::new(None)),
+            receive_threads: Arc::new(Mutex::new(Vec::new())),
+            outbox_thread: Arc::new(Mutex::new(None)),
+            file_server: Arc::new(Mutex::new(None)),
+            heartbeat_thread: Arc::new(Mutex::new(None)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -211,9 +326,13 @@ impl AppState {
         // Create repositories
         let message_repo = MessageRepository::new(db.clone());
         let peer_repo = PeerRepository::new(db.clone());
+        let config_repo = ConfigRepository::new(db.clone());
+        let transfer_repo = TransferRepository::new(db.clone());
 
         *self.message_repo.lock().unwrap() = Some(message_repo);
         *self.peer_repo.lock().unwrap() = Some(peer_repo);
+        *self.config_repo.lock().unwrap() = Some(config_repo);
+        *self.transfer_repo.lock().unwrap() = Some(transfer_repo);
 
         tracing::info!("Database initialized successfully");
 
@@ -234,6 +353,20 @@ impl AppState {
         self.peer_repo.lock().unwrap().as_ref().cloned()
     }
 
+    /// Get the settings repository
+    ///
+    /// Returns None if database hasn't been initialized.
+    pub fn get_config_repo(&self) -> Option<ConfigRepository> {
+        self.config_repo.lock().unwrap().as_ref().cloned()
+    }
+
+    /// Get the transfer repository
+    ///
+    /// Returns None if database hasn't been initialized.
+    pub fn get_transfer_repo(&self) -> Option<TransferRepository> {
+        self.transfer_repo.lock().unwrap().as_ref().cloned()
+    }
+
     /// Check if database is initialized
     pub fn is_database_initialized(&self) -> bool {
         self.db.lock().unwrap().is_some()
@@ -295,6 +428,7 @@ impl AppState {
 
     /// Set the configuration
     pub fn set_config(&self, config: AppConfig) {
+        self.apply_flood_limits(&config);
         *self.config.lock().unwrap() = config;
         self.emit_event(super::events::AppEvent::ConfigChanged);
     }
@@ -306,11 +440,94 @@ impl AppState {
     {
         let mut config = self.config.lock().unwrap();
         updater(&mut config);
+        let updated = config.clone();
         drop(config);
+        self.apply_flood_limits(&updated);
         self.emit_event(super::events::AppEvent::ConfigChanged);
         Ok(())
     }
 
+    /// Hand the flood protection thresholds to the running receive path
+    fn apply_flood_limits(&self, config: &AppConfig) {
+        if let Some(manager) = self.get_peer_manager() {
+            manager.discovery().set_flood_limits(FloodLimits::from_config(config));
+        }
+    }
+
+    /// Get the network counters (traffic, parse failures, receipt round trips,
+    /// TCP transfers and the receive path's dropped and throttled packets)
+    pub fn get_network_stats(&self) -> NetworkStatsSnapshot {
+        let mut stats = NetworkStats::global().snapshot(std::time::Instant::now());
+        if let Some(manager) = self.peer_manager.lock().unwrap().as_ref() {
+            stats.flood = manager.discovery().flood_stats();
+        }
+        stats
+    }
+
+    /// Emit the network counters to the frontend
+    pub fn emit_network_stats(&self) {
+        self.emit_tauri_event(TauriEvent::NetworkStats {
+            stats: self.get_network_stats(),
+        });
+    }
+
+    // ==================== Extra Broadcast Hosts ====================
+
+    /// Load the persisted extra broadcast host list into the configuration
+    ///
+    /// Must run after migrations and before the peer manager is built. An
+    /// unreadable or invalid stored list is logged and ignored.
+    pub async fn load_extra_broadcast_hosts(&self) {
+        let Some(repo) = self.get_config_repo() else {
+            return;
+        };
+
+        match repo.load_extra_broadcast_hosts().await {
+            Ok(hosts) => match crate::network::expand_hosts(&hosts) {
+                Ok(_) => {
+                    self.config.lock().unwrap().extra_broadcast_hosts = hosts;
+                }
+                Err(e) => tracing::warn!("Ignoring stored extra broadcast hosts: {}", e),
+            },
+            Err(e) => tracing::warn!("Failed to load extra broadcast hosts: {}", e),
+        }
+    }
+
+    /// Replace the extra broadcast host list
+    ///
+    /// Validates the list, persists it (when the database is available),
+    /// updates the configuration and the running discovery, and announces
+    /// ourselves to the new hosts right away.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<String>)` - The stored list
+    /// * `Err(NeoLanError::Validation)` - An entry is invalid or the list is too large
+    pub async fn set_extra_broadcast_hosts(&self, hosts: Vec<String>) -> Result<Vec<String>> {
+        let mut hosts: Vec<String> = hosts
+            .iter()
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect();
+        hosts.dedup();
+        crate::network::expand_hosts(&hosts)?;
+
+        if let Some(repo) = self.get_config_repo() {
+            repo.save_extra_broadcast_hosts(&hosts).await?;
+        }
+
+        self.update_config(|config| config.extra_broadcast_hosts = hosts.clone())?;
+
+        if let Some(manager) = self.get_peer_manager() {
+            let discovery = manager.discovery();
+            discovery.set_extra_hosts(&hosts)?;
+            if let Err(e) = discovery.announce_to_extra_hosts() {
+                tracing::warn!("Failed to announce to extra broadcast hosts: {}", e);
+            }
+        }
+
+        Ok(hosts)
+    }
+
     /// Initialize the peer manager
     ///
     /// This should be called once during application startup.
@@ -403,12 +620,9 @@ impl AppState {
     /// * `Err(NeoLanError)` - Send failed
     pub fn send_message(&self, target_ip: std::net::IpAddr, content: &str) -> Result<String> {
         if let Some(handler) = self.message_handler.lock().unwrap().as_ref() {
-            handler.send_text_message(target_ip, content)?;
-
-            // Get the packet ID that was used
-            let msg_id = handler.packet_id_counter().to_string();
+            let packet_id = handler.send_text_message(target_ip, content)?;
 
-            Ok(msg_id)
+            Ok(packet_id.to_string())
         } else {
             Err(crate::NeoLanError::Other(
                 "Message handler not initialized".to_string(),
@@ -440,6 +654,232 @@ impl AppState {
             ))
         }
     }
+
+    // ==================== File Transfer Methods ====================
+
+    /// Initialize file transfer
+    ///
+    /// This should be called once during application startup, with the
+    /// response handler given to the message handler (it registers the
+    /// incoming requests).
+    pub fn init_file_transfer(&self, manager: Arc<FileTransferManager>, response: Arc<FileTransferResponse>) {
+        *self.file_transfer_manager.lock().unwrap() = Some(manager);
+        *self.file_transfer.lock().unwrap() = Some(response);
+    }
+
+    /// Send files to a peer
+    ///
+    /// Peers that advertise JSON file offers (IPMSG_CAPFILEREQOPT) get one
+    /// offer per file, and the file is pushed to them once they accept it. Other IPMsg
+    /// clients get one attachment message listing every file, and pull the
+    /// files from our file data server.
+    ///
+    /// # Arguments
+    /// * `peer_ip` - Target peer
+    /// * `paths` - Files to send
+    ///
+    /// # Returns
+    /// * `Ok(Vec<Uuid>)` - Upload task IDs, one per file, in the order of `paths`
+    /// * `Err(NeoLanError)` - No files, a path that isn't a file, or sending failed
+    pub fn send_files(&self, peer_ip: std::net::IpAddr, paths: &[std::path::PathBuf]) -> Result<Vec<uuid::Uuid>> {
+        if paths.is_empty() {
+            return Err(crate::NeoLanError::Validation("No files to send".to_string()));
+        }
+        if let Some(path) = paths.iter().find(|path| !path.is_file()) {
+            return Err(crate::NeoLanError::Validation(format!("Not a file: {}", path.display())));
+        }
+
+        let manager = self.file_transfer_manager.lock().unwrap().clone().ok_or_else(|| {
+            crate::NeoLanError::Other("File transfer not initialized".to_string())
+        })?;
+
+        match self.get_peer(peer_ip) {
+            Some(peer) if peer.supports_file_requests => {
+                paths.iter().map(|path| manager.send_request(path, peer_ip)).collect()
+            }
+            peer => {
+                let encoding = peer.map(|peer| peer.encoding).unwrap_or_default();
+                manager.send_attachments(paths, peer_ip, encoding)
+            }
+        }
+    }
+
+    /// Accept a file transfer request and start downloading the file
+    ///
+    /// The file is saved in the configured `file_save_dir`.
+    ///
+    /// # Arguments
+    /// * `request_id` - ID of the pending request
+    /// * `tcp_port` - Port to receive on (None: any free port in range)
+    ///
+    /// # Returns
+    /// * `Ok(Uuid)` - ID of the download task
+    /// * `Err(NeoLanError)` - Unknown request, or the download couldn't be set up
+    pub fn accept_file_transfer(&self, request_id: uuid::Uuid, tcp_port: Option<u16>) -> Result<uuid::Uuid> {
+        let save_dir = std::path::PathBuf::from(self.get_config().file_save_dir);
+        self.file_transfer()?.accept(request_id, &save_dir, tcp_port)
+    }
+
+    /// Reject a file transfer request (the sender is told)
+    pub fn reject_file_transfer(&self, request_id: uuid::Uuid) -> Result<()> {
+        self.file_transfer()?.reject(request_id)
+    }
+
+    /// Cancel an unfinished transfer task (the sender of a download is told)
+    pub fn cancel_file_transfer(&self, task_id: uuid::Uuid) -> Result<()> {
+        self.file_transfer()?.cancel(task_id)
+    }
+
+    /// Pause an active transfer (its connection is closed until it is resumed)
+    pub fn pause_file_transfer(&self, task_id: uuid::Uuid) -> Result<()> {
+        self.file_transfer()?.pause(task_id)
+    }
+
+    /// Resume a paused transfer, or a failed download, from where it stopped
+    ///
+    /// # Arguments
+    /// * `task_id` - ID of the transfer task
+    /// * `tcp_port` - Port to receive on (None: any free port in range)
+    pub fn resume_file_transfer(&self, task_id: uuid::Uuid, tcp_port: Option<u16>) -> Result<()> {
+        self.file_transfer()?.resume(task_id, tcp_port)
+    }
+
+    /// Get all transfer tasks
+    pub fn get_file_transfers(&self) -> Vec<TransferTask> {
+        if let Some(manager) = self.file_transfer_manager.lock().unwrap().as_ref() {
+            manager.get_tasks()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Start writing transfer task changes to the database
+    ///
+    /// The store (given to the file transfer manager) is closed and its
+    /// thread joined at shutdown, after the transfers have been released.
+    pub fn start_transfer_store(&self, store: Arc<TransferStore>) {
+        let handle = store.start();
+        *self.transfer_store.lock().unwrap() = Some((store, handle));
+    }
+
+    /// Get one page of the transfer history, newest first
+    ///
+    /// # Arguments
+    /// * `page` - Page number (from 0)
+    /// * `page_size` - Tasks per page
+    ///
+    /// # Returns
+    /// * `Ok((Vec<TransferTask>, u64))` - Tasks of the page and the total number of tasks
+    ///   (nothing without a database)
+    /// * `Err(NeoLanError)` - Query failed
+    pub async fn get_transfer_history(&self, page: u64, page_size: u64) -> Result<(Vec<TransferTask>, u64)> {
+        let store = self.transfer_store.lock().unwrap().as_ref().map(|(store, _)| store.clone());
+        match store {
+            Some(store) => store.history(page, page_size).await,
+            None => Ok((Vec::new(), 0)),
+        }
+    }
+
+    fn file_transfer(&self) -> Result<Arc<FileTransferResponse>> {
+        self.file_transfer.lock().unwrap().clone().ok_or_else(|| {
+            crate::NeoLanError::Other("File transfer not initialized".to_string())
+        })
+    }
+
+    // ==================== Shutdown ====================
+
+    /// Register a thread that receives or routes incoming packets (the peer
+    /// listener, the message router), to be joined at shutdown
+    pub fn register_receive_thread(&self, name: &'static str, handle: JoinHandle<()>) {
+        self.receive_threads.lock().unwrap().push((name, handle));
+    }
+
+    /// Register the outbox thread (see `MessageHandler::start_outbox`), to be joined at shutdown
+    pub fn register_outbox_thread(&self, handle: JoinHandle<()>) {
+        *self.outbox_thread.lock().unwrap() = Some(handle);
+    }
+
+    /// Whether shutdown has started (background loops that aren't joined check it to end)
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Register the extra broadcast host heartbeat thread (see
+    /// `heartbeat::spawn_extra_host_heartbeat`), to be joined at shutdown
+    pub fn register_heartbeat_thread(&self, handle: JoinHandle<()>) {
+        *self.heartbeat_thread.lock().unwrap() = Some(handle);
+    }
+
+    /// Register the file data server and its thread, to be stopped and joined at shutdown
+    pub fn register_file_server(&self, server: Arc<FileServer>, handle: JoinHandle<()>) {
+        *self.file_server.lock().unwrap() = Some((server, handle));
+    }
+
+    /// Shut the network side down in order (on application exit)
+    ///
+    /// 1. Wait for the heartbeat thread, so no BR_ENTRY follows our BR_EXIT;
+    ///    announce BR_EXIT, stop the UDP listener and close the channel
+    ///    feeding the message router
+    /// 2. Wait for the receive threads, so queued incoming messages are
+    ///    handled and stored (and their receipts reach the outbox); stop the
+    ///    file data server and wait for it, so no new upload starts
+    /// 3. Release unfinished file transfers (IPMSG_RELEASEFILES) and stop the
+    ///    outbox, which stores the queued delivery changes; wait for it
+    /// 4. Close the transfer store and wait until the task changes are written
+    ///
+    /// Threads still running after [`SHUTDOWN_TIMEOUT`] are left behind so
+    /// exit isn't held up. Only the first call does anything.
+    pub fn shutdown(&self) {
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        tracing::info!("Shutting down");
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        if let Some(handle) = self.heartbeat_thread.lock().unwrap().take() {
+            Self::join_until("heartbeat", handle, deadline);
+        }
+        if let Some(manager) = self.get_peer_manager() {
+            manager.stop();
+        }
+        let receive_threads = std::mem::take(&mut *self.receive_threads.lock().unwrap());
+        for (name, handle) in receive_threads {
+            Self::join_until(name, handle, deadline);
+        }
+        if let Some((server, handle)) = self.file_server.lock().unwrap().take() {
+            server.stop();
+            Self::join_until("file data server", handle, deadline);
+        }
+
+        if let Some(handler) = self.message_handler.lock().unwrap().as_ref() {
+            handler.shutdown();
+        }
+        if let Some(handle) = self.outbox_thread.lock().unwrap().take() {
+            Self::join_until("outbox", handle, deadline);
+        }
+
+        if let Some((store, handle)) = self.transfer_store.lock().unwrap().take() {
+            store.close();
+            Self::join_until("transfer store", handle, deadline);
+        }
+
+        tracing::info!("Shutdown complete");
+    }
+
+    /// Join a thread if it finishes before `deadline`
+    fn join_until(name: &str, handle: JoinHandle<()>, deadline: Instant) {
+        while !handle.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        if !handle.is_finished() {
+            tracing::warn!("{} thread still running at shutdown, not waiting for it", name);
+        } else if handle.join().is_err() {
+            tracing::warn!("{} thread panicked", name);
+        } else {
+            tracing::debug!("{} thread finished", name);
+        }
+    }
 }
 
 /// Peer statistics
@@ -454,6 +894,111 @@ pub struct PeerStats {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_shutdown_joins_threads() {
+        use crate::modules::peer::discovery::PeerDiscovery;
+        use crate::modules::peer::manager::MessageRouteRequest;
+        use crate::network::UdpTransport;
+
+        let state = AppState::new(AppConfig::default());
+        let manager = PeerManager::new(PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let (route_tx, route_rx) = mpsc::channel::<MessageRouteRequest>();
+        manager.set_message_handler_channel(route_tx);
+        state.init_peer_manager(manager);
+
+        let handler = MessageHandler::new(UdpTransport::bind(0).unwrap().dry_run().sender(), AppConfig::default());
+        state.register_outbox_thread(handler.start_outbox());
+        state.init_message_handler(handler);
+
+        let listener_state = state.clone();
+        state.register_receive_thread(
+            "peer listener",
+            std::thread::spawn(move || listener_state.start_peer_manager().unwrap()),
+        );
+        state.register_receive_thread("message router", std::thread::spawn(move || for _ in route_rx {}));
+
+        let transfers = Arc::new(FileTransferManager::new(
+            UdpTransport::bind(0).unwrap().dry_run().sender(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let server = Arc::new(FileServer::new(transfers));
+        let serving = server.clone();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        state.register_file_server(server, std::thread::spawn(move || serving.serve(listener)));
+        state.register_heartbeat_thread(crate::modules::peer::heartbeat::spawn_extra_host_heartbeat(
+            PeerDiscovery::new(UdpTransport::bind(0).unwrap(), "TestUser".to_string(), "test-host".to_string()),
+            60,
+            state.clone(),
+        ));
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Every thread ends by itself: nothing waits out the timeout
+        let started = Instant::now();
+        assert!(!state.is_shutting_down());
+        state.shutdown();
+        assert!(state.is_shutting_down());
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT);
+        assert!(state.receive_threads.lock().unwrap().is_empty());
+        assert!(state.outbox_thread.lock().unwrap().is_none());
+        assert!(state.file_server.lock().unwrap().is_none());
+        assert!(state.heartbeat_thread.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_send_files_creates_task_per_file() {
+        use crate::network::UdpTransport;
+
+        let state = AppState::new(AppConfig::default());
+        let udp = UdpTransport::bind(0).unwrap().dry_run().sender();
+        let manager = Arc::new(FileTransferManager::new(udp, "TestUser".to_string(), "test-host".to_string()));
+        let response = Arc::new(FileTransferResponse::new(manager.clone(), "TestUser".to_string(), "test-host".to_string()));
+        state.init_file_transfer(manager.clone(), response);
+
+        let dir = std::env::temp_dir().join(format!("neolan_send_files_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = vec![dir.join("a.txt"), dir.join("b.txt")];
+        for path in &paths {
+            std::fs::write(path, b"data").unwrap();
+        }
+        let peer: std::net::IpAddr = "192.168.1.100".parse().unwrap();
+
+        assert!(state.send_files(peer, &[]).is_err());
+        assert!(state.send_files(peer, &[dir.join("missing.txt")]).is_err());
+
+        // A peer we know nothing about gets IPMsg attachments
+        let task_ids = state.send_files(peer, &paths).unwrap();
+        assert_eq!(task_ids.len(), 2);
+        let tasks = state.get_file_transfers();
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|task| task.is_attachment() && task.packet_id == tasks[0].packet_id));
+
+        // Chunked-message support alone doesn't mean the peer takes JSON offers
+        let peers = PeerManager::new(crate::modules::peer::discovery::PeerDiscovery::new(
+            UdpTransport::bind(0).unwrap().dry_run(),
+            "TestUser".to_string(),
+            "test-host".to_string(),
+        ));
+        let mut node = PeerNode::new(peer, 2425);
+        node.supports_chunks = true;
+        peers.add_peer(node.clone()).unwrap();
+        state.init_peer_manager(peers.clone());
+        state.send_files(peer, &paths[..1]).unwrap();
+        assert!(state.get_file_transfers().iter().all(|task| task.is_attachment()));
+
+        node.supports_file_requests = true;
+        peers.add_peer(node).unwrap();
+        let task_ids = state.send_files(peer, &paths[..1]).unwrap();
+        let task = state.get_file_transfers().into_iter().find(|task| task.id == task_ids[0]).unwrap();
+        assert!(!task.is_attachment());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_app_state_creation() {
         let config = AppConfig::default();
@@ -40,6 +40,10 @@ pub struct Model {
 
     #[sea_orm(column_type = "BigInteger")]
     pub created_at: DateTime,
+
+    /// 送达状态 (pending / delivered / failed)，仅发出的消息有值
+    #[sea_orm(column_type = "Text", nullable)]
+    pub delivery_status: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
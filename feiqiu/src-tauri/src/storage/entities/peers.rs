@@ -14,6 +14,9 @@ pub struct Model {
     #[sea_orm(column_type = "Integer")]
     pub port: i32,
 
+    #[sea_orm(column_type = "Integer", nullable)]
+    pub scope_id: Option<i32>, // IPv6 链路本地地址的接口索引
+
     #[sea_orm(column_type = "Text", nullable)]
     pub username: Option<String>,
 
@@ -30,7 +30,7 @@ pub struct Model {
     pub peer_name: String,
 
     #[sea_orm(column_type = "Text")]
-    pub status: String, // "pending", "transferring", "paused", "completed", "failed", "cancelled"
+    pub status: String, // "pending", "active", "paused", "completed", "failed", "cancelled"
 
     #[sea_orm(column_type = "BigInteger", default_value = "0")]
     pub transferred_size: i64,
@@ -43,6 +43,18 @@ pub struct Model {
 
     #[sea_orm(column_type = "BigInteger")]
     pub created_at: DateTime,
+
+    #[sea_orm(column_type = "Text", default_value = "")]
+    pub file_path: String,
+
+    #[sea_orm(column_type = "Text", nullable)]
+    pub error_message: Option<String>,
+
+    #[sea_orm(column_type = "BigInteger", nullable)]
+    pub packet_id: Option<i64>,
+
+    #[sea_orm(column_type = "BigInteger", nullable)]
+    pub file_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
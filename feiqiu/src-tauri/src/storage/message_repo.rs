@@ -8,6 +8,15 @@ pub type MessageModel = messages::Model;
 pub type MessageActiveModel = messages::ActiveModel;
 pub type MessageEntity = messages::Entity;
 
+/// 送达状态：已发出，等待回执
+pub const DELIVERY_PENDING: &str = "pending";
+
+/// 送达状态：已收到回执 (IPMSG_RECVMSG)
+pub const DELIVERY_DELIVERED: &str = "delivered";
+
+/// 送达状态：重传次数用尽仍未收到回执
+pub const DELIVERY_FAILED: &str = "failed";
+
 /// 消息数据访问层
 ///
 /// 提供 messages 表的 CRUD 操作
@@ -131,9 +140,15 @@ impl MessageRepository {
             .await?
             .ok_or_else(|| NeoLanError::Storage(format!("Message not found: {}", msg_id)))?;
 
+        // 只有发出的消息记录送达状态
+        let outgoing = existing.delivery_status.is_some();
+
         let mut active_model: MessageActiveModel = existing.into();
         active_model.is_offline = Set(false);
         active_model.received_at = Set(Some(chrono::Utc::now().naive_utc()));
+        if outgoing {
+            active_model.delivery_status = Set(Some(DELIVERY_DELIVERED.to_string()));
+        }
 
         MessageEntity::update(active_model)
             .exec(&self.db)
@@ -143,6 +158,24 @@ impl MessageRepository {
         Ok(())
     }
 
+    /// 标记消息为发送失败 (重传次数用尽仍未收到回执)
+    pub async fn mark_as_failed(&self, msg_id: &str) -> Result<()> {
+        let existing = self
+            .find_by_msg_id(msg_id)
+            .await?
+            .ok_or_else(|| NeoLanError::Storage(format!("Message not found: {}", msg_id)))?;
+
+        let mut active_model: MessageActiveModel = existing.into();
+        active_model.delivery_status = Set(Some(DELIVERY_FAILED.to_string()));
+
+        MessageEntity::update(active_model)
+            .exec(&self.db)
+            .await
+            .map_err(|e| NeoLanError::Storage(format!("Failed to mark message as failed: {}", e)))?;
+
+        Ok(())
+    }
+
     /// 批量标记离线消息为已送达
     ///
     /// # 参数
@@ -3,3 +3,4 @@ pub mod database;
 pub mod entities;
 pub mod peer_repo;
 pub mod message_repo;
+pub mod transfer_repo;
@@ -35,6 +35,7 @@ impl PeerRepository {
             // 如果已存在，更新它
             let mut active: PeerActiveModel = existing.into();
             active.port = Set(peer.port);
+            active.scope_id = Set(peer.scope_id);
             active.username = Set(peer.username.clone());
             active.hostname = Set(peer.hostname.clone());
             active.nickname = Set(peer.nickname.clone());
@@ -68,6 +69,7 @@ impl PeerRepository {
 
         let mut active_model: PeerActiveModel = existing.into();
         active_model.port = Set(peer.port);
+        active_model.scope_id = Set(peer.scope_id);
         active_model.username = Set(peer.username.clone());
         active_model.hostname = Set(peer.hostname.clone());
         active_model.nickname = Set(peer.nickname.clone());
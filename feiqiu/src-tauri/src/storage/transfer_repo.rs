@@ -0,0 +1,193 @@
+// src-tauri/src/storage/transfer_repo.rs
+use crate::error::{NeoLanError, Result};
+use crate::storage::entities::transfers;
+use sea_orm::*;
+
+pub type TransferModel = transfers::Model;
+pub type TransferActiveModel = transfers::ActiveModel;
+pub type TransferEntity = transfers::Entity;
+
+/// 未结束的传输状态（等待接受 / 传输中 / 已暂停）
+pub const UNFINISHED_STATUSES: [&str; 3] = ["pending", "active", "paused"];
+
+/// 文件传输数据访问层
+///
+/// 提供 transfers 表的 CRUD 操作
+#[derive(Clone)]
+pub struct TransferRepository {
+    db: DatabaseConnection,
+}
+
+impl TransferRepository {
+    /// 创建新的 TransferRepository
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// 保存传输记录
+    ///
+    /// 如果 task_id 已存在，则更新现有记录；首次开始时间 (started_at) 保留不变，
+    /// 对端名称 (peer_name) 为空时也保留原值
+    pub async fn save(&self, transfer: &TransferModel) -> Result<()> {
+        if let Some(existing) = self.find_by_task_id(&transfer.task_id).await? {
+            let started_at = existing.started_at.or(transfer.started_at);
+
+            let mut active: TransferActiveModel = existing.into();
+            active.file_name = Set(transfer.file_name.clone());
+            active.file_size = Set(transfer.file_size);
+            active.file_md5 = Set(transfer.file_md5.clone());
+            active.file_path = Set(transfer.file_path.clone());
+            if !transfer.peer_name.is_empty() {
+                active.peer_name = Set(transfer.peer_name.clone());
+            }
+            active.status = Set(transfer.status.clone());
+            active.transferred_size = Set(transfer.transferred_size);
+            active.error_message = Set(transfer.error_message.clone());
+            active.packet_id = Set(transfer.packet_id);
+            active.file_id = Set(transfer.file_id);
+            active.started_at = Set(started_at);
+            active.completed_at = Set(transfer.completed_at);
+
+            TransferEntity::update(active)
+                .exec(&self.db)
+                .await
+                .map_err(|e| NeoLanError::Storage(format!("Failed to update transfer: {}", e)))?;
+        } else {
+            let mut active: TransferActiveModel = transfer.clone().into();
+            active.id = NotSet;
+
+            TransferEntity::insert(active)
+                .exec(&self.db)
+                .await
+                .map_err(|e| NeoLanError::Storage(format!("Failed to insert transfer: {}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// 根据 task_id 查找传输记录
+    pub async fn find_by_task_id(&self, task_id: &str) -> Result<Option<TransferModel>> {
+        let result = TransferEntity::find()
+            .filter(transfers::Column::TaskId.eq(task_id))
+            .one(&self.db)
+            .await
+            .map_err(|e| NeoLanError::Storage(format!("Failed to find transfer by task ID: {}", e)))?;
+
+        Ok(result)
+    }
+
+    /// 查找未结束的传输（应用上次退出时仍在进行）
+    ///
+    /// # 返回
+    /// 状态为 pending / active / paused 的记录，按创建时间正序
+    pub async fn find_unfinished(&self) -> Result<Vec<TransferModel>> {
+        let result = TransferEntity::find()
+            .filter(transfers::Column::Status.is_in(UNFINISHED_STATUSES))
+            .order_by_asc(transfers::Column::CreatedAt)
+            .all(&self.db)
+            .await
+            .map_err(|e| NeoLanError::Storage(format!("Failed to find unfinished transfers: {}", e)))?;
+
+        Ok(result)
+    }
+
+    /// 分页查找传输历史
+    ///
+    /// # 参数
+    /// - `page`: 页码（从 0 开始）
+    /// - `page_size`: 每页记录数
+    ///
+    /// # 返回
+    /// 该页的传输记录，按创建时间倒序
+    pub async fn find_page(&self, page: u64, page_size: u64) -> Result<Vec<TransferModel>> {
+        let result = TransferEntity::find()
+            .order_by_desc(transfers::Column::CreatedAt)
+            .order_by_desc(transfers::Column::Id)
+            .paginate(&self.db, page_size)
+            .fetch_page(page)
+            .await
+            .map_err(|e| NeoLanError::Storage(format!("Failed to find transfer history: {}", e)))?;
+
+        Ok(result)
+    }
+
+    /// 统计传输记录数量
+    pub async fn count(&self) -> Result<u64> {
+        let count = TransferEntity::find()
+            .count(&self.db)
+            .await
+            .map_err(|e| NeoLanError::Storage(format!("Failed to count transfers: {}", e)))?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::{Migrator, MigratorTrait};
+    use chrono::{Duration, Utc};
+
+    fn transfer(task_id: &str, status: &str, created_at: chrono::NaiveDateTime) -> TransferModel {
+        TransferModel {
+            id: 0,
+            task_id: task_id.to_string(),
+            direction: "download".to_string(),
+            file_name: format!("{}.bin", task_id),
+            file_size: 100,
+            file_md5: String::new(),
+            peer_ip: "192.168.1.100".to_string(),
+            peer_name: String::new(),
+            status: status.to_string(),
+            transferred_size: 0,
+            started_at: None,
+            completed_at: None,
+            created_at,
+            file_path: String::new(),
+            error_message: None,
+            packet_id: Some(1),
+            file_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_find_and_paginate() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let repo = TransferRepository::new(db);
+
+        let now = Utc::now().naive_utc();
+        repo.save(&transfer("a", "completed", now - Duration::seconds(2))).await.unwrap();
+        repo.save(&transfer("b", "pending", now - Duration::seconds(1))).await.unwrap();
+        repo.save(&transfer("c", "active", now)).await.unwrap();
+
+        // 更新保留首次开始时间
+        let mut started = transfer("c", "active", now);
+        started.started_at = Some(now);
+        repo.save(&started).await.unwrap();
+        let mut paused = transfer("c", "paused", now);
+        paused.peer_name = "张三".to_string();
+        paused.transferred_size = 40;
+        paused.started_at = Some(now + Duration::seconds(5));
+        repo.save(&paused).await.unwrap();
+
+        let stored = repo.find_by_task_id("c").await.unwrap().unwrap();
+        assert_eq!(stored.status, "paused");
+        assert_eq!(stored.transferred_size, 40);
+        assert_eq!(stored.started_at, Some(now));
+        assert_eq!(stored.peer_name, "张三");
+        assert_eq!(repo.count().await.unwrap(), 3);
+
+        // 对端名称未知时保留已记录的名称
+        repo.save(&transfer("c", "paused", now)).await.unwrap();
+        assert_eq!(repo.find_by_task_id("c").await.unwrap().unwrap().peer_name, "张三");
+
+        let unfinished: Vec<_> = repo.find_unfinished().await.unwrap().into_iter().map(|t| t.task_id).collect();
+        assert_eq!(unfinished, ["b", "c"]);
+
+        let first: Vec<_> = repo.find_page(0, 2).await.unwrap().into_iter().map(|t| t.task_id).collect();
+        let second: Vec<_> = repo.find_page(1, 2).await.unwrap().into_iter().map(|t| t.task_id).collect();
+        assert_eq!(first, ["c", "b"]);
+        assert_eq!(second, ["a"]);
+    }
+}
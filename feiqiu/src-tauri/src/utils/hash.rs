@@ -65,6 +65,59 @@ pub fn calculate_file_md5(path: &Path) -> Result<String> {
     Ok(format!("{:x}", result))
 }
 
+/// Calculate MD5 hash of the first `len` bytes of a file
+///
+/// Used to check that the part of a file a receiver already has matches
+/// the sender's file before resuming the transfer.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file to hash
+/// * `len` - Number of bytes to hash from the start of the file
+///
+/// # Returns
+///
+/// MD5 hash as a hexadecimal string (32 characters)
+///
+/// # Errors
+///
+/// Returns `NeoLanError::FileTransfer` if:
+/// - File cannot be opened or read
+/// - File is shorter than `len` bytes
+pub fn calculate_prefix_md5(path: &Path, len: u64) -> Result<String> {
+    let file = File::open(path).map_err(|e| {
+        NeoLanError::FileTransfer(format!("Failed to open file {}: {}", path.display(), e))
+    })?;
+
+    let mut reader = BufReader::new(file).take(len);
+    let mut hasher = Md5::new();
+    let mut buffer = [0u8; 8192];
+    let mut hashed = 0u64;
+
+    loop {
+        let n = reader.read(&mut buffer).map_err(|e| {
+            NeoLanError::FileTransfer(format!("Failed to read file {}: {}", path.display(), e))
+        })?;
+
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..n]);
+        hashed += n as u64;
+    }
+
+    if hashed < len {
+        return Err(NeoLanError::FileTransfer(format!(
+            "File {} is shorter than {} bytes",
+            path.display(),
+            len
+        )));
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 /// Get the size of a file in bytes
 ///
 /// # Arguments
@@ -160,6 +213,24 @@ mod tests {
         std::fs::remove_file(&test_file).unwrap();
     }
 
+    #[test]
+    fn test_calculate_prefix_md5() {
+        let temp_dir = env::temp_dir();
+        let test_file = temp_dir.join("test_md5_prefix.txt");
+
+        let mut file = File::create(&test_file).unwrap();
+        file.write_all(b"Hello World, and more").unwrap();
+
+        // Same as the MD5 of "Hello World"
+        let md5 = calculate_prefix_md5(&test_file, 11).unwrap();
+        assert_eq!(md5, "b10a8db164e0754105b7a99be72e3fe5");
+        assert_eq!(calculate_prefix_md5(&test_file, 0).unwrap(), "d41d8cd98f00b204e9800998ecf8427e");
+        assert!(calculate_prefix_md5(&test_file, 1000).is_err());
+
+        // Clean up
+        std::fs::remove_file(&test_file).unwrap();
+    }
+
     #[test]
     fn test_get_file_size() {
         // Create a temporary file
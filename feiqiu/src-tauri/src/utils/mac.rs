@@ -0,0 +1,118 @@
+// Local MAC address lookup (used as the FeiQ client identity)
+use md5::{Digest, Md5};
+use std::sync::OnceLock;
+
+/// Get the MAC address of this machine
+///
+/// Returns the first non-loopback hardware address as 12 uppercase hex
+/// digits without separators (the format FeiQ puts in its packet header).
+/// When no interface address can be read, a stable locally administered
+/// address is derived from the hostname instead.
+///
+/// The value is looked up once and cached for the lifetime of the process.
+///
+/// # Example
+///
+/// ```no_run
+/// use neolan_lib::utils::mac;
+///
+/// let mac = mac::local_mac_address();
+/// assert_eq!(mac.len(), 12);
+/// ```
+pub fn local_mac_address() -> &'static str {
+    static LOCAL_MAC: OnceLock<String> = OnceLock::new();
+
+    LOCAL_MAC.get_or_init(|| {
+        read_interface_mac().unwrap_or_else(|| {
+            let hostname = whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string());
+            tracing::debug!("No interface MAC address found, deriving one from hostname");
+            derive_mac(&hostname)
+        })
+    })
+}
+
+/// Read the first usable interface MAC address (Linux: /sys/class/net)
+#[cfg(target_os = "linux")]
+fn read_interface_mac() -> Option<String> {
+    let mut interfaces: Vec<_> = std::fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    interfaces.sort();
+
+    interfaces
+        .iter()
+        .filter(|path| path.file_name().is_some_and(|name| name != "lo"))
+        .filter_map(|path| std::fs::read_to_string(path.join("address")).ok())
+        .filter_map(|address| normalize_mac(address.trim()))
+        .find(|mac| mac != "000000000000")
+}
+
+/// Read the first usable interface MAC address (not available on this platform)
+#[cfg(not(target_os = "linux"))]
+fn read_interface_mac() -> Option<String> {
+    None
+}
+
+/// Normalize `aa:bb:cc:dd:ee:ff` / `AA-BB-...` into `AABBCCDDEEFF`
+///
+/// # Returns
+/// * `Some(String)` - 12 uppercase hex digits
+/// * `None` - Not a 6-byte MAC address
+pub fn normalize_mac(address: &str) -> Option<String> {
+    let hex: String = address
+        .chars()
+        .filter(|c| *c != ':' && *c != '-')
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    if hex.len() == 12 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(hex)
+    } else {
+        None
+    }
+}
+
+/// Derive a stable, locally administered MAC address from a seed string
+fn derive_mac(seed: &str) -> String {
+    let digest = Md5::digest(seed.as_bytes());
+    let mut bytes = [0u8; 6];
+    bytes.copy_from_slice(&digest[..6]);
+
+    // Unicast, locally administered (so it can't clash with a real vendor address)
+    bytes[0] = (bytes[0] & 0xfc) | 0x02;
+
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_mac() {
+        assert_eq!(normalize_mac("24:f5:aa:d7:c9:6a"), Some("24F5AAD7C96A".to_string()));
+        assert_eq!(normalize_mac("24-F5-AA-D7-C9-6A"), Some("24F5AAD7C96A".to_string()));
+        assert_eq!(normalize_mac("24F5AAD7C96A"), Some("24F5AAD7C96A".to_string()));
+        assert_eq!(normalize_mac("24:f5:aa"), None);
+        assert_eq!(normalize_mac("zz:f5:aa:d7:c9:6a"), None);
+    }
+
+    #[test]
+    fn test_derive_mac_is_stable_and_local() {
+        let mac = derive_mac("alice-pc");
+        assert_eq!(mac, derive_mac("alice-pc"));
+        assert_ne!(mac, derive_mac("bob-pc"));
+        assert_eq!(normalize_mac(&mac), Some(mac.clone()));
+
+        let first = u8::from_str_radix(&mac[..2], 16).unwrap();
+        assert_eq!(first & 0x03, 0x02);
+    }
+
+    #[test]
+    fn test_local_mac_address() {
+        let mac = local_mac_address();
+        assert_eq!(normalize_mac(mac).as_deref(), Some(mac));
+    }
+}
@@ -1,3 +1,4 @@
 // Utility functions
 pub mod logger;
 pub mod hash;
+pub mod mac;